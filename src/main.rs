@@ -2,13 +2,199 @@
 //! This module handles listening for incoming connections and dispatching them
 //! to the appropriate obfuscated protocols (OTLS/WS, AOQUIC).
 
-use tokio::net::{TcpListener, UdpSocket};
-use std::io;
-use tracing::{info, error, debug};
+use std::{io, sync::Arc, time::Duration};
+use tracing::{info, warn, error};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-// Import the ObfuscatedProtocol trait and specific protocol modules
+// Import the protocol registry and specific protocol modules
 use crate::protocols::{otls_ws, aoquic, ObfuscatedProtocol};
+use crate::protocols::common::{ProtocolConfig, ProtocolType};
+use crate::protocols::handoff::SocketHandoff;
+use crate::protocols::handshake_limiter::HandshakeLimiter;
+use crate::protocols::handshake_stats::HandshakeStatsTracker;
+use crate::protocols::listener::{run_tcp_accept_loop, run_udp_recv_loop, Admission, BindStagger, ConnectionLimiter};
+#[cfg(unix)]
+use crate::protocols::listener::{bind_unix_listener, run_unix_accept_loop};
+use crate::protocols::peer_rate_limiter::PeerRateLimiter;
+use crate::protocols::registry::ProtocolRegistry;
+use crate::security::kill_switch::KillSwitchManager;
+use crate::security::probe_detection::ProbeDetector;
+use crate::security::traffic_obfuscation::{validate_profiles, ObfuscationProfileTier, Obfuscator, ObfuscatorConfig};
+use crate::security::transform_registry::TransformRegistry;
+use crate::utils::bandwidth::BandwidthLimiter;
+use crate::utils::config::{self, CliArgs, ServerConfig};
+use crate::utils::logging::{set_redactor, AuditLog, FileAuditSink};
+use crate::utils::metrics::MetricsExporter;
+
+/// Most source subnets whose handshake outcomes are reported; the least recently seen are dropped.
+const TRACKED_SUBNETS: usize = 256;
+
+/// Server-wide state the protocol handlers share.
+struct SharedState {
+    kill_switch: KillSwitchManager,
+    handshake_limiter: HandshakeLimiter,
+    handshake_stats: Arc<HandshakeStatsTracker>,
+    audit: AuditLog,
+    bandwidth: Option<BandwidthLimiter>,
+    obfuscator: Option<Arc<Obfuscator>>,
+    probe_detector: Option<Arc<ProbeDetector>>,
+}
+
+/// Builds the handler for `protocol_type` with the configured mimic domain. Its Kill Switch gate
+/// follows `kill_switch` only while the tunnel's `enable_kill_switch` is set.
+fn build_protocol(protocol_type: ProtocolType, config: &ServerConfig, shared: &SharedState) -> Arc<dyn ObfuscatedProtocol> {
+    let SharedState {
+        kill_switch,
+        handshake_limiter,
+        handshake_stats,
+        audit,
+        bandwidth,
+        obfuscator,
+        probe_detector,
+    } = shared;
+    let mut protocol_config = ProtocolConfig::default_for(protocol_type.clone());
+    config.apply_to(&mut protocol_config);
+    match protocol_type {
+        ProtocolType::OtlsWs => {
+            let mut protocol = otls_ws::OtlsWsProtocol::new()
+                .with_kill_switch_manager(kill_switch.clone())
+                .with_handshake_limiter(handshake_limiter.clone())
+                .with_handshake_stats(handshake_stats.clone())
+                .with_audit_log(audit.clone());
+            if let Some(bandwidth) = bandwidth {
+                protocol = protocol.with_bandwidth_limiter(bandwidth.clone());
+            }
+            if let Some(obfuscator) = obfuscator {
+                protocol = protocol.with_obfuscator(obfuscator.clone());
+            }
+            if let Some(detector) = probe_detector {
+                protocol = protocol.with_probe_detector(detector.clone());
+            }
+            if let Some(shaping) = &config.video_shaping {
+                protocol = protocol.with_video_shaping(shaping.to_config());
+            }
+            if let Some(timing) = &config.response_timing {
+                protocol = protocol.with_response_timing(timing.to_config());
+            }
+            if let Some(level) = config.min_obfuscation_level {
+                protocol = protocol.with_min_obfuscation_level(level);
+            }
+            protocol.update_config(protocol_config);
+            Arc::new(protocol)
+        }
+        ProtocolType::AoQuic => {
+            let mut protocol = aoquic::AoQuicProtocol::new()
+                .with_kill_switch_manager(kill_switch.clone())
+                .with_handshake_limiter(handshake_limiter.clone())
+                .with_audit_log(audit.clone());
+            protocol.update_config(protocol_config);
+            Arc::new(protocol)
+        }
+    }
+}
+
+/// Starts the Kill Switch health check if the configuration enables it and names a probe target.
+fn spawn_health_check(kill_switch: &KillSwitchManager, config: &ServerConfig) -> Option<JoinHandle<()>> {
+    let target = config.kill_switch.probe_target.filter(|_| config.kill_switch.enabled)?;
+    let kill_switch = kill_switch.clone();
+    let probe_interval = config.kill_switch.to_config().probe_interval;
+    Some(tokio::spawn(async move { kill_switch.run_health_check(target, probe_interval).await }))
+}
+
+/// Installs the nftables rules while the Kill Switch is triggered, if the configuration names a
+/// table. Only the health-check target stays reachable, so the switch can still recover.
+#[cfg(all(target_os = "linux", feature = "firewall"))]
+fn spawn_firewall_hook(kill_switch: &KillSwitchManager, config: &ServerConfig) -> Option<JoinHandle<()>> {
+    use crate::security::firewall::{FirewallHook, NftablesBackend};
+
+    let table = config.kill_switch.firewall_table.as_deref()?;
+    let allowed = config.kill_switch.probe_target?;
+    info!("Kill Switch will block outbound traffic except to {} via nftables table {}", allowed, table);
+    Some(FirewallHook::new(Arc::new(NftablesBackend::new(table)), allowed).spawn(kill_switch))
+}
+
+/// Re-reads the configuration file on every SIGHUP and applies it to the running protocols
+/// (see `config::reload`). A bad file is logged and the previous configuration stays in effect.
+#[cfg(unix)]
+async fn reload_on_sighup(
+    cli: CliArgs,
+    mut config: ServerConfig,
+    registry: Arc<ProtocolRegistry>,
+    kill_switch: KillSwitchManager,
+    mut health_check: Option<JoinHandle<()>>,
+) -> io::Result<()> {
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        let Some(path) = cli.config_path() else {
+            warn!("SIGHUP received, but no configuration file was given; nothing to reload");
+            continue;
+        };
+        info!("SIGHUP received, reloading configuration from {}", path);
+        match config::reload(&path, &cli, &config, &registry, &kill_switch) {
+            Ok(reloaded) => config = reloaded,
+            Err(e) => {
+                error!("Keeping the previous configuration: {}", e);
+                continue;
+            }
+        }
+        if !config.kill_switch.enabled {
+            if let Some(task) = health_check.take() {
+                task.abort();
+            }
+        } else if health_check.is_none() {
+            health_check = spawn_health_check(&kill_switch, &config);
+        }
+    }
+    Ok(())
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on Unix.
+async fn shutdown_signal() -> io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "Ctrl+C"),
+            _ = sigterm.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|_| "Ctrl+C")
+    }
+}
+
+/// Waits for a shutdown signal. SIGUSR2 first starts the upgraded server with this one's
+/// listening sockets (see `handoff`); if it can't be started, this one keeps serving.
+#[cfg(unix)]
+async fn wait_for_exit(handoff: &SocketHandoff) -> io::Result<&'static str> {
+    let mut sigusr2 = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined2())?;
+    loop {
+        tokio::select! {
+            signal = shutdown_signal() => return signal,
+            _ = sigusr2.recv() => {
+                // Started by the name it was run as, so a replaced executable is the one that runs.
+                let mut args = std::env::args_os();
+                let mut command = std::process::Command::new(args.next().unwrap_or_default());
+                command.args(args);
+                match handoff.spawn_successor(command) {
+                    Ok(child) => {
+                        info!("SIGUSR2 received, handed the listeners to the upgraded server (pid {})", child.id());
+                        return Ok("SIGUSR2");
+                    }
+                    Err(e) => error!("SIGUSR2 received, but the upgraded server didn't start; still serving: {}", e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_exit(_handoff: &SocketHandoff) -> io::Result<&'static str> {
+    shutdown_signal().await
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
@@ -19,89 +205,227 @@ async fn main() -> io::Result<()> {
 
     info!("HezarDastan Core is starting up...");
 
-    // --- Configuration (will be loaded from config file later) ---
-    let tcp_listen_addr = "0.0.0.0:8443"; // Default port for OTLS/WS (mimics HTTPS)
-    let udp_listen_addr = "0.0.0.0:8444"; // Default port for AOQUIC
+    // --- Configuration ---
+    // Without a config file the defaults apply: OTLS/WS on 0.0.0.0:8443 (mimics HTTPS), AOQUIC on 0.0.0.0:8444.
+    // `--tcp-addr` / `--udp-addr` override the addresses from the file.
+    let cli = CliArgs::parse(std::env::args().skip(1)).map_err(|e| {
+        error!("Invalid command line: {}", e);
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    })?;
+    let mut config = match cli.config_path() {
+        Some(path) => {
+            info!("Loading configuration from {}", path);
+            ServerConfig::from_file(&path).map_err(|e| {
+                error!("Failed to load configuration: {}", e);
+                io::Error::from(e)
+            })?
+        }
+        None => ServerConfig::default(),
+    };
+    config.apply_cli(&cli);
+    // Installed before any connection is logged; later reloads don't change it.
+    set_redactor(config.redaction.to_redactor());
+
+    // --- Kill Switch ---
+    // Each protocol builds its gate from its tunnel's `enable_kill_switch` and rebuilds it when a
+    // reload changes the setting; while disabled the gate never blocks.
+    let kill_switch = KillSwitchManager::with_config(config.kill_switch.enabled, config.kill_switch.to_config());
+    let health_check = spawn_health_check(&kill_switch, &config);
+    #[cfg(all(target_os = "linux", feature = "firewall"))]
+    let _firewall_hook = spawn_firewall_hook(&kill_switch, &config);
+
+    // --- Audit log ---
+    // Connection attempts go to their own file, apart from the general log.
+    let audit = match &config.audit_log_path {
+        Some(path) => {
+            let sink = FileAuditSink::open(path).map_err(|e| {
+                error!("failed to open audit log {}: {}", path.display(), e);
+                e
+            })?;
+            info!("Writing the connection audit log to {}", path.display());
+            AuditLog::new(Arc::new(sink))
+        }
+        None => AuditLog::disabled(),
+    };
+
+    // --- Obfuscation self-check ---
+    // Every bundled profile must round-trip before any listener binds, so a broken build
+    // fails here instead of on its first tunnel.
+    let profiles: Vec<_> = ObfuscationProfileTier::ALL.iter().map(|tier| (tier.as_str(), tier.config())).collect();
+    validate_profiles(&profiles).map_err(|e| {
+        error!("Obfuscation self-check failed: {}", e);
+        io::Error::from(e)
+    })?;
 
     // --- Initialize Protocols ---
-    // Create instances of our obfuscated protocols.
-    // In a real scenario, these might be configured with specific keys/settings.
-    let otls_ws_protocol = otls_ws::OtlsWsProtocol::new();
-    let aoquic_protocol = aoquic::AoQuicProtocol::new();
-
-    // --- Start TCP Listener for OTLS/WS ---
-    let tcp_listener = TcpListener::bind(tcp_listen_addr).await
-        .map_err(|e| {
-            error!("Failed to bind TCP listener on {}: {}", tcp_listen_addr, e);
+    // Register an instance of each enabled protocol; listeners dispatch to them by type.
+    // They share one handshake limiter and one bandwidth limiter, so both caps are server-wide.
+    let handshake_limiter = HandshakeLimiter::new(config.handshake_limiter_config());
+    let bandwidth = config.bandwidth.as_ref().map(|settings| BandwidthLimiter::new(settings.to_config()));
+    // Transforms compiled into the server are registered on `transforms` before this point.
+    let transforms = TransformRegistry::new();
+    let obfuscator = if config.transforms.is_empty() {
+        None
+    } else {
+        let pipeline = transforms
+            .build_pipeline(&config.transforms)
+            .and_then(|pipeline| {
+                pipeline.with_required_checksum(config.require_checksum).into_obfuscator(ObfuscatorConfig::default())
+            })
+            .map(|pipeline| if config.obfuscation_stage_timing { pipeline.with_stage_timing() } else { pipeline })
+            .map_err(|e| {
+                error!("Invalid obfuscation pipeline: {}", e);
+                io::Error::from(e)
+            })?;
+        let names: Vec<&str> = config.transforms.iter().map(|spec| spec.name.as_str()).collect();
+        info!("Framing OTLS/WS tunnels with the {} pipeline", names.join(" -> "));
+        Some(Arc::new(pipeline))
+    };
+    match (&config.app_preset, config.obfuscation_tier, &obfuscator) {
+        (Some(preset), _, None) => info!("Framing OTLS/WS tunnels to look like the {} app", preset),
+        (None, Some(tier), None) => info!("Framing OTLS/WS tunnels with the {} obfuscation tier", tier.as_str()),
+        _ => {}
+    }
+    // Peers that look like active probers get the cover page instead of the handshake.
+    let probe_detector = config
+        .probe_detection
+        .as_ref()
+        .map(|settings| Arc::new(ProbeDetector::new(settings.to_config())));
+    let shared = SharedState {
+        kill_switch: kill_switch.clone(),
+        handshake_limiter,
+        handshake_stats: Arc::new(HandshakeStatsTracker::new(TRACKED_SUBNETS)),
+        audit: audit.clone(),
+        bandwidth,
+        obfuscator,
+        probe_detector,
+    };
+    let mut registry = ProtocolRegistry::new();
+    for protocol_type in config.protocol_types()? {
+        let protocol = build_protocol(protocol_type.clone(), &config, &shared);
+        registry.register(protocol_type, protocol);
+    }
+    let registry = Arc::new(registry);
+
+    // Cancelled on shutdown to stop every accept loop.
+    let shutdown = CancellationToken::new();
+    let mut accept_loops = Vec::new();
+    // Caps concurrent TCP connections across all listeners.
+    let connection_limiter = ConnectionLimiter::new(config.max_connections);
+    // Limits how fast each peer IP may open connections; idle peers are forgotten once a minute.
+    let peer_rate_limiter = PeerRateLimiter::new(config.peer_connections_per_second);
+    peer_rate_limiter.spawn_eviction(Duration::from_secs(60), shutdown.clone());
+    // Refused connections are answered like a rate-limiting CDN instead of being dropped.
+    let admission = Admission::new(connection_limiter, peer_rate_limiter)
+        .with_rejection_response(config.rejection.clone())
+        .with_audit_log(audit);
+    // Binds are optionally staggered, so a restart doesn't open every port at the same instant.
+    let stagger = config.listener_stagger.as_ref().map_or(BindStagger::NONE, |settings| settings.to_stagger());
+    // After a hot upgrade the previous process passes its listening sockets in; those are
+    // taken over instead of bound.
+    let mut handoff = SocketHandoff::from_env().map_err(|e| {
+        error!("{}", e);
+        e
+    })?;
+
+    if registry.get(&ProtocolType::OtlsWs).is_some() {
+        // --- Start TCP Listeners for OTLS/WS ---
+        let tcp_listeners = handoff.bind_tcp_listeners(&config.tcp_listen_addrs, config.dual_stack, stagger).await.map_err(|e| {
+            error!("{}", e);
             e
         })?;
-    info!("Listening for OTLS/WS connections on {}", tcp_listen_addr);
-
-    // Spawn a task to handle incoming TCP connections
-    tokio::spawn(async move {
-        loop {
-            match tcp_listener.accept().await {
-                Ok((socket, peer_addr)) => {
-                    info!("OTLS/WS: New TCP connection from {}", peer_addr);
-                    // Clone the protocol instance to move into the spawned task
-                    // (Alternatively, use Arc<Self> if protocol has shared state)
-                    let protocol_instance = otls_ws_protocol.clone(); // Assuming .clone() is implemented for the protocol struct
-                    tokio::spawn(async move {
-                        if let Err(e) = protocol_instance.handle_tcp_stream(socket).await {
-                            error!("OTLS/WS: Error handling TCP stream from {}: {}", peer_addr, e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    error!("OTLS/WS: TCP accept error: {}", e);
-                }
-            }
+        // The connection cap ramps up from the moment the listeners are bound, so a freshly
+        // restarted server isn't hit by every reconnecting client at once.
+        let admission = admission.clone().with_warmup(stagger.warmup);
+
+        // Spawn a task per listener to handle incoming TCP connections
+        for (tcp_listener, tcp_listen_addr) in tcp_listeners.into_iter().zip(&config.tcp_listen_addrs) {
+            info!("Listening for OTLS/WS connections on {}", tcp_listen_addr);
+            accept_loops.push(tokio::spawn(run_tcp_accept_loop(
+                tcp_listener,
+                registry.clone(),
+                ProtocolType::OtlsWs,
+                admission.clone(),
+                shutdown.clone(),
+            )));
         }
-    });
 
-    // --- Start UDP Listener for AOQUIC ---
-    let udp_socket = UdpSocket::bind(udp_listen_addr).await
-        .map_err(|e| {
-            error!("Failed to bind UDP socket on {}: {}", udp_listen_addr, e);
+        // --- Start the Unix socket listener for local chaining ---
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_listen_path {
+            let unix_listener = bind_unix_listener(path).await.map_err(|e| {
+                error!("{}", e);
+                e
+            })?;
+            info!("Listening for local OTLS/WS connections on {}", path.display());
+            accept_loops.push(tokio::spawn(run_unix_accept_loop(
+                unix_listener,
+                registry.clone(),
+                ProtocolType::OtlsWs,
+                admission.clone(),
+                shutdown.clone(),
+            )));
+        }
+    }
+
+    if registry.get(&ProtocolType::AoQuic).is_some() {
+        // --- Start UDP Listener for AOQUIC ---
+        let udp_socket = handoff.bind_udp_socket(config.udp_listen_addr, config.dual_stack, stagger).await.map_err(|e| {
+            error!("{}", e);
             e
         })?;
-    info!("Listening for AOQUIC connections on {}", udp_listen_addr);
-
-    // Spawn a task to handle incoming UDP packets
-    // Note: For UDP, the socket itself needs to be shared or cloned carefully
-    // For simplicity, we'll pass a reference to the socket for now,
-    // but real QUIC implementations manage their own socket state.
-    let aoquic_socket = udp_socket.into_std().expect("Failed to convert to std socket");
-    aoquic_socket.set_nonblocking(true).expect("Failed to set non-blocking");
-    let aoquic_socket = UdpSocket::from_std(aoquic_socket).expect("Failed to convert back to tokio socket");
-
-    tokio::spawn(async move {
-        let mut buf = vec![0u8; 65536]; // Max UDP packet size
-        loop {
-            match aoquic_socket.recv_from(&mut buf).await {
-                Ok((len, peer_addr)) => {
-                    debug!("AOQUIC: New UDP packet from {} ({} bytes)", peer_addr, len);
-                    // Clone the protocol instance
-                    let protocol_instance = aoquic_protocol.clone(); // Assuming .clone() is implemented
-                    let packet_data = buf[..len].to_vec(); // Copy packet data for the spawned task
-                    tokio::spawn(async move {
-                        if let Err(e) = protocol_instance.handle_udp_packet(&aoquic_socket, &packet_data, peer_addr).await {
-                            error!("AOQUIC: Error handling UDP packet from {}: {}", peer_addr, e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    // Handle WouldBlock error specifically for non-blocking UDP socket
-                    if e.kind() != io::ErrorKind::WouldBlock {
-                        error!("AOQUIC: UDP recv_from error: {}", e);
-                    }
-                }
-            }
+        info!("Listening for AOQUIC connections on {}", config.udp_listen_addr);
+
+        // Spawn a task to handle incoming UDP packets. The socket is shared with every
+        // packet handler so replies go out the same socket the packet arrived on.
+        accept_loops.push(tokio::spawn(run_udp_recv_loop(
+            Arc::new(udp_socket),
+            registry.clone(),
+            ProtocolType::AoQuic,
+            shutdown.clone(),
+        )));
+    }
+
+    if let Some(metrics_addr) = config.metrics_addr {
+        // --- Start the Prometheus metrics endpoint ---
+        let metrics_listener = handoff.bind_tcp_listener(metrics_addr).await.map_err(|e| {
+            error!("failed to bind metrics endpoint on {}: {}", metrics_addr, e);
+            e
+        })?;
+        info!("Serving Prometheus metrics on http://{}/metrics", metrics_addr);
+        let mut exporter =
+            MetricsExporter::new(registry.clone(), kill_switch.clone()).with_handshake_stats(shared.handshake_stats.clone());
+        if let Some(obfuscator) = &shared.obfuscator {
+            exporter = exporter.with_obfuscator(obfuscator.clone());
         }
-    });
+        tokio::spawn(exporter.serve(metrics_listener, shutdown.clone()));
+    }
+
+    handoff.close_unclaimed();
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(cli, config, registry.clone(), kill_switch, health_check));
+    #[cfg(not(unix))]
+    let _ = health_check;
 
     info!("HezarDastan Core is running. Press Ctrl+C to stop.");
-    std::future::pending::<()>().await;
+    let signal = wait_for_exit(&handoff).await?;
+
+    info!("{} received, stopping listeners...", signal);
+    shutdown.cancel();
+    for accept_loop in accept_loops {
+        if let Err(e) = accept_loop.await {
+            error!("Listener task failed: {}", e);
+        }
+    }
+
+    // Accept loops are stopped, so no new connections can be counted after this.
+    let draining: u64 = registry.protocols().iter().map(|protocol| protocol.metrics().active_connections).sum();
+    info!("Closing {} active tunnel(s)...", draining);
+    for protocol in registry.protocols() {
+        protocol.shutdown().await;
+    }
+    info!("Drained {} connection(s). HezarDastan Core stopped.", draining);
 
     Ok(())
 }
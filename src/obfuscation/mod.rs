@@ -2,17 +2,28 @@
 //! Defines traits and modules for various obfuscated protocols used by HezarDastan Core.
 
 use async_trait::async_trait; // For async traits
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpStream, UdpSocket, SocketAddr};
 use std::io;
 
+use crate::protocols::common::{HealthStatus, ProtocolConfig, ProtocolMetrics};
+use crate::protocols::connection_state::{ConnectionState, ConnectionSummary};
+
 // Re-export specific protocol modules
 pub mod otls_ws;
 pub mod aoquic;
 
+/// A byte stream a connection-oriented protocol can run over: a TCP connection or, on Unix,
+/// a local socket connection.
+pub trait TunnelStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> TunnelStream for T {}
+
 /// A trait defining the common interface for all obfuscated protocols.
 /// Each protocol implementation must adhere to this interface.
+/// Handlers are shared across listener tasks (see `registry::ProtocolRegistry`), hence `Send + Sync`.
 #[async_trait]
-pub trait ObfuscatedProtocol {
+pub trait ObfuscatedProtocol: Send + Sync {
     /// Returns the name of the protocol (e.g., "OTLS/WS", "AOQUIC").
     fn name(&self) -> &'static str;
 
@@ -20,12 +31,57 @@ pub trait ObfuscatedProtocol {
     /// This method should perform the obfuscation handshake and then tunnel the traffic.
     async fn handle_tcp_stream(&self, stream: TcpStream) -> io::Result<()>;
 
+    /// Handles any other byte stream the same way as a TCP stream from `peer_addr`.
+    /// Protocols that don't run over streams keep this default, which refuses it.
+    async fn handle_stream(&self, _stream: Box<dyn TunnelStream>, peer_addr: SocketAddr) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} does not handle byte streams from {}", self.name(), peer_addr),
+        ))
+    }
+
     /// Handles an incoming UDP packet for connectionless protocols.
     /// This method should de-obfuscate the packet and potentially forward it.
     async fn handle_udp_packet(&self, socket: &UdpSocket, buf: &[u8], peer_addr: SocketAddr) -> io::Result<()>;
 
-    // TODO: Add methods for protocol-specific configuration, metrics, etc.
-    // For example:
-    // fn get_config(&self) -> &ProtocolConfig;
-    // fn update_config(&mut self, new_config: ProtocolConfig);
+    /// Returns the configuration currently in effect.
+    fn get_config(&self) -> &ProtocolConfig;
+
+    /// Replaces the configuration (e.g. a new mimic domain pushed by the panel).
+    /// Connections accepted afterwards use the new values.
+    fn update_config(&mut self, new_config: ProtocolConfig);
+
+    /// Returns a copy of this handler that shares its connections and counters, so a
+    /// registered handler can be reconfigured and swapped in while it is serving traffic.
+    fn clone_box(&self) -> Box<dyn ObfuscatedProtocol>;
+
+    /// Returns a snapshot of this protocol's connection and traffic counters,
+    /// shared by every clone of the handler.
+    fn metrics(&self) -> ProtocolMetrics;
+
+    /// Returns how many live connections are in each lifecycle state (see `ConnectionTracker`).
+    /// Protocols without per-connection state, like AOQUIC's packet-at-a-time handling, keep
+    /// this default, which reports none.
+    fn connection_states(&self) -> Vec<(ConnectionState, usize)> {
+        Vec::new()
+    }
+
+    /// Returns every live connection with its own counters. Like `connection_states`, the
+    /// default reports none.
+    fn connections(&self) -> Vec<ConnectionSummary> {
+        Vec::new()
+    }
+
+    /// Zeroes the counters of live connection `id`, or of all of them if `id` is `None`, and
+    /// returns how many were reset (see `ConnectionTracker::reset_counters`).
+    fn reset_connection_counters(&self, _id: Option<u64>) -> usize {
+        0
+    }
+
+    /// Asks every active connection to drain and close cleanly, and returns once they have.
+    /// Connections arriving afterwards are closed straight away.
+    async fn shutdown(&self);
+
+    /// Reports whether this protocol is ready to accept connections, for readiness checks.
+    async fn health(&self) -> HealthStatus;
 }
@@ -3,16 +3,30 @@
 
 use async_trait::async_trait;
 use tokio::net::{TcpStream, UdpSocket, SocketAddr};
-use std::io;
+use std::{io, sync::Arc};
 use tracing::{info, debug, error, warn}; // Import tracing macros
 
 use crate::protocols::ObfuscatedProtocol; // Import the trait
+use crate::protocols::handshake_limiter::HandshakeLimiter;
+use crate::protocols::common::{ConnectionRegistry, HealthStatus, ProtocolConfig, ProtocolCounters, ProtocolMetrics, ProtocolType};
+use crate::security::kill_switch::{KillSwitchGate, KillSwitchManager};
+use crate::utils::logging::{redact_addr, AuditLog, AuditOutcome};
 
 /// Represents the AOQUIC obfuscated protocol.
 /// This struct will hold configuration and state specific to AOQUIC.
 #[derive(Clone)] // Required for .clone() in main.rs
 pub struct AoQuicProtocol {
     // TODO: Add fields for QUIC configuration, obfuscation keys, etc.
+    /// Drops packets while the Kill Switch is triggered.
+    kill_switch: KillSwitchGate,
+    /// Set by `with_kill_switch_manager`; the gate is rebuilt from it whenever the config changes.
+    kill_switch_manager: Option<KillSwitchManager>,
+    config: ProtocolConfig,
+    /// Caps concurrent handshakes across protocols; `None` leaves them uncapped.
+    handshake_limiter: Option<HandshakeLimiter>,
+    counters: Arc<ProtocolCounters>,
+    connections: ConnectionRegistry,
+    audit: AuditLog,
 }
 
 impl AoQuicProtocol {
@@ -21,8 +35,41 @@ impl AoQuicProtocol {
         info!("Initializing AOQUIC Protocol.");
         AoQuicProtocol {
             // Initialize fields here
+            kill_switch: KillSwitchGate::disabled(),
+            kill_switch_manager: None,
+            config: ProtocolConfig::default_for(ProtocolType::AoQuic),
+            handshake_limiter: None,
+            counters: Arc::new(ProtocolCounters::default()),
+            connections: ConnectionRegistry::new(),
+            audit: AuditLog::disabled(),
         }
     }
+
+    /// Gates tunnel traffic on `gate` (see `KillSwitchGate::for_tunnel`).
+    pub fn with_kill_switch(mut self, gate: KillSwitchGate) -> Self {
+        self.kill_switch = gate;
+        self
+    }
+
+    /// Gates tunnel traffic on `manager`, honouring the tunnel's `enable_kill_switch` setting.
+    /// The gate follows later `update_config` calls, so a reload can turn it on or off.
+    pub fn with_kill_switch_manager(mut self, manager: KillSwitchManager) -> Self {
+        self.kill_switch = KillSwitchGate::for_tunnel(&self.config.tunnel, &manager);
+        self.kill_switch_manager = Some(manager);
+        self
+    }
+
+    /// Holds a permit from `limiter` while each handshake packet is processed.
+    pub fn with_handshake_limiter(mut self, limiter: HandshakeLimiter) -> Self {
+        self.handshake_limiter = Some(limiter);
+        self
+    }
+
+    /// Records failed handshakes in `audit`.
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
 }
 
 #[async_trait]
@@ -35,12 +82,45 @@ impl ObfuscatedProtocol for AoQuicProtocol {
     // or it might log an error if called.
     async fn handle_tcp_stream(&self, stream: TcpStream) -> io::Result<()> {
         let peer_addr = stream.peer_addr()?;
-        error!("AOQUIC: Received unexpected TCP stream from {}. This protocol is UDP-based.", peer_addr);
+        error!("AOQUIC: Received unexpected TCP stream from {}. This protocol is UDP-based.", redact_addr(peer_addr));
         Err(io::Error::new(io::ErrorKind::Other, "AOQUIC does not handle TCP streams."))
     }
 
     async fn handle_udp_packet(&self, socket: &UdpSocket, buf: &[u8], peer_addr: SocketAddr) -> io::Result<()> {
-        debug!("AOQUIC: Handling incoming UDP packet from {} ({} bytes)", peer_addr, buf.len());
+        debug!("AOQUIC: Handling incoming UDP packet from {} ({} bytes)", redact_addr(peer_addr), buf.len());
+
+        if self.connections.is_shutting_down() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "AOQUIC is shutting down"));
+        }
+
+        if buf.len() > self.config.max_datagram_size {
+            debug!("AOQUIC: Dropping oversized packet from {} ({} bytes)", redact_addr(peer_addr), buf.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "datagram exceeds max_datagram_size"));
+        }
+
+        // Nothing is forwarded while the Kill Switch is triggered.
+        if let Err(e) = self.kill_switch.check() {
+            warn!("AOQUIC: Dropping packet from {}: {}", redact_addr(peer_addr), e);
+            return Err(e);
+        }
+        self.counters.add_bytes_in(buf.len());
+
+        // There are no QUIC sessions yet, so every packet goes through the handshake path
+        // and holds a handshake permit while it is processed.
+        let _permit = match &self.handshake_limiter {
+            Some(limiter) => match limiter.acquire().await {
+                Ok(permit) => Some(permit),
+                Err(e) => {
+                    self.counters.handshake_failed();
+                    let config = &self.config;
+                    let profile = config.obfuscation_profile();
+                    self.audit.record(peer_addr.ip(), &config.tunnel.protocol_type, profile, AuditOutcome::HandshakeFailed);
+                    debug!("AOQUIC: Dropping packet from {}: {}", redact_addr(peer_addr), e);
+                    return Err(e.into());
+                }
+            },
+            None => None,
+        };
 
         // TODO: Here's where the actual QUIC packet processing and obfuscation/de-obfuscation logic will go.
         // This will involve:
@@ -56,9 +136,42 @@ impl ObfuscatedProtocol for AoQuicProtocol {
         //     warn!("AOQUIC: Failed to echo UDP packet back to {}: {}", peer_addr, e);
         // }
 
-        info!("AOQUIC: Successfully processed simulated UDP packet from {}", peer_addr);
+        info!("AOQUIC: Successfully processed simulated UDP packet from {}", redact_addr(peer_addr));
         Ok(())
     }
+
+    fn get_config(&self) -> &ProtocolConfig {
+        &self.config
+    }
+
+    fn update_config(&mut self, new_config: ProtocolConfig) {
+        info!("AOQUIC: Configuration updated (mimic domain {}).", new_config.tunnel.mimic_domain);
+        if let Some(manager) = &self.kill_switch_manager {
+            self.kill_switch = KillSwitchGate::for_tunnel(&new_config.tunnel, manager);
+        }
+        self.config = new_config;
+    }
+
+    fn clone_box(&self) -> Box<dyn ObfuscatedProtocol> {
+        Box::new(self.clone())
+    }
+
+    fn metrics(&self) -> ProtocolMetrics {
+        self.counters.snapshot()
+    }
+
+    async fn shutdown(&self) {
+        info!("AOQUIC: Shutting down, closing {} active connection(s).", self.connections.live_connections());
+        self.connections.shutdown().await;
+        info!("AOQUIC: All connections closed.");
+    }
+
+    async fn health(&self) -> HealthStatus {
+        if self.connections.is_shutting_down() {
+            return HealthStatus::Down;
+        }
+        self.kill_switch.health()
+    }
 }
 
 // Add unit tests for this module
@@ -77,11 +190,11 @@ mod tests {
     #[tokio::test]
     async fn test_aoquic_handles_udp_packet_successfully() {
         let protocol = AoQuicProtocol::new();
-        let listener_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap(); // Bind to ephemeral port
+        let listener_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()); // Bind to ephemeral port
         let addr = listener_socket.local_addr().unwrap();
 
         // Spawn a task to simulate receiving and handling a packet
-        let listener_socket_clone = listener_socket.try_clone().unwrap();
+        let listener_socket_clone = listener_socket.clone();
         tokio::spawn(async move {
             let mut buf = vec![0u8; 1024];
             let (len, peer_addr) = listener_socket_clone.recv_from(&mut buf).await.unwrap();
@@ -117,4 +230,83 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Other);
     }
+
+    #[tokio::test]
+    async fn test_aoquic_drops_packets_when_kill_switch_triggers() {
+        use crate::security::kill_switch::KillSwitchState;
+        use tokio::sync::watch;
+
+        let (state_tx, state_rx) = watch::channel(KillSwitchState::Active);
+        let protocol = AoQuicProtocol::new().with_kill_switch(KillSwitchGate::new(state_rx));
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        assert!(protocol.handle_udp_packet(&socket, b"packet", peer).await.is_ok());
+        state_tx.send(KillSwitchState::Triggered).unwrap();
+        let result = protocol.handle_udp_packet(&socket, b"packet", peer).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[tokio::test]
+    async fn test_aoquic_packets_respect_the_handshake_limit() {
+        use crate::protocols::handshake_limiter::{HandshakeLimiterConfig, OverflowPolicy};
+
+        let limiter = HandshakeLimiter::new(HandshakeLimiterConfig {
+            max_concurrent: 1,
+            overflow: OverflowPolicy::Reject,
+        });
+        let audit = Arc::new(crate::utils::logging::MemoryAuditSink::default());
+        let protocol = AoQuicProtocol::new()
+            .with_handshake_limiter(limiter.clone())
+            .with_audit_log(AuditLog::new(audit.clone()));
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        // Another handshake holds the only permit.
+        let held = limiter.acquire().await.unwrap();
+        let result = protocol.handle_udp_packet(&socket, b"initial", peer).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+        assert_eq!(protocol.metrics().handshake_failures, 1);
+        assert_eq!(audit.outcomes(), vec![AuditOutcome::HandshakeFailed]);
+
+        drop(held);
+        assert!(protocol.handle_udp_packet(&socket, b"initial", peer).await.is_ok());
+        assert_eq!(limiter.in_progress(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_aoquic_refuses_packets_after_shutdown() {
+        let protocol = AoQuicProtocol::new();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        assert!(protocol.handle_udp_packet(&socket, b"ping", peer).await.is_ok());
+
+        assert_eq!(protocol.health().await, HealthStatus::Ok);
+        protocol.shutdown().await;
+        assert_eq!(protocol.health().await, HealthStatus::Down);
+        let result = protocol.handle_udp_packet(&socket, b"ping", peer).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[tokio::test]
+    async fn test_aoquic_update_config_is_reflected() {
+        let mut protocol = AoQuicProtocol::new();
+        assert_eq!(protocol.get_config().tunnel.protocol_type, ProtocolType::AoQuic);
+
+        let mut config = protocol.get_config().clone();
+        config.tunnel.mimic_domain = "cdn.example.net".to_string();
+        config.max_datagram_size = 1200;
+        protocol.update_config(config);
+
+        assert_eq!(protocol.get_config().tunnel.mimic_domain, "cdn.example.net");
+        assert_eq!(protocol.get_config().max_datagram_size, 1200);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        assert!(protocol.handle_udp_packet(&socket, &[0u8; 1200], peer).await.is_ok());
+        let result = protocol.handle_udp_packet(&socket, &[0u8; 1300], peer).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Only the accepted packet counts.
+        assert_eq!(protocol.metrics().bytes_in, 1200);
+    }
 }
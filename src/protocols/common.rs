@@ -1,7 +1,17 @@
 //! This module defines common structures and utilities shared across various
 //! protocols and modules within the HezarDastan Core.
 
-use std::fmt;
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
 /// Represents a generic error that can occur within the HezarDastan core protocols.
 #[derive(Debug)]
@@ -13,6 +23,8 @@ pub enum ProtocolError {
     ObfuscationError(String),
     /// An unexpected protocol state or data format was encountered.
     ProtocolViolation(String),
+    /// The connection was refused by policy.
+    AccessDenied(String),
     /// General error with a descriptive message.
     Other(String),
 }
@@ -31,11 +43,36 @@ impl fmt::Display for ProtocolError {
             ProtocolError::HandshakeError(msg) => write!(f, "Handshake Error: {}", msg),
             ProtocolError::ObfuscationError(msg) => write!(f, "Obfuscation Error: {}", msg),
             ProtocolError::ProtocolViolation(msg) => write!(f, "Protocol Violation: {}", msg),
+            ProtocolError::AccessDenied(msg) => write!(f, "Access Denied: {}", msg),
             ProtocolError::Other(msg) => write!(f, "Error: {}", msg),
         }
     }
 }
 
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtocolError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Lets code that returns `io::Result` (e.g. stream adapters) surface a `ProtocolError`.
+// The original error stays reachable through `io::Error::get_ref`.
+impl From<ProtocolError> for std::io::Error {
+    fn from(err: ProtocolError) -> Self {
+        let kind = match &err {
+            ProtocolError::Io(io_err) => return std::io::Error::new(io_err.kind(), err),
+            ProtocolError::ObfuscationError(_) | ProtocolError::ProtocolViolation(_) => std::io::ErrorKind::InvalidData,
+            ProtocolError::HandshakeError(_) => std::io::ErrorKind::ConnectionAborted,
+            ProtocolError::AccessDenied(_) => std::io::ErrorKind::PermissionDenied,
+            ProtocolError::Other(_) => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
 /// `TunnelConfig` defines configuration parameters for a specific tunnel connection.
 /// This will be passed from the panel/client to the core.
 #[derive(Debug, Clone)]
@@ -81,6 +118,204 @@ impl ProtocolType {
     }
 }
 
+/// `ProtocolConfig` is the runtime configuration of one protocol handler:
+/// the tunnel settings plus knobs that only some protocols use.
+#[derive(Debug, Clone)]
+pub struct ProtocolConfig {
+    pub tunnel: TunnelConfig,
+    /// Path the WebSocket upgrade is served on. Used by OTLS/WS.
+    pub ws_path: String,
+    /// Largest datagram accepted, in bytes. Used by AOQUIC.
+    pub max_datagram_size: usize,
+    /// How long a client may take to complete the handshake before the connection is dropped.
+    /// Bounds the time a slow or malicious client can hold a half-open connection.
+    pub handshake_timeout: Duration,
+    /// Where tunnel traffic is relayed once the handshake completes. Used by OTLS/WS;
+    /// without one, connections are closed after the handshake.
+    pub upstream_addr: Option<SocketAddr>,
+}
+
+impl ProtocolConfig {
+    /// Wraps `tunnel` with the default protocol knobs.
+    pub fn new(tunnel: TunnelConfig) -> Self {
+        ProtocolConfig {
+            tunnel,
+            ws_path: "/".to_string(),
+            max_datagram_size: 1350,
+            handshake_timeout: Duration::from_secs(10),
+            upstream_addr: None,
+        }
+    }
+
+    /// Name of the obfuscation profile in use, from the tunnel's `profile` parameter.
+    pub fn obfuscation_profile(&self) -> &str {
+        self.tunnel.protocol_params.get("profile").map(String::as_str).unwrap_or("default")
+    }
+
+    /// Server-side defaults for `protocol_type`, used until the panel pushes a real configuration.
+    pub fn default_for(protocol_type: ProtocolType) -> Self {
+        let server_port = match protocol_type {
+            ProtocolType::OtlsWs => 8443,
+            ProtocolType::AoQuic => 8444,
+        };
+        Self::new(TunnelConfig {
+            server_address: "0.0.0.0".to_string(),
+            server_port,
+            user_id: String::new(),
+            protocol_type,
+            protocol_params: std::collections::HashMap::new(),
+            enable_kill_switch: false,
+            mimic_domain: "www.example.com".to_string(),
+        })
+    }
+}
+
+/// `ProtocolMetrics` is a snapshot of one protocol handler's counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolMetrics {
+    /// Connections currently being handled.
+    pub active_connections: u64,
+    /// Bytes received from peers.
+    pub bytes_in: u64,
+    /// Bytes sent to peers.
+    pub bytes_out: u64,
+    /// Connections that closed or failed before the handshake completed.
+    pub handshake_failures: u64,
+    /// Relayed connections whose throughput collapsed mid-flow (see `ThroughputMonitor`).
+    pub throttle_events: u64,
+    /// Relayed connections force-closed because they stopped making progress (see `StallDetector`).
+    pub stalled_connections: u64,
+}
+
+/// `HealthStatus` reports whether a protocol is ready to accept connections.
+/// Ordered from best to worst, so the aggregate of several is their `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    /// Accepting and forwarding traffic.
+    Ok,
+    /// Accepting connections, but forwarding may be interrupted (e.g. the Kill Switch is reconnecting).
+    Degraded,
+    /// Not forwarding traffic: shutting down, or blocked by the Kill Switch.
+    Down,
+}
+
+/// `ProtocolCounters` backs `ObfuscatedProtocol::metrics`.
+/// Protocol structs are cloned per connection, so they hold it behind an `Arc`.
+#[derive(Debug, Default)]
+pub struct ProtocolCounters {
+    active_connections: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    handshake_failures: AtomicU64,
+    throttle_events: AtomicU64,
+    stalled_connections: AtomicU64,
+}
+
+impl ProtocolCounters {
+    /// Counts a connection as active until the returned guard is dropped.
+    pub fn connection_opened(&self) -> ActiveConnection<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection { counters: self }
+    }
+
+    pub fn add_bytes_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn handshake_failed(&self) {
+        self.handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_throttled(&self) {
+        self.throttle_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_stalled(&self) {
+        self.stalled_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProtocolMetrics {
+        ProtocolMetrics {
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            handshake_failures: self.handshake_failures.load(Ordering::Relaxed),
+            throttle_events: self.throttle_events.load(Ordering::Relaxed),
+            stalled_connections: self.stalled_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Keeps a connection counted in `active_connections` while alive.
+pub struct ActiveConnection<'a> {
+    counters: &'a ProtocolCounters,
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.counters.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// `ConnectionRegistry` tracks a protocol's live connections so `shutdown` can close them.
+/// Clones share the same registry.
+#[derive(Debug, Clone)]
+pub struct ConnectionRegistry {
+    /// `true` once shutdown has started. Every live connection holds a receiver,
+    /// so the sender sees all of them closed when the last connection ends.
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        let (closing, _) = watch::channel(false);
+        ConnectionRegistry { closing: Arc::new(closing) }
+    }
+
+    /// Registers a connection. It counts as live until the returned handle is dropped.
+    pub fn register(&self) -> ConnectionHandle {
+        ConnectionHandle { closing: self.closing.subscribe() }
+    }
+
+    pub fn live_connections(&self) -> usize {
+        self.closing.receiver_count()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.closing.borrow()
+    }
+
+    /// Tells every live connection to close and waits until they all have.
+    pub async fn shutdown(&self) {
+        self.closing.send_replace(true);
+        self.closing.closed().await;
+    }
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A connection's entry in a `ConnectionRegistry`.
+pub struct ConnectionHandle {
+    closing: watch::Receiver<bool>,
+}
+
+impl ConnectionHandle {
+    /// Resolves once the registry starts shutting down.
+    /// The connection should then drain and close cleanly, and drop this handle.
+    pub async fn closing(&mut self) {
+        // The sender lives as long as the protocol, which outlives its connections.
+        let _ = self.closing.wait_for(|closing| *closing).await;
+    }
+}
+
 /// Represents a data packet with optional metadata, used for internal communication.
 #[derive(Debug, Clone)]
 pub struct Packet {
@@ -0,0 +1,435 @@
+//! This module models each connection's lifecycle as an explicit state machine.
+//! Making the state observable lets operators spot stuck connections (for example,
+//! a pile-up in `Handshaking`) instead of guessing from logs.
+//!
+//! OTLS/WS moves each connection through the states as it accepts, handshakes, relays and
+//! closes it, and the metrics endpoint reports the live counts per state. AOQUIC handles one
+//! packet at a time and has no connections to track yet.
+//!
+//! Each tracked connection also carries its own `ConnectionCounters` (bytes, errors, retries),
+//! so a leak or a drifting quota can be pinned on one connection. The metrics endpoint lists
+//! them and can reset them (see `ConnectionTracker::reset_counters`).
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+use crate::protocols::common::ProtocolError;
+use crate::utils::logging::redact_addr;
+
+/// The lifecycle states of a single connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    Accepted,
+    Handshaking,
+    Authenticated,
+    Tunneling,
+    Draining,
+    Closed,
+}
+
+impl ConnectionState {
+    const ALL: [ConnectionState; 6] = [
+        ConnectionState::Accepted,
+        ConnectionState::Handshaking,
+        ConnectionState::Authenticated,
+        ConnectionState::Tunneling,
+        ConnectionState::Draining,
+        ConnectionState::Closed,
+    ];
+
+    /// Lower-case label used in metrics, e.g. `"tunneling"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Accepted => "accepted",
+            ConnectionState::Handshaking => "handshaking",
+            ConnectionState::Authenticated => "authenticated",
+            ConnectionState::Tunneling => "tunneling",
+            ConnectionState::Draining => "draining",
+            ConnectionState::Closed => "closed",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            ConnectionState::Accepted => 0,
+            ConnectionState::Handshaking => 1,
+            ConnectionState::Authenticated => 2,
+            ConnectionState::Tunneling => 3,
+            ConnectionState::Draining => 4,
+            ConnectionState::Closed => 5,
+        }
+    }
+
+    /// Returns true if moving from `self` to `next` is allowed.
+    /// Connections only move forward, and any open connection may be closed abruptly.
+    pub fn can_transition_to(&self, next: ConnectionState) -> bool {
+        use ConnectionState::*;
+        matches!(
+            (self, next),
+            (Accepted, Handshaking)
+                | (Handshaking, Authenticated)
+                | (Authenticated, Tunneling)
+                | (Tunneling, Draining)
+                | (Accepted | Handshaking | Authenticated | Tunneling | Draining, Closed)
+        )
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A point-in-time view of one tracked connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionSummary {
+    pub id: u64,
+    pub peer_addr: SocketAddr,
+    pub state: ConnectionState,
+    /// How long the connection has been in its current state.
+    pub in_state_for: Duration,
+    pub counts: ConnectionCounts,
+}
+
+/// A snapshot of one connection's `ConnectionCounters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionCounts {
+    /// Bytes received from the peer.
+    pub bytes_in: u64,
+    /// Bytes sent to the peer.
+    pub bytes_out: u64,
+    /// Failed operations on the connection, including attempts that were then retried.
+    pub errors: u64,
+    /// Operations that failed and were tried again.
+    pub retries: u64,
+}
+
+/// `ConnectionCounters` counts one connection's traffic. The connection's handler holds it
+/// through its `TrackedConnection`; anything the handler hands work to (a `Relay`, say) can
+/// share it.
+#[derive(Debug, Default)]
+pub struct ConnectionCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    errors: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl ConnectionCounters {
+    pub fn add_bytes_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnectionCounts {
+        ConnectionCounts {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sets every counter back to zero.
+    pub fn reset(&self) {
+        for counter in [&self.bytes_in, &self.bytes_out, &self.errors, &self.retries] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Entry {
+    peer_addr: SocketAddr,
+    state: ConnectionState,
+    entered_at: Instant,
+    counters: Arc<ConnectionCounters>,
+}
+
+struct TrackerInner {
+    connections: Mutex<HashMap<u64, Entry>>,
+    next_id: AtomicU64,
+    /// Number of transitions into each state, indexed by `ConnectionState::index`.
+    transitions: [AtomicU64; 6],
+}
+
+/// `ConnectionTracker` holds the current state of every live connection.
+#[derive(Clone)]
+pub struct ConnectionTracker {
+    inner: Arc<TrackerInner>,
+}
+
+impl ConnectionTracker {
+    /// Creates an empty tracker.
+    pub fn new() -> Self {
+        ConnectionTracker {
+            inner: Arc::new(TrackerInner {
+                connections: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+                transitions: Default::default(),
+            }),
+        }
+    }
+
+    /// Registers a newly accepted connection. It is tracked until the handle is dropped.
+    pub fn register(&self, peer_addr: SocketAddr) -> TrackedConnection {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let counters = Arc::new(ConnectionCounters::default());
+        self.inner.connections.lock().unwrap().insert(
+            id,
+            Entry {
+                peer_addr,
+                state: ConnectionState::Accepted,
+                entered_at: Instant::now(),
+                counters: counters.clone(),
+            },
+        );
+        self.inner.transitions[ConnectionState::Accepted.index()].fetch_add(1, Ordering::Relaxed);
+        TrackedConnection {
+            id,
+            tracker: self.clone(),
+            counters,
+        }
+    }
+
+    /// Returns a summary of every live connection.
+    pub fn active(&self) -> Vec<ConnectionSummary> {
+        let now = Instant::now();
+        let connections = self.inner.connections.lock().unwrap();
+        let mut out: Vec<_> = connections
+            .iter()
+            .map(|(id, entry)| ConnectionSummary {
+                id: *id,
+                peer_addr: entry.peer_addr,
+                state: entry.state,
+                in_state_for: now.saturating_duration_since(entry.entered_at),
+                counts: entry.counters.snapshot(),
+            })
+            .collect();
+        out.sort_by_key(|summary| summary.id);
+        out
+    }
+
+    /// Zeroes the counters of connection `id`, or of every live connection if `id` is `None`.
+    /// Returns how many connections were reset; `0` means `id` isn't live.
+    pub fn reset_counters(&self, id: Option<u64>) -> usize {
+        let connections = self.inner.connections.lock().unwrap();
+        let mut reset = 0;
+        for (_, entry) in connections.iter().filter(|(candidate, _)| id.is_none_or(|id| **candidate == id)) {
+            entry.counters.reset();
+            reset += 1;
+        }
+        reset
+    }
+
+    /// Number of live connections currently in `state`.
+    pub fn count_in(&self, state: ConnectionState) -> usize {
+        let connections = self.inner.connections.lock().unwrap();
+        connections.values().filter(|entry| entry.state == state).count()
+    }
+
+    /// Total number of transitions into `state` since the tracker was created.
+    pub fn transitions_into(&self, state: ConnectionState) -> u64 {
+        self.inner.transitions[state.index()].load(Ordering::Relaxed)
+    }
+
+    /// Live connection counts for every state, for metrics export.
+    pub fn state_counts(&self) -> Vec<(ConnectionState, usize)> {
+        ConnectionState::ALL
+            .iter()
+            .map(|state| (*state, self.count_in(*state)))
+            .collect()
+    }
+}
+
+impl Default for ConnectionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle for a single tracked connection. Dropping it removes the connection from the tracker.
+pub struct TrackedConnection {
+    id: u64,
+    tracker: ConnectionTracker,
+    counters: Arc<ConnectionCounters>,
+}
+
+impl TrackedConnection {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The connection's traffic counters.
+    pub fn counters(&self) -> &Arc<ConnectionCounters> {
+        &self.counters
+    }
+
+    /// Returns the connection's current state.
+    pub fn state(&self) -> ConnectionState {
+        let connections = self.tracker.inner.connections.lock().unwrap();
+        connections
+            .get(&self.id)
+            .map(|entry| entry.state)
+            .unwrap_or(ConnectionState::Closed)
+    }
+
+    /// Moves the connection to `next`, rejecting transitions the state machine doesn't allow.
+    pub fn transition(&self, next: ConnectionState) -> Result<(), ProtocolError> {
+        let mut connections = self.tracker.inner.connections.lock().unwrap();
+        let entry = connections
+            .get_mut(&self.id)
+            .ok_or_else(|| ProtocolError::ProtocolViolation(format!("connection {} is no longer tracked", self.id)))?;
+
+        if !entry.state.can_transition_to(next) {
+            return Err(ProtocolError::ProtocolViolation(format!(
+                "connection {}: invalid transition {} -> {}",
+                self.id, entry.state, next
+            )));
+        }
+
+        debug!("Connection {} ({}): {} -> {}", self.id, redact_addr(entry.peer_addr), entry.state, next);
+        entry.state = next;
+        entry.entered_at = Instant::now();
+        self.tracker.inner.transitions[next.index()].fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.tracker.inner.connections.lock() {
+            if let Some(entry) = connections.remove(&self.id) {
+                if entry.state != ConnectionState::Closed {
+                    self.tracker.inner.transitions[ConnectionState::Closed.index()].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "192.0.2.1:50000".parse().unwrap()
+    }
+
+    #[test]
+    fn test_connection_walks_through_lifecycle() {
+        let tracker = ConnectionTracker::new();
+        let conn = tracker.register(peer());
+        assert_eq!(conn.state(), ConnectionState::Accepted);
+
+        for next in [
+            ConnectionState::Handshaking,
+            ConnectionState::Authenticated,
+            ConnectionState::Tunneling,
+            ConnectionState::Draining,
+            ConnectionState::Closed,
+        ] {
+            conn.transition(next).unwrap();
+            assert_eq!(conn.state(), next);
+
+            let active = tracker.active();
+            assert_eq!(active.len(), 1);
+            assert_eq!(active[0].id, conn.id());
+            assert_eq!(active[0].state, next);
+            assert_eq!(tracker.count_in(next), 1);
+            assert_eq!(tracker.transitions_into(next), 1);
+        }
+
+        drop(conn);
+        assert!(tracker.active().is_empty());
+        assert_eq!(tracker.transitions_into(ConnectionState::Closed), 1);
+    }
+
+    #[test]
+    fn test_invalid_transitions_are_rejected() {
+        let tracker = ConnectionTracker::new();
+        let conn = tracker.register(peer());
+
+        assert!(conn.transition(ConnectionState::Tunneling).is_err());
+        assert_eq!(conn.state(), ConnectionState::Accepted);
+
+        conn.transition(ConnectionState::Closed).unwrap();
+        assert!(conn.transition(ConnectionState::Handshaking).is_err());
+    }
+
+    #[test]
+    fn test_stuck_handshakes_are_visible() {
+        let tracker = ConnectionTracker::new();
+        let stuck: Vec<_> = (0..3).map(|_| tracker.register(peer())).collect();
+        for conn in &stuck {
+            conn.transition(ConnectionState::Handshaking).unwrap();
+        }
+        let healthy = tracker.register(peer());
+        healthy.transition(ConnectionState::Handshaking).unwrap();
+        healthy.transition(ConnectionState::Authenticated).unwrap();
+
+        let counts = tracker.state_counts();
+        assert!(counts.contains(&(ConnectionState::Handshaking, 3)));
+        assert!(counts.contains(&(ConnectionState::Authenticated, 1)));
+
+        // Dropping a handle without closing it still counts as a close.
+        drop(healthy);
+        assert_eq!(tracker.transitions_into(ConnectionState::Closed), 1);
+        assert_eq!(tracker.active().len(), 3);
+    }
+
+    #[test]
+    fn test_counters_are_per_connection_and_resettable() {
+        let tracker = ConnectionTracker::new();
+        let first = tracker.register(peer());
+        let second = tracker.register(peer());
+        first.counters().add_bytes_in(100);
+        first.counters().add_bytes_out(40);
+        first.counters().record_error();
+        first.counters().record_retry();
+        second.counters().add_bytes_in(7);
+
+        let counts: Vec<ConnectionCounts> = tracker.active().iter().map(|summary| summary.counts).collect();
+        assert_eq!(
+            counts,
+            [
+                ConnectionCounts { bytes_in: 100, bytes_out: 40, errors: 1, retries: 1 },
+                ConnectionCounts { bytes_in: 7, ..ConnectionCounts::default() },
+            ]
+        );
+
+        assert_eq!(tracker.reset_counters(Some(first.id())), 1);
+        assert_eq!(first.counters().snapshot(), ConnectionCounts::default());
+        assert_eq!(second.counters().snapshot().bytes_in, 7);
+        // Counting carries on after a reset.
+        first.counters().add_bytes_in(5);
+        assert_eq!(tracker.active()[0].counts.bytes_in, 5);
+
+        assert_eq!(tracker.reset_counters(None), 2);
+        assert!(tracker.active().iter().all(|summary| summary.counts == ConnectionCounts::default()));
+        let gone = second.id();
+        drop(second);
+        assert_eq!(tracker.reset_counters(Some(gone)), 0);
+    }
+}
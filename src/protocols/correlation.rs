@@ -0,0 +1,132 @@
+//! This module derives the connection nonce used to correlate client and server logs.
+//! Both peers compute the nonce from the shared secret and the two handshake randoms,
+//! so it is never sent on the wire. An observer who only sees the (public) randoms
+//! can't compute it and can't link the logged value to captured traffic. A support engineer holding
+//! the client's log line can search the server logs for the same `conn_nonce`.
+//! OTLS/WS logs it for every completed handshake on a tunnel with a `user_id`.
+
+use std::{fmt, net::SocketAddr};
+use tracing::info;
+
+use crate::security::rotation::derive_bytes;
+use crate::utils::logging::redact_addr;
+
+/// Which end of the connection is logging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Client => "client",
+            Role::Server => "server",
+        }
+    }
+}
+
+/// `ConnectionNonce` identifies one connection in both peers' logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionNonce([u8; 8]);
+
+impl ConnectionNonce {
+    /// Derives the nonce from the shared secret (e.g. the `TunnelConfig.user_id`) and the
+    /// random values each side contributed to the handshake.
+    pub fn derive(shared_secret: &[u8], client_random: &[u8], server_random: &[u8]) -> Self {
+        let bytes = derive_bytes(shared_secret, &[b"connection-nonce", client_random, server_random]);
+        ConnectionNonce(bytes[..8].try_into().expect("derived output is 32 bytes"))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 8] {
+        &self.0
+    }
+}
+
+// Lowercase hex, so the value can be pasted straight into a log search.
+impl fmt::Display for ConnectionNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The line each peer logs once its handshake completes.
+#[derive(Debug, Clone)]
+pub struct CorrelationRecord {
+    pub role: Role,
+    pub nonce: ConnectionNonce,
+    pub peer: SocketAddr,
+}
+
+// `key=value` pairs, like the audit log. The peer goes through the installed redactor.
+impl fmt::Display for CorrelationRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "conn_nonce={} role={} peer={}",
+            self.nonce,
+            self.role.as_str(),
+            redact_addr(self.peer)
+        )
+    }
+}
+
+/// Logs the connection nonce for a completed handshake and returns the record that was logged.
+pub fn log_handshake_complete(role: Role, nonce: ConnectionNonce, peer: SocketAddr) -> CorrelationRecord {
+    let record = CorrelationRecord { role, nonce, peer };
+    info!("Handshake complete: {}", record);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn_nonce(line: &str) -> &str {
+        line.split(' ')
+            .find_map(|field| field.strip_prefix("conn_nonce="))
+            .expect("line has a conn_nonce field")
+    }
+
+    #[test]
+    fn test_client_and_server_log_the_same_nonce() {
+        let secret = b"6f1c2a9e-user";
+        let (client_random, server_random) = ([0x11u8; 32], [0x22u8; 32]);
+        let client_addr: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        let server_addr: SocketAddr = "198.51.100.1:443".parse().unwrap();
+
+        // Each side derives the nonce independently from what it saw during the handshake.
+        let client_nonce = ConnectionNonce::derive(secret, &client_random, &server_random);
+        let server_nonce = ConnectionNonce::derive(secret, &client_random, &server_random);
+        assert_eq!(client_nonce, server_nonce);
+
+        let client_line = log_handshake_complete(Role::Client, client_nonce, server_addr).to_string();
+        let server_line = log_handshake_complete(Role::Server, server_nonce, client_addr).to_string();
+        assert_eq!(conn_nonce(&client_line), conn_nonce(&server_line));
+        assert_eq!(conn_nonce(&client_line).len(), 16);
+        assert!(client_line.contains("role=client"));
+        assert!(server_line.contains("role=server"));
+    }
+
+    #[test]
+    fn test_nonce_depends_on_every_input() {
+        let base = ConnectionNonce::derive(b"secret", b"client", b"server");
+        assert_ne!(base, ConnectionNonce::derive(b"other", b"client", b"server"));
+        assert_ne!(base, ConnectionNonce::derive(b"secret", b"client2", b"server"));
+        assert_ne!(base, ConnectionNonce::derive(b"secret", b"client", b"server2"));
+        // Swapping the randoms must not give the same nonce.
+        assert_ne!(base, ConnectionNonce::derive(b"secret", b"server", b"client"));
+    }
+
+    #[test]
+    fn test_nonce_is_not_made_of_the_public_randoms() {
+        let (client_random, server_random) = ([0xABu8; 16], [0xCDu8; 16]);
+        let nonce = ConnectionNonce::derive(b"secret", &client_random, &server_random);
+        assert!(!client_random.windows(8).any(|w| w == nonce.as_bytes()));
+        assert!(!server_random.windows(8).any(|w| w == nonce.as_bytes()));
+    }
+}
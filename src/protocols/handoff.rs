@@ -0,0 +1,252 @@
+//! This module hands the listening sockets from one server process to the next, so the
+//! server can be upgraded without refusing connections. On Unix, SIGUSR2 makes the running
+//! server start its executable again (the new build, if it was replaced) and pass it every
+//! listening socket: the OTLS/WS listeners, the AOQUIC socket and the metrics endpoint. Their
+//! descriptors are left open across `exec` and listed in `HEZARDASTAN_LISTEN_FDS`.
+//!
+//! The new process adopts each passed socket whose address it is configured to listen on
+//! instead of binding it, so connection attempts queue on the same socket while it starts.
+//! The old process stops accepting and drains its tunnels as on SIGTERM; established tunnels
+//! are not moved and finish there. A supervisor can follow them through `GET /connections` on
+//! the old process's metrics endpoint until it exits.
+//!
+//! On other platforms nothing is passed and every socket is bound as usual.
+
+use socket2::{Socket, Type};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::process::{Child, Command};
+use std::{collections::HashMap, io, net::SocketAddr};
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{info, warn};
+
+use crate::protocols::listener::{bind_tcp_listeners, bind_udp_socket, BindStagger};
+
+/// Environment variable listing the descriptors of the passed sockets, comma-separated.
+pub const LISTEN_FDS_ENV: &str = "HEZARDASTAN_LISTEN_FDS";
+
+/// `SocketHandoff` holds the sockets passed in by the previous process and remembers every
+/// listening socket of this one, to pass on to the next.
+#[derive(Default)]
+pub struct SocketHandoff {
+    /// Passed-in stream sockets not yet adopted, by local address.
+    tcp: HashMap<SocketAddr, Socket>,
+    /// Passed-in datagram sockets not yet adopted, by local address.
+    udp: HashMap<SocketAddr, Socket>,
+    /// Descriptors of this process's listening sockets. Only valid while those sockets are
+    /// open, which for the server is until shutdown.
+    #[cfg(unix)]
+    fds: Vec<RawFd>,
+}
+
+impl SocketHandoff {
+    /// Takes the sockets listed in `HEZARDASTAN_LISTEN_FDS`, if the previous process set it.
+    /// Fails if a listed descriptor isn't a socket.
+    pub fn from_env() -> io::Result<Self> {
+        let mut handoff = SocketHandoff::default();
+        #[cfg(unix)]
+        if let Ok(list) = std::env::var(LISTEN_FDS_ENV) {
+            use std::os::fd::FromRawFd;
+
+            for fd in list.split(',').filter(|fd| !fd.is_empty()) {
+                let fd: RawFd = fd.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} lists \"{}\", not a descriptor", LISTEN_FDS_ENV, fd),
+                    )
+                })?;
+                // SAFETY: the previous process left these descriptors open for us alone; nothing
+                // else in this process knows of them, so this is their only owner.
+                let socket = unsafe { Socket::from_raw_fd(fd) };
+                handoff.adopt(socket).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("descriptor {} from {} is not a usable socket: {}", fd, LISTEN_FDS_ENV, e),
+                    )
+                })?;
+            }
+        }
+        Ok(handoff)
+    }
+
+    fn adopt(&mut self, socket: Socket) -> io::Result<()> {
+        #[cfg(unix)]
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+        let addr = socket
+            .local_addr()?
+            .as_socket()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not an IP socket"))?;
+        match socket.r#type()? {
+            Type::STREAM => self.tcp.insert(addr, socket),
+            Type::DGRAM => self.udp.insert(addr, socket),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "neither a stream nor a datagram socket")),
+        };
+        Ok(())
+    }
+
+    /// Whether any passed-in socket is still waiting to be adopted.
+    pub fn has_inherited(&self) -> bool {
+        !self.tcp.is_empty() || !self.udp.is_empty()
+    }
+
+    /// Like `listener::bind_tcp_listeners`, but adopts the passed-in listener for an address
+    /// instead of binding it, without pausing.
+    pub async fn bind_tcp_listeners(
+        &mut self,
+        addrs: &[SocketAddr],
+        dual_stack: bool,
+        stagger: BindStagger,
+    ) -> io::Result<Vec<TcpListener>> {
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let listener = match self.tcp.remove(addr) {
+                Some(socket) => {
+                    info!("Took over the listener on {} from the previous process", addr);
+                    TcpListener::from_std(socket.into())?
+                }
+                None => bind_tcp_listeners(&[*addr], dual_stack, stagger).await?.remove(0),
+            };
+            self.remember(&listener);
+            listeners.push(listener);
+        }
+        Ok(listeners)
+    }
+
+    /// Like `listener::bind_udp_socket`, but adopts the passed-in socket for `addr` if there is one.
+    pub async fn bind_udp_socket(
+        &mut self,
+        addr: SocketAddr,
+        dual_stack: bool,
+        stagger: BindStagger,
+    ) -> io::Result<UdpSocket> {
+        let socket = match self.udp.remove(&addr) {
+            Some(socket) => {
+                info!("Took over the UDP socket on {} from the previous process", addr);
+                UdpSocket::from_std(socket.into())?
+            }
+            None => bind_udp_socket(addr, dual_stack, stagger).await?,
+        };
+        self.remember(&socket);
+        Ok(socket)
+    }
+
+    /// Like `TcpListener::bind`, but adopts the passed-in listener for `addr` if there is one.
+    pub async fn bind_tcp_listener(&mut self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let listener = match self.tcp.remove(&addr) {
+            Some(socket) => {
+                info!("Took over the listener on {} from the previous process", addr);
+                TcpListener::from_std(socket.into())?
+            }
+            None => TcpListener::bind(addr).await?,
+        };
+        self.remember(&listener);
+        Ok(listener)
+    }
+
+    /// Closes the passed-in sockets no configured address claimed, such as a listener the new
+    /// configuration dropped.
+    pub fn close_unclaimed(&mut self) {
+        for addr in self.tcp.keys().chain(self.udp.keys()) {
+            warn!("Closing the socket on {} passed by the previous process: nothing listens there now", addr);
+        }
+        self.tcp.clear();
+        self.udp.clear();
+    }
+
+    #[cfg(unix)]
+    fn remember(&mut self, socket: &impl AsRawFd) {
+        self.fds.push(socket.as_raw_fd());
+    }
+
+    #[cfg(not(unix))]
+    fn remember<T>(&mut self, _socket: &T) {}
+
+    /// Descriptors of every listening socket bound or adopted through this handoff.
+    #[cfg(unix)]
+    pub fn listener_fds(&self) -> &[RawFd] {
+        &self.fds
+    }
+
+    /// Spawns `command` with every listening socket left open for it and listed in
+    /// `HEZARDASTAN_LISTEN_FDS`. The sockets go back to close-on-exec once it has started, so
+    /// no later child inherits them.
+    #[cfg(unix)]
+    pub fn spawn_successor(&self, mut command: Command) -> io::Result<Child> {
+        use std::os::fd::BorrowedFd;
+
+        let set_cloexec = |close_on_exec: bool| -> io::Result<()> {
+            for &fd in &self.fds {
+                // SAFETY: `fds` only holds descriptors of sockets this server keeps open.
+                let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+                socket2::SockRef::from(&fd).set_cloexec(close_on_exec)?;
+            }
+            Ok(())
+        };
+        let list: Vec<String> = self.fds.iter().map(RawFd::to_string).collect();
+        command.env(LISTEN_FDS_ENV, list.join(","));
+        let spawned = set_cloexec(false).and_then(|_| command.spawn());
+        set_cloexec(true)?;
+        spawned
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+
+    const TEST_NAME: &str = "protocols::handoff::tests::test_listener_is_taken_over_by_a_child_process";
+    /// Tells the child which address its listener should be on.
+    const ADDR_ENV: &str = "HEZARDASTAN_HANDOFF_TEST_ADDR";
+
+    #[test]
+    fn test_listener_is_taken_over_by_a_child_process() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        if let Ok(addr) = std::env::var(ADDR_ENV) {
+            // The child: adopt the listener and answer the connection already queued on it.
+            return runtime.block_on(async {
+                let mut handoff = SocketHandoff::from_env().unwrap();
+                assert!(handoff.has_inherited());
+                let addr: SocketAddr = addr.parse().unwrap();
+                let listener = handoff.bind_tcp_listeners(&[addr], false, BindStagger::NONE).await.unwrap().remove(0);
+                assert!(!handoff.has_inherited());
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = stream.into_std().unwrap();
+                stream.set_nonblocking(false).unwrap();
+                stream.write_all(b"from the child").unwrap();
+            });
+        }
+
+        let (mut client, mut child, addr) = runtime.block_on(async {
+            let mut handoff = SocketHandoff::default();
+            let listener = handoff.bind_tcp_listener("127.0.0.1:0".parse().unwrap()).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            assert_eq!(handoff.listener_fds(), [listener.as_raw_fd()]);
+            // Queued before the handoff: only the same socket, not a fresh bind, can accept it.
+            let client = std::net::TcpStream::connect(addr).unwrap();
+            let mut command = Command::new(std::env::current_exe().unwrap());
+            command.args(["--exact", TEST_NAME, "--test-threads=1"]).env(ADDR_ENV, addr.to_string());
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+            let child = handoff.spawn_successor(command).unwrap();
+            (client, child, addr)
+        });
+        // This process's copy is closed; the child holds the only one.
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"from the child");
+        assert!(child.wait().unwrap().success());
+        assert!(std::net::TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_a_descriptor_that_is_not_a_socket_is_refused() {
+        let mut handoff = SocketHandoff::default();
+        let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        let socket =
+            unsafe { <Socket as std::os::fd::FromRawFd>::from_raw_fd(std::os::fd::IntoRawFd::into_raw_fd(file)) };
+        assert!(handoff.adopt(socket).is_err());
+    }
+}
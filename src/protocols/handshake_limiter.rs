@@ -0,0 +1,188 @@
+//! This module limits how many handshakes run at the same time.
+//! TLS, QUIC and the obfuscation handshake are CPU-heavy. A burst of simultaneous
+//! handshakes can starve established tunnels even when the total connection count is
+//! under its cap. `HandshakeLimiter` hands out a permit for the duration of each
+//! handshake only; once a connection is established it releases the permit and is no
+//! longer counted.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::protocols::common::ProtocolError;
+
+/// What happens to a handshake that arrives while all permits are taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for a permit, up to the given time, then reject.
+    Queue(Duration),
+    /// Reject immediately.
+    Reject,
+}
+
+/// `HandshakeLimiterConfig` sets the concurrency limit and overflow behaviour.
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakeLimiterConfig {
+    /// Maximum number of handshakes in progress at once.
+    pub max_concurrent: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for HandshakeLimiterConfig {
+    fn default() -> Self {
+        HandshakeLimiterConfig {
+            max_concurrent: 64,
+            overflow: OverflowPolicy::Queue(Duration::from_secs(5)),
+        }
+    }
+}
+
+/// Held while a handshake is in progress; dropping it frees the slot.
+pub struct HandshakePermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+/// `HandshakeLimiter` is shared by all accept loops.
+#[derive(Clone)]
+pub struct HandshakeLimiter {
+    config: HandshakeLimiterConfig,
+    semaphore: Arc<Semaphore>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl HandshakeLimiter {
+    pub fn new(config: HandshakeLimiterConfig) -> Self {
+        HandshakeLimiter {
+            config,
+            semaphore: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+            rejected: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Waits for (or fails to get) a handshake slot according to the overflow policy.
+    pub async fn acquire(&self) -> Result<HandshakePermit, ProtocolError> {
+        let permit = match self.config.overflow {
+            OverflowPolicy::Reject => self.semaphore.clone().try_acquire_owned().ok(),
+            OverflowPolicy::Queue(max_wait) => {
+                tokio::time::timeout(max_wait, self.semaphore.clone().acquire_owned())
+                    .await
+                    .ok()
+                    .and_then(Result::ok)
+            }
+        };
+        match permit {
+            Some(permit) => Ok(HandshakePermit { _permit: permit }),
+            None => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(ProtocolError::HandshakeError("too many concurrent handshakes".to_string()))
+            }
+        }
+    }
+
+    /// Number of handshakes currently holding a permit.
+    pub fn in_progress(&self) -> usize {
+        self.config.max_concurrent.max(1) - self.semaphore.available_permits()
+    }
+
+    /// Number of handshakes rejected since the limiter was created.
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::time::{sleep, Instant};
+
+    /// Simulates a handshake: holds the permit for `work` and records peak concurrency.
+    async fn handshake(limiter: HandshakeLimiter, current: Arc<AtomicUsize>, peak: Arc<AtomicUsize>, work: Duration) -> bool {
+        let Ok(_permit) = limiter.acquire().await else {
+            return false;
+        };
+        let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        sleep(work).await;
+        current.fetch_sub(1, Ordering::SeqCst);
+        true
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_handshakes_respect_the_limit() {
+        let limiter = HandshakeLimiter::new(HandshakeLimiterConfig {
+            max_concurrent: 4,
+            overflow: OverflowPolicy::Queue(Duration::from_secs(60)),
+        });
+        let current = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        // An established connection relays once per 10ms throughout the flood.
+        let established = tokio::spawn(async {
+            let start = Instant::now();
+            let mut worst_gap = Duration::ZERO;
+            let mut last = start;
+            while start.elapsed() < Duration::from_secs(2) {
+                sleep(Duration::from_millis(10)).await;
+                worst_gap = worst_gap.max(last.elapsed());
+                last = Instant::now();
+            }
+            worst_gap
+        });
+
+        let handshakes: Vec<_> = (0..40)
+            .map(|_| tokio::spawn(handshake(limiter.clone(), current.clone(), peak.clone(), Duration::from_millis(100))))
+            .collect();
+        for task in handshakes {
+            assert!(task.await.unwrap());
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 4);
+        assert_eq!(limiter.in_progress(), 0);
+        assert_eq!(limiter.rejected_count(), 0);
+        assert!(established.await.unwrap() <= Duration::from_millis(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_excess_handshakes_are_rejected() {
+        let limiter = HandshakeLimiter::new(HandshakeLimiterConfig {
+            max_concurrent: 3,
+            overflow: OverflowPolicy::Reject,
+        });
+        let current = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let handshakes: Vec<_> = (0..10)
+            .map(|_| tokio::spawn(handshake(limiter.clone(), current.clone(), peak.clone(), Duration::from_secs(1))))
+            .collect();
+        let mut accepted = 0;
+        for task in handshakes {
+            if task.await.unwrap() {
+                accepted += 1;
+            }
+        }
+
+        assert_eq!(accepted, 3);
+        assert_eq!(limiter.rejected_count(), 7);
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_timeout_rejects() {
+        let limiter = HandshakeLimiter::new(HandshakeLimiterConfig {
+            max_concurrent: 1,
+            overflow: OverflowPolicy::Queue(Duration::from_millis(50)),
+        });
+        let held = limiter.acquire().await.unwrap();
+        assert!(matches!(limiter.acquire().await, Err(ProtocolError::HandshakeError(_))));
+        assert_eq!(limiter.in_progress(), 1);
+
+        drop(held);
+        assert!(limiter.acquire().await.is_ok());
+    }
+}
@@ -0,0 +1,210 @@
+//! This module aggregates handshake outcomes by source subnet.
+//! A sudden drop in the success rate for one subnet is usually the first sign of
+//! region-level blocking, so operators need it broken down per /24 (IPv4) and /48 (IPv6).
+//! OTLS/WS records into a tracker shared with the metrics endpoint, which reports it as
+//! `hezardastan_subnet_handshakes_total`.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+};
+
+/// A source subnet: /24 for IPv4, /48 for IPv6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    /// Returns the tracking subnet containing `ip`.
+    pub fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(v4) => {
+                let [a, b, c, _] = v4.octets();
+                Subnet {
+                    network: IpAddr::V4(Ipv4Addr::new(a, b, c, 0)),
+                    prefix_len: 24,
+                }
+            }
+            IpAddr::V6(v6) => {
+                let s = v6.segments();
+                Subnet {
+                    network: IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0)),
+                    prefix_len: 48,
+                }
+            }
+        }
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Handshake counters for a single subnet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubnetHandshakeStats {
+    pub successes: u64,
+    pub failures: u64,
+}
+
+impl SubnetHandshakeStats {
+    /// Fraction of handshakes that succeeded, or `None` if none were seen.
+    pub fn success_rate(&self) -> Option<f64> {
+        let total = self.successes + self.failures;
+        if total == 0 {
+            None
+        } else {
+            Some(self.successes as f64 / total as f64)
+        }
+    }
+}
+
+struct Entry {
+    stats: SubnetHandshakeStats,
+    last_update: u64,
+}
+
+/// `HandshakeStatsTracker` keeps per-subnet handshake counters for a bounded number of subnets.
+/// When full, the subnet that was updated least recently is evicted.
+pub struct HandshakeStatsTracker {
+    max_subnets: usize,
+    inner: Mutex<TrackerState>,
+}
+
+struct TrackerState {
+    entries: HashMap<Subnet, Entry>,
+    clock: u64,
+}
+
+impl HandshakeStatsTracker {
+    /// Creates a tracker that remembers at most `max_subnets` subnets.
+    pub fn new(max_subnets: usize) -> Self {
+        HandshakeStatsTracker {
+            max_subnets: max_subnets.max(1),
+            inner: Mutex::new(TrackerState {
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Records the outcome of a handshake from `peer`.
+    pub fn record(&self, peer: IpAddr, success: bool) {
+        let subnet = Subnet::of(peer);
+        let mut state = self.inner.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+
+        if !state.entries.contains_key(&subnet) && state.entries.len() >= self.max_subnets {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_update)
+                .map(|(subnet, _)| *subnet);
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        let entry = state.entries.entry(subnet).or_insert(Entry {
+            stats: SubnetHandshakeStats::default(),
+            last_update: clock,
+        });
+        entry.last_update = clock;
+        if success {
+            entry.stats.successes += 1;
+        } else {
+            entry.stats.failures += 1;
+        }
+    }
+
+    /// Returns the counters for the subnet containing `ip`, if it is tracked.
+    pub fn stats_for(&self, ip: IpAddr) -> Option<SubnetHandshakeStats> {
+        let state = self.inner.lock().unwrap();
+        state.entries.get(&Subnet::of(ip)).map(|entry| entry.stats)
+    }
+
+    /// Returns every tracked subnet, worst success rate first.
+    pub fn snapshot(&self) -> Vec<(Subnet, SubnetHandshakeStats)> {
+        let state = self.inner.lock().unwrap();
+        let mut out: Vec<_> = state
+            .entries
+            .iter()
+            .map(|(subnet, entry)| (*subnet, entry.stats))
+            .collect();
+        out.sort_by(|a, b| {
+            let ra = a.1.success_rate().unwrap_or(1.0);
+            let rb = b.1.success_rate().unwrap_or(1.0);
+            ra.partial_cmp(&rb).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_grouping() {
+        let a: IpAddr = "203.0.113.7".parse().unwrap();
+        let b: IpAddr = "203.0.113.250".parse().unwrap();
+        assert_eq!(Subnet::of(a), Subnet::of(b));
+        assert_eq!(Subnet::of(a).to_string(), "203.0.113.0/24");
+
+        let v6: IpAddr = "2001:db8:abcd:1234::1".parse().unwrap();
+        assert_eq!(Subnet::of(v6).to_string(), "2001:db8:abcd::/48");
+    }
+
+    #[test]
+    fn test_per_subnet_success_rates() {
+        let tracker = HandshakeStatsTracker::new(16);
+        let healthy: IpAddr = "192.0.2.10".parse().unwrap();
+        let blocked: IpAddr = "198.51.100.20".parse().unwrap();
+
+        for _ in 0..9 {
+            tracker.record(healthy, true);
+        }
+        tracker.record(healthy, false);
+        tracker.record(blocked, true);
+        for _ in 0..3 {
+            tracker.record("198.51.100.99".parse().unwrap(), false);
+        }
+
+        let healthy_stats = tracker.stats_for(healthy).unwrap();
+        assert_eq!(healthy_stats, SubnetHandshakeStats { successes: 9, failures: 1 });
+        assert!((healthy_stats.success_rate().unwrap() - 0.9).abs() < f64::EPSILON);
+
+        let blocked_stats = tracker.stats_for(blocked).unwrap();
+        assert_eq!(blocked_stats.success_rate(), Some(0.25));
+
+        // The failing subnet is reported first.
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot[0].0, Subnet::of(blocked));
+    }
+
+    #[test]
+    fn test_tracked_subnets_are_bounded() {
+        let tracker = HandshakeStatsTracker::new(2);
+        tracker.record("192.0.2.1".parse().unwrap(), true);
+        tracker.record("198.51.100.1".parse().unwrap(), true);
+        tracker.record("203.0.113.1".parse().unwrap(), false);
+
+        assert_eq!(tracker.snapshot().len(), 2);
+        assert!(tracker.stats_for("192.0.2.1".parse().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,972 @@
+//! This module runs the accept loops that feed incoming connections to the `ProtocolRegistry`.
+//! Each loop runs until its `CancellationToken` is cancelled; connections already handed
+//! to a protocol are left running and are closed by that protocol's `shutdown`.
+//! `ConnectionLimiter` caps how many accepted connections may be handled at once, so a
+//! flood of connections can't spawn an unbounded number of handler tasks.
+//! Refused connections are answered by `rejection::reject` (when enabled) instead of a bare
+//! close: after the peer's first bytes, in the protocol it opened with. Every accepted or
+//! refused connection is written to the audit log.
+//!
+//! Every handler runs inside a `conn` span carrying a short `conn_id`, so all log lines for
+//! one connection (or one datagram) can be picked out of interleaved output.
+//!
+//! IPv6 listen sockets set `IPV6_V6ONLY` explicitly instead of relying on the OS default:
+//! with `dual_stack` a single `[::]` bind also accepts IPv4 clients (as IPv4-mapped
+//! addresses), without it IPv4 needs its own `0.0.0.0` bind.
+//!
+//! A `BindStagger` spaces the listeners (TCP and UDP) out by random pauses when they are bound,
+//! so a restarting server doesn't open every port at the same instant. Its warm-up then ramps
+//! the connection cap up from a single connection (see `Admission::with_warmup`), so the
+//! restarted server doesn't take its full load back in the first instant either.
+//!
+//! On Unix, a stream protocol can also listen on a Unix domain socket, for chaining behind
+//! another proxy on the same host. Those connections are handled like TCP ones from
+//! `LOCAL_PEER`; they all share that address, so only the connection cap applies to them.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+use rand::Rng;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::protocols::common::ProtocolType;
+use crate::protocols::peer_rate_limiter::PeerRateLimiter;
+use crate::protocols::registry::{Incoming, ProtocolRegistry, LOCAL_PEER};
+use crate::protocols::rejection::{reject, RejectionCause, RejectionResponseConfig};
+use crate::utils::logging::{redact_addr, AuditLog, AuditOutcome};
+
+/// Refused connections waiting for their first bytes at once; beyond this they are just closed.
+const MAX_PENDING_REFUSALS: usize = 256;
+
+/// `ConnectionLimiter` is shared by all accept loops, so the cap is server-wide.
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    max_connections: usize,
+    semaphore: Arc<Semaphore>,
+    rejected: Arc<AtomicU64>,
+}
+
+/// Held by a connection's handler task; dropping it frees the slot.
+pub struct ConnectionPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize) -> Self {
+        let max_connections = max_connections.max(1);
+        ConnectionLimiter {
+            max_connections,
+            semaphore: Arc::new(Semaphore::new(max_connections)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Takes a slot if one is free. Connections are refused rather than queued when saturated.
+    pub fn try_acquire(&self) -> Option<ConnectionPermit> {
+        match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Some(ConnectionPermit { _permit: permit }),
+            Err(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Number of connections currently holding a permit.
+    pub fn in_use(&self) -> usize {
+        self.max_connections - self.semaphore.available_permits()
+    }
+
+    /// Number of connections refused since the limiter was created.
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// `Admission` is what a TCP accept loop checks before handing a connection to a protocol:
+/// the server-wide connection cap and the per-peer rate limit. It also decides how refused
+/// peers are answered. Clones share the same limiters.
+#[derive(Clone)]
+pub struct Admission {
+    limiter: ConnectionLimiter,
+    rate_limiter: PeerRateLimiter,
+    rejection: RejectionResponseConfig,
+    audit: AuditLog,
+    refusals: Arc<Semaphore>,
+    /// When the warm-up started and how long it lasts.
+    warmup: Option<(Instant, Duration)>,
+}
+
+impl Admission {
+    /// Refused connections get the default rejection response.
+    pub fn new(limiter: ConnectionLimiter, rate_limiter: PeerRateLimiter) -> Self {
+        Admission {
+            limiter,
+            rate_limiter,
+            rejection: RejectionResponseConfig::default(),
+            audit: AuditLog::disabled(),
+            refusals: Arc::new(Semaphore::new(MAX_PENDING_REFUSALS)),
+            warmup: None,
+        }
+    }
+
+    /// Answers refused connections according to `rejection`.
+    pub fn with_rejection_response(mut self, rejection: RejectionResponseConfig) -> Self {
+        self.rejection = rejection;
+        self
+    }
+
+    /// Records every accepted and refused connection in `audit`.
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Starts a warm-up of `period` from now: the connection cap ramps up linearly from one
+    /// connection to its full size, and connections beyond the ramped cap are refused like
+    /// those over the full one. A zero `period` leaves the cap as it is.
+    pub fn with_warmup(mut self, period: Duration) -> Self {
+        self.warmup = (!period.is_zero()).then(|| (Instant::now(), period));
+        self
+    }
+
+    /// The connection cap in force right now, lowered while warming up.
+    fn connection_cap(&self) -> usize {
+        let max_connections = self.limiter.max_connections();
+        match self.warmup {
+            Some((start, period)) if start.elapsed() < period => {
+                let ramped = max_connections as f64 * start.elapsed().as_secs_f64() / period.as_secs_f64();
+                (ramped.ceil() as usize).clamp(1, max_connections)
+            }
+            _ => max_connections,
+        }
+    }
+
+    /// Takes a connection slot if one is free under the current cap.
+    fn try_admit(&self) -> Option<ConnectionPermit> {
+        if self.limiter.in_use() >= self.connection_cap() {
+            self.limiter.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.limiter.try_acquire()
+    }
+
+    /// Answers a refused connection with `reject` (if enabled) on its own task, so waiting for
+    /// the peer's first bytes doesn't stall the accept loop. At most `MAX_PENDING_REFUSALS`
+    /// such tasks run at once; a flood of refused peers beyond that is just closed.
+    fn refuse(&self, mut socket: TcpStream, cause: RejectionCause, mimic_domain: &str) {
+        if !self.rejection.enabled {
+            return;
+        }
+        let Ok(permit) = self.refusals.clone().try_acquire_owned() else {
+            return;
+        };
+        let rejection = self.rejection.clone();
+        let mimic_domain = mimic_domain.to_string();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = reject(&mut socket, cause, &mimic_domain, &rejection).await {
+                debug!("Failed to answer a refused connection: {}", e);
+            }
+        });
+    }
+}
+
+/// A random id for one accepted TCP connection.
+fn new_conn_id() -> String {
+    format!("{:08x}", rand::random::<u32>())
+}
+
+/// An id for one datagram, derived from its sender and arrival time. Hashed, so the
+/// peer address itself doesn't end up in the id.
+fn packet_conn_id(peer_addr: SocketAddr, received_at: SystemTime) -> String {
+    let mut hasher = DefaultHasher::new();
+    peer_addr.hash(&mut hasher);
+    received_at.hash(&mut hasher);
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+/// `BindStagger` is the range of the random pause taken before each listener is bound, and
+/// the warm-up after binding during which the connection cap ramps up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindStagger {
+    pub min: Duration,
+    pub max: Duration,
+    pub warmup: Duration,
+}
+
+impl BindStagger {
+    /// Binds every listener straight away, with no warm-up.
+    pub const NONE: BindStagger = BindStagger {
+        min: Duration::ZERO,
+        max: Duration::ZERO,
+        warmup: Duration::ZERO,
+    };
+
+    fn delay(&self) -> Duration {
+        if self.max <= self.min {
+            return self.min;
+        }
+        rand::thread_rng().gen_range(self.min..=self.max)
+    }
+}
+
+/// Binds one TCP listener per address, in order, pausing for a random `stagger` delay before
+/// each. Fails on the first address that can't be bound, naming it in the error.
+/// `dual_stack` lets IPv6 addresses accept IPv4 clients too.
+pub async fn bind_tcp_listeners(addrs: &[SocketAddr], dual_stack: bool, stagger: BindStagger) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let delay = stagger.delay();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let listener = bind_tcp_listener(*addr, dual_stack)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to bind TCP listener on {}: {}", addr, e)))?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Binds the UDP socket for a datagram protocol after a random `stagger` delay, naming the
+/// address in the error.
+pub async fn bind_udp_socket(addr: SocketAddr, dual_stack: bool, stagger: BindStagger) -> io::Result<UdpSocket> {
+    let delay = stagger.delay();
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    let bind = || {
+        let socket = new_socket(addr, Type::DGRAM, Protocol::UDP, dual_stack)?;
+        socket.bind(&addr.into())?;
+        UdpSocket::from_std(socket.into())
+    };
+    bind().map_err(|e| io::Error::new(e.kind(), format!("failed to bind UDP socket on {}: {}", addr, e)))
+}
+
+/// Binds a Unix domain socket listener at `path`, naming it in the error. A socket file left
+/// behind by an earlier run is replaced; any other file at `path` makes the bind fail.
+#[cfg(unix)]
+pub async fn bind_unix_listener(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let bind = || {
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        UnixListener::bind(path)
+    };
+    bind().map_err(|e| io::Error::new(e.kind(), format!("failed to bind Unix socket at {}: {}", path.display(), e)))
+}
+
+fn bind_tcp_listener(addr: SocketAddr, dual_stack: bool) -> io::Result<TcpListener> {
+    let socket = new_socket(addr, Type::STREAM, Protocol::TCP, dual_stack)?;
+    // Same as `TcpListener::bind`: lets a restarted server rebind while old connections linger.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Creates a non-blocking socket for `addr`. For IPv6 addresses `IPV6_V6ONLY` is cleared when
+/// `dual_stack` is set; platforms that can't clear it keep serving IPv6 only, with a warning.
+fn new_socket(addr: SocketAddr, ty: Type, protocol: Protocol, dual_stack: bool) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        if let Err(e) = socket.set_only_v6(!dual_stack) {
+            if !dual_stack {
+                return Err(e);
+            }
+            warn!("Dual-stack listening is not supported here, {} accepts IPv6 only: {}", addr, e);
+        }
+    }
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Accepts TCP connections on `listener` and dispatches each one to `protocol_type`'s handler
+/// on its own task, until `shutdown` is cancelled. Connections from peers over their rate, or
+/// beyond the connection cap, are refused (see `Admission`).
+pub async fn run_tcp_accept_loop(
+    listener: TcpListener,
+    registry: Arc<ProtocolRegistry>,
+    protocol_type: ProtocolType,
+    admission: Admission,
+    shutdown: CancellationToken,
+) {
+    let name = protocol_type.to_string_repr();
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((socket, peer_addr)) => {
+                // Rejection pages and audit records follow the protocol's current (possibly reloaded) config.
+                let (mimic_domain, profile) = registry
+                    .get(&protocol_type)
+                    .map(|protocol| {
+                        let config = protocol.get_config();
+                        (config.tunnel.mimic_domain.clone(), config.obfuscation_profile().to_string())
+                    })
+                    .unwrap_or_default();
+                let audit = |outcome| admission.audit.record(peer_addr.ip(), &protocol_type, &profile, outcome);
+                if !admission.rate_limiter.check(peer_addr.ip()) {
+                    warn!("{}: Dropping connection from {}: connection rate exceeded", name, redact_addr(peer_addr));
+                    audit(AuditOutcome::RejectedByRateLimit);
+                    admission.refuse(socket, RejectionCause::RateLimited, &mimic_domain);
+                    continue;
+                }
+                let Some(permit) = admission.try_admit() else {
+                    warn!(
+                        "{}: Refusing connection from {}: {} connections already active",
+                        name,
+                        redact_addr(peer_addr),
+                        admission.connection_cap()
+                    );
+                    audit(AuditOutcome::RejectedByCapacity);
+                    admission.refuse(socket, RejectionCause::OverQuota, &mimic_domain);
+                    continue;
+                };
+                audit(AuditOutcome::Accepted);
+                let span = info_span!("conn", conn_id = %new_conn_id(), protocol = name);
+                span.in_scope(|| info!("{}: New TCP connection from {}", name, redact_addr(peer_addr)));
+                let registry = registry.clone();
+                let protocol_type = protocol_type.clone();
+                tokio::spawn(
+                    async move {
+                        let _permit = permit;
+                        if let Err(e) = registry.dispatch(&protocol_type, Incoming::Tcp(socket)).await {
+                            error!("{}: Error handling TCP stream from {}: {}", protocol_type.to_string_repr(), redact_addr(peer_addr), e);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                error!("{}: TCP accept error: {}", name, e);
+            }
+        }
+    }
+    info!("{}: Stopped accepting connections.", name);
+}
+
+/// Accepts connections on the Unix socket `listener` and dispatches each one to
+/// `protocol_type`'s handler on its own task, until `shutdown` is cancelled. Connections
+/// beyond the connection cap are closed.
+#[cfg(unix)]
+pub async fn run_unix_accept_loop(
+    listener: UnixListener,
+    registry: Arc<ProtocolRegistry>,
+    protocol_type: ProtocolType,
+    admission: Admission,
+    shutdown: CancellationToken,
+) {
+    let name = protocol_type.to_string_repr();
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((socket, _)) => {
+                let profile = registry
+                    .get(&protocol_type)
+                    .map(|protocol| protocol.get_config().obfuscation_profile().to_string())
+                    .unwrap_or_default();
+                let audit = |outcome| admission.audit.record(LOCAL_PEER.ip(), &protocol_type, &profile, outcome);
+                let Some(permit) = admission.try_admit() else {
+                    warn!(
+                        "{}: Refusing local connection: {} connections already active",
+                        name,
+                        admission.connection_cap()
+                    );
+                    audit(AuditOutcome::RejectedByCapacity);
+                    continue;
+                };
+                audit(AuditOutcome::Accepted);
+                let span = info_span!("conn", conn_id = %new_conn_id(), protocol = name);
+                span.in_scope(|| info!("{}: New Unix socket connection", name));
+                let registry = registry.clone();
+                let protocol_type = protocol_type.clone();
+                tokio::spawn(
+                    async move {
+                        let _permit = permit;
+                        if let Err(e) = registry.dispatch(&protocol_type, Incoming::Unix(socket)).await {
+                            error!("{}: Error handling Unix socket connection: {}", protocol_type.to_string_repr(), e);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                error!("{}: Unix socket accept error: {}", name, e);
+            }
+        }
+    }
+    info!("{}: Stopped accepting local connections.", name);
+}
+
+/// Receives datagrams on `socket` and dispatches each one to `protocol_type`'s handler on its
+/// own task, until `shutdown` is cancelled. Handlers get the same socket to send replies on.
+pub async fn run_udp_recv_loop(
+    socket: Arc<UdpSocket>,
+    registry: Arc<ProtocolRegistry>,
+    protocol_type: ProtocolType,
+    shutdown: CancellationToken,
+) {
+    let name = protocol_type.to_string_repr();
+    let mut buf = vec![0u8; 65536]; // Max UDP packet size
+    loop {
+        let received = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            received = socket.recv_from(&mut buf) => received,
+        };
+        match received {
+            Ok((len, peer_addr)) => {
+                let span = info_span!("conn", conn_id = %packet_conn_id(peer_addr, SystemTime::now()), protocol = name);
+                span.in_scope(|| debug!("{}: New UDP packet from {} ({} bytes)", name, redact_addr(peer_addr), len));
+                let registry = registry.clone();
+                let protocol_type = protocol_type.clone();
+                let socket = socket.clone();
+                let packet_data = buf[..len].to_vec(); // Copy packet data for the spawned task
+                tokio::spawn(
+                    async move {
+                        let packet = Incoming::Udp { socket: &socket, buf: &packet_data, peer_addr };
+                        if let Err(e) = registry.dispatch(&protocol_type, packet).await {
+                            error!("{}: Error handling UDP packet from {}: {}", protocol_type.to_string_repr(), redact_addr(peer_addr), e);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                // ICMP errors from earlier sends surface here on some platforms; keep receiving.
+                error!("{}: UDP recv_from error: {}", name, e);
+            }
+        }
+    }
+    info!("{}: Stopped receiving packets.", name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::common::{HealthStatus, ProtocolConfig, ProtocolMetrics};
+    use crate::protocols::otls_ws::OtlsWsProtocol;
+    use crate::protocols::ObfuscatedProtocol;
+    use crate::utils::logging::MemoryAuditSink;
+    use async_trait::async_trait;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_bind_tcp_listeners_binds_every_address() {
+        let addrs: Vec<SocketAddr> = vec!["127.0.0.1:0".parse().unwrap(), "127.0.0.1:0".parse().unwrap()];
+        let listeners = bind_tcp_listeners(&addrs, false, BindStagger::NONE).await.unwrap();
+        assert_eq!(listeners.len(), 2);
+        assert_ne!(listeners[0].local_addr().unwrap(), listeners[1].local_addr().unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_staggered_listeners_become_ready_apart() {
+        let stagger = BindStagger {
+            min: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+            warmup: Duration::ZERO,
+        };
+        // Free ports, found by binding and releasing them.
+        let addrs: Vec<SocketAddr> = (0..3)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap())
+            .collect();
+        let start = tokio::time::Instant::now();
+        let binding = tokio::spawn({
+            let addrs = addrs.clone();
+            async move { bind_tcp_listeners(&addrs, false, stagger).await }
+        });
+
+        // Poll each port every 5ms of (paused) time and note when it first accepts a connection.
+        let step = Duration::from_millis(5);
+        let mut ready = vec![None; addrs.len()];
+        while ready.iter().any(Option::is_none) {
+            for (addr, ready) in addrs.iter().zip(&mut ready) {
+                if ready.is_none() && std::net::TcpStream::connect(addr).is_ok() {
+                    *ready = Some(start.elapsed());
+                }
+            }
+            tokio::time::sleep(step).await;
+        }
+        assert_eq!(binding.await.unwrap().unwrap().len(), 3);
+
+        let mut previous = Duration::ZERO;
+        for ready in ready.into_iter().flatten() {
+            let gap = ready - previous;
+            assert!(gap + step >= stagger.min && gap <= stagger.max + step, "listener ready {:?} after the previous one", gap);
+            previous = ready;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_udp_socket_bind_is_staggered_too() {
+        let stagger = BindStagger {
+            min: Duration::from_millis(200),
+            max: Duration::from_millis(200),
+            warmup: Duration::ZERO,
+        };
+        let start = tokio::time::Instant::now();
+        bind_udp_socket("127.0.0.1:0".parse().unwrap(), false, stagger).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_warmup_ramps_the_connection_cap_up() {
+        let limiter = ConnectionLimiter::new(10);
+        let admission = Admission::new(limiter.clone(), PeerRateLimiter::new(100.0)).with_warmup(Duration::from_secs(10));
+
+        // Right after binding, only one connection is let in.
+        let first = admission.try_admit().unwrap();
+        assert!(admission.try_admit().is_none());
+        assert_eq!(limiter.rejected_count(), 1);
+
+        // Halfway through, half the cap.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let permits: Vec<_> = std::iter::from_fn(|| admission.try_admit()).collect();
+        assert_eq!(permits.len() + 1, 5);
+
+        // After the warm-up, the full cap.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let more: Vec<_> = std::iter::from_fn(|| admission.try_admit()).collect();
+        assert_eq!(limiter.in_use(), 10);
+        drop((first, permits, more));
+    }
+
+    #[tokio::test]
+    async fn test_bind_errors_name_the_address() {
+        let holder = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let taken = holder.local_addr().unwrap();
+        let err = bind_tcp_listeners(&["127.0.0.1:0".parse().unwrap(), taken], false, BindStagger::NONE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(err.to_string().contains(&taken.to_string()));
+
+        let udp = bind_udp_socket("127.0.0.1:0".parse().unwrap(), false, BindStagger::NONE).await.unwrap();
+        let err = bind_udp_socket(udp.local_addr().unwrap(), false, BindStagger::NONE).await.unwrap_err();
+        assert!(err.to_string().contains("failed to bind UDP socket"));
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_listener_accepts_ipv6_clients() {
+        let listeners = bind_tcp_listeners(&["[::1]:0".parse().unwrap()], false, BindStagger::NONE).await.unwrap();
+        let addr = listeners[0].local_addr().unwrap();
+        assert!(addr.is_ipv6());
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, peer_addr) = listeners[0].accept().await.unwrap();
+        assert!(peer_addr.is_ipv6());
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let udp = bind_udp_socket("[::1]:0".parse().unwrap(), false, BindStagger::NONE).await.unwrap();
+        assert!(udp.local_addr().unwrap().is_ipv6());
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_controls_ipv6_only() {
+        let unspecified: SocketAddr = "[::]:0".parse().unwrap();
+        let v6_only = bind_tcp_listeners(&[unspecified], false, BindStagger::NONE).await.unwrap();
+        assert!(socket2::SockRef::from(&v6_only[0]).only_v6().unwrap());
+
+        let dual = bind_tcp_listeners(&[unspecified], true, BindStagger::NONE).await.unwrap();
+        assert!(!socket2::SockRef::from(&dual[0]).only_v6().unwrap());
+        // IPv4 clients reach the `[::]` listener as IPv4-mapped addresses.
+        let port = dual[0].local_addr().unwrap().port();
+        let _client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, peer_addr) = dual[0].accept().await.unwrap();
+        assert_eq!(peer_addr.ip().to_canonical(), "127.0.0.1".parse::<std::net::IpAddr>().unwrap());
+
+        let udp = bind_udp_socket(unspecified, true, BindStagger::NONE).await.unwrap();
+        assert!(!socket2::SockRef::from(&udp).only_v6().unwrap());
+    }
+
+    /// Everything the server sends before closing the connection.
+    async fn read_until_closed(mut stream: TcpStream) -> String {
+        let mut received = Vec::new();
+        // The connection may be reset rather than closed; whatever arrived before that counts.
+        let _ = tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut received)).await.unwrap();
+        String::from_utf8_lossy(&received).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_refuses_excess_connections() {
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limiter = ConnectionLimiter::new(1);
+        let audit = Arc::new(MemoryAuditSink::default());
+        let shutdown = CancellationToken::new();
+        tokio::spawn(run_tcp_accept_loop(
+            listener,
+            Arc::new(registry),
+            ProtocolType::OtlsWs,
+            Admission::new(limiter.clone(), PeerRateLimiter::new(100.0)).with_audit_log(AuditLog::new(audit.clone())),
+            shutdown.clone(),
+        ));
+
+        // The first client stays idle mid-handshake, holding the only slot.
+        let _first = TcpStream::connect(addr).await.unwrap();
+        while limiter.in_use() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // The second is accepted by the kernel, then refused by the server once it speaks.
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(b"GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n").await.unwrap();
+        let response = read_until_closed(second).await;
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"), "{}", response);
+        assert!(response.contains("www.example.com"));
+        assert_eq!(limiter.in_use(), 1);
+        assert_eq!(limiter.rejected_count(), 1);
+        assert_eq!(audit.outcomes(), vec![AuditOutcome::Accepted, AuditOutcome::RejectedByCapacity]);
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_accept_loop_drops_peers_over_their_rate() {
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let rate_limiter = PeerRateLimiter::new(1.0);
+        let audit = Arc::new(MemoryAuditSink::default());
+        let shutdown = CancellationToken::new();
+        tokio::spawn(run_tcp_accept_loop(
+            listener,
+            Arc::new(registry),
+            ProtocolType::OtlsWs,
+            Admission::new(ConnectionLimiter::new(16), rate_limiter.clone()).with_audit_log(AuditLog::new(audit.clone())),
+            shutdown.clone(),
+        ));
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let response = read_until_closed(second).await;
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"), "{}", response);
+        assert!(response.contains("\r\nRetry-After: 30\r\n"));
+
+        // A peer opening with a ClientHello is refused the way a TLS server would.
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        third.write_all(&[0x16, 0x03, 0x01, 0x00, 0x05, 0x01, 0x00, 0x00, 0x01, 0x03]).await.unwrap();
+        let mut alert = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(1), third.read_to_end(&mut alert)).await.unwrap();
+        assert_eq!(alert, [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28]);
+
+        // Nothing is sent before the peer speaks.
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+        let mut byte = [0u8; 1];
+        assert!(tokio::time::timeout(Duration::from_millis(200), silent.read(&mut byte)).await.is_err());
+
+        assert_eq!(rate_limiter.rejected_count(), 3);
+        assert_eq!(
+            audit.outcomes(),
+            vec![
+                AuditOutcome::Accepted,
+                AuditOutcome::RejectedByRateLimit,
+                AuditOutcome::RejectedByRateLimit,
+                AuditOutcome::RejectedByRateLimit
+            ]
+        );
+        assert_eq!(audit.records()[1].source_ip, addr.ip());
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_refused_peers_are_just_closed_when_responses_are_disabled() {
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admission = Admission::new(ConnectionLimiter::new(16), PeerRateLimiter::new(1.0)).with_rejection_response(
+            RejectionResponseConfig {
+                enabled: false,
+                ..RejectionResponseConfig::default()
+            },
+        );
+        let shutdown = CancellationToken::new();
+        tokio::spawn(run_tcp_accept_loop(listener, Arc::new(registry), ProtocolType::OtlsWs, admission, shutdown.clone()));
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        // Writing may fail if the server has already closed the connection.
+        let _ = second.write_all(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert_eq!(read_until_closed(second).await, "");
+        shutdown.cancel();
+    }
+
+    #[test]
+    fn test_permits_are_released_on_drop() {
+        let limiter = ConnectionLimiter::new(2);
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.in_use(), 2);
+        drop(first);
+        assert_eq!(limiter.in_use(), 1);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    /// Datagram protocol that sends every packet straight back to its sender.
+    #[derive(Clone)]
+    struct EchoProtocol {
+        config: ProtocolConfig,
+    }
+
+    #[async_trait]
+    impl ObfuscatedProtocol for EchoProtocol {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        async fn handle_tcp_stream(&self, _stream: TcpStream) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "echo is UDP-only"))
+        }
+
+        async fn handle_udp_packet(&self, socket: &UdpSocket, buf: &[u8], peer_addr: SocketAddr) -> io::Result<()> {
+            socket.send_to(buf, peer_addr).await.map(|_| ())
+        }
+
+        fn get_config(&self) -> &ProtocolConfig {
+            &self.config
+        }
+
+        fn update_config(&mut self, new_config: ProtocolConfig) {
+            self.config = new_config;
+        }
+
+        fn clone_box(&self) -> Box<dyn ObfuscatedProtocol> {
+            Box::new(self.clone())
+        }
+
+        fn metrics(&self) -> ProtocolMetrics {
+            ProtocolMetrics::default()
+        }
+
+        async fn shutdown(&self) {}
+
+        async fn health(&self) -> HealthStatus {
+            HealthStatus::Ok
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_replies_leave_from_the_listening_socket() {
+        let mut registry = ProtocolRegistry::new();
+        let config = ProtocolConfig::default_for(ProtocolType::AoQuic);
+        registry.register(ProtocolType::AoQuic, Arc::new(EchoProtocol { config }));
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let recv_loop = tokio::spawn(run_udp_recv_loop(socket, Arc::new(registry), ProtocolType::AoQuic, shutdown.clone()));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"ping", server_addr).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(from, server_addr);
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(1), recv_loop).await.unwrap().unwrap();
+    }
+
+    /// Collects formatted log output for assertions.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn lines_containing(&self, needle: &str) -> Vec<String> {
+            let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            text.lines().filter(|line| line.contains(needle)).map(str::to_string).collect()
+        }
+    }
+
+    fn conn_id_of(line: &str) -> &str {
+        let start = line.find("conn_id=").expect("line carries a conn_id") + "conn_id=".len();
+        &line[start..start + 8]
+    }
+
+    #[tokio::test]
+    async fn test_handler_logs_carry_the_connection_id() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        // Current-thread runtime, so the spawned handlers run on this thread and see the subscriber.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        let registry = Arc::new(registry);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        tokio::spawn(run_tcp_accept_loop(
+            listener,
+            registry.clone(),
+            ProtocolType::OtlsWs,
+            Admission::new(ConnectionLimiter::new(16), PeerRateLimiter::new(100.0)),
+            shutdown.clone(),
+        ));
+
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"hello").await.unwrap();
+        }
+        let otls = registry.get(&ProtocolType::OtlsWs).unwrap();
+        while otls.metrics().bytes_in < 10 || otls.metrics().active_connections > 0 {
+            tokio::task::yield_now().await;
+        }
+        shutdown.cancel();
+
+        // Both the accept-loop line and the handler's own lines carry the connection's id.
+        let accepted = logs.lines_containing("New TCP connection");
+        let handled = logs.lines_containing("Successfully processed simulated connection");
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(handled.len(), 2);
+        let mut accepted_ids: Vec<&str> = accepted.iter().map(|line| conn_id_of(line)).collect();
+        let mut handled_ids: Vec<&str> = handled.iter().map(|line| conn_id_of(line)).collect();
+        accepted_ids.sort();
+        handled_ids.sort();
+        assert_eq!(accepted_ids, handled_ids);
+        assert_ne!(accepted_ids[0], accepted_ids[1]);
+    }
+
+    #[test]
+    fn test_packet_ids_depend_on_peer_and_time() {
+        let peer: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        let other: SocketAddr = "203.0.113.8:51234".parse().unwrap();
+        let now = SystemTime::now();
+        let later = now + Duration::from_millis(1);
+        assert_eq!(packet_conn_id(peer, now), packet_conn_id(peer, now));
+        assert_ne!(packet_conn_id(peer, now), packet_conn_id(other, now));
+        assert_ne!(packet_conn_id(peer, now), packet_conn_id(peer, later));
+        assert_eq!(packet_conn_id(peer, now).len(), 8);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_connections_complete_the_handshake() {
+        use tokio::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("hezardastan-uds-{}.sock", std::process::id()));
+        // A socket file left over from an earlier run is replaced.
+        drop(bind_unix_listener(&path).await.unwrap());
+        let listener = bind_unix_listener(&path).await.unwrap();
+
+        let audit = Arc::new(MemoryAuditSink::default());
+        let mut registry = ProtocolRegistry::new();
+        let otls = OtlsWsProtocol::new().with_audit_log(AuditLog::new(audit.clone()));
+        registry.register(ProtocolType::OtlsWs, Arc::new(otls));
+        let registry = Arc::new(registry);
+        let shutdown = CancellationToken::new();
+        let accept_loop = tokio::spawn(run_unix_accept_loop(
+            listener,
+            registry.clone(),
+            ProtocolType::OtlsWs,
+            Admission::new(ConnectionLimiter::new(16), PeerRateLimiter::new(1.0)).with_audit_log(AuditLog::new(audit.clone())),
+            shutdown.clone(),
+        ));
+
+        // Without an upstream, OTLS/WS closes the connection once the handshake is done.
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(1), client.read_to_end(&mut rest)).await.unwrap().unwrap();
+        let otls = registry.get(&ProtocolType::OtlsWs).unwrap();
+        assert_eq!(otls.metrics().bytes_in, 5);
+        assert_eq!(otls.metrics().handshake_failures, 0);
+
+        // Local clients share one address, so the per-peer rate limit doesn't apply to them.
+        let mut second = UnixStream::connect(&path).await.unwrap();
+        second.write_all(b"again").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), second.read_to_end(&mut rest)).await.unwrap().unwrap();
+        let mut outcomes = audit.outcomes();
+        outcomes.sort_by_key(|outcome| outcome.as_str());
+        assert_eq!(
+            outcomes,
+            [AuditOutcome::Accepted, AuditOutcome::Accepted, AuditOutcome::Completed, AuditOutcome::Completed]
+        );
+        assert!(audit.records().iter().all(|record| record.source_ip == LOCAL_PEER.ip()));
+
+        shutdown.cancel();
+        accept_loop.await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_accept_loop_stops_on_cancellation() {
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        let registry = Arc::new(registry);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let accept_loop = tokio::spawn(run_tcp_accept_loop(
+            listener,
+            registry.clone(),
+            ProtocolType::OtlsWs,
+            Admission::new(ConnectionLimiter::new(16), PeerRateLimiter::new(100.0)),
+            shutdown.clone(),
+        ));
+
+        // The loop serves connections while the token is live.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let otls = registry.get(&ProtocolType::OtlsWs).unwrap();
+        while otls.metrics().bytes_in == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(1), accept_loop).await.unwrap().unwrap();
+        // The listener was dropped with the loop, so new connections are refused.
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! This module simulates a lossy network link, for testing handshakes under packet loss.
+//! `lossy_link` returns two connected streams, like `tokio::io::duplex`, but whatever one end
+//! writes crosses a simulated link: it is cut into packets of at most `mtu` bytes, each
+//! packet and each acknowledgement is lost with probability `loss_rate`, and a packet that
+//! isn't acknowledged is sent again after a timeout that doubles each time, as TCP does.
+//! A packet still unacknowledged after `max_retransmits` retries makes that direction give
+//! up: the link stays open but carries nothing more, so the receiver sees silence rather
+//! than an error, as it would behind a black-holing path.
+//!
+//! Delays use `tokio::time`, so a test under a paused clock runs many simulated seconds at
+//! once. Loss is drawn from an RNG seeded with `seed`, so every run of a test sees the same
+//! packets lost.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{io, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::time::sleep;
+
+/// How much each end of the link can buffer before writes wait for the link to catch up.
+const LINK_BUFFER: usize = 64 * 1024;
+
+/// `LossyLinkConfig` describes the simulated path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossyLinkConfig {
+    /// Probability (0.0..=1.0) that a packet, or an acknowledgement, is lost.
+    pub loss_rate: f64,
+    /// One-way delay of every packet and acknowledgement.
+    pub latency: Duration,
+    /// Largest packet payload, in bytes.
+    pub mtu: usize,
+    /// Wait before the first retransmission; doubled after each one.
+    pub retransmit_timeout: Duration,
+    /// Retransmissions of one packet before its direction gives up.
+    pub max_retransmits: u32,
+    pub seed: u64,
+}
+
+impl Default for LossyLinkConfig {
+    fn default() -> Self {
+        // A clean path with TCP's initial retransmission timeout (RFC 6298).
+        LossyLinkConfig {
+            loss_rate: 0.0,
+            latency: Duration::from_millis(20),
+            mtu: 1200,
+            retransmit_timeout: Duration::from_secs(1),
+            max_retransmits: 6,
+            seed: 0,
+        }
+    }
+}
+
+/// Returns the two ends of a link described by `config`. Must be called within a Tokio runtime;
+/// each direction is carried by its own task.
+pub fn lossy_link(config: LossyLinkConfig) -> (DuplexStream, DuplexStream) {
+    let (client, client_net) = tokio::io::duplex(LINK_BUFFER);
+    let (server_net, server) = tokio::io::duplex(LINK_BUFFER);
+    let (client_rx, client_tx) = tokio::io::split(client_net);
+    let (server_rx, server_tx) = tokio::io::split(server_net);
+    tokio::spawn(carry(client_rx, server_tx, config, StdRng::seed_from_u64(config.seed)));
+    tokio::spawn(carry(server_rx, client_tx, config, StdRng::seed_from_u64(config.seed.wrapping_add(1))));
+    (client, server)
+}
+
+/// Carries one direction of the link until its writer closes, the receiver goes away, or a
+/// packet is never acknowledged.
+async fn carry(
+    mut from: ReadHalf<DuplexStream>,
+    mut to: WriteHalf<DuplexStream>,
+    config: LossyLinkConfig,
+    mut rng: StdRng,
+) {
+    let mut packet = vec![0u8; config.mtu.max(1)];
+    loop {
+        let n = match from.read(&mut packet).await {
+            Ok(0) | Err(_) => {
+                let _ = to.shutdown().await;
+                return;
+            }
+            Ok(n) => n,
+        };
+        match send(&packet[..n], &mut to, &config, &mut rng).await {
+            Ok(true) => {}
+            // Gave up: keep both halves open so the receiver sees silence, not a closed stream.
+            Ok(false) => std::future::pending::<()>().await,
+            Err(_) => return,
+        }
+    }
+}
+
+/// Sends `packet` until it is acknowledged. Returns `false` if it never was.
+async fn send(
+    packet: &[u8],
+    to: &mut WriteHalf<DuplexStream>,
+    config: &LossyLinkConfig,
+    rng: &mut StdRng,
+) -> io::Result<bool> {
+    let mut timeout = config.retransmit_timeout;
+    let mut delivered = false;
+    for _ in 0..=config.max_retransmits {
+        if !rng.gen_bool(config.loss_rate) {
+            // A retransmission of a packet that got through is a duplicate the receiver drops.
+            if !delivered {
+                sleep(config.latency).await;
+                to.write_all(packet).await?;
+                delivered = true;
+            }
+            if !rng.gen_bool(config.loss_rate) {
+                sleep(config.latency).await;
+                return Ok(true);
+            }
+        }
+        sleep(timeout).await;
+        timeout *= 2;
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clean_link_delivers_everything_in_order() {
+        let (mut client, mut server) = lossy_link(LossyLinkConfig::default());
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let started = Instant::now();
+        client.write_all(&data).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, data);
+        // Five packets, each waiting for the previous one's acknowledgement.
+        assert_eq!(started.elapsed(), Duration::from_millis(5 * 40));
+
+        server.write_all(b"reply").await.unwrap();
+        let mut reply = [0u8; 5];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"reply");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lossy_link_retransmits_then_gives_up() {
+        let config = LossyLinkConfig { loss_rate: 0.5, seed: 5, ..LossyLinkConfig::default() };
+        let (mut client, mut server) = lossy_link(config);
+        let started = Instant::now();
+        client.write_all(b"hello").await.unwrap();
+        let mut received = [0u8; 5];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hello");
+        // With this seed the first three sends are lost, so it arrives on the third retry.
+        assert_eq!(started.elapsed(), Duration::from_millis(1000 + 2000 + 4000 + 20));
+
+        // Nothing gets through a dead link: the receiver waits, with no error and no end of stream.
+        let (mut client, mut server) = lossy_link(LossyLinkConfig { loss_rate: 1.0, ..LossyLinkConfig::default() });
+        client.write_all(b"hello").await.unwrap();
+        let mut byte = [0u8; 1];
+        assert!(tokio::time::timeout(Duration::from_secs(600), server.read(&mut byte)).await.is_err());
+    }
+}
@@ -2,3 +2,16 @@
 pub mod common;
 pub mod otls_ws; // Obfuscated TLS over WebSocket
 pub mod aoquic;  // Adaptive Obfuscated QUIC
+pub mod handshake_stats;
+pub mod connection_state;
+pub mod throughput_monitor;
+pub mod rejection;
+pub mod stall_detector;
+pub mod handshake_limiter;
+pub mod correlation;
+pub mod registry;
+pub mod relay;
+pub mod listener;
+pub mod peer_rate_limiter;
+pub mod lossy_link;
+pub mod handoff;
@@ -2,17 +2,67 @@
 //! Implements the Obfuscated TLS over WebSocket (OTLS/WS) protocol.
 
 use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpStream, UdpSocket, SocketAddr};
-use std::io;
-use tracing::{info, debug, error}; // Import tracing macros
+use tokio::sync::mpsc;
+use std::{io, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+use tracing::{info, debug, error, warn}; // Import tracing macros
 
-use crate::protocols::ObfuscatedProtocol; // Import the trait
+use crate::protocols::{ObfuscatedProtocol, TunnelStream}; // Import the trait
+use crate::protocols::handshake_limiter::HandshakeLimiter;
+use crate::protocols::handshake_stats::HandshakeStatsTracker;
+use crate::protocols::connection_state::{ConnectionState, ConnectionSummary, ConnectionTracker, TrackedConnection};
+use crate::protocols::relay::Relay;
+use crate::protocols::stall_detector::{StallDetector, StallDetectorConfig};
+use crate::protocols::throughput_monitor::{QualitySignal, ThroughputMonitorConfig};
+use crate::protocols::correlation::{log_handshake_complete, ConnectionNonce, Role};
+use crate::protocols::common::{ConnectionHandle, ConnectionRegistry, HealthStatus, ProtocolConfig, ProtocolError, ProtocolCounters, ProtocolMetrics, ProtocolType};
+use crate::security::app_presets::AppPreset;
+use crate::security::kill_switch::{KillSwitchGate, KillSwitchManager};
+use crate::security::obfuscated_stream::ObfuscatedStream;
+use crate::security::probe_detection::{AcceptMode, ProbeDetector, ProbeEvent};
+use crate::security::replay_guard::{ReplayGuard, ReplayGuardConfig};
+use crate::security::traffic_obfuscation::{ObfuscationLevel, ObfuscationProfileTier, Obfuscator};
+use crate::security::traffic_shaping::{ResponseTimingConfig, VideoShapingConfig};
+use crate::utils::bandwidth::{BandwidthLimiter, ConnectionPriority};
+use crate::utils::logging::{redact_addr, redact_user, AuditLog, AuditOutcome};
 
 /// Represents the OTLS/WS obfuscated protocol.
 /// This struct will hold configuration and state specific to OTLS/WS.
 #[derive(Clone)] // Required for .clone() in main.rs
 pub struct OtlsWsProtocol {
     // TODO: Add fields for TLS certificates, WebSocket path, etc.
+    /// Stops forwarding when the Kill Switch triggers.
+    kill_switch: KillSwitchGate,
+    /// Set by `with_kill_switch_manager`; the gate is rebuilt from it whenever the config changes.
+    kill_switch_manager: Option<KillSwitchManager>,
+    config: ProtocolConfig,
+    /// Caps concurrent handshakes across protocols; `None` leaves them uncapped.
+    handshake_limiter: Option<HandshakeLimiter>,
+    /// Counts handshake outcomes per source subnet; `None` doesn't track them.
+    handshake_stats: Option<Arc<HandshakeStatsTracker>>,
+    /// Server-wide cap on relayed traffic; `None` leaves it unlimited.
+    bandwidth: Option<BandwidthLimiter>,
+    /// Frames the client side of relayed tunnels; `None` relays the bytes as they are.
+    obfuscator: Option<Arc<Obfuscator>>,
+    /// Paces client-bound tunnel traffic like a video stream; `None` doesn't shape it.
+    video_shaping: Option<VideoShapingConfig>,
+    /// Delays the first byte the server sends, like a web server's think time; `None` answers at once.
+    response_timing: Option<ResponseTimingConfig>,
+    /// Refuses tunnels whose obfuscation is weaker than this; `None` accepts any.
+    min_obfuscation_level: Option<ObfuscationLevel>,
+    /// Closes tunnels whose relay holds data without making progress; each is counted in `stalled_connections`.
+    stall_detector: StallDetector,
+    /// Switches peers that look like active probers to the cover page; `None` never does.
+    probe_detector: Option<Arc<ProbeDetector>>,
+    /// Remembers recent ClientHello randoms so a recorded handshake can't be replayed, and
+    /// refuses ClientHellos whose timestamp is too old to still be remembered.
+    replay_guard: Arc<ReplayGuard>,
+    counters: Arc<ProtocolCounters>,
+    connections: ConnectionRegistry,
+    /// Where each connection is in its lifecycle, from accept to close.
+    tracker: ConnectionTracker,
+    audit: AuditLog,
 }
 
 impl OtlsWsProtocol {
@@ -21,8 +71,329 @@ impl OtlsWsProtocol {
         info!("Initializing OTLS/WS Protocol.");
         OtlsWsProtocol {
             // Initialize fields here
+            kill_switch: KillSwitchGate::disabled(),
+            kill_switch_manager: None,
+            config: ProtocolConfig::default_for(ProtocolType::OtlsWs),
+            handshake_limiter: None,
+            handshake_stats: None,
+            bandwidth: None,
+            obfuscator: None,
+            video_shaping: None,
+            response_timing: None,
+            min_obfuscation_level: None,
+            stall_detector: StallDetector::new(StallDetectorConfig::default()),
+            probe_detector: None,
+            replay_guard: Arc::new(ReplayGuard::new(ReplayGuardConfig::default())),
+            counters: Arc::new(ProtocolCounters::default()),
+            connections: ConnectionRegistry::new(),
+            tracker: ConnectionTracker::new(),
+            audit: AuditLog::disabled(),
         }
     }
+
+    /// Gates tunnel traffic on `gate` (see `KillSwitchGate::for_tunnel`).
+    pub fn with_kill_switch(mut self, gate: KillSwitchGate) -> Self {
+        self.kill_switch = gate;
+        self
+    }
+
+    /// Gates tunnel traffic on `manager`, honouring the tunnel's `enable_kill_switch` setting.
+    /// The gate follows later `update_config` calls, so a reload can turn it on or off.
+    pub fn with_kill_switch_manager(mut self, manager: KillSwitchManager) -> Self {
+        self.kill_switch = KillSwitchGate::for_tunnel(&self.config.tunnel, &manager);
+        self.kill_switch_manager = Some(manager);
+        self
+    }
+
+    /// Paces relayed traffic on `limiter`, which should be shared by every protocol.
+    pub fn with_bandwidth_limiter(mut self, limiter: BandwidthLimiter) -> Self {
+        self.bandwidth = Some(limiter);
+        self
+    }
+
+    /// Runs the client side of every relayed tunnel through `obfuscator` (see `ObfuscatedStream`).
+    /// The opening flight is read before the handshake completes and is relayed as it arrived;
+    /// everything after it must be framed by the client with a matching pipeline.
+    /// Without one, a tunnel whose parameters name an `app_preset` or an `obfuscation_tier` is
+    /// framed by that preset or, failing that, that tier.
+    pub fn with_obfuscator(mut self, obfuscator: Arc<Obfuscator>) -> Self {
+        self.obfuscator = Some(obfuscator);
+        self
+    }
+
+    /// Reports connects, early resets and empty closes to `detector`. Peers it flags get the
+    /// cover page instead of the handshake. Loopback peers (local proxies, the Unix socket)
+    /// are never flagged.
+    pub fn with_probe_detector(mut self, detector: Arc<ProbeDetector>) -> Self {
+        self.probe_detector = Some(detector);
+        self
+    }
+
+    /// Paces the client side of every relayed tunnel with `config` (see `VideoShaper`).
+    pub fn with_video_shaping(mut self, config: VideoShapingConfig) -> Self {
+        self.video_shaping = Some(config);
+        self
+    }
+
+    /// Delays the server's first response on each connection (the cover page, or the start of
+    /// the tunnel) by a delay drawn from `config`.
+    pub fn with_response_timing(mut self, config: ResponseTimingConfig) -> Self {
+        self.response_timing = Some(config);
+        self
+    }
+
+    /// Refuses, with `AccessDenied`, every tunnel whose obfuscation (see `Obfuscator::level`)
+    /// is weaker than `level`. It is checked once the handshake has settled which obfuscation
+    /// the tunnel uses, before the upstream is contacted; an unframed tunnel counts as `None`.
+    pub fn with_min_obfuscation_level(mut self, level: ObfuscationLevel) -> Self {
+        self.min_obfuscation_level = Some(level);
+        self
+    }
+
+    /// Holds a permit from `limiter` while each handshake is in progress.
+    pub fn with_handshake_limiter(mut self, limiter: HandshakeLimiter) -> Self {
+        self.handshake_limiter = Some(limiter);
+        self
+    }
+
+    /// Records each handshake's outcome under its peer's subnet in `stats`.
+    pub fn with_handshake_stats(mut self, stats: Arc<HandshakeStatsTracker>) -> Self {
+        self.handshake_stats = Some(stats);
+        self
+    }
+
+    /// Records failed handshakes and completed tunnels in `audit`.
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    fn audit(&self, peer_addr: SocketAddr, outcome: AuditOutcome) {
+        let config = &self.config;
+        self.audit.record(peer_addr.ip(), &config.tunnel.protocol_type, config.obfuscation_profile(), outcome);
+    }
+
+    fn record_probe_event(&self, peer_addr: SocketAddr, event: ProbeEvent) -> AcceptMode {
+        match &self.probe_detector {
+            Some(detector) if !peer_addr.ip().is_loopback() => detector.record(peer_addr.ip(), event),
+            _ => AcceptMode::Normal,
+        }
+    }
+
+    /// The nonce the client logs for this connection. It needs a secret to derive from (the
+    /// tunnel's `user_id`) and the ClientHello random; the simulated handshake has no server
+    /// random yet, so that input is empty.
+    fn correlation_nonce(&self, opening: &[u8]) -> Option<ConnectionNonce> {
+        let user_id = &self.config.tunnel.user_id;
+        let client_random = client_hello_random(opening)?;
+        (!user_id.is_empty()).then(|| ConnectionNonce::derive(user_id.as_bytes(), client_random, &[]))
+    }
+
+    /// Answers with `COVER_PAGE` and closes our side, as a plain web server would.
+    async fn serve_cover_page(&self, stream: &mut Box<dyn TunnelStream>, tracked: &TrackedConnection) -> io::Result<()> {
+        self.delay_first_response().await;
+        stream.write_all(COVER_PAGE).await?;
+        stream.shutdown().await?;
+        self.counters.add_bytes_out(COVER_PAGE.len());
+        tracked.counters().add_bytes_out(COVER_PAGE.len());
+        Ok(())
+    }
+
+    /// Connects to `upstream_addr`, trying again after a short backoff if it fails: an
+    /// upstream that is restarting refuses connections for a moment. Every failed attempt is
+    /// counted as an error on `tracked`, and every new attempt as a retry.
+    async fn connect_upstream(&self, upstream_addr: SocketAddr, tracked: &TrackedConnection) -> io::Result<TcpStream> {
+        let mut backoff = UPSTREAM_RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match TcpStream::connect(upstream_addr).await {
+                Ok(upstream) => return Ok(upstream),
+                Err(e) => {
+                    tracked.counters().record_error();
+                    if attempt == UPSTREAM_CONNECT_ATTEMPTS {
+                        return Err(e);
+                    }
+                    debug!("OTLS/WS: Connection {} couldn't reach upstream {} ({}), retrying", tracked.id(), upstream_addr, e);
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            tracked.counters().record_retry();
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    async fn delay_first_response(&self) {
+        if let Some(timing) = &self.response_timing {
+            timing.delay_first_response().await;
+        }
+    }
+
+    fn record_handshake(&self, peer_addr: SocketAddr, success: bool) {
+        if let Some(stats) = &self.handshake_stats {
+            stats.record(peer_addr.ip(), success);
+        }
+    }
+
+    /// The obfuscator framing this tunnel: the server-wide one, else the tunnel's app preset,
+    /// else its obfuscation tier. `None` relays the tunnel unframed.
+    fn tunnel_obfuscator(&self) -> Option<Arc<Obfuscator>> {
+        self.obfuscator.clone().or_else(|| {
+            let tunnel = &self.config.tunnel;
+            let key = Some(tunnel.user_id.as_bytes()).filter(|key| !key.is_empty());
+            if let Some(preset) = AppPreset::from_params(&tunnel.protocol_params) {
+                return Some(Arc::new(preset.obfuscator(key)));
+            }
+            let tier = ObfuscationProfileTier::from_params(&tunnel.protocol_params)?;
+            Some(Arc::new(Obfuscator::for_tier(tier, key, Some(&tunnel.mimic_domain))))
+        })
+    }
+
+    /// Checks `obfuscator` against `min_obfuscation_level`.
+    fn check_obfuscation_level(&self, obfuscator: Option<&Obfuscator>) -> Result<(), ProtocolError> {
+        let Some(minimum) = self.min_obfuscation_level else {
+            return Ok(());
+        };
+        let level = obfuscator.map_or(ObfuscationLevel::None, Obfuscator::level);
+        if level < minimum {
+            return Err(ProtocolError::AccessDenied(format!(
+                "obfuscation level {} is below the required {}",
+                level.as_str(),
+                minimum.as_str()
+            )));
+        }
+        Ok(())
+    }
+
+    fn handshake_failed(&self, peer_addr: SocketAddr) {
+        self.counters.handshake_failed();
+        self.record_handshake(peer_addr, false);
+        self.audit(peer_addr, AuditOutcome::HandshakeFailed);
+    }
+
+    /// Relays the tunnel to `upstream_addr` until both sides close it or the server shuts down.
+    /// `opening` is the client data read during the handshake. A tunnel whose obfuscation is
+    /// below `min_obfuscation_level` is refused before the upstream is contacted.
+    async fn relay(
+        &self,
+        stream: Box<dyn TunnelStream>,
+        peer_addr: SocketAddr,
+        upstream_addr: SocketAddr,
+        opening: &[u8],
+        connection: &mut ConnectionHandle,
+        tracked: &TrackedConnection,
+    ) -> io::Result<()> {
+        let obfuscator = self.tunnel_obfuscator();
+        if let Err(e) = self.check_obfuscation_level(obfuscator.as_deref()) {
+            self.audit(peer_addr, AuditOutcome::RejectedByAccess);
+            return Err(e.into());
+        }
+        self.delay_first_response().await;
+        let upstream = self.connect_upstream(upstream_addr, tracked).await?;
+        let _ = tracked.transition(ConnectionState::Tunneling);
+        let (quality_tx, quality_rx) = mpsc::unbounded_channel();
+        let progress = self.stall_detector.monitor();
+        let mut relay = Relay::new(self.counters.clone())
+            .with_connection_counters(tracked.counters().clone())
+            .with_throughput_monitor(ThroughputMonitorConfig::default())
+            .with_quality_signals(quality_tx)
+            .with_progress_monitor(progress.clone());
+        if let Some(limiter) = &self.bandwidth {
+            let priority =
+                ConnectionPriority::from_params(&self.config.tunnel.protocol_params).unwrap_or(ConnectionPriority::Normal);
+            relay = relay.with_bandwidth(limiter.connection_with_priority(priority));
+        }
+        if let Some(config) = self.video_shaping {
+            relay = relay.with_video_shaping(config);
+        }
+        let adapt = adapt_to_quality(obfuscator.clone(), quality_rx, peer_addr);
+        let relayed = self.stall_detector.guard(tracked, &progress, async {
+            match obfuscator {
+                Some(obfuscator) => relay.run(ObfuscatedStream::new(stream, obfuscator), upstream, opening).await,
+                None => relay.run(stream, upstream, opening).await,
+            }
+        });
+        tokio::select! {
+            stats = relayed => {
+                let stats = stats.map_err(|e| {
+                    self.counters.connection_stalled();
+                    io::Error::new(io::ErrorKind::TimedOut, e.to_string())
+                })??;
+                debug!(
+                    "OTLS/WS: Tunnel from {} closed ({} bytes up, {} bytes down)",
+                    redact_addr(peer_addr),
+                    stats.client_to_upstream,
+                    stats.upstream_to_client
+                );
+            }
+            _ = connection.closing() => {
+                let _ = tracked.transition(ConnectionState::Draining);
+                info!("OTLS/WS: Closed tunnel from {} for shutdown", redact_addr(peer_addr));
+            }
+            never = adapt => match never {},
+        }
+        Ok(())
+    }
+}
+
+/// The tunnel's adaptive controller: each time the relay reports the tunnel throttled, its
+/// obfuscator moves to the next mutation preset (see `Obfuscator::mutate`), so the pattern the
+/// throttling keyed on changes. Unframed tunnels have nothing to adapt. Never resolves.
+async fn adapt_to_quality(
+    obfuscator: Option<Arc<Obfuscator>>,
+    mut signals: mpsc::UnboundedReceiver<QualitySignal>,
+    peer_addr: SocketAddr,
+) -> std::convert::Infallible {
+    while let Some(signal) = signals.recv().await {
+        if let (QualitySignal::Throttled { .. }, Some(obfuscator)) = (signal, &obfuscator) {
+            let config = obfuscator.mutate();
+            info!(
+                "OTLS/WS: Tunnel from {} looks throttled, switching obfuscation (noise {}, mimicry {}, delay {}ms)",
+                redact_addr(peer_addr),
+                config.max_noise_bytes,
+                config.mimicry_probability,
+                config.max_delay_ms
+            );
+        }
+    }
+    // The relay is gone, so the tunnel is closing anyway.
+    std::future::pending().await
+}
+
+/// Attempts to connect to the upstream before a tunnel gives up on it.
+const UPSTREAM_CONNECT_ATTEMPTS: u32 = 3;
+/// Wait before the first upstream reconnect; doubled after each one.
+const UPSTREAM_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What a peer in decoy mode gets instead of the handshake: a plain response that reveals nothing.
+const COVER_PAGE: &[u8] = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// When the client sent `random`: like TLS 1.2's `gmt_unix_time`, the first four bytes of a
+/// HezarDastan client's ClientHello random are its clock in Unix seconds, big-endian.
+fn client_hello_time(random: &[u8]) -> SystemTime {
+    let secs = u32::from_be_bytes([random[0], random[1], random[2], random[3]]);
+    UNIX_EPOCH + Duration::from_secs(secs.into())
+}
+
+/// Whether `opening` starts a TLS handshake record that hasn't fully arrived yet.
+fn client_hello_incomplete(opening: &[u8]) -> bool {
+    match opening {
+        [0x16, _, _, hi, lo, rest @ ..] => rest.len() < u16::from_be_bytes([*hi, *lo]) as usize,
+        [0x16, ..] => true,
+        _ => false,
+    }
+}
+
+/// Returns the 32-byte random of a TLS ClientHello, or `None` if `opening` doesn't start with one.
+fn client_hello_random(opening: &[u8]) -> Option<&[u8]> {
+    // Record header (type, version, length), then handshake type and length, then client version.
+    const RANDOM: std::ops::Range<usize> = 11..43;
+    let is_client_hello = opening.first() == Some(&0x16) && opening.get(5) == Some(&0x01);
+    if is_client_hello {
+        opening.get(RANDOM)
+    } else {
+        None
+    }
 }
 
 #[async_trait]
@@ -33,28 +404,188 @@ impl ObfuscatedProtocol for OtlsWsProtocol {
 
     async fn handle_tcp_stream(&self, stream: TcpStream) -> io::Result<()> {
         let peer_addr = stream.peer_addr()?;
-        info!("OTLS/WS: Handling incoming TCP stream from {}", peer_addr);
+        info!("OTLS/WS: Handling incoming TCP stream from {}", redact_addr(peer_addr));
+        self.handle_stream(Box::new(stream), peer_addr).await
+    }
 
-        // TODO: Here's where the actual TLS handshake and WebSocket framing logic will go.
-        // For now, we'll just simulate success and close the connection.
+    async fn handle_stream(&self, stream: Box<dyn TunnelStream>, peer_addr: SocketAddr) -> io::Result<()> {
+        let _active = self.counters.connection_opened();
+        let mut connection = self.connections.register();
+        // Dropped when the handler returns, which counts the connection as closed.
+        let tracked = self.tracker.register(peer_addr);
+        let mode = self.record_probe_event(peer_addr, ProbeEvent::Connected);
 
-        // Example of what might happen:
-        // 1. Perform TLS handshake
-        // 2. Perform WebSocket handshake
-        // 3. Tunnel traffic through the WebSocket
+        // The whole tunnel runs under the kill-switch gate: if it triggers, forwarding stops
+        // and the stream is dropped instead of leaking traffic.
+        self.kill_switch
+            .guard(async {
+                let mut stream = stream;
+                // The permit covers the handshake only and is released before tunneling starts.
+                let permit = match &self.handshake_limiter {
+                    Some(limiter) => match limiter.acquire().await {
+                        Ok(permit) => Some(permit),
+                        Err(e) => {
+                            self.handshake_failed(peer_addr);
+                            return Err(e.into());
+                        }
+                    },
+                    None => None,
+                };
+                let _ = tracked.transition(ConnectionState::Handshaking);
+                // TODO: Here's where the actual TLS handshake and WebSocket framing logic will go.
+                // For now, we read the client's opening flight (the future ClientHello),
+                // then simulate success and close the connection.
+                let mut opening = [0u8; 4096];
+                let handshake = tokio::time::timeout(self.config.handshake_timeout, async {
+                    let mut n = 0;
+                    loop {
+                        let read = tokio::select! {
+                            read = stream.read(&mut opening[n..]) => read?,
+                            _ = connection.closing() => return Ok(None),
+                        };
+                        n += read;
+                        // A ClientHello can span several packets; a TLS server needs all of it.
+                        if read == 0 || n == opening.len() || !client_hello_incomplete(&opening[..n]) {
+                            return Ok::<_, io::Error>(Some(n));
+                        }
+                    }
+                });
+                let n = match handshake.await {
+                    Ok(read) => match read {
+                        Ok(Some(n)) => n,
+                        Err(e) => {
+                            if e.kind() == io::ErrorKind::ConnectionReset {
+                                self.record_probe_event(peer_addr, ProbeEvent::EarlyReset);
+                            }
+                            return Err(e);
+                        }
+                        Ok(None) => {
+                            // Server is stopping: close our side cleanly instead of just dropping the socket.
+                            stream.shutdown().await?;
+                            info!("OTLS/WS: Closed connection from {} for shutdown", redact_addr(peer_addr));
+                            return Ok(());
+                        }
+                    },
+                    Err(_) => {
+                        self.handshake_failed(peer_addr);
+                        return Err(ProtocolError::HandshakeError(format!(
+                            "no handshake within {:?}",
+                            self.config.handshake_timeout
+                        ))
+                        .into());
+                    }
+                };
+                if n == 0 {
+                    self.record_probe_event(peer_addr, ProbeEvent::ClosedWithoutData);
+                    self.handshake_failed(peer_addr);
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed before the handshake"));
+                }
+                self.counters.add_bytes_in(n);
+                tracked.counters().add_bytes_in(n);
+                if mode == AcceptMode::Decoy {
+                    // A suspected prober only ever sees the cover page, never the handshake.
+                    self.serve_cover_page(&mut stream, &tracked).await?;
+                    info!("OTLS/WS: Served the cover page to suspected prober {}", redact_addr(peer_addr));
+                    self.audit(peer_addr, AuditOutcome::RejectedByAccess);
+                    return Ok(());
+                }
+                if let Some(random) = client_hello_random(&opening[..n]) {
+                    if !self.replay_guard.check_fresh(random, client_hello_time(random)) {
+                        // A replay gets the same cover page as a prober, so it learns nothing
+                        // about why it was turned away.
+                        self.counters.handshake_failed();
+                        self.record_handshake(peer_addr, false);
+                        self.serve_cover_page(&mut stream, &tracked).await?;
+                        info!(
+                            "OTLS/WS: Served the cover page to a replayed or stale ClientHello from {}",
+                            redact_addr(peer_addr)
+                        );
+                        self.audit(peer_addr, AuditOutcome::RejectedByAccess);
+                        return Ok(());
+                    }
+                }
+                self.record_handshake(peer_addr, true);
+                let _ = tracked.transition(ConnectionState::Authenticated);
+                drop(permit);
 
-        debug!("OTLS/WS: Successfully processed simulated connection from {}", peer_addr);
-        // In a real scenario, the stream would be kept open for tunneling.
-        // For this basic implementation, we just return Ok(()).
-        Ok(())
+                // Example of what might happen:
+                // 1. Perform TLS handshake
+                // 2. Perform WebSocket handshake
+                // 3. Tunnel traffic through the WebSocket
+
+                debug!("OTLS/WS: Successfully processed simulated connection from {}", redact_addr(peer_addr));
+                if let Some(nonce) = self.correlation_nonce(&opening[..n]) {
+                    log_handshake_complete(Role::Server, nonce, peer_addr);
+                }
+                let user_id = &self.config.tunnel.user_id;
+                if !user_id.is_empty() {
+                    debug!("OTLS/WS: Tunnel from {} is for user {}", redact_addr(peer_addr), redact_user(user_id));
+                }
+                // Without an upstream there is nowhere to tunnel to, so the connection ends here.
+                if let Some(upstream_addr) = self.config.upstream_addr {
+                    self.relay(stream, peer_addr, upstream_addr, &opening[..n], &mut connection, &tracked).await?;
+                }
+                self.audit(peer_addr, AuditOutcome::Completed);
+                Ok(())
+            })
+            .await
+            .map_err(|e| {
+                warn!("OTLS/WS: Tunnel from {} stopped: {}", redact_addr(peer_addr), e);
+                e
+            })
     }
 
     // OTLS/WS is a TCP-based protocol, so this method will likely not be used,
     // or it might log an error if called.
     async fn handle_udp_packet(&self, _socket: &UdpSocket, _buf: &[u8], peer_addr: SocketAddr) -> io::Result<()> {
-        error!("OTLS/WS: Received unexpected UDP packet from {}. This protocol is TCP-based.", peer_addr);
+        error!("OTLS/WS: Received unexpected UDP packet from {}. This protocol is TCP-based.", redact_addr(peer_addr));
         Err(io::Error::new(io::ErrorKind::Other, "OTLS/WS does not handle UDP packets."))
     }
+
+    fn get_config(&self) -> &ProtocolConfig {
+        &self.config
+    }
+
+    fn update_config(&mut self, new_config: ProtocolConfig) {
+        info!("OTLS/WS: Configuration updated (mimic domain {}).", new_config.tunnel.mimic_domain);
+        if let Some(manager) = &self.kill_switch_manager {
+            self.kill_switch = KillSwitchGate::for_tunnel(&new_config.tunnel, manager);
+        }
+        self.config = new_config;
+    }
+
+    fn clone_box(&self) -> Box<dyn ObfuscatedProtocol> {
+        Box::new(self.clone())
+    }
+
+    fn metrics(&self) -> ProtocolMetrics {
+        self.counters.snapshot()
+    }
+
+    fn connection_states(&self) -> Vec<(ConnectionState, usize)> {
+        self.tracker.state_counts()
+    }
+
+    fn connections(&self) -> Vec<ConnectionSummary> {
+        self.tracker.active()
+    }
+
+    fn reset_connection_counters(&self, id: Option<u64>) -> usize {
+        self.tracker.reset_counters(id)
+    }
+
+    async fn shutdown(&self) {
+        info!("OTLS/WS: Shutting down, closing {} active connection(s).", self.connections.live_connections());
+        self.connections.shutdown().await;
+        info!("OTLS/WS: All connections closed.");
+    }
+
+    async fn health(&self) -> HealthStatus {
+        if self.connections.is_shutting_down() {
+            return HealthStatus::Down;
+        }
+        self.kill_switch.health()
+    }
 }
 
 // Add unit tests for this module
@@ -63,6 +594,7 @@ mod tests {
     use super::*;
     use tokio::net::TcpListener;
     use std::time::Duration;
+    use crate::security::app_presets::APP_PRESET_PARAM;
 
     #[tokio::test]
     async fn test_otlsws_protocol_name() {
@@ -102,4 +634,614 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Other);
     }
+
+    #[tokio::test]
+    async fn test_otlsws_stops_when_kill_switch_triggers() {
+        use crate::security::kill_switch::KillSwitchState;
+        use tokio::sync::watch;
+
+        let (state_tx, state_rx) = watch::channel(KillSwitchState::Active);
+        let protocol = OtlsWsProtocol::new().with_kill_switch(KillSwitchGate::new(state_rx));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(protocol.handle_tcp_stream(stream).await.is_ok());
+
+        state_tx.send(KillSwitchState::Triggered).unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(1), protocol.handle_tcp_stream(stream)).await.unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_gate_follows_tunnel_enable_kill_switch() {
+        use crate::security::kill_switch::KillSwitchState;
+
+        let manager = KillSwitchManager::new(true);
+        manager.set_state(KillSwitchState::Active).unwrap();
+        manager.set_state(KillSwitchState::Triggered).unwrap();
+
+        // The default tunnel config has the Kill Switch off, so a triggered manager doesn't block it.
+        let mut protocol = OtlsWsProtocol::new().with_kill_switch_manager(manager.clone());
+        assert!(protocol.kill_switch.check().is_ok());
+
+        let mut config = protocol.get_config().clone();
+        config.tunnel.enable_kill_switch = true;
+        protocol.update_config(config.clone());
+        assert_eq!(protocol.kill_switch.check().unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+
+        config.tunnel.enable_kill_switch = false;
+        protocol.update_config(config);
+        assert!(protocol.kill_switch.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_handshakes_hold_a_limiter_permit() {
+        use crate::protocols::handshake_limiter::{HandshakeLimiterConfig, OverflowPolicy};
+
+        let limiter = HandshakeLimiter::new(HandshakeLimiterConfig {
+            max_concurrent: 1,
+            overflow: OverflowPolicy::Reject,
+        });
+        let protocol = OtlsWsProtocol::new().with_handshake_limiter(limiter.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // The first client stays silent, so its handshake keeps the only permit.
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let first_handler = {
+            let protocol = protocol.clone();
+            tokio::spawn(async move { protocol.handle_tcp_stream(stream).await })
+        };
+        while limiter.in_progress() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let _second = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let err = protocol.handle_tcp_stream(stream).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(err.to_string().contains("too many concurrent handshakes"));
+        assert_eq!(limiter.rejected_count(), 1);
+
+        // Once the first handshake completes, its permit is released.
+        first.write_all(b"hello").await.unwrap();
+        first_handler.await.unwrap().unwrap();
+        assert_eq!(limiter.in_progress(), 0);
+        assert_eq!(protocol.metrics().handshake_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_relays_to_upstream_after_the_handshake() {
+        use crate::utils::bandwidth::BandwidthConfig;
+
+        // Upstream echoes whatever it receives.
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+        config.upstream_addr = Some(upstream.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let (mut read, mut write) = stream.split();
+            tokio::io::copy(&mut read, &mut write).await.unwrap();
+        });
+
+        let mut protocol = OtlsWsProtocol::new().with_bandwidth_limiter(BandwidthLimiter::new(BandwidthConfig::default()));
+        protocol.update_config(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let handler = {
+            let protocol = protocol.clone();
+            tokio::spawn(async move { protocol.handle_tcp_stream(stream).await })
+        };
+
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0u8; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello");
+        assert!(protocol.connection_states().contains(&(ConnectionState::Tunneling, 1)));
+        client.write_all(b" tunnel").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b" tunnel");
+
+        handler.await.unwrap().unwrap();
+        let metrics = protocol.metrics();
+        assert_eq!(metrics.bytes_in, 12);
+        assert_eq!(metrics.bytes_out, 12);
+        assert!(protocol.connection_states().iter().all(|(_, count)| *count == 0));
+        assert_eq!(protocol.tracker.transitions_into(ConnectionState::Authenticated), 1);
+        assert_eq!(protocol.tracker.transitions_into(ConnectionState::Closed), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_otlsws_retries_an_unreachable_upstream() {
+        // A port nothing listens on any more.
+        let upstream_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let protocol = OtlsWsProtocol::new();
+        let tracked = protocol.tracker.register("127.0.0.1:40000".parse().unwrap());
+        let started = tokio::time::Instant::now();
+        assert!(protocol.connect_upstream(upstream_addr, &tracked).await.is_err());
+        assert_eq!(started.elapsed(), Duration::from_millis(100 + 200));
+        let counts = tracked.counters().snapshot();
+        assert_eq!((counts.errors, counts.retries), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_frames_the_tunnel_with_the_obfuscator() {
+        use crate::security::transform_registry::{TransformRegistry, TransformSpec};
+        use crate::security::traffic_obfuscation::ObfuscatorConfig;
+
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+        config.upstream_addr = Some(upstream.local_addr().unwrap());
+        let upstream = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            stream.write_all(b"pong").await.unwrap();
+            received
+        });
+
+        let specs = [TransformSpec {
+            name: "keystream".to_string(),
+            params: [("key".to_string(), "user-secret".to_string())].into(),
+        }];
+        let pipeline = || Arc::new(TransformRegistry::new().pipeline(&specs, ObfuscatorConfig::default()).unwrap());
+        let mut protocol = OtlsWsProtocol::new().with_obfuscator(pipeline());
+        protocol.update_config(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let handler = tokio::spawn(async move { protocol.handle_tcp_stream(stream).await });
+
+        // The opening flight goes out as is; the rest of the tunnel is framed.
+        client.write_all(b"hello").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut tunnel = ObfuscatedStream::new(client, pipeline());
+        tunnel.write_all(b" tunnel").await.unwrap();
+        tunnel.shutdown().await.unwrap();
+        assert_eq!(upstream.await.unwrap(), b"hello tunnel");
+        let mut reply = Vec::new();
+        tunnel.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"pong");
+        handler.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_frames_the_tunnel_with_its_obfuscation_tier() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+        config.upstream_addr = Some(upstream.local_addr().unwrap());
+        config.tunnel.protocol_params.insert("obfuscation_tier".to_string(), "minimal".to_string());
+        let mimic_domain = config.tunnel.mimic_domain.clone();
+        let upstream = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let mut protocol = OtlsWsProtocol::new();
+        protocol.update_config(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let handler = tokio::spawn(async move { protocol.handle_tcp_stream(stream).await });
+
+        client.write_all(b"hello").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let tier = Obfuscator::for_tier(ObfuscationProfileTier::Minimal, None, Some(&mimic_domain));
+        let mut tunnel = ObfuscatedStream::new(client, Arc::new(tier));
+        tunnel.write_all(b" tunnel").await.unwrap();
+        tunnel.shutdown().await.unwrap();
+        assert_eq!(upstream.await.unwrap(), b"hello tunnel");
+        handler.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_enforces_the_minimum_obfuscation_level() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // The minimal tier only scrambles, and without a user id there is no key to scramble with.
+        let cases = [("minimal", "user-1", false), ("balanced", "", false), ("balanced", "user-1", true)];
+        for (tier, user_id, admitted) in cases {
+            let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+            config.upstream_addr = Some(upstream.local_addr().unwrap());
+            config.tunnel.user_id = user_id.to_string();
+            config.tunnel.protocol_params.insert("obfuscation_tier".to_string(), tier.to_string());
+            let mut protocol = OtlsWsProtocol::new().with_min_obfuscation_level(ObfuscationLevel::Padded);
+            protocol.update_config(config);
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let handler = tokio::spawn(async move { protocol.handle_tcp_stream(stream).await });
+            client.write_all(b"hello").await.unwrap();
+
+            if admitted {
+                let (mut relayed, _) = upstream.accept().await.unwrap();
+                let mut opening = [0u8; 5];
+                relayed.read_exact(&mut opening).await.unwrap();
+                assert_eq!(&opening, b"hello");
+                drop(relayed);
+                client.shutdown().await.unwrap();
+                handler.await.unwrap().unwrap();
+            } else {
+                let err = handler.await.unwrap().unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{} {:?}", tier, user_id);
+                assert!(err.to_string().contains("below the required padded"), "{}", err);
+                // Refused before the upstream was contacted.
+                assert!(tokio::time::timeout(Duration::from_millis(50), upstream.accept()).await.is_err());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_app_preset_wins_over_the_obfuscation_tier() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+        config.upstream_addr = Some(upstream.local_addr().unwrap());
+        config.tunnel.protocol_params.insert("obfuscation_tier".to_string(), "minimal".to_string());
+        config.tunnel.protocol_params.insert(APP_PRESET_PARAM.to_string(), "teams".to_string());
+        let upstream = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let mut protocol = OtlsWsProtocol::new();
+        protocol.update_config(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let handler = tokio::spawn(async move { protocol.handle_tcp_stream(stream).await });
+
+        client.write_all(b"hello").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let preset = AppPreset::lookup("teams").unwrap().obfuscator(None);
+        let mut tunnel = ObfuscatedStream::new(client, Arc::new(preset));
+        tunnel.write_all(b" tunnel").await.unwrap();
+        tunnel.shutdown().await.unwrap();
+        assert_eq!(upstream.await.unwrap(), b"hello tunnel");
+        handler.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_metrics_count_bytes_and_failures() {
+        use crate::utils::logging::MemoryAuditSink;
+
+        let audit = Arc::new(MemoryAuditSink::default());
+        let stats = Arc::new(HandshakeStatsTracker::new(16));
+        let protocol = OtlsWsProtocol::new()
+            .with_audit_log(AuditLog::new(audit.clone()))
+            .with_handshake_stats(stats.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(protocol.metrics(), ProtocolMetrics::default());
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        protocol.clone().handle_tcp_stream(stream).await.unwrap();
+
+        // A client that hangs up without sending anything fails the handshake.
+        drop(TcpStream::connect(addr).await.unwrap());
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(protocol.handle_tcp_stream(stream).await.is_err());
+
+        let metrics = protocol.metrics();
+        assert_eq!(metrics.bytes_in, 18);
+        let subnet = stats.stats_for(addr.ip()).unwrap();
+        assert_eq!((subnet.successes, subnet.failures), (1, 1));
+        assert_eq!(metrics.handshake_failures, 1);
+        assert_eq!(metrics.active_connections, 0);
+        assert_eq!(audit.outcomes(), vec![AuditOutcome::Completed, AuditOutcome::HandshakeFailed]);
+        assert!(audit.records().iter().all(|record| record.profile == "default"));
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_rejects_a_replayed_client_hello() {
+        use crate::utils::logging::MemoryAuditSink;
+
+        let audit = Arc::new(MemoryAuditSink::default());
+        let protocol = OtlsWsProtocol::new().with_audit_log(AuditLog::new(audit.clone()));
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+        let client_hello = |sent_at: u32, random: u8| {
+            let mut hello = vec![0x16, 0x03, 0x01, 0x00, 0x2e, 0x01, 0x00, 0x00, 0x2a, 0x03, 0x03];
+            hello.extend(sent_at.to_be_bytes());
+            hello.extend([random; 28]);
+            hello.extend([0u8; 8]);
+            hello
+        };
+        // Returns what the server sent back.
+        let handshake = |flight: Vec<u8>| {
+            let protocol = protocol.clone();
+            async move {
+                let (stream, mut client) = tokio::io::duplex(256);
+                client.write_all(&flight).await.unwrap();
+                protocol.handle_stream(Box::new(stream), peer).await.unwrap();
+                let mut reply = Vec::new();
+                client.read_to_end(&mut reply).await.unwrap();
+                reply
+            }
+        };
+
+        assert!(handshake(client_hello(now, 1)).await.is_empty());
+        // A replay, and a recording too old for the guard to still remember, look like any
+        // other unwanted visitor.
+        assert_eq!(handshake(client_hello(now, 1)).await, COVER_PAGE);
+        assert_eq!(handshake(client_hello(now - 3600, 2)).await, COVER_PAGE);
+        assert!(handshake(client_hello(now, 2)).await.is_empty());
+        // Flights that aren't a ClientHello carry no random to check.
+        handshake(b"hello".to_vec()).await;
+        handshake(b"hello".to_vec()).await;
+
+        assert_eq!(protocol.metrics().handshake_failures, 2);
+        assert_eq!(
+            audit.outcomes(),
+            vec![
+                AuditOutcome::Completed,
+                AuditOutcome::RejectedByAccess,
+                AuditOutcome::RejectedByAccess,
+                AuditOutcome::Completed,
+                AuditOutcome::Completed,
+                AuditOutcome::Completed,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_otlsws_delays_the_first_response() {
+        let timing = ResponseTimingConfig {
+            min: Duration::from_millis(10),
+            median: Duration::from_millis(40),
+            max: Duration::from_millis(200),
+        };
+        let protocol = OtlsWsProtocol::new().with_response_timing(timing);
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+        let mut hello = vec![0x16, 0x03, 0x01, 0x00, 0x2e, 0x01, 0x00, 0x00, 0x2a, 0x03, 0x03];
+        hello.extend(now.to_be_bytes());
+        hello.extend([9u8; 36]);
+
+        // Each replay is answered with the cover page; the clock only moves while the server waits.
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            let (stream, mut client) = tokio::io::duplex(256);
+            client.write_all(&hello).await.unwrap();
+            let started = tokio::time::Instant::now();
+            protocol.handle_stream(Box::new(stream), peer).await.unwrap();
+            delays.push(started.elapsed());
+            let mut reply = Vec::new();
+            client.read_to_end(&mut reply).await.unwrap();
+        }
+        // The first attempt completes the handshake without sending anything, so it isn't delayed.
+        assert_eq!(delays[0], Duration::ZERO);
+        assert!(delays[1..].iter().all(|delay| (timing.min..=timing.max).contains(delay)), "{:?}", delays);
+        assert!(delays[1..].windows(2).any(|pair| pair[0] != pair[1]), "{:?}", delays);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_completes_padded_handshakes() {
+        use crate::security::handshake_padding::{pad_ws_upgrade, HandshakeSizes};
+        use crate::security::traffic_obfuscation::fake_client_hello;
+        use crate::utils::logging::MemoryAuditSink;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let audit = Arc::new(MemoryAuditSink::default());
+        let protocol = OtlsWsProtocol::new().with_audit_log(AuditLog::new(audit.clone()));
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+        let mut hello = fake_client_hello("www.aparat.com", &mut rng);
+        hello[11..15].copy_from_slice(&now.to_be_bytes());
+        let sizes = HandshakeSizes::for_domain("www.aparat.com");
+        let padded_hello = sizes.pad_client_hello(&hello, &mut rng).unwrap();
+        assert!(sizes.client_hello.contains(&padded_hello.len()));
+        let upgrade = b"GET /chat HTTP/1.1\r\nHost: www.aparat.com\r\nUpgrade: websocket\r\n\r\n";
+        let padded_upgrade = pad_ws_upgrade(upgrade, 903, &mut rng).unwrap();
+        assert_eq!(padded_upgrade.len(), 903);
+
+        for flight in [&padded_hello, &padded_upgrade] {
+            let (stream, mut client) = tokio::io::duplex(4096);
+            client.write_all(flight).await.unwrap();
+            protocol.handle_stream(Box::new(stream), peer).await.unwrap();
+        }
+        assert_eq!(audit.outcomes(), vec![AuditOutcome::Completed, AuditOutcome::Completed]);
+        // The padded ClientHello's random was still found: replaying it is caught.
+        let (stream, mut client) = tokio::io::duplex(4096);
+        client.write_all(&padded_hello).await.unwrap();
+        protocol.handle_stream(Box::new(stream), peer).await.unwrap();
+        assert_eq!(protocol.metrics().handshake_failures, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_otlsws_handshake_over_a_lossy_link() {
+        use crate::protocols::lossy_link::{lossy_link, LossyLinkConfig};
+        use crate::security::handshake_padding::pad_client_hello;
+        use crate::security::traffic_obfuscation::fake_client_hello;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let protocol = OtlsWsProtocol::new();
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(9);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+        // A ClientHello the size of one with a post-quantum key share, over a path with the
+        // smallest MTU IPv4 allows: four packets, each of which has to get through.
+        let mut handshake = |loss_rate: f64, seed: u64| {
+            let mut hello = fake_client_hello("www.example.com", &mut rng);
+            hello[11..15].copy_from_slice(&now.to_be_bytes());
+            let hello = pad_client_hello(&hello, 1797).unwrap();
+            let protocol = protocol.clone();
+            async move {
+                let config = LossyLinkConfig {
+                    loss_rate,
+                    mtu: 576,
+                    seed,
+                    ..LossyLinkConfig::default()
+                };
+                let (mut client, server) = lossy_link(config);
+                client.write_all(&hello).await.unwrap();
+                protocol.handle_stream(Box::new(server), peer).await
+            }
+        };
+
+        // Light loss only costs retransmissions; the handshake completes within its timeout.
+        for seed in 0..10 {
+            handshake(0.05, seed).await.unwrap();
+        }
+        assert_eq!(protocol.metrics().handshake_failures, 0);
+        // Heavy loss runs the handshake out of time, and the connection is cleaned up.
+        for seed in 0..10 {
+            let err = handshake(0.8, seed).await.unwrap_err();
+            assert!(err.to_string().contains("no handshake within 10s"), "{}", err);
+        }
+        assert_eq!(protocol.metrics().handshake_failures, 10);
+        assert_eq!(protocol.metrics().active_connections, 0);
+    }
+
+    #[test]
+    fn test_otlsws_correlation_nonce_needs_a_user_and_a_client_hello() {
+        let mut hello = vec![0x16, 0x03, 0x01, 0x00, 0x40, 0x01, 0x00, 0x00, 0x3c, 0x03, 0x03];
+        hello.extend([7u8; 32]);
+        let mut protocol = OtlsWsProtocol::new();
+        assert_eq!(protocol.correlation_nonce(&hello), None);
+
+        let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+        config.tunnel.user_id = "6f1c2a9e-user".to_string();
+        protocol.update_config(config);
+        let expected = ConnectionNonce::derive(b"6f1c2a9e-user", &[7u8; 32], &[]);
+        assert_eq!(protocol.correlation_nonce(&hello), Some(expected));
+        assert_eq!(protocol.correlation_nonce(b"hello"), None);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_serves_the_cover_page_to_suspected_probers() {
+        use crate::protocols::registry::LOCAL_PEER;
+        use crate::security::probe_detection::ProbeDetectorConfig;
+        use crate::utils::logging::MemoryAuditSink;
+
+        let audit = Arc::new(MemoryAuditSink::default());
+        let detector = Arc::new(ProbeDetector::new(ProbeDetectorConfig {
+            suspicious_threshold: 2,
+            ..ProbeDetectorConfig::default()
+        }));
+        let protocol = OtlsWsProtocol::new()
+            .with_audit_log(AuditLog::new(audit.clone()))
+            .with_probe_detector(detector.clone());
+        let connect = |peer: SocketAddr, flight: &'static [u8]| {
+            let protocol = protocol.clone();
+            async move {
+                let (stream, mut client) = tokio::io::duplex(256);
+                client.write_all(flight).await.unwrap();
+                client.shutdown().await.unwrap();
+                let result = protocol.handle_stream(Box::new(stream), peer).await;
+                let mut reply = Vec::new();
+                client.read_to_end(&mut reply).await.unwrap();
+                (result, reply)
+            }
+        };
+
+        // Two empty connections flag the prober; its next real attempt gets the cover page.
+        let prober: SocketAddr = "192.0.2.7:40000".parse().unwrap();
+        for _ in 0..2 {
+            assert!(connect(prober, b"").await.0.is_err());
+        }
+        let (result, reply) = connect(prober, b"hello").await;
+        result.unwrap();
+        assert_eq!(reply, COVER_PAGE);
+
+        // Other peers, and loopback ones however they behave, still get the handshake.
+        let (result, reply) = connect("192.0.2.8:40000".parse().unwrap(), b"hello").await;
+        result.unwrap();
+        assert!(reply.is_empty());
+        for _ in 0..3 {
+            assert!(connect(LOCAL_PEER, b"").await.0.is_err());
+        }
+        assert!(connect(LOCAL_PEER, b"hello").await.1.is_empty());
+
+        let decoyed = audit.outcomes().iter().filter(|outcome| **outcome == AuditOutcome::RejectedByAccess).count();
+        assert_eq!(decoyed, 1);
+        assert_eq!(detector.mode(prober.ip()), AcceptMode::Decoy);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_shutdown_closes_active_connections() {
+        let protocol = OtlsWsProtocol::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // The client connects but stays idle, so the handler is still waiting on it.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let handler = {
+            let protocol = protocol.clone();
+            tokio::spawn(async move { protocol.handle_tcp_stream(stream).await })
+        };
+        while protocol.connections.live_connections() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        tokio::time::timeout(Duration::from_secs(1), protocol.shutdown()).await.unwrap();
+        assert!(handler.await.unwrap().is_ok());
+        assert_eq!(protocol.metrics().active_connections, 0);
+        // The server closed its side rather than resetting the connection.
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_otlsws_drops_client_that_never_handshakes() {
+        let protocol = OtlsWsProtocol::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Connects, then sends nothing.
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let started = tokio::time::Instant::now();
+        let result = protocol.handle_tcp_stream(stream).await;
+
+        assert!(started.elapsed() >= Duration::from_secs(10));
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(err.to_string().contains("no handshake within 10s"));
+        assert_eq!(protocol.metrics().handshake_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_health() {
+        use crate::security::kill_switch::KillSwitchState;
+        use tokio::sync::watch;
+
+        assert_eq!(OtlsWsProtocol::new().health().await, HealthStatus::Ok);
+
+        let (state_tx, state_rx) = watch::channel(KillSwitchState::Active);
+        let protocol = OtlsWsProtocol::new().with_kill_switch(KillSwitchGate::new(state_rx));
+        state_tx.send(KillSwitchState::Reconnecting).unwrap();
+        assert_eq!(protocol.health().await, HealthStatus::Degraded);
+        state_tx.send(KillSwitchState::Triggered).unwrap();
+        assert_eq!(protocol.health().await, HealthStatus::Down);
+    }
+
+    #[tokio::test]
+    async fn test_otlsws_update_config_is_reflected() {
+        let mut protocol = OtlsWsProtocol::new();
+        assert_eq!(protocol.get_config().tunnel.protocol_type, ProtocolType::OtlsWs);
+
+        let mut config = protocol.get_config().clone();
+        config.tunnel.mimic_domain = "cdn.example.net".to_string();
+        config.ws_path = "/ws/updates".to_string();
+        protocol.update_config(config);
+
+        assert_eq!(protocol.get_config().tunnel.mimic_domain, "cdn.example.net");
+        assert_eq!(protocol.get_config().ws_path, "/ws/updates");
+    }
 }
@@ -0,0 +1,170 @@
+//! This module limits how fast a single peer IP may open connections.
+//! Each IP gets a token bucket refilled at `connections_per_second`, holding at most one
+//! second's worth of tokens as burst. The accept loops take a token per connection and
+//! drop the connection when the bucket is empty, so one peer can't flood the server while
+//! everyone else is unaffected.
+//!
+//! A bucket that has refilled to capacity behaves exactly like a new one, so the periodic
+//! eviction drops those without changing any limit.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+struct PeerBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+struct Inner {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, PeerBucket>>,
+    rejected: AtomicU64,
+}
+
+/// `PeerRateLimiter` is shared by all accept loops; clones share the same buckets.
+#[derive(Clone)]
+pub struct PeerRateLimiter {
+    inner: Arc<Inner>,
+}
+
+impl PeerRateLimiter {
+    /// Allows each peer IP `connections_per_second` new connections per second on average,
+    /// with bursts of up to one second's worth (at least one connection).
+    pub fn new(connections_per_second: f64) -> Self {
+        let rate = connections_per_second.max(f64::MIN_POSITIVE);
+        PeerRateLimiter {
+            inner: Arc::new(Inner {
+                rate,
+                burst: rate.max(1.0),
+                buckets: Mutex::new(HashMap::new()),
+                rejected: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Takes a token for a new connection from `ip`. Returns `false` if the peer is over its rate.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.inner.buckets.lock().unwrap();
+        let bucket = buckets.entry(ip).or_insert(PeerBucket {
+            tokens: self.inner.burst,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.inner.rate).min(self.inner.burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Drops the buckets of peers that have been quiet long enough to refill completely.
+    /// Returns how many were removed.
+    pub fn evict_stale(&self) -> usize {
+        let now = Instant::now();
+        let (rate, burst) = (self.inner.rate, self.inner.burst);
+        let mut buckets = self.inner.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens + elapsed * rate < burst
+        });
+        before - buckets.len()
+    }
+
+    /// Runs `evict_stale` every `interval` until `shutdown` is cancelled.
+    pub fn spawn_eviction(&self, interval: Duration, shutdown: CancellationToken) -> JoinHandle<()> {
+        let limiter = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = ticker.tick() => {
+                        limiter.evict_stale();
+                    }
+                }
+            }
+        })
+    }
+
+    /// Number of peers with a bucket in memory.
+    pub fn tracked_peers(&self) -> usize {
+        self.inner.buckets.lock().unwrap().len()
+    }
+
+    /// Number of connections rejected since the limiter was created.
+    pub fn rejected_count(&self) -> u64 {
+        self.inner.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flooding_peer_is_limited_without_affecting_others() {
+        let limiter = PeerRateLimiter::new(5.0);
+        let flooder: IpAddr = "203.0.113.7".parse().unwrap();
+        let bystander: IpAddr = "198.51.100.20".parse().unwrap();
+
+        let accepted = (0..50).filter(|_| limiter.check(flooder)).count();
+        assert_eq!(accepted, 5);
+        assert_eq!(limiter.rejected_count(), 45);
+        assert!(limiter.check(bystander));
+
+        // Tokens come back at the configured rate.
+        tokio::time::advance(Duration::from_millis(400)).await;
+        assert!(limiter.check(flooder));
+        assert!(limiter.check(flooder));
+        assert!(!limiter.check(flooder));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fractional_rate_allows_one_connection() {
+        let limiter = PeerRateLimiter::new(0.5);
+        let peer: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(limiter.check(peer));
+        assert!(!limiter.check(peer));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(limiter.check(peer));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_eviction_drops_only_refilled_buckets() {
+        let limiter = PeerRateLimiter::new(10.0);
+        let quiet: IpAddr = "192.0.2.1".parse().unwrap();
+        let busy: IpAddr = "192.0.2.2".parse().unwrap();
+        assert!(limiter.check(quiet));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        for _ in 0..10 {
+            limiter.check(busy);
+        }
+        assert_eq!(limiter.evict_stale(), 1);
+        assert_eq!(limiter.tracked_peers(), 1);
+
+        let shutdown = CancellationToken::new();
+        let eviction = limiter.spawn_eviction(Duration::from_secs(5), shutdown.clone());
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        assert_eq!(limiter.tracked_peers(), 0);
+        shutdown.cancel();
+        eviction.await.unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! This module maps each `ProtocolType` to the handler that serves it.
+//! Listeners hand incoming traffic to `ProtocolRegistry::dispatch` instead of holding a
+//! concrete protocol, so adding a protocol is one `register` call at startup.
+//! Handlers can be reconfigured while running: `update_config` swaps in a reconfigured copy,
+//! and connections already being handled finish with the handler they started on.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{Arc, RwLock},
+};
+use tokio::net::{TcpStream, UdpSocket, SocketAddr};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use crate::protocols::common::{HealthStatus, ProtocolConfig, ProtocolType};
+use crate::protocols::ObfuscatedProtocol;
+
+/// The peer address Unix socket connections are handled and audited under: they come from
+/// this host, and a Unix socket peer has no IP address of its own.
+pub const LOCAL_PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+/// Traffic arriving on a listener, waiting to be handed to a protocol.
+pub enum Incoming<'a> {
+    Tcp(TcpStream),
+    /// A connection on a Unix domain socket, handled like a TCP stream from `LOCAL_PEER`.
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Udp {
+        socket: &'a UdpSocket,
+        buf: &'a [u8],
+        peer_addr: SocketAddr,
+    },
+}
+
+/// `ProtocolRegistry` holds one shared handler per `ProtocolType`.
+#[derive(Default)]
+pub struct ProtocolRegistry {
+    protocols: RwLock<HashMap<ProtocolType, Arc<dyn ObfuscatedProtocol>>>,
+}
+
+impl ProtocolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `protocol` as the handler for `protocol_type`, returning the handler it replaced.
+    pub fn register(
+        &mut self,
+        protocol_type: ProtocolType,
+        protocol: Arc<dyn ObfuscatedProtocol>,
+    ) -> Option<Arc<dyn ObfuscatedProtocol>> {
+        self.protocols.write().unwrap().insert(protocol_type, protocol)
+    }
+
+    pub fn get(&self, protocol_type: &ProtocolType) -> Option<Arc<dyn ObfuscatedProtocol>> {
+        self.protocols.read().unwrap().get(protocol_type).cloned()
+    }
+
+    /// Every registered handler, in no particular order.
+    pub fn protocols(&self) -> Vec<Arc<dyn ObfuscatedProtocol>> {
+        self.protocols.read().unwrap().values().cloned().collect()
+    }
+
+    /// Applies `new_config` to `protocol_type`'s handler without interrupting it: a reconfigured
+    /// copy (see `ObfuscatedProtocol::clone_box`) replaces it for new connections.
+    /// Returns `false` if no handler is registered for `protocol_type`.
+    pub fn update_config(&self, protocol_type: &ProtocolType, new_config: ProtocolConfig) -> bool {
+        let mut protocols = self.protocols.write().unwrap();
+        let Some(current) = protocols.get(protocol_type) else {
+            return false;
+        };
+        let mut updated = current.clone_box();
+        updated.update_config(new_config);
+        protocols.insert(protocol_type.clone(), Arc::from(updated));
+        true
+    }
+
+    /// The worst health reported by any registered handler; `Down` if there are none.
+    pub async fn health(&self) -> HealthStatus {
+        let protocols = self.protocols();
+        if protocols.is_empty() {
+            return HealthStatus::Down;
+        }
+        let mut worst = HealthStatus::Ok;
+        for protocol in protocols {
+            worst = worst.max(protocol.health().await);
+        }
+        worst
+    }
+
+    /// Hands `incoming` to the handler registered for `protocol_type`.
+    /// Fails with `NotFound` if there is none.
+    pub async fn dispatch(&self, protocol_type: &ProtocolType, incoming: Incoming<'_>) -> io::Result<()> {
+        let protocol = self.get(protocol_type).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no handler registered for {}", protocol_type.to_string_repr()),
+            )
+        })?;
+        match incoming {
+            Incoming::Tcp(stream) => protocol.handle_tcp_stream(stream).await,
+            #[cfg(unix)]
+            Incoming::Unix(stream) => protocol.handle_stream(Box::new(stream), LOCAL_PEER).await,
+            Incoming::Udp { socket, buf, peer_addr } => protocol.handle_udp_packet(socket, buf, peer_addr).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::{aoquic::AoQuicProtocol, otls_ws::OtlsWsProtocol};
+
+    fn registry() -> ProtocolRegistry {
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        registry.register(ProtocolType::AoQuic, Arc::new(AoQuicProtocol::new()));
+        registry
+    }
+
+    #[test]
+    fn test_resolves_protocols_by_name() {
+        let registry = registry();
+        let otls = registry.get(&ProtocolType::from_str("otls-ws").unwrap()).unwrap();
+        let aoquic = registry.get(&ProtocolType::from_str("AOQUIC").unwrap()).unwrap();
+        assert_eq!(otls.name(), "OTLS/WS");
+        assert_eq!(aoquic.name(), "AOQUIC");
+        assert_eq!(registry.protocols().len(), 2);
+    }
+
+    #[test]
+    fn test_register_replaces_existing_handler() {
+        let mut registry = registry();
+        let previous = registry.register(ProtocolType::AoQuic, Arc::new(AoQuicProtocol::new()));
+        assert_eq!(previous.unwrap().name(), "AOQUIC");
+        assert_eq!(registry.protocols().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_config_swaps_in_a_handler_sharing_state() {
+        let registry = registry();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packet = Incoming::Udp { socket: &socket, buf: b"ping", peer_addr: "127.0.0.1:12345".parse().unwrap() };
+        registry.dispatch(&ProtocolType::AoQuic, packet).await.unwrap();
+        let before = registry.get(&ProtocolType::AoQuic).unwrap();
+
+        let mut config = before.get_config().clone();
+        config.tunnel.mimic_domain = "cdn.example.net".to_string();
+        assert!(registry.update_config(&ProtocolType::AoQuic, config));
+
+        let after = registry.get(&ProtocolType::AoQuic).unwrap();
+        assert_eq!(after.get_config().tunnel.mimic_domain, "cdn.example.net");
+        // The old handle keeps its config; counters carry over to the new one.
+        assert_eq!(before.get_config().tunnel.mimic_domain, "www.example.com");
+        assert_eq!(after.metrics().bytes_in, 4);
+        assert!(!ProtocolRegistry::new().update_config(&ProtocolType::AoQuic, after.get_config().clone()));
+    }
+
+    #[tokio::test]
+    async fn test_health_is_the_worst_of_all_protocols() {
+        assert_eq!(ProtocolRegistry::new().health().await, HealthStatus::Down);
+        let registry = registry();
+        assert_eq!(registry.health().await, HealthStatus::Ok);
+
+        registry.get(&ProtocolType::AoQuic).unwrap().shutdown().await;
+        assert_eq!(registry.health().await, HealthStatus::Down);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_routes_to_the_registered_protocol() {
+        let registry = registry();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let packet = Incoming::Udp { socket: &socket, buf: b"ping", peer_addr };
+
+        assert!(registry.dispatch(&ProtocolType::AoQuic, packet).await.is_ok());
+        let aoquic = registry.get(&ProtocolType::AoQuic).unwrap();
+        assert_eq!(aoquic.metrics().bytes_in, 4);
+
+        // OTLS/WS is TCP-only, so the same packet is rejected there.
+        let packet = Incoming::Udp { socket: &socket, buf: b"ping", peer_addr };
+        assert!(registry.dispatch(&ProtocolType::OtlsWs, packet).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_without_handler_is_not_found() {
+        let registry = ProtocolRegistry::new();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packet = Incoming::Udp { socket: &socket, buf: b"ping", peer_addr: "127.0.0.1:12345".parse().unwrap() };
+        let err = registry.dispatch(&ProtocolType::AoQuic, packet).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
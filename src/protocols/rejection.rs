@@ -0,0 +1,220 @@
+//! This module builds the response sent to connections the server rejects.
+//! Silently dropping a rejected connection is itself a fingerprint, and so is a server that
+//! speaks before the client does. Instead, `reject` waits for the peer's first bytes and
+//! answers in the protocol they open: a plain HTTP request gets a `429 Too Many Requests`
+//! page carrying ordinary CDN headers and a `Retry-After`, so the rejection looks like
+//! everyday rate limiting by the fronting CDN; a TLS ClientHello gets the fatal alert a TLS
+//! server sends when it won't go on with a handshake.
+
+use serde::Deserialize;
+use std::{io, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How long a rejected peer has to send its first bytes before it is closed without a reply.
+pub const FIRST_BYTES_TIMEOUT: Duration = Duration::from_secs(10);
+/// Content type of a TLS handshake record, the first byte of a ClientHello.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+/// A TLS 1.2 alert record: fatal (2), `handshake_failure` (40).
+const TLS_HANDSHAKE_FAILURE_ALERT: [u8; 7] = [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28];
+
+/// Why a connection is being rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionCause {
+    /// The server is at its connection cap.
+    OverQuota,
+    /// The peer exceeded its connection rate limit.
+    RateLimited,
+}
+
+impl RejectionCause {
+    /// The HTTP status used for this cause.
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            RejectionCause::OverQuota | RejectionCause::RateLimited => (429, "Too Many Requests"),
+        }
+    }
+}
+
+/// `RejectionResponseConfig` controls what a rejected connection receives.
+/// It is also the `[rejection]` table of the server configuration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RejectionResponseConfig {
+    /// When false, rejected connections are closed without a response.
+    pub enabled: bool,
+    /// Value of the `Retry-After` header, in seconds.
+    pub retry_after_secs: u32,
+    /// Value of the `Server` header; should match the CDN fronting the mimic domain.
+    pub server_header: String,
+}
+
+impl Default for RejectionResponseConfig {
+    fn default() -> Self {
+        RejectionResponseConfig {
+            enabled: true,
+            retry_after_secs: 30,
+            server_header: "cloudflare".to_string(),
+        }
+    }
+}
+
+/// Builds the raw HTTP/1.1 response for `cause`, branded for `mimic_domain`.
+/// Returns `None` if rejection responses are disabled and the connection should just be closed.
+pub fn rejection_response(cause: RejectionCause, mimic_domain: &str, config: &RejectionResponseConfig) -> Option<Vec<u8>> {
+    if !config.enabled {
+        return None;
+    }
+    let (code, reason) = cause.status();
+    let body = format!(
+        "<html>\r\n<head><title>{code} {reason}</title></head>\r\n<body>\r\n<center><h1>{code} {reason}</h1></center>\r\n<center>{host}</center>\r\n</body>\r\n</html>\r\n",
+        code = code,
+        reason = reason,
+        host = mimic_domain,
+    );
+    let response = format!(
+        "HTTP/1.1 {code} {reason}\r\n\
+         Server: {server}\r\n\
+         Content-Type: text/html; charset=UTF-8\r\n\
+         Content-Length: {len}\r\n\
+         Retry-After: {retry}\r\n\
+         Cache-Control: private, max-age=0, no-store, no-cache, must-revalidate\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        code = code,
+        reason = reason,
+        server = config.server_header,
+        len = body.len(),
+        retry = config.retry_after_secs,
+        body = body,
+    );
+    Some(response.into_bytes())
+}
+
+/// The reply to a rejected peer whose connection opened with `opening`: the TLS alert for a
+/// ClientHello, the rejection page for a plain HTTP request. Returns `None` if responses are
+/// disabled or the peer spoke neither, and the connection should just be closed.
+pub fn rejection_reply(
+    opening: &[u8],
+    cause: RejectionCause,
+    mimic_domain: &str,
+    config: &RejectionResponseConfig,
+) -> Option<Vec<u8>> {
+    if !config.enabled {
+        return None;
+    }
+    if opening.first() == Some(&TLS_HANDSHAKE_RECORD) {
+        return Some(TLS_HANDSHAKE_FAILURE_ALERT.to_vec());
+    }
+    // An HTTP request line starts with an upper-case method and a space.
+    let method_len = opening.iter().take_while(|byte| byte.is_ascii_uppercase()).count();
+    if method_len > 0 && opening.get(method_len) == Some(&b' ') {
+        return rejection_response(cause, mimic_domain, config);
+    }
+    None
+}
+
+/// Waits up to `FIRST_BYTES_TIMEOUT` for the peer's first bytes, answers them with
+/// `rejection_reply` and shuts `stream` down.
+pub async fn reject<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    cause: RejectionCause,
+    mimic_domain: &str,
+    config: &RejectionResponseConfig,
+) -> io::Result<()> {
+    let mut opening = [0u8; 64];
+    let n = tokio::time::timeout(FIRST_BYTES_TIMEOUT, stream.read(&mut opening)).await.unwrap_or(Ok(0))?;
+    if let Some(reply) = rejection_reply(&opening[..n], cause, mimic_domain, config) {
+        stream.write_all(&reply).await?;
+        stream.flush().await?;
+    }
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_text(cause: RejectionCause) -> String {
+        let bytes = rejection_response(cause, "www.example.com", &RejectionResponseConfig::default()).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_rate_limited_and_over_quota_return_429() {
+        for cause in [RejectionCause::RateLimited, RejectionCause::OverQuota] {
+            let text = response_text(cause);
+            assert!(text.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+            assert!(text.contains("\r\nRetry-After: 30\r\n"));
+            assert!(text.contains("\r\nServer: cloudflare\r\n"));
+            assert!(text.contains("<center>www.example.com</center>"));
+        }
+    }
+
+    #[test]
+    fn test_reply_follows_the_protocol_the_peer_opened() {
+        let config = RejectionResponseConfig::default();
+        let reply = |opening: &[u8]| rejection_reply(opening, RejectionCause::RateLimited, "www.example.com", &config);
+
+        let page = reply(b"GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n").unwrap();
+        assert!(page.starts_with(b"HTTP/1.1 429 Too Many Requests\r\n"));
+        assert_eq!(reply(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01]).unwrap(), TLS_HANDSHAKE_FAILURE_ALERT);
+        assert_eq!(reply(b""), None);
+        assert_eq!(reply(b"\x00\x01binary"), None);
+        assert_eq!(reply(b"GET"), None);
+    }
+
+    #[test]
+    fn test_content_length_matches_body() {
+        let text = response_text(RejectionCause::RateLimited);
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        let length: usize = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(length, body.len());
+    }
+
+    #[test]
+    fn test_disabled_config_sends_nothing() {
+        let config = RejectionResponseConfig {
+            enabled: false,
+            ..RejectionResponseConfig::default()
+        };
+        assert!(rejection_response(RejectionCause::RateLimited, "www.example.com", &config).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reject_writes_response_and_closes() {
+        let (mut server, mut client) = tokio::io::duplex(4096);
+        let config = RejectionResponseConfig {
+            retry_after_secs: 120,
+            server_header: "AmazonS3".to_string(),
+            ..RejectionResponseConfig::default()
+        };
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        reject(&mut server, RejectionCause::OverQuota, "cdn.example.net", &config).await.unwrap();
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).await.unwrap();
+        assert!(received.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(received.contains("\r\nRetry-After: 120\r\n"));
+        assert!(received.contains("\r\nServer: AmazonS3\r\n"));
+        assert!(received.contains("cdn.example.net"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reject_says_nothing_to_a_silent_peer() {
+        let (mut server, mut client) = tokio::io::duplex(4096);
+        let rejected = tokio::spawn(async move {
+            reject(&mut server, RejectionCause::RateLimited, "www.example.com", &RejectionResponseConfig::default()).await
+        });
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert!(received.is_empty());
+        rejected.await.unwrap().unwrap();
+    }
+}
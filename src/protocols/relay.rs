@@ -0,0 +1,561 @@
+//! This module copies tunnel traffic between a client and its upstream once the handshake is done.
+//! Each direction runs its own pump. Every write first waits on the connection's
+//! `ConnectionBandwidth` (when one is set), so the server-wide `BandwidthLimiter` caps
+//! relayed traffic in both directions. With a throughput monitor set, the relay also samples
+//! the bytes it moves once per `THROUGHPUT_SAMPLE_INTERVAL`, counts collapses as
+//! `throttle_events` and sends each `QualitySignal` to the connection's adaptive controller
+//! (see `with_quality_signals`). Time spent waiting on our own pacing (bandwidth cap, video
+//! shaping) is taken out of each sample, so shaping doesn't read as throttling; an interval
+//! that moved nothing counts only if a write was stuck, so a blackholed tunnel reads as a
+//! collapse while an idle one doesn't. With a progress monitor set (see `StallDetector::guard`),
+//! the relay reports its progress and whether it is holding data it hasn't handed to a writer.
+//! With video shaping set, traffic toward the client is paced by a `VideoShaper`. With
+//! connection counters set, the bytes are also counted for the one connection.
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, warn};
+
+use crate::protocols::common::ProtocolCounters;
+use crate::protocols::connection_state::ConnectionCounters;
+use crate::protocols::stall_detector::ProgressMonitor;
+use crate::protocols::throughput_monitor::{QualitySignal, ThroughputMonitor, ThroughputMonitorConfig};
+use crate::security::traffic_shaping::{VideoShaper, VideoShapingConfig};
+use crate::utils::bandwidth::ConnectionBandwidth;
+
+/// Size of the buffer each pump reads into.
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+/// How often the throughput monitor is fed.
+const THROUGHPUT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bytes moved in each direction by a finished relay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayStats {
+    pub client_to_upstream: u64,
+    pub upstream_to_client: u64,
+}
+
+/// Which way a pump copies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    ToUpstream,
+    ToClient,
+}
+
+/// `Relay` pumps bytes both ways between a client and its upstream, counting them in the
+/// protocol's `ProtocolCounters`.
+pub struct Relay {
+    counters: Arc<ProtocolCounters>,
+    connection: Option<Arc<ConnectionCounters>>,
+    bandwidth: Option<ConnectionBandwidth>,
+    throughput: Option<ThroughputMonitorConfig>,
+    progress: Option<ProgressMonitor>,
+    video_shaping: Option<VideoShapingConfig>,
+    quality_signals: Option<mpsc::UnboundedSender<QualitySignal>>,
+    /// Bytes written in either direction, read by the throughput sampler.
+    relayed: AtomicU64,
+    /// Writes started but not finished, across both directions.
+    pending_writes: AtomicUsize,
+    /// Chunks read but not yet handed to a writer, across both directions.
+    held: AtomicUsize,
+    /// Nanoseconds spent waiting on our own pacing, across both directions.
+    paced_nanos: AtomicU64,
+    /// Pumps currently waiting on our own pacing.
+    pacing: AtomicUsize,
+}
+
+impl Relay {
+    pub fn new(counters: Arc<ProtocolCounters>) -> Self {
+        Relay {
+            counters,
+            connection: None,
+            bandwidth: None,
+            throughput: None,
+            progress: None,
+            video_shaping: None,
+            quality_signals: None,
+            relayed: AtomicU64::new(0),
+            pending_writes: AtomicUsize::new(0),
+            held: AtomicUsize::new(0),
+            paced_nanos: AtomicU64::new(0),
+            pacing: AtomicUsize::new(0),
+        }
+    }
+
+    /// Also counts the relayed bytes in `counters`, the connection's own.
+    pub fn with_connection_counters(mut self, counters: Arc<ConnectionCounters>) -> Self {
+        self.connection = Some(counters);
+        self
+    }
+
+    /// Paces every write, in both directions, on `bandwidth`.
+    pub fn with_bandwidth(mut self, bandwidth: ConnectionBandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Watches the relayed throughput for collapses, counting each in `throttle_events`.
+    pub fn with_throughput_monitor(mut self, config: ThroughputMonitorConfig) -> Self {
+        self.throughput = Some(config);
+        self
+    }
+
+    /// Sends every `QualitySignal` the throughput monitor raises to `signals`, the connection's
+    /// adaptive controller. A closed receiver is ignored.
+    pub fn with_quality_signals(mut self, signals: mpsc::UnboundedSender<QualitySignal>) -> Self {
+        self.quality_signals = Some(signals);
+        self
+    }
+
+    /// Reports progress to `monitor`, for a `StallDetector` guarding this relay. Data counts as
+    /// pending while the relay holds it (pacing, waiting on the bandwidth cap) but not while a
+    /// write is blocked: that is the peer not reading, which backpressure handles, not a stall.
+    pub fn with_progress_monitor(mut self, monitor: ProgressMonitor) -> Self {
+        self.progress = Some(monitor);
+        self
+    }
+
+    /// Paces traffic toward the client like an adaptive-bitrate video session. Traffic toward
+    /// the upstream isn't shaped.
+    pub fn with_video_shaping(mut self, config: VideoShapingConfig) -> Self {
+        self.video_shaping = Some(config);
+        self
+    }
+
+    /// Sends `opening` (client data read during the handshake) upstream, then relays until both
+    /// sides have closed their write half. Fails as soon as either direction does.
+    pub async fn run<C, U>(self, client: C, upstream: U, opening: &[u8]) -> io::Result<RelayStats>
+    where
+        C: AsyncRead + AsyncWrite + Unpin,
+        U: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let (mut upstream_read, mut upstream_write) = tokio::io::split(upstream);
+        if !opening.is_empty() {
+            self.hold();
+            self.write(&mut upstream_write, opening).await?;
+        }
+        let pumps = async {
+            tokio::try_join!(
+                self.pump(&mut client_read, &mut upstream_write, Direction::ToUpstream),
+                self.pump(&mut upstream_read, &mut client_write, Direction::ToClient),
+            )
+        };
+        let (to_upstream, to_client) = tokio::select! {
+            result = pumps => result?,
+            never = self.watch_throughput() => match never {},
+        };
+        Ok(RelayStats {
+            client_to_upstream: to_upstream + opening.len() as u64,
+            upstream_to_client: to_client,
+        })
+    }
+
+    /// Copies `reader` to `writer` until EOF, then shuts `writer` down. Returns the bytes copied.
+    async fn pump<R, W>(&self, reader: &mut R, writer: &mut W, direction: Direction) -> io::Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+        let mut total = 0u64;
+        let mut shaper = self.video_shaping.filter(|_| direction == Direction::ToClient).map(VideoShaper::new);
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                writer.shutdown().await?;
+                return Ok(total);
+            }
+            if direction == Direction::ToUpstream {
+                self.counters.add_bytes_in(n);
+                if let Some(connection) = &self.connection {
+                    connection.add_bytes_in(n);
+                }
+            }
+            self.hold();
+            if let Some(shaper) = &mut shaper {
+                self.paced(shaper.pace(n)).await;
+            }
+            self.write(writer, &buf[..n]).await?;
+            if direction == Direction::ToClient {
+                self.counters.add_bytes_out(n);
+                if let Some(connection) = &self.connection {
+                    connection.add_bytes_out(n);
+                }
+            }
+            total += n as u64;
+        }
+    }
+
+    /// Writes `data`, which the caller has marked as held, releasing it once the write starts.
+    async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W, data: &[u8]) -> io::Result<()> {
+        if let Some(bandwidth) = &self.bandwidth {
+            self.paced(bandwidth.acquire(data.len())).await;
+        }
+        self.release();
+        self.pending_writes.fetch_add(1, Ordering::Relaxed);
+        let written = writer.write_all(data).await;
+        self.pending_writes.fetch_sub(1, Ordering::Relaxed);
+        if let (Some(monitor), Ok(())) = (&self.progress, &written) {
+            monitor.record_progress();
+        }
+        written?;
+        self.relayed.fetch_add(data.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Runs `wait`, one of our own pacing delays, recording the time it takes.
+    async fn paced<F: Future<Output = ()>>(&self, wait: F) {
+        let start = Instant::now();
+        self.pacing.fetch_add(1, Ordering::Relaxed);
+        wait.await;
+        self.pacing.fetch_sub(1, Ordering::Relaxed);
+        self.paced_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Marks a chunk as read and not yet handed to a writer.
+    fn hold(&self) {
+        if let Some(monitor) = &self.progress {
+            self.held.fetch_add(1, Ordering::Relaxed);
+            monitor.set_pending(true);
+        }
+    }
+
+    /// Marks a held chunk as handed to its writer.
+    fn release(&self) {
+        if let Some(monitor) = &self.progress {
+            // Both pumps run on this task, so nothing holds a chunk between these two steps.
+            if self.held.fetch_sub(1, Ordering::Relaxed) == 1 {
+                monitor.set_pending(false);
+            }
+        }
+    }
+
+    /// Feeds the throughput monitor until the relay finishes and drops this future; never
+    /// resolves, and does nothing without a monitor.
+    async fn watch_throughput(&self) -> Infallible {
+        let Some(config) = self.throughput else {
+            return std::future::pending().await;
+        };
+        let mut monitor = ThroughputMonitor::new(config);
+        let mut ticker = tokio::time::interval(THROUGHPUT_SAMPLE_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+        let (mut last_relayed, mut last_paced) = (0, 0);
+        loop {
+            ticker.tick().await;
+            let relayed = self.relayed.load(Ordering::Relaxed);
+            let paced = self.paced_nanos.load(Ordering::Relaxed);
+            let moved = relayed - last_relayed;
+            let paced_for = Duration::from_nanos(paced - last_paced);
+            (last_relayed, last_paced) = (relayed, paced);
+            // An idle tunnel isn't a throttled one, but one whose writes are stuck is.
+            if moved == 0 && self.pending_writes.load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            // The rate our own pacing allowed says nothing about the network, so only the time
+            // spent outside it counts, and intervals that were mostly pacing are skipped.
+            let unpaced = THROUGHPUT_SAMPLE_INTERVAL.saturating_sub(paced_for);
+            if self.pacing.load(Ordering::Relaxed) > 0 || unpaced < THROUGHPUT_SAMPLE_INTERVAL / 10 {
+                continue;
+            }
+            let signal = monitor.record(moved as f64 / unpaced.as_secs_f64());
+            match signal {
+                Some(QualitySignal::Throttled { current_bps, baseline_bps }) => {
+                    self.counters.connection_throttled();
+                    warn!("Relay: Throughput fell to {:.0} B/s from a {:.0} B/s baseline", current_bps, baseline_bps);
+                }
+                Some(QualitySignal::Recovered) => debug!("Relay: Throughput recovered"),
+                None => continue,
+            }
+            if let (Some(signals), Some(signal)) = (&self.quality_signals, signal) {
+                let _ = signals.send(signal);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::connection_state::{ConnectionState, ConnectionTracker, TrackedConnection};
+    use crate::protocols::stall_detector::{StallDetector, StallDetectorConfig};
+    use crate::utils::bandwidth::{BandwidthConfig, BandwidthLimiter};
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn test_relays_both_directions_and_counts_bytes() {
+        let counters = Arc::new(ProtocolCounters::default());
+        let connection = Arc::new(ConnectionCounters::default());
+        let (client, mut client_peer) = tokio::io::duplex(64);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(64);
+        let relay = Relay::new(counters.clone()).with_connection_counters(connection.clone());
+        let relay = tokio::spawn(relay.run(client, upstream, b"hello"));
+
+        client_peer.write_all(b" world").await.unwrap();
+        client_peer.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        upstream_peer.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"hello world");
+
+        upstream_peer.write_all(b"pong").await.unwrap();
+        upstream_peer.shutdown().await.unwrap();
+        let mut reply = Vec::new();
+        client_peer.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"pong");
+
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!(stats, RelayStats { client_to_upstream: 11, upstream_to_client: 4 });
+        // The opening flight was counted by the handshake, not the relay.
+        assert_eq!(counters.snapshot().bytes_in, 6);
+        assert_eq!(counters.snapshot().bytes_out, 4);
+        assert_eq!((connection.snapshot().bytes_in, connection.snapshot().bytes_out), (6, 4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_writes_are_paced_by_the_bandwidth_limiter() {
+        let limiter = BandwidthLimiter::new(BandwidthConfig {
+            total_bytes_per_sec: 10_000,
+            max_connection_share: 1.0,
+        });
+        let relay = Relay::new(Arc::new(ProtocolCounters::default())).with_bandwidth(limiter.connection());
+        let (client, mut client_peer) = tokio::io::duplex(64 * 1024);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(64 * 1024);
+        let relay = tokio::spawn(relay.run(client, upstream, b""));
+
+        let start = Instant::now();
+        upstream_peer.write_all(&[7u8; 30_000]).await.unwrap();
+        upstream_peer.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        client_peer.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received.len(), 30_000);
+        // 30 KB at 10 KB/s, minus the initial 1 KB burst, needs at least 2.9s.
+        assert!(start.elapsed() >= Duration::from_millis(2_800), "relayed 30 KB in {:?}", start.elapsed());
+
+        client_peer.shutdown().await.unwrap();
+        assert_eq!(relay.await.unwrap().unwrap().upstream_to_client, 30_000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_video_shaping_paces_the_client_direction_only() {
+        let relay = Relay::new(Arc::new(ProtocolCounters::default())).with_video_shaping(VideoShapingConfig {
+            segment_interval: Duration::from_secs(10),
+            burst_bytes: 100,
+            burst_rate_bytes_per_sec: 1_000,
+            steady_rate_bytes_per_sec: 100,
+        });
+        let (client, mut client_peer) = tokio::io::duplex(1024);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(1024);
+        let relay = tokio::spawn(relay.run(client, upstream, b""));
+
+        let start = Instant::now();
+        client_peer.write_all(&[1u8; 100]).await.unwrap();
+        let mut buf = [0u8; 100];
+        upstream_peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // The first 100 bytes go at the burst rate, the next 100 at the steady rate.
+        for _ in 0..2 {
+            upstream_peer.write_all(&[2u8; 100]).await.unwrap();
+            client_peer.read_exact(&mut buf).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(1_100), "took {:?}", start.elapsed());
+
+        client_peer.shutdown().await.unwrap();
+        upstream_peer.shutdown().await.unwrap();
+        relay.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throughput_collapse_is_counted_as_throttling() {
+        let counters = Arc::new(ProtocolCounters::default());
+        let relay = Relay::new(counters.clone()).with_throughput_monitor(ThroughputMonitorConfig::default());
+        let (client, mut client_peer) = tokio::io::duplex(64 * 1024);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(64 * 1024);
+        let relay = tokio::spawn(relay.run(client, upstream, b""));
+        let reader = tokio::spawn(async move {
+            let mut sink = Vec::new();
+            client_peer.read_to_end(&mut sink).await.unwrap();
+            client_peer
+        });
+
+        // 30s at 100 KB/s, then 10s at 1 KB/s.
+        for (chunk, ticks) in [(10_000, 300), (100, 100)] {
+            for _ in 0..ticks {
+                upstream_peer.write_all(&vec![0u8; chunk]).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+        assert_eq!(counters.snapshot().throttle_events, 1);
+
+        upstream_peer.shutdown().await.unwrap();
+        let mut client_peer = reader.await.unwrap();
+        client_peer.shutdown().await.unwrap();
+        relay.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_video_shaping_is_not_counted_as_throttling() {
+        let counters = Arc::new(ProtocolCounters::default());
+        let relay = Relay::new(counters.clone())
+            .with_throughput_monitor(ThroughputMonitorConfig::default())
+            .with_video_shaping(VideoShapingConfig {
+                segment_interval: Duration::from_secs(10),
+                burst_bytes: 10_000,
+                burst_rate_bytes_per_sec: 10_000,
+                steady_rate_bytes_per_sec: 100,
+            });
+        let (client, mut client_peer) = tokio::io::duplex(64);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(64);
+        let relay = tokio::spawn(relay.run(client, upstream, b""));
+        let reader = tokio::spawn(async move {
+            let mut sink = Vec::new();
+            client_peer.read_to_end(&mut sink).await.unwrap();
+            client_peer
+        });
+
+        // The upstream always has data ready, so only the shaper sets the pace: a burst at the
+        // start of each segment, then a trickle a hundredth as fast.
+        let writer = tokio::spawn(async move {
+            loop {
+                if upstream_peer.write_all(&[0u8; 64]).await.is_err() {
+                    break;
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(counters.snapshot().throttle_events, 0);
+
+        writer.abort();
+        let mut client_peer = reader.await.unwrap();
+        client_peer.shutdown().await.unwrap();
+        let _ = relay.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blackholed_tunnel_signals_throttling() {
+        let counters = Arc::new(ProtocolCounters::default());
+        let (signals, mut received) = mpsc::unbounded_channel();
+        let relay = Relay::new(counters.clone())
+            .with_throughput_monitor(ThroughputMonitorConfig::default())
+            .with_quality_signals(signals);
+        let (client, mut client_peer) = tokio::io::duplex(4096);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(4096);
+        let _relay = tokio::spawn(relay.run(client, upstream, b""));
+        tokio::spawn(async move {
+            loop {
+                if upstream_peer.write_all(&[0u8; 1000]).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        });
+
+        // 20s of steady traffic, then the client side stops taking anything at all.
+        let mut buf = vec![0u8; 4096];
+        let reading = tokio::time::sleep(Duration::from_secs(20));
+        tokio::pin!(reading);
+        loop {
+            tokio::select! {
+                _ = &mut reading => break,
+                n = client_peer.read(&mut buf) => assert!(n.unwrap() > 0),
+            }
+        }
+        assert_eq!(counters.snapshot().throttle_events, 0);
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(counters.snapshot().throttle_events, 1);
+        assert!(matches!(received.try_recv(), Ok(QualitySignal::Throttled { .. })));
+        drop(client_peer);
+    }
+
+    fn stall_detector() -> StallDetector {
+        StallDetector::new(StallDetectorConfig {
+            stall_timeout: Duration::from_secs(30),
+            check_interval: Duration::from_secs(1),
+        })
+    }
+
+    fn tunneling(tracker: &ConnectionTracker) -> TrackedConnection {
+        let connection = tracker.register("198.51.100.9:40000".parse().unwrap());
+        for state in [ConnectionState::Handshaking, ConnectionState::Authenticated, ConnectionState::Tunneling] {
+            connection.transition(state).unwrap();
+        }
+        connection
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_relay_holding_data_without_progress_is_closed() {
+        // A bandwidth cap so low that the first chunk is never let through: the relay holds it forever.
+        let limiter = BandwidthLimiter::new(BandwidthConfig {
+            total_bytes_per_sec: 1,
+            max_connection_share: 1.0,
+        });
+        let detector = stall_detector();
+        let monitor = detector.monitor();
+        let relay = Relay::new(Arc::new(ProtocolCounters::default()))
+            .with_bandwidth(limiter.connection())
+            .with_progress_monitor(monitor.clone());
+        let (client, mut client_peer) = tokio::io::duplex(64 * 1024);
+        let (upstream, _upstream_peer) = tokio::io::duplex(64 * 1024);
+        client_peer.write_all(&[1u8; 10_000]).await.unwrap();
+
+        let tracker = ConnectionTracker::new();
+        let connection = tunneling(&tracker);
+        let start = Instant::now();
+        let result = detector.guard(&connection, &monitor, relay.run(client, upstream, b"")).await;
+        assert!(result.is_err());
+        assert!(start.elapsed() >= Duration::from_secs(30), "closed after {:?}", start.elapsed());
+        assert_eq!(connection.state(), ConnectionState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_peer_backpressure_is_not_a_stall() {
+        let detector = stall_detector();
+        let monitor = detector.monitor();
+        let relay = Relay::new(Arc::new(ProtocolCounters::default())).with_progress_monitor(monitor.clone());
+        let (client, mut client_peer) = tokio::io::duplex(64);
+        let (upstream, upstream_peer) = tokio::io::duplex(64);
+        let tracker = ConnectionTracker::new();
+        let connection = tunneling(&tracker);
+
+        // Upstream never reads, so the relay's write toward it stays blocked: that is
+        // backpressure from a slow peer, and the relay keeps waiting on it.
+        tokio::spawn(async move { client_peer.write_all(&[1u8; 200]).await });
+        let guarded = detector.guard(&connection, &monitor, relay.run(client, upstream, b""));
+        assert!(tokio::time::timeout(Duration::from_secs(120), guarded).await.is_err());
+        assert_eq!(connection.state(), ConnectionState::Tunneling);
+        drop(upstream_peer);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_relay_is_not_a_stall() {
+        let detector = stall_detector();
+        let monitor = detector.monitor();
+        let relay = Relay::new(Arc::new(ProtocolCounters::default())).with_progress_monitor(monitor.clone());
+        let (client, mut client_peer) = tokio::io::duplex(64);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(64);
+        let tracker = ConnectionTracker::new();
+        let connection = tunneling(&tracker);
+        let relay = tokio::spawn(async move { detector.guard(&connection, &monitor, relay.run(client, upstream, b"hi")).await });
+
+        let mut opening = [0u8; 2];
+        upstream_peer.read_exact(&mut opening).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        client_peer.shutdown().await.unwrap();
+        upstream_peer.shutdown().await.unwrap();
+        relay.await.unwrap().unwrap().unwrap();
+    }
+}
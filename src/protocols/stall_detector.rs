@@ -0,0 +1,213 @@
+//! This module detects connections that are open but no longer making progress.
+//! A bug in the relay or the obfuscation layer can leave both pumps parked with data
+//! still pending. Such connections hold resources forever and never show up as errors.
+//! The relay reports progress to a `ProgressMonitor`; `StallDetector::guard` runs the
+//! relay and force-closes it if neither direction moves for the configured period
+//! while data is pending, logging the connection's `ConnectionTracker` state. OTLS/WS
+//! guards every tunnel this way and counts each stall in its `stalled_connections` metric.
+//! A relay blocked on a peer that stopped reading isn't holding data (see
+//! `Relay::with_progress_monitor`), so backpressure never counts as a stall.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+
+use crate::protocols::{
+    common::ProtocolError,
+    connection_state::{ConnectionState, TrackedConnection},
+};
+
+/// `StallDetectorConfig` controls how long a connection may sit without progress.
+#[derive(Debug, Clone, Copy)]
+pub struct StallDetectorConfig {
+    /// A connection with pending data and no progress for this long is considered stalled.
+    pub stall_timeout: Duration,
+    /// How often the watchdog checks for progress.
+    pub check_interval: Duration,
+}
+
+impl Default for StallDetectorConfig {
+    fn default() -> Self {
+        StallDetectorConfig {
+            stall_timeout: Duration::from_secs(60),
+            check_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+struct MonitorInner {
+    last_progress: Mutex<Instant>,
+    pending: AtomicBool,
+}
+
+/// `ProgressMonitor` is updated by a connection's relay pumps.
+#[derive(Clone)]
+pub struct ProgressMonitor {
+    inner: Arc<MonitorInner>,
+}
+
+impl ProgressMonitor {
+    fn new() -> Self {
+        ProgressMonitor {
+            inner: Arc::new(MonitorInner {
+                last_progress: Mutex::new(Instant::now()),
+                pending: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Records that either direction moved data.
+    pub fn record_progress(&self) {
+        *self.inner.last_progress.lock().unwrap() = Instant::now();
+    }
+
+    /// Marks whether data is waiting to be relayed. Idle connections with nothing pending are never stalled.
+    pub fn set_pending(&self, pending: bool) {
+        self.inner.pending.store(pending, Ordering::Relaxed);
+    }
+
+    /// Time since the last recorded progress.
+    pub fn idle_for(&self) -> Duration {
+        self.inner.last_progress.lock().unwrap().elapsed()
+    }
+
+    fn is_stalled(&self, timeout: Duration) -> bool {
+        self.inner.pending.load(Ordering::Relaxed) && self.idle_for() >= timeout
+    }
+}
+
+/// `StallDetector` watches relays and closes the ones that stop making progress.
+#[derive(Clone)]
+pub struct StallDetector {
+    config: StallDetectorConfig,
+}
+
+impl StallDetector {
+    pub fn new(config: StallDetectorConfig) -> Self {
+        StallDetector { config }
+    }
+
+    /// Creates a progress monitor for a new connection.
+    pub fn monitor(&self) -> ProgressMonitor {
+        ProgressMonitor::new()
+    }
+
+    /// Resolves once `monitor` has had pending data and no progress for `stall_timeout`.
+    pub async fn wait_for_stall(&self, monitor: &ProgressMonitor) {
+        while !monitor.is_stalled(self.config.stall_timeout) {
+            sleep(self.config.check_interval).await;
+        }
+    }
+
+    /// Runs `relay` for `connection`. If it stalls, the relay future is dropped (closing
+    /// whatever sockets it owns), the connection is moved to `Closed`, and an error is returned
+    /// for the caller to count.
+    pub async fn guard<F: Future>(
+        &self,
+        connection: &TrackedConnection,
+        monitor: &ProgressMonitor,
+        relay: F,
+    ) -> Result<F::Output, ProtocolError> {
+        tokio::select! {
+            output = relay => Ok(output),
+            _ = self.wait_for_stall(monitor) => {
+                let idle = monitor.idle_for();
+                let state = connection.state();
+                warn!(
+                    "Connection {} stalled in state {} (no progress for {:?}), force-closing.",
+                    connection.id(),
+                    state,
+                    idle
+                );
+                if state != ConnectionState::Closed {
+                    let _ = connection.transition(ConnectionState::Closed);
+                }
+                Err(ProtocolError::Other(format!("connection {} stalled", connection.id())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::connection_state::ConnectionTracker;
+
+    fn detector() -> StallDetector {
+        StallDetector::new(StallDetectorConfig {
+            stall_timeout: Duration::from_secs(30),
+            check_interval: Duration::from_secs(1),
+        })
+    }
+
+    fn tunneling(tracker: &ConnectionTracker) -> TrackedConnection {
+        let connection = tracker.register("198.51.100.9:40000".parse().unwrap());
+        for state in [ConnectionState::Handshaking, ConnectionState::Authenticated, ConnectionState::Tunneling] {
+            connection.transition(state).unwrap();
+        }
+        connection
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_relay_is_detected_and_closed() {
+        let detector = detector();
+        let tracker = ConnectionTracker::new();
+        let connection = tunneling(&tracker);
+        let monitor = detector.monitor();
+
+        // Both pumps are parked with data pending and never make progress.
+        let relay_monitor = monitor.clone();
+        let stalled_relay = async move {
+            relay_monitor.set_pending(true);
+            std::future::pending::<()>().await
+        };
+
+        let start = Instant::now();
+        let result = detector.guard(&connection, &monitor, stalled_relay).await;
+        assert!(result.is_err());
+        assert!(start.elapsed() >= Duration::from_secs(30));
+        assert!(start.elapsed() < Duration::from_secs(32));
+        assert_eq!(connection.state(), ConnectionState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_progressing_relay_is_left_alone() {
+        let detector = detector();
+        let tracker = ConnectionTracker::new();
+        let connection = tunneling(&tracker);
+        let monitor = detector.monitor();
+
+        let relay_monitor = monitor.clone();
+        let relay = async move {
+            relay_monitor.set_pending(true);
+            for _ in 0..10 {
+                sleep(Duration::from_secs(20)).await;
+                relay_monitor.record_progress();
+            }
+            42
+        };
+
+        assert_eq!(detector.guard(&connection, &monitor, relay).await.unwrap(), 42);
+        assert_eq!(connection.state(), ConnectionState::Tunneling);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_connection_without_pending_data_is_not_stalled() {
+        let detector = detector();
+        let tracker = ConnectionTracker::new();
+        let connection = tunneling(&tracker);
+        let monitor = detector.monitor();
+
+        let idle = async {
+            sleep(Duration::from_secs(600)).await;
+        };
+        assert!(detector.guard(&connection, &monitor, idle).await.is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+//! This module watches a connection's throughput for sudden collapses.
+//! Censors often throttle a flow rather than block it outright; a sharp drop in
+//! throughput mid-connection is the usual symptom. The monitor compares a fast-moving
+//! EWMA against a slow one and raises a `QualitySignal` when the fast one falls
+//! below a configured fraction of the recent average.
+//!
+//! The relay (see `relay.rs`) feeds each connection's monitor once per sampling interval,
+//! counts every `Throttled` signal in the protocol's `throttle_events` metric and passes the
+//! signals on to the tunnel's adaptive controller, which for OTLS/WS mutates the obfuscation.
+
+/// A connection-quality event reported to the adaptive controller and metrics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualitySignal {
+    /// Throughput fell sharply below its recent average.
+    Throttled {
+        /// Short-term throughput, in bytes per second.
+        current_bps: f64,
+        /// Longer-term average it was compared against, in bytes per second.
+        baseline_bps: f64,
+    },
+    /// Throughput recovered after a `Throttled` signal.
+    Recovered,
+}
+
+/// `ThroughputMonitorConfig` tunes how eagerly throttling is reported.
+#[derive(Debug, Clone, Copy)]
+pub struct ThroughputMonitorConfig {
+    /// Smoothing factor of the fast EWMA (closer to 1.0 reacts faster).
+    pub short_alpha: f64,
+    /// Smoothing factor of the slow EWMA that acts as the baseline.
+    pub long_alpha: f64,
+    /// The fast EWMA must fall below this fraction of the baseline to count as throttling.
+    pub drop_fraction: f64,
+    /// Samples required before any signal is raised.
+    pub warmup_samples: u32,
+}
+
+impl Default for ThroughputMonitorConfig {
+    fn default() -> Self {
+        ThroughputMonitorConfig {
+            short_alpha: 0.5,
+            long_alpha: 0.05,
+            drop_fraction: 0.3,
+            warmup_samples: 10,
+        }
+    }
+}
+
+/// `ThroughputMonitor` tracks one connection's throughput samples.
+pub struct ThroughputMonitor {
+    config: ThroughputMonitorConfig,
+    short_ewma: f64,
+    long_ewma: f64,
+    samples: u32,
+    throttled: bool,
+    throttle_events: u64,
+}
+
+impl ThroughputMonitor {
+    /// Creates a monitor with no history.
+    pub fn new(config: ThroughputMonitorConfig) -> Self {
+        ThroughputMonitor {
+            config,
+            short_ewma: 0.0,
+            long_ewma: 0.0,
+            samples: 0,
+            throttled: false,
+            throttle_events: 0,
+        }
+    }
+
+    /// Feeds one throughput sample (bytes per second over the last interval).
+    /// Returns a signal only when the throttled/recovered state changes.
+    pub fn record(&mut self, bytes_per_sec: f64) -> Option<QualitySignal> {
+        if self.samples == 0 {
+            self.short_ewma = bytes_per_sec;
+            self.long_ewma = bytes_per_sec;
+        } else {
+            self.short_ewma += self.config.short_alpha * (bytes_per_sec - self.short_ewma);
+            // Freeze the baseline while throttled so the collapse doesn't become the new normal.
+            if !self.throttled {
+                self.long_ewma += self.config.long_alpha * (bytes_per_sec - self.long_ewma);
+            }
+        }
+        self.samples = self.samples.saturating_add(1);
+
+        if self.samples < self.config.warmup_samples || self.long_ewma <= 0.0 {
+            return None;
+        }
+
+        let threshold = self.long_ewma * self.config.drop_fraction;
+        if !self.throttled && self.short_ewma < threshold {
+            self.throttled = true;
+            self.throttle_events += 1;
+            return Some(QualitySignal::Throttled {
+                current_bps: self.short_ewma,
+                baseline_bps: self.long_ewma,
+            });
+        }
+        // Require a clear recovery (twice the threshold) to avoid flapping.
+        if self.throttled && self.short_ewma >= threshold * 2.0 {
+            self.throttled = false;
+            return Some(QualitySignal::Recovered);
+        }
+        None
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// Number of `Throttled` signals raised so far, for metrics.
+    pub fn throttle_events(&self) -> u64 {
+        self.throttle_events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput_collapse_raises_throttled() {
+        let mut monitor = ThroughputMonitor::new(ThroughputMonitorConfig::default());
+        let mut signals = Vec::new();
+
+        let healthy = (0..30).map(|i| 1_000_000.0 + if i % 2 == 0 { 50_000.0 } else { -50_000.0 });
+        let collapsed = std::iter::repeat_n(40_000.0, 10);
+        for sample in healthy.chain(collapsed) {
+            if let Some(signal) = monitor.record(sample) {
+                signals.push(signal);
+            }
+        }
+
+        assert_eq!(signals.len(), 1);
+        match signals[0] {
+            QualitySignal::Throttled { current_bps, baseline_bps } => {
+                assert!(current_bps < baseline_bps * 0.3);
+                assert!(baseline_bps > 800_000.0);
+            }
+            other => panic!("unexpected signal {:?}", other),
+        }
+        assert!(monitor.is_throttled());
+        assert_eq!(monitor.throttle_events(), 1);
+
+        // Throughput coming back clears the state.
+        let recovered = (0..10).filter_map(|_| monitor.record(1_000_000.0)).collect::<Vec<_>>();
+        assert_eq!(recovered, vec![QualitySignal::Recovered]);
+    }
+
+    #[test]
+    fn test_steady_throughput_raises_nothing() {
+        let mut monitor = ThroughputMonitor::new(ThroughputMonitorConfig::default());
+        for i in 0..200 {
+            // +-40% jitter around 1 MB/s.
+            let sample = 1_000_000.0 * (1.0 + 0.4 * ((i % 5) as f64 - 2.0) / 2.0);
+            assert_eq!(monitor.record(sample), None);
+        }
+        assert!(!monitor.is_throttled());
+    }
+
+    #[test]
+    fn test_no_signal_during_warmup() {
+        let mut monitor = ThroughputMonitor::new(ThroughputMonitorConfig::default());
+        monitor.record(1_000_000.0);
+        for _ in 0..5 {
+            assert_eq!(monitor.record(0.0), None);
+        }
+    }
+}
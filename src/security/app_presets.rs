@@ -0,0 +1,171 @@
+//! This module bundles obfuscation presets that imitate one specific, widely allowed app
+//! instead of generic web traffic. Where a censor allows a popular messenger or video site
+//! but throttles everything it can't classify, looking like that app is the better disguise.
+//!
+//! An `AppPreset` fixes the cover (`MimicryProfile`, including the server name the app's
+//! handshake shows), the packet sizes (`PaddingScheme`) and the send jitter (`TimingPolicy`).
+//! `app_preset` in the configuration selects one by name for every OTLS/WS tunnel; it is
+//! carried in `TunnelConfig.protocol_params` like the obfuscation tier and takes precedence
+//! over it.
+
+use std::collections::HashMap;
+
+use crate::security::traffic_obfuscation::{
+    BucketPadding, HttpMimicry, KeystreamMask, MimicryStyle, NoisePadding, ObfuscationStrategy, Obfuscator,
+    ObfuscatorConfig, TlsHelloMimicry,
+};
+
+/// Key the preset name is stored under in `TunnelConfig.protocol_params`.
+pub const APP_PRESET_PARAM: &str = "app_preset";
+
+/// `MimicryProfile` is the cover an app's packets are dressed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MimicryProfile {
+    pub style: MimicryStyle,
+    /// Probability (0.0..=1.0) of covering a packet.
+    pub probability: f64,
+    /// Server name the app's handshake shows: the SNI of a TLS cover, the `Host` of an HTTP one.
+    pub server_name: &'static str,
+}
+
+/// `PaddingScheme` is how packet sizes are blurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingScheme {
+    /// Up to `max_bytes` (exclusive) of random noise per packet.
+    Noise { max_bytes: usize },
+    /// Every frame padded up to the next of these sizes.
+    Buckets(&'static [usize]),
+}
+
+/// `TimingPolicy` is the jitter added before each packet is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingPolicy {
+    /// Maximum random delay per packet, in milliseconds (exclusive upper bound).
+    pub max_delay_ms: u64,
+}
+
+/// `AppPreset` is everything needed to imitate one app.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AppPreset {
+    /// Name the preset is selected by.
+    pub name: &'static str,
+    pub mimicry: MimicryProfile,
+    pub padding: PaddingScheme,
+    pub timing: TimingPolicy,
+}
+
+/// The bundled presets.
+const PRESETS: [AppPreset; 2] = [
+    // A messenger: every packet opens like a TLS connection to its chat service, and message
+    // sizes collapse onto a few buckets, with little jitter so chats stay responsive.
+    AppPreset {
+        name: "teams",
+        mimicry: MimicryProfile {
+            style: MimicryStyle::TlsClientHello,
+            probability: 1.0,
+            server_name: "teams.microsoft.com",
+        },
+        padding: PaddingScheme::Buckets(&[256, 512, 1200]),
+        timing: TimingPolicy { max_delay_ms: 20 },
+    },
+    // A video site: segment requests to its CDN, sized loosely, with the jitter of a player
+    // fetching on its own schedule.
+    AppPreset {
+        name: "aparat",
+        mimicry: MimicryProfile {
+            style: MimicryStyle::Http,
+            probability: 0.5,
+            server_name: "www.aparat.com",
+        },
+        padding: PaddingScheme::Noise { max_bytes: 256 },
+        timing: TimingPolicy { max_delay_ms: 80 },
+    },
+];
+
+impl AppPreset {
+    /// The preset called `name`, ignoring case.
+    pub fn lookup(name: &str) -> Option<&'static AppPreset> {
+        PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Names of every bundled preset.
+    pub fn names() -> Vec<&'static str> {
+        PRESETS.iter().map(|preset| preset.name).collect()
+    }
+
+    /// Reads the preset from connection parameters, if one was requested.
+    pub fn from_params(params: &HashMap<String, String>) -> Option<&'static AppPreset> {
+        Self::lookup(params.get(APP_PRESET_PARAM)?)
+    }
+
+    /// Builds an `Obfuscator` for this preset: keystream masking (if `key` is given), then the
+    /// padding, with the cover around the whole frame.
+    pub fn obfuscator(&self, key: Option<&[u8]>) -> Obfuscator {
+        let mut strategies: Vec<Box<dyn ObfuscationStrategy>> = Vec::new();
+        if let Some(key) = key {
+            strategies.push(Box::new(KeystreamMask::new(key)));
+        }
+        match self.padding {
+            PaddingScheme::Noise { max_bytes } => strategies.push(Box::new(NoisePadding::new(max_bytes))),
+            PaddingScheme::Buckets(sizes) => strategies.push(Box::new(BucketPadding::new(sizes.to_vec()))),
+        }
+        let MimicryProfile { style, probability, server_name } = self.mimicry;
+        match style {
+            MimicryStyle::Http => strategies.push(Box::new(HttpMimicry::with_host(probability, server_name))),
+            MimicryStyle::TlsClientHello => strategies.push(Box::new(TlsHelloMimicry::new(probability, server_name))),
+        }
+        let config = ObfuscatorConfig {
+            max_noise_bytes: 0,
+            mimicry_probability: probability,
+            mimicry_style: style,
+            max_delay_ms: self.timing.max_delay_ms,
+        };
+        Obfuscator::with_strategies(config, key, strategies).expect("preset strategies have distinct ids")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::traffic_obfuscation::{
+        STRATEGY_HTTP_MIMICRY, STRATEGY_KEYSTREAM, STRATEGY_NOISE, STRATEGY_SIZE_BUCKETS, STRATEGY_TLS_MIMICRY,
+    };
+
+    #[test]
+    fn test_presets_configure_their_pipeline() {
+        let cases = [
+            ("teams", (1 << STRATEGY_SIZE_BUCKETS) | (1 << STRATEGY_TLS_MIMICRY), 20),
+            ("aparat", (1 << STRATEGY_NOISE) | (1 << STRATEGY_HTTP_MIMICRY), 80),
+        ];
+        assert_eq!(AppPreset::names(), ["teams", "aparat"]);
+        for (name, strategies, max_delay_ms) in cases {
+            let preset = AppPreset::lookup(name).unwrap();
+            let obfuscator = preset.obfuscator(None);
+            assert_eq!(obfuscator.strategy_mask(), strategies, "{}", name);
+            assert_eq!(obfuscator.current_params().max_delay_ms, max_delay_ms, "{}", name);
+            let keyed = preset.obfuscator(Some(b"user-key"));
+            assert_eq!(keyed.strategy_mask(), strategies | (1 << STRATEGY_KEYSTREAM), "{}", name);
+            for obfuscator in [obfuscator, keyed] {
+                let packet = obfuscator.transform(b"payload");
+                assert_eq!(obfuscator.deobfuscate_data(&packet).unwrap(), b"payload", "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_messenger_preset_shows_the_app_handshake() {
+        let obfuscator = AppPreset::lookup("Teams").unwrap().obfuscator(Some(b"user-key"));
+        let packet = obfuscator.transform(&[0x5A; 300]);
+        // Every packet opens with a ClientHello naming the app's server.
+        assert_eq!(packet[0], 0x16);
+        assert!(packet.windows(19).any(|window| window == b"teams.microsoft.com"));
+    }
+
+    #[test]
+    fn test_preset_is_read_from_params() {
+        let params: HashMap<String, String> = [(APP_PRESET_PARAM.to_string(), "aparat".to_string())].into();
+        assert_eq!(AppPreset::from_params(&params).map(|preset| preset.name), Some("aparat"));
+        assert!(AppPreset::from_params(&HashMap::new()).is_none());
+        assert!(AppPreset::lookup("skype").is_none());
+    }
+}
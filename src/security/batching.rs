@@ -0,0 +1,221 @@
+//! This module batches several small payloads into a single frame before obfuscation.
+//! Individually sent small datagrams (DNS, game state updates, ...) have very
+//! recognisable sizes and rates. Coalescing the ones that arrive within a short
+//! window reduces the packet count and evens out the size distribution.
+//!
+//! Batch frame layout: `[count: u16][len: u16][payload]...`, all big-endian.
+//!
+//! NOTE: Library-only for now. The server doesn't batch anything: AOQUIC doesn't forward
+//! datagrams yet, so there are no small payloads to coalesce.
+
+use std::{io, time::Duration};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+/// Bytes used by the frame's packet count.
+const COUNT_PREFIX_LEN: usize = 2;
+/// Bytes used by each packet's length prefix.
+const LEN_PREFIX_LEN: usize = 2;
+
+/// `BatchConfig` controls how payloads are grouped.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// How long to wait for more payloads after the first one arrives.
+    pub batch_window: Duration,
+    /// Maximum size of an encoded batch frame.
+    pub max_batch_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            batch_window: Duration::from_millis(5),
+            max_batch_bytes: 1200,
+        }
+    }
+}
+
+/// Encodes `payloads` into a single length-delimited batch frame.
+/// Each payload must fit in a `u16` length prefix.
+pub fn encode_batch(payloads: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    if payloads.len() > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many payloads in one batch"));
+    }
+    let body: usize = payloads.iter().map(|p| LEN_PREFIX_LEN + p.len()).sum();
+    let mut frame = Vec::with_capacity(COUNT_PREFIX_LEN + body);
+    frame.extend_from_slice(&(payloads.len() as u16).to_be_bytes());
+    for payload in payloads {
+        if payload.len() > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "payload too large to batch"));
+        }
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(payload);
+    }
+    Ok(frame)
+}
+
+/// Splits a batch frame produced by `encode_batch` back into its payloads.
+pub fn split_batch(frame: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "truncated batch frame");
+
+    let count_bytes = frame.get(..COUNT_PREFIX_LEN).ok_or_else(truncated)?;
+    let count = u16::from_be_bytes([count_bytes[0], count_bytes[1]]) as usize;
+
+    let mut payloads = Vec::with_capacity(count);
+    let mut offset = COUNT_PREFIX_LEN;
+    for _ in 0..count {
+        let len_bytes = frame.get(offset..offset + LEN_PREFIX_LEN).ok_or_else(truncated)?;
+        let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+        offset += LEN_PREFIX_LEN;
+        let payload = frame.get(offset..offset + len).ok_or_else(truncated)?;
+        payloads.push(payload.to_vec());
+        offset += len;
+    }
+    if offset != frame.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "trailing bytes after batch frame"));
+    }
+    Ok(payloads)
+}
+
+/// `PacketBatcher` accumulates payloads and emits encoded batch frames.
+pub struct PacketBatcher {
+    config: BatchConfig,
+    pending: Vec<Vec<u8>>,
+    pending_bytes: usize,
+}
+
+impl PacketBatcher {
+    /// Creates a new, empty batcher.
+    pub fn new(config: BatchConfig) -> Self {
+        PacketBatcher {
+            config,
+            pending: Vec::new(),
+            pending_bytes: COUNT_PREFIX_LEN,
+        }
+    }
+
+    /// Adds a payload. If it does not fit in the current batch, the current batch is
+    /// returned as a finished frame and the payload starts the next one.
+    pub fn push(&mut self, payload: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let added = LEN_PREFIX_LEN + payload.len();
+        let ready = if !self.pending.is_empty() && self.pending_bytes + added > self.config.max_batch_bytes {
+            self.flush()?
+        } else {
+            None
+        };
+        self.pending_bytes += added;
+        self.pending.push(payload);
+        Ok(ready)
+    }
+
+    /// Encodes and returns everything currently pending, if anything.
+    pub fn flush(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let frame = encode_batch(&self.pending)?;
+        self.pending.clear();
+        self.pending_bytes = COUNT_PREFIX_LEN;
+        Ok(Some(frame))
+    }
+
+    /// Number of payloads waiting to be flushed.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drives the batcher: reads payloads from `input` and sends encoded frames to `output`.
+    /// A batch is flushed when the window since its first payload elapses or it fills up.
+    /// Returns when `input` is closed (after flushing) or `output` is dropped.
+    pub async fn run(mut self, mut input: mpsc::Receiver<Vec<u8>>, output: mpsc::Sender<Vec<u8>>) -> io::Result<()> {
+        while let Some(first) = input.recv().await {
+            if let Some(frame) = self.push(first)? {
+                if output.send(frame).await.is_err() {
+                    return Ok(());
+                }
+            }
+            let deadline = Instant::now() + self.config.batch_window;
+            loop {
+                tokio::select! {
+                    _ = sleep_until(deadline) => break,
+                    next = input.recv() => match next {
+                        Some(payload) => {
+                            if let Some(frame) = self.push(payload)? {
+                                if output.send(frame).await.is_err() {
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        None => break,
+                    },
+                }
+            }
+            if let Some(frame) = self.flush()? {
+                if output.send(frame).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_split_round_trip() {
+        let payloads = vec![b"dns query".to_vec(), Vec::new(), vec![7u8; 300], b"x".to_vec()];
+        let frame = encode_batch(&payloads).unwrap();
+        assert_eq!(split_batch(&frame).unwrap(), payloads);
+    }
+
+    #[test]
+    fn test_split_rejects_truncated_frames() {
+        let frame = encode_batch(&[b"hello".to_vec(), b"world".to_vec()]).unwrap();
+        for cut in 0..frame.len() {
+            assert!(split_batch(&frame[..cut]).is_err(), "accepted frame cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn test_push_respects_max_batch_bytes() {
+        let mut batcher = PacketBatcher::new(BatchConfig {
+            batch_window: Duration::from_millis(5),
+            max_batch_bytes: 32,
+        });
+        assert!(batcher.push(vec![1u8; 10]).unwrap().is_none());
+        assert!(batcher.push(vec![2u8; 10]).unwrap().is_none());
+        // 2 + 12 + 12 + 12 > 32, so the first two come out together.
+        let frame = batcher.push(vec![3u8; 10]).unwrap().unwrap();
+        assert!(frame.len() <= 32);
+        assert_eq!(split_batch(&frame).unwrap(), vec![vec![1u8; 10], vec![2u8; 10]]);
+        assert_eq!(batcher.pending_len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_batches_payloads_within_window() {
+        let (in_tx, in_rx) = mpsc::channel(16);
+        let (out_tx, mut out_rx) = mpsc::channel(16);
+        let batcher = PacketBatcher::new(BatchConfig {
+            batch_window: Duration::from_millis(20),
+            max_batch_bytes: 1200,
+        });
+        let task = tokio::spawn(batcher.run(in_rx, out_tx));
+
+        let payloads: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 8 + i as usize]).collect();
+        for payload in &payloads {
+            in_tx.send(payload.clone()).await.unwrap();
+        }
+
+        let frame = out_rx.recv().await.unwrap();
+        assert_eq!(split_batch(&frame).unwrap(), payloads);
+
+        drop(in_tx);
+        task.await.unwrap().unwrap();
+        assert!(out_rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,242 @@
+//! This module connects the Kill Switch to platform firewall rules.
+//! When the Kill Switch is `Triggered`, a `FirewallBackend` installs rules that block
+//! all traffic except to the tunnel server; when it returns to `Active` (or is
+//! disabled) the rules are removed again.
+//! NOTE: Real backends modify the host firewall and require root / CAP_NET_ADMIN.
+//! The nftables backend is only compiled with the `firewall` feature on Linux; the server
+//! runs it when `kill_switch.firewall_table` is configured.
+
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::security::kill_switch::{KillSwitchManager, KillSwitchState};
+
+/// A platform firewall that can block everything except the tunnel endpoint.
+pub trait FirewallBackend: Send + Sync {
+    /// Blocks all outbound traffic except to `tunnel_server`.
+    fn block_all_except(&self, tunnel_server: SocketAddr) -> io::Result<()>;
+    /// Removes any rules installed by `block_all_except`.
+    fn unblock(&self) -> io::Result<()>;
+}
+
+/// Tracks whether rules are installed so block/unblock are only issued on real changes.
+pub struct FirewallHook {
+    backend: Arc<dyn FirewallBackend>,
+    tunnel_server: SocketAddr,
+    blocked: bool,
+}
+
+impl FirewallHook {
+    /// Creates a hook that will protect traffic to `tunnel_server`.
+    pub fn new(backend: Arc<dyn FirewallBackend>, tunnel_server: SocketAddr) -> Self {
+        FirewallHook {
+            backend,
+            tunnel_server,
+            blocked: false,
+        }
+    }
+
+    /// Applies the firewall action for a Kill Switch state.
+    pub fn apply(&mut self, state: KillSwitchState) -> io::Result<()> {
+        match state {
+            KillSwitchState::Triggered if !self.blocked => {
+                self.backend.block_all_except(self.tunnel_server)?;
+                self.blocked = true;
+                info!("Kill Switch: Firewall rules installed (only {} allowed)", self.tunnel_server);
+            }
+            KillSwitchState::Active | KillSwitchState::Disabled if self.blocked => {
+                self.backend.unblock()?;
+                self.blocked = false;
+                info!("Kill Switch: Firewall rules removed");
+            }
+            // Still within the grace period: keep whatever rules are in place.
+            KillSwitchState::Reconnecting => {}
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Spawns a task that follows `manager`'s state and applies it to the firewall.
+    /// The task ends when the manager's state channel closes.
+    pub fn spawn(mut self, manager: &KillSwitchManager) -> JoinHandle<()> {
+        let mut receiver = manager.subscribe_state();
+        tokio::spawn(async move {
+            loop {
+                let state = *receiver.borrow_and_update();
+                if let Err(e) = self.apply(state) {
+                    warn!("Kill Switch: Failed to apply firewall rules for {:?}: {}", state, e);
+                }
+                if receiver.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// `NftablesBackend` installs a dedicated `inet` table with a drop-by-default output chain.
+#[cfg(all(target_os = "linux", feature = "firewall"))]
+pub struct NftablesBackend {
+    table: String,
+}
+
+#[cfg(all(target_os = "linux", feature = "firewall"))]
+impl NftablesBackend {
+    /// Creates a backend that manages the nftables table `table`.
+    pub fn new(table: &str) -> Self {
+        NftablesBackend {
+            table: table.to_string(),
+        }
+    }
+
+    /// Builds the nft script that blocks everything except loopback and `tunnel_server`.
+    pub fn ruleset(&self, tunnel_server: SocketAddr) -> String {
+        let family = if tunnel_server.is_ipv4() { "ip" } else { "ip6" };
+        format!(
+            "table inet {table} {{\n\
+             \x20 chain output {{\n\
+             \x20   type filter hook output priority 0; policy drop;\n\
+             \x20   oif \"lo\" accept\n\
+             \x20   {family} daddr {ip} tcp dport {port} accept\n\
+             \x20   {family} daddr {ip} udp dport {port} accept\n\
+             \x20 }}\n\
+             }}\n",
+            table = self.table,
+            family = family,
+            ip = tunnel_server.ip(),
+            port = tunnel_server.port(),
+        )
+    }
+
+    fn run_nft(&self, args: &[&str], stdin: Option<&str>) -> io::Result<()> {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let mut child = Command::new("nft")
+            .args(args)
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .spawn()?;
+        if let (Some(script), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            pipe.write_all(script.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("nft {:?} exited with {}", args, status)))
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "firewall"))]
+impl FirewallBackend for NftablesBackend {
+    fn block_all_except(&self, tunnel_server: SocketAddr) -> io::Result<()> {
+        self.run_nft(&["-f", "-"], Some(&self.ruleset(tunnel_server)))
+    }
+
+    fn unblock(&self) -> io::Result<()> {
+        self.run_nft(&["delete", "table", "inet", &self.table], None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Mutex, time::Duration};
+
+    #[derive(Default)]
+    struct MockBackend {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl FirewallBackend for MockBackend {
+        fn block_all_except(&self, tunnel_server: SocketAddr) -> io::Result<()> {
+            self.calls.lock().unwrap().push(format!("block except {}", tunnel_server));
+            Ok(())
+        }
+
+        fn unblock(&self) -> io::Result<()> {
+            self.calls.lock().unwrap().push("unblock".to_string());
+            Ok(())
+        }
+    }
+
+    fn server() -> SocketAddr {
+        "203.0.113.5:8443".parse().unwrap()
+    }
+
+    #[test]
+    fn test_hook_blocks_and_unblocks_on_transitions() {
+        let backend = Arc::new(MockBackend::default());
+        let mut hook = FirewallHook::new(backend.clone(), server());
+
+        hook.apply(KillSwitchState::Active).unwrap();
+        hook.apply(KillSwitchState::Reconnecting).unwrap(); // Grace period doesn't block yet.
+        assert!(!hook.is_blocked());
+        hook.apply(KillSwitchState::Triggered).unwrap();
+        hook.apply(KillSwitchState::Triggered).unwrap(); // Repeated state is a no-op.
+        hook.apply(KillSwitchState::Reconnecting).unwrap(); // Nor does it lift a block.
+        assert!(hook.is_blocked());
+        hook.apply(KillSwitchState::Active).unwrap();
+        hook.apply(KillSwitchState::Triggered).unwrap();
+        hook.apply(KillSwitchState::Disabled).unwrap();
+
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec![
+                "block except 203.0.113.5:8443",
+                "unblock",
+                "block except 203.0.113.5:8443",
+                "unblock",
+            ]
+        );
+        assert!(!hook.is_blocked());
+    }
+
+    #[tokio::test]
+    async fn test_spawned_hook_follows_kill_switch() {
+        let manager = KillSwitchManager::new(true);
+        let backend = Arc::new(MockBackend::default());
+        let _task = FirewallHook::new(backend.clone(), server()).spawn(&manager);
+
+        let wait_for = |n: usize| {
+            let backend = backend.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(1), async {
+                    while backend.calls.lock().unwrap().len() < n {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                })
+                .await
+                .expect("firewall hook did not react in time");
+            }
+        };
+
+        manager.set_state(KillSwitchState::Active).unwrap();
+        manager.set_state(KillSwitchState::Triggered).unwrap();
+        wait_for(1).await;
+        manager.set_state(KillSwitchState::Active).unwrap();
+        wait_for(2).await;
+
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec!["block except 203.0.113.5:8443", "unblock"]
+        );
+    }
+
+    #[cfg(all(target_os = "linux", feature = "firewall"))]
+    #[test]
+    fn test_nftables_ruleset_allows_only_tunnel_server() {
+        let backend = NftablesBackend::new("hezardastan_killswitch");
+        let rules = backend.ruleset(server());
+        assert!(rules.contains("table inet hezardastan_killswitch"));
+        assert!(rules.contains("policy drop;"));
+        assert!(rules.contains("ip daddr 203.0.113.5 tcp dport 8443 accept"));
+        assert!(!rules.contains("ip6 daddr"));
+    }
+}
@@ -0,0 +1,239 @@
+//! This module pads handshake messages to the sizes real browsers send to the mimic domain.
+//! Padding data frames hides the tunnel's packet sizes, but the handshake is the first thing a
+//! censor sees: a ClientHello or WebSocket upgrade request that is always the same unusual
+//! length is a fingerprint of its own.
+//!
+//! A ClientHello grows through the TLS padding extension (RFC 7685), as Chrome pads its own;
+//! a WebSocket upgrade request grows through an extra header. `HandshakeSizes::for_domain`
+//! gives the target sizes for a mimic domain. The `tls-mimicry` transform pads its cover
+//! ClientHellos this way when its `pad_handshake` parameter is set.
+
+use rand::Rng;
+use std::io;
+
+/// Extension type of the TLS padding extension.
+const PADDING_EXTENSION: u16 = 0x0015;
+/// Largest plaintext a TLS record may carry.
+const MAX_RECORD_BODY: usize = 16384;
+/// Header added to pad a WebSocket upgrade request; Chrome sends it to Google's own sites.
+const WS_PADDING_HEADER: &[u8] = b"X-Client-Data: ";
+
+/// `HandshakeSizes` is the sizes a mimic domain's handshakes come in. Each size in a list is
+/// equally likely, so padded handshakes spread over the same few lengths browsers produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeSizes {
+    /// Sizes of the ClientHello record, header included.
+    pub client_hello: &'static [usize],
+    /// Sizes of the WebSocket upgrade request, up to and including its blank line.
+    pub ws_upgrade: &'static [usize],
+}
+
+/// Sizes for domains without their own entry: Chrome's usual 517-byte ClientHello, Firefox's,
+/// and the larger ones post-quantum key shares give, with upgrade requests carrying a few cookies.
+const DEFAULT_SIZES: HandshakeSizes = HandshakeSizes {
+    client_hello: &[517, 583, 652, 1797],
+    ws_upgrade: &[420, 512, 640],
+};
+
+/// Domains whose upgrade requests carry noticeably more cookies and headers than the default.
+const DOMAIN_SIZES: [(&str, HandshakeSizes); 4] = [
+    ("www.aparat.com", HandshakeSizes { client_hello: &[517, 583, 1797], ws_upgrade: &[610, 744, 903] }),
+    ("www.microsoft.com", HandshakeSizes { client_hello: &[517, 652, 1797], ws_upgrade: &[980, 1210, 1475] }),
+    ("www.yandex.ru", HandshakeSizes { client_hello: &[517, 583, 1797], ws_upgrade: &[720, 866, 1032] }),
+    ("www.trendyol.com", HandshakeSizes { client_hello: &[517, 583, 652], ws_upgrade: &[690, 812, 958] }),
+];
+
+impl HandshakeSizes {
+    /// The sizes for `domain`, ignoring case.
+    pub fn for_domain(domain: &str) -> &'static HandshakeSizes {
+        DOMAIN_SIZES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(domain))
+            .map(|(_, sizes)| sizes)
+            .unwrap_or(&DEFAULT_SIZES)
+    }
+
+    /// Pads `record` to a ClientHello size drawn from this distribution.
+    pub fn pad_client_hello(&self, record: &[u8], rng: &mut impl Rng) -> io::Result<Vec<u8>> {
+        pad_client_hello(record, self.client_hello[rng.gen_range(0..self.client_hello.len())])
+    }
+
+    /// Pads `request` to an upgrade request size drawn from this distribution.
+    pub fn pad_ws_upgrade(&self, request: &[u8], rng: &mut impl Rng) -> io::Result<Vec<u8>> {
+        pad_ws_upgrade(request, self.ws_upgrade[rng.gen_range(0..self.ws_upgrade.len())], rng)
+    }
+}
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+fn read_u16(data: &[u8], at: usize) -> io::Result<usize> {
+    match data.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize),
+        None => Err(malformed("truncated ClientHello")),
+    }
+}
+
+/// Grows the ClientHello in `record` to exactly `target` bytes by appending a padding
+/// extension, fixing up the record, handshake and extension lengths. A record already at or
+/// past `target`, too close to it to fit the extension's 4-byte header, or already carrying
+/// a padding extension is returned unchanged.
+pub fn pad_client_hello(record: &[u8], target: usize) -> io::Result<Vec<u8>> {
+    if record.len() < 9 || record[0] != 0x16 || record[5] != 0x01 {
+        return Err(malformed("not a ClientHello record"));
+    }
+    if read_u16(record, 3)? != record.len() - 5 {
+        return Err(malformed("ClientHello record length doesn't match"));
+    }
+    // Skip the version and random, then the session id, cipher suites and compression methods.
+    let mut at = 9 + 2 + 32;
+    at += 1 + *record.get(at).ok_or_else(|| malformed("truncated ClientHello"))? as usize;
+    at += 2 + read_u16(record, at)?;
+    at += 1 + *record.get(at).ok_or_else(|| malformed("truncated ClientHello"))? as usize;
+    let extensions_at = at;
+    let extensions_len = read_u16(record, extensions_at)?;
+    if extensions_at + 2 + extensions_len != record.len() {
+        return Err(malformed("ClientHello extensions length doesn't match"));
+    }
+
+    let mut ext = extensions_at + 2;
+    while ext < record.len() {
+        if read_u16(record, ext)? as u16 == PADDING_EXTENSION {
+            return Ok(record.to_vec());
+        }
+        ext += 4 + read_u16(record, ext + 2)?;
+    }
+    let target = target.min(5 + MAX_RECORD_BODY);
+    if target < record.len() + 4 {
+        return Ok(record.to_vec());
+    }
+
+    let padding = target - record.len() - 4;
+    let mut out = Vec::with_capacity(target);
+    out.extend_from_slice(record);
+    out.extend_from_slice(&PADDING_EXTENSION.to_be_bytes());
+    out.extend_from_slice(&(padding as u16).to_be_bytes());
+    // RFC 7685: the extension's body is all zeros.
+    out.resize(target, 0);
+    let grow = |out: &mut Vec<u8>, at: usize, width: usize| {
+        let len = out[at..at + width].iter().fold(0usize, |len, byte| len << 8 | *byte as usize) + 4 + padding;
+        out[at..at + width].copy_from_slice(&(len as u32).to_be_bytes()[4 - width..]);
+    };
+    grow(&mut out, 3, 2);
+    grow(&mut out, 6, 3);
+    grow(&mut out, extensions_at, 2);
+    Ok(out)
+}
+
+/// Grows the HTTP upgrade request in `request` to exactly `target` bytes by adding an
+/// `X-Client-Data` header of random characters just before its blank line. Anything after the
+/// blank line is kept. A request already at or past `target`, or too close to it to fit the
+/// header, is returned unchanged.
+pub fn pad_ws_upgrade(request: &[u8], target: usize, rng: &mut impl Rng) -> io::Result<Vec<u8>> {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let end = request
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| malformed("unterminated upgrade request"))?;
+    let head_len = end + 4;
+    let overhead = WS_PADDING_HEADER.len() + 2;
+    if target < head_len + overhead + 1 {
+        return Ok(request.to_vec());
+    }
+
+    let mut out = Vec::with_capacity(request.len() + target - head_len);
+    out.extend_from_slice(&request[..end + 2]);
+    out.extend_from_slice(WS_PADDING_HEADER);
+    out.extend((0..target - head_len - overhead).map(|_| CHARSET[rng.gen_range(0..CHARSET.len())]));
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&request[end + 2..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::traffic_obfuscation::fake_client_hello;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const UPGRADE: &[u8] = b"GET /chat HTTP/1.1\r\nHost: www.aparat.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+    /// The extension types of a ClientHello record, in order.
+    fn extension_types(record: &[u8]) -> Vec<u16> {
+        let mut at = 9 + 2 + 32;
+        at += 1 + record[at] as usize;
+        at += 2 + u16::from_be_bytes([record[at], record[at + 1]]) as usize;
+        at += 1 + record[at] as usize;
+        at += 2;
+        let mut types = Vec::new();
+        while at < record.len() {
+            types.push(u16::from_be_bytes([record[at], record[at + 1]]));
+            at += 4 + u16::from_be_bytes([record[at + 2], record[at + 3]]) as usize;
+        }
+        assert_eq!(at, record.len());
+        types
+    }
+
+    #[test]
+    fn test_client_hello_is_padded_to_the_target() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let record = fake_client_hello("www.aparat.com", &mut rng);
+        assert!(record.len() < 517);
+
+        let padded = pad_client_hello(&record, 517).unwrap();
+        assert_eq!(padded.len(), 517);
+        // Every length field agrees with the new size, and the original hello is untouched.
+        assert_eq!(u16::from_be_bytes([padded[3], padded[4]]) as usize, 517 - 5);
+        assert_eq!(u32::from_be_bytes([0, padded[6], padded[7], padded[8]]) as usize, 517 - 9);
+        assert_eq!(extension_types(&padded).last(), Some(&PADDING_EXTENSION));
+        assert_eq!(padded[9..43], record[9..43]);
+        assert!(padded[record.len() + 4..].iter().all(|byte| *byte == 0));
+
+        // Padding twice, or to a size it can't reach, changes nothing.
+        assert_eq!(pad_client_hello(&padded, 1797).unwrap(), padded);
+        assert_eq!(pad_client_hello(&record, record.len() + 3).unwrap(), record);
+        assert_eq!(pad_client_hello(&record, 100).unwrap(), record);
+        assert!(pad_client_hello(b"GET / HTTP/1.1\r\n\r\n", 517).is_err());
+    }
+
+    #[test]
+    fn test_ws_upgrade_is_padded_to_the_target() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let with_body = [UPGRADE, b"early data"].concat();
+        let padded = pad_ws_upgrade(&with_body, 744, &mut rng).unwrap();
+        assert_eq!(padded.len(), 744 + b"early data".len());
+        assert!(padded.ends_with(b"\r\n\r\nearly data"));
+        // The request's own headers come first, then the padding header.
+        let head = String::from_utf8(padded[..744].to_vec()).unwrap();
+        let lines: Vec<&str> = head.trim_end().split("\r\n").collect();
+        assert_eq!(lines[..6], *String::from_utf8_lossy(UPGRADE).trim_end().split("\r\n").collect::<Vec<_>>());
+        assert!(lines[6].starts_with("X-Client-Data: "));
+
+        assert_eq!(pad_ws_upgrade(UPGRADE, UPGRADE.len() + 10, &mut rng).unwrap(), UPGRADE);
+        assert!(pad_ws_upgrade(b"GET / HTTP/1.1\r\n", 744, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_sizes_are_drawn_from_the_domain_distribution() {
+        let sizes = HandshakeSizes::for_domain("WWW.APARAT.COM");
+        assert_eq!(sizes.ws_upgrade, &[610, 744, 903]);
+        assert_eq!(HandshakeSizes::for_domain("cdn.example.net"), &DEFAULT_SIZES);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut hello_sizes = Vec::new();
+        let mut upgrade_sizes = Vec::new();
+        for _ in 0..50 {
+            let record = fake_client_hello("www.aparat.com", &mut rng);
+            hello_sizes.push(sizes.pad_client_hello(&record, &mut rng).unwrap().len());
+            upgrade_sizes.push(sizes.pad_ws_upgrade(UPGRADE, &mut rng).unwrap().len());
+        }
+        hello_sizes.sort_unstable();
+        hello_sizes.dedup();
+        upgrade_sizes.sort_unstable();
+        upgrade_sizes.dedup();
+        assert_eq!(hello_sizes, sizes.client_hello);
+        assert_eq!(upgrade_sizes, sizes.ws_upgrade);
+    }
+}
@@ -5,19 +5,28 @@
 //! for detecting connection state and signaling.
 
 use std::{
-    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}},
     time::Duration,
 };
 use tokio::{
+    net::TcpStream,
     sync::watch,
-    time::sleep,
+    time::{interval, sleep, timeout, Instant, MissedTickBehavior},
 };
 
+use crate::protocols::common::{HealthStatus, ProtocolError, TunnelConfig};
+
 /// `KillSwitchState` represents the current state of the Kill Switch.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KillSwitchState {
     /// The secure tunnel is active and healthy.
     Active,
+    /// The tunnel is down but the grace period hasn't expired; it is being restored.
+    /// Traffic isn't blocked yet, so consumers may queue rather than drop it.
+    Reconnecting,
     /// The secure tunnel is compromised or disconnected.
     /// Network access should be blocked.
     Triggered,
@@ -25,6 +34,62 @@ pub enum KillSwitchState {
     Disabled,
 }
 
+impl KillSwitchState {
+    /// Whether an enabled Kill Switch may move from `self` to `next` through `set_state`.
+    /// Re-setting the current state is a no-op and always allowed. `Disabled` can only be
+    /// left for `Active` (once the tunnel first comes up) and is only re-entered through
+    /// `set_enabled(false)`, so the switch can't fail before the tunnel was ever active.
+    pub fn can_transition_to(self, next: KillSwitchState) -> bool {
+        use KillSwitchState::*;
+        self == next
+            || matches!(
+                (self, next),
+                (Disabled, Active)
+                    | (Active, Reconnecting)
+                    | (Active, Triggered)
+                    | (Reconnecting, Active)
+                    | (Reconnecting, Triggered)
+                    | (Triggered, Active)
+                    | (Triggered, Reconnecting)
+            )
+    }
+}
+
+/// `KillSwitchConfig` controls the health-check timing.
+#[derive(Debug, Clone, Copy)]
+pub struct KillSwitchConfig {
+    /// Time between the starts of two consecutive probes.
+    pub probe_interval: Duration,
+    /// A probe that takes longer than this counts as a failure.
+    /// Operators on flaky links can raise it to avoid false triggers.
+    pub probe_timeout: Duration,
+    /// Number of consecutive failed probes before the state moves to `Triggered`.
+    /// Any successful probe resets the count, so transient packet loss doesn't cause flapping.
+    pub failure_threshold: u32,
+}
+
+impl Default for KillSwitchConfig {
+    fn default() -> Self {
+        KillSwitchConfig {
+            probe_interval: Duration::from_secs(10),
+            probe_timeout: Duration::from_secs(5),
+            failure_threshold: 3,
+        }
+    }
+}
+
+/// `KillSwitchStats` is a diagnostic snapshot of how often the Kill Switch has fired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KillSwitchStats {
+    /// Number of transitions into `Triggered`.
+    pub trigger_count: u64,
+    /// When the most recent transition into `Triggered` happened.
+    pub last_triggered: Option<Instant>,
+}
+
+/// A callback run synchronously on every state transition.
+type StateCallback = Box<dyn Fn(KillSwitchState) + Send + Sync>;
+
 /// `KillSwitchManager` manages the state and logic of the Kill Switch.
 pub struct KillSwitchManager {
     // This sender will be used by the core to update the Kill Switch state.
@@ -34,11 +99,24 @@ pub struct KillSwitchManager {
     state_receiver: watch::Receiver<KillSwitchState>,
     // A flag to indicate if the Kill Switch is enabled by configuration.
     is_enabled: Arc<AtomicBool>,
+    config: KillSwitchConfig,
+    // Failed probes since the last successful one.
+    consecutive_failures: Arc<AtomicU32>,
+    // Run from `set_state` whenever the state actually changes.
+    callbacks: Arc<Mutex<Vec<StateCallback>>>,
+    // Diagnostics, updated on every transition into `Triggered`.
+    trigger_count: Arc<AtomicU64>,
+    last_triggered: Arc<Mutex<Option<Instant>>>,
 }
 
 impl KillSwitchManager {
-    /// Creates a new `KillSwitchManager`.
+    /// Creates a new `KillSwitchManager` with the default health-check timing.
     pub fn new(enabled_by_config: bool) -> Self {
+        Self::with_config(enabled_by_config, KillSwitchConfig::default())
+    }
+
+    /// Creates a new `KillSwitchManager` with custom health-check timing.
+    pub fn with_config(enabled_by_config: bool, config: KillSwitchConfig) -> Self {
         let (state_sender, state_receiver) = watch::channel(KillSwitchState::Disabled);
         let is_enabled = Arc::new(AtomicBool::new(enabled_by_config));
 
@@ -52,25 +130,169 @@ impl KillSwitchManager {
             state_sender,
             state_receiver,
             is_enabled,
+            config,
+            consecutive_failures: Arc::new(AtomicU32::new(0)),
+            callbacks: Arc::new(Mutex::new(Vec::new())),
+            trigger_count: Arc::new(AtomicU64::new(0)),
+            last_triggered: Arc::new(Mutex::new(None)),
         }
     }
 
+    pub fn config(&self) -> &KillSwitchConfig {
+        &self.config
+    }
+
+    /// Number of failed health-check probes since the last successful one.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures.load(Ordering::SeqCst)
+    }
+
     /// Returns a receiver to monitor the Kill Switch state.
     pub fn subscribe_state(&self) -> watch::Receiver<KillSwitchState> {
         self.state_receiver.clone()
     }
 
+    /// Registers `f` to run whenever the state changes, e.g. to flush connections or fire a
+    /// webhook. Callbacks run synchronously inside `set_state`, in registration order, so
+    /// they should be quick and must not register further callbacks.
+    pub fn on_state_change(&self, f: impl Fn(KillSwitchState) + Send + Sync + 'static) {
+        self.callbacks.lock().unwrap().push(Box::new(f));
+    }
+
     /// Sets the Kill Switch state.
     /// This method would be called by the core when tunnel status changes.
-    pub fn set_state(&self, new_state: KillSwitchState) {
-        if self.is_enabled.load(Ordering::SeqCst) {
-            let _ = self.state_sender.send(new_state);
-            println!("Kill Switch: State changed to {:?}", new_state);
-        } else {
+    /// Returns a `ProtocolViolation` (and leaves the state unchanged) if the transition isn't
+    /// allowed by `KillSwitchState::can_transition_to`, or if the Kill Switch is disabled.
+    pub fn set_state(&self, new_state: KillSwitchState) -> Result<(), ProtocolError> {
+        if !self.is_enabled.load(Ordering::SeqCst) {
             // If Kill Switch is disabled, we don't change its state.
             // It always remains `Disabled`.
-            if new_state != KillSwitchState::Disabled {
-                println!("Kill Switch: Attempted to set state to {:?}, but it's disabled.", new_state);
+            if new_state == KillSwitchState::Disabled {
+                return Ok(());
+            }
+            println!("Kill Switch: Attempted to set state to {:?}, but it's disabled.", new_state);
+            return Err(ProtocolError::ProtocolViolation(format!(
+                "kill switch is disabled, cannot enter {:?}",
+                new_state
+            )));
+        }
+
+        let current = self.state();
+        if !current.can_transition_to(new_state) {
+            println!("Kill Switch: Rejected invalid transition {:?} -> {:?}.", current, new_state);
+            return Err(ProtocolError::ProtocolViolation(format!(
+                "invalid kill switch transition {:?} -> {:?}",
+                current, new_state
+            )));
+        }
+        self.publish(new_state);
+        Ok(())
+    }
+
+    /// Sends `new_state` to subscribers and, if it differs from the current state,
+    /// updates the trigger stats and runs the callbacks.
+    fn publish(&self, new_state: KillSwitchState) {
+        let old_state = self.state_sender.send_replace(new_state);
+        println!("Kill Switch: State changed to {:?}", new_state);
+        if old_state != new_state {
+            if new_state == KillSwitchState::Triggered {
+                self.trigger_count.fetch_add(1, Ordering::SeqCst);
+                *self.last_triggered.lock().unwrap() = Some(Instant::now());
+            }
+            for callback in self.callbacks.lock().unwrap().iter() {
+                callback(new_state);
+            }
+        }
+    }
+
+    /// Turns the Kill Switch on or off at runtime.
+    /// Disabling forces the state to `Disabled`; enabling moves it to `Active` (a running
+    /// health check then takes over). Subscribers see the change through the usual channel.
+    pub fn set_enabled(&self, enabled: bool) {
+        let was_enabled = self.is_enabled.swap(enabled, Ordering::SeqCst);
+        if was_enabled == enabled {
+            return;
+        }
+        if enabled {
+            println!("Kill Switch: Enabled at runtime.");
+            self.publish(KillSwitchState::Active);
+        } else {
+            println!("Kill Switch: Disabled at runtime.");
+            self.publish(KillSwitchState::Disabled);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled.load(Ordering::SeqCst)
+    }
+
+    /// Returns how many times the Kill Switch has triggered and when it last did,
+    /// so operators can line triggers up with outages.
+    pub fn stats(&self) -> KillSwitchStats {
+        KillSwitchStats {
+            trigger_count: self.trigger_count.load(Ordering::SeqCst),
+            last_triggered: *self.last_triggered.lock().unwrap(),
+        }
+    }
+
+    /// Returns the current Kill Switch state.
+    pub fn state(&self) -> KillSwitchState {
+        *self.state_receiver.borrow()
+    }
+
+    /// Probes the tunnel endpoint `target` with a TCP connect every `probe_interval` and sets
+    /// the state to `Active` on success, `Reconnecting` after the first failure, and
+    /// `Triggered` once `failure_threshold` consecutive probes have failed.
+    /// Runs until the returned future is dropped (e.g. by aborting its task or losing a
+    /// `select!`); it holds no state that needs cleaning up.
+    pub async fn run_health_check(&self, target: SocketAddr, probe_interval: Duration) {
+        println!("Kill Switch: Starting health checks against {} every {:?}.", target, probe_interval);
+        self.run_probes(probe_interval, || async move { TcpStream::connect(target).await.is_ok() }).await;
+    }
+
+    /// Like `run_health_check`, but with a caller-supplied probe (e.g. a ping over the tunnel),
+    /// run every `probe_interval` from the manager's `KillSwitchConfig`.
+    /// `probe` resolves to true if the tunnel is healthy; one that exceeds `probe_timeout` counts as a failure.
+    pub async fn run_health_check_with<F, Fut>(&self, probe: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        self.run_probes(self.config.probe_interval, probe).await;
+    }
+
+    async fn run_probes<F, Fut>(&self, probe_interval: Duration, mut probe: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        if !self.is_enabled.load(Ordering::SeqCst) {
+            return; // Don't run if disabled
+        }
+
+        let mut ticker = interval(probe_interval);
+        // A slow probe delays the next one instead of causing a burst to catch up.
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let healthy = timeout(self.config.probe_timeout, probe()).await.unwrap_or(false);
+            let new_state = if healthy {
+                self.consecutive_failures.store(0, Ordering::SeqCst);
+                KillSwitchState::Active
+            } else {
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+                if failures < self.config.failure_threshold.max(1) {
+                    KillSwitchState::Reconnecting
+                } else {
+                    KillSwitchState::Triggered
+                }
+            };
+            // Only report transitions, so subscribers aren't woken on every probe.
+            if self.state() != new_state {
+                // E.g. a tunnel that was never up can't trigger; the next probe tries again.
+                if let Err(e) = self.set_state(new_state) {
+                    println!("Kill Switch: Health check could not update state: {}", e);
+                }
             }
         }
     }
@@ -83,15 +305,82 @@ impl KillSwitchManager {
         }
 
         println!("Kill Switch: Running health check simulation...");
-        self.set_state(KillSwitchState::Active); // Assume active initially
+        let _ = self.set_state(KillSwitchState::Active); // Assume active initially
         sleep(Duration::from_secs(10)).await; // Simulate healthy period
 
         println!("Kill Switch: Simulating tunnel failure...");
-        self.set_state(KillSwitchState::Triggered); // Simulate failure
+        let _ = self.set_state(KillSwitchState::Reconnecting); // Simulate the first failed probes
+        sleep(Duration::from_secs(2)).await; // Grace period
+
+        let _ = self.set_state(KillSwitchState::Triggered); // Simulate failure
         sleep(Duration::from_secs(5)).await; // Stay triggered for a bit
 
         println!("Kill Switch: Simulating tunnel recovery...");
-        self.set_state(KillSwitchState::Active); // Simulate recovery
+        let _ = self.set_state(KillSwitchState::Active); // Simulate recovery
+    }
+}
+
+/// `KillSwitchGate` lets a protocol handler stop forwarding when the Kill Switch triggers.
+/// Handlers hold one and run their tunnel loop through `guard`, so a tunnel that should be
+/// blocked returns an error instead of leaking packets.
+#[derive(Clone, Default)]
+pub struct KillSwitchGate {
+    // `None` when the tunnel has the Kill Switch turned off; every check then passes.
+    receiver: Option<watch::Receiver<KillSwitchState>>,
+}
+
+impl KillSwitchGate {
+    /// A gate that follows `receiver` (see `KillSwitchManager::subscribe_state`).
+    pub fn new(receiver: watch::Receiver<KillSwitchState>) -> Self {
+        KillSwitchGate { receiver: Some(receiver) }
+    }
+
+    /// A gate that never blocks.
+    pub fn disabled() -> Self {
+        KillSwitchGate { receiver: None }
+    }
+
+    /// Builds the gate for a tunnel, honouring its `enable_kill_switch` setting.
+    pub fn for_tunnel(config: &TunnelConfig, manager: &KillSwitchManager) -> Self {
+        if config.enable_kill_switch {
+            Self::new(manager.subscribe_state())
+        } else {
+            Self::disabled()
+        }
+    }
+
+    fn blocked_error() -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionAborted, "kill switch triggered, tunnel traffic blocked")
+    }
+
+    /// Returns an error if traffic must not be forwarded right now.
+    pub fn check(&self) -> io::Result<()> {
+        match &self.receiver {
+            Some(receiver) if *receiver.borrow() == KillSwitchState::Triggered => Err(Self::blocked_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// How the Kill Switch affects readiness: `Down` while triggered, `Degraded` while reconnecting.
+    pub fn health(&self) -> HealthStatus {
+        match self.receiver.as_ref().map(|receiver| *receiver.borrow()) {
+            Some(KillSwitchState::Triggered) => HealthStatus::Down,
+            Some(KillSwitchState::Reconnecting) => HealthStatus::Degraded,
+            _ => HealthStatus::Ok,
+        }
+    }
+
+    /// Runs `tunnel` until it finishes or the Kill Switch triggers, whichever comes first.
+    /// On trigger the tunnel future is dropped, which closes whatever sockets it owns.
+    pub async fn guard<T, F: Future<Output = io::Result<T>>>(&self, tunnel: F) -> io::Result<T> {
+        self.check()?;
+        let Some(mut receiver) = self.receiver.clone() else {
+            return tunnel.await;
+        };
+        tokio::select! {
+            result = tunnel => result,
+            _ = receiver.wait_for(|state| *state == KillSwitchState::Triggered) => Err(Self::blocked_error()),
+        }
     }
 }
 
@@ -117,10 +406,13 @@ mod tests {
         receiver.changed().await.unwrap(); // Wait for first state change (to Active)
         assert_eq!(*receiver.borrow(), KillSwitchState::Active);
 
-        receiver.changed().await.unwrap(); // Wait for second state change (to Triggered)
+        receiver.changed().await.unwrap(); // Wait for second state change (to Reconnecting)
+        assert_eq!(*receiver.borrow(), KillSwitchState::Reconnecting);
+
+        receiver.changed().await.unwrap(); // Wait for third state change (to Triggered)
         assert_eq!(*receiver.borrow(), KillSwitchState::Triggered);
 
-        receiver.changed().await.unwrap(); // Wait for third state change (to Active)
+        receiver.changed().await.unwrap(); // Wait for fourth state change (to Active)
         assert_eq!(*receiver.borrow(), KillSwitchState::Active);
 
         println!("Kill Switch test completed.");
@@ -134,14 +426,247 @@ mod tests {
         assert_eq!(*receiver.borrow(), KillSwitchState::Disabled);
 
         // Try to set state, it should remain Disabled
-        manager.set_state(KillSwitchState::Active);
+        assert!(manager.set_state(KillSwitchState::Active).is_err());
         // No change should occur, so this `changed()` call would hang if not for timeout or other logic
         // For a simple test, we just assert the state directly after attempting to set.
         assert_eq!(*receiver.borrow(), KillSwitchState::Disabled); 
 
-        manager.set_state(KillSwitchState::Triggered);
+        assert!(manager.set_state(KillSwitchState::Triggered).is_err());
         assert_eq!(*receiver.borrow(), KillSwitchState::Disabled);
     }
+
+    #[tokio::test]
+    async fn test_health_check_triggers_when_endpoint_goes_down() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        let manager = KillSwitchManager::with_config(
+            true,
+            KillSwitchConfig {
+                // The interval passed to `run_health_check` wins over the configured one.
+                probe_interval: Duration::from_secs(60),
+                probe_timeout: Duration::from_millis(500),
+                failure_threshold: 2,
+            },
+        );
+        let mut receiver = manager.subscribe_state();
+
+        let checker = manager.clone();
+        let probe_task = tokio::spawn(async move {
+            checker.run_health_check(target, Duration::from_millis(50)).await;
+        });
+
+        receiver.changed().await.unwrap();
+        assert_eq!(*receiver.borrow(), KillSwitchState::Active);
+
+        // The tunnel endpoint goes away mid-run.
+        drop(listener);
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed()).await.unwrap().unwrap();
+        assert_eq!(*receiver.borrow(), KillSwitchState::Reconnecting);
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed()).await.unwrap().unwrap();
+        assert_eq!(*receiver.borrow(), KillSwitchState::Triggered);
+
+        // The loop is cancellable.
+        probe_task.abort();
+        assert!(probe_task.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_cadence_and_timeout_follow_config() {
+        let manager = KillSwitchManager::with_config(
+            true,
+            KillSwitchConfig {
+                probe_interval: Duration::from_secs(7),
+                probe_timeout: Duration::from_secs(2),
+                failure_threshold: 1,
+            },
+        );
+        let probe_times = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let start = tokio::time::Instant::now();
+
+        let times = probe_times.clone();
+        let checks = manager.run_health_check_with(move || {
+            let times = times.clone();
+            async move {
+                let probes = {
+                    let mut times = times.lock().unwrap();
+                    times.push(start.elapsed());
+                    times.len()
+                };
+                // The fourth probe hangs past the timeout.
+                if probes == 4 {
+                    sleep(Duration::from_secs(3)).await;
+                }
+                true
+            }
+        });
+        let _ = timeout(Duration::from_secs(24), checks).await;
+
+        let times = probe_times.lock().unwrap().clone();
+        let expected: Vec<Duration> = [0, 7, 14, 21].iter().map(|&s| Duration::from_secs(s)).collect();
+        assert_eq!(times, expected);
+        assert_eq!(manager.state(), KillSwitchState::Triggered);
+    }
+
+    #[tokio::test]
+    async fn test_gate_stops_tunnel_when_triggered() {
+        let manager = KillSwitchManager::new(true);
+        manager.set_state(KillSwitchState::Active).unwrap();
+        let gate = KillSwitchGate::new(manager.subscribe_state());
+        assert!(gate.check().is_ok());
+
+        let trigger = manager.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(20)).await;
+            trigger.set_state(KillSwitchState::Triggered).unwrap();
+        });
+        // A tunnel loop that would otherwise forward forever.
+        let result = gate.guard(std::future::pending::<io::Result<()>>()).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+        assert!(gate.check().is_err());
+
+        // New tunnels are refused while triggered; a disabled gate ignores the state.
+        assert!(gate.guard(async { Ok(()) }).await.is_err());
+        assert!(KillSwitchGate::disabled().guard(async { Ok(()) }).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_enable_and_disable_at_runtime() {
+        let manager = KillSwitchManager::new(false);
+        let mut receiver = manager.subscribe_state();
+        assert!(manager.set_state(KillSwitchState::Triggered).is_err());
+        assert_eq!(manager.state(), KillSwitchState::Disabled);
+
+        manager.set_enabled(true);
+        assert!(manager.is_enabled());
+        receiver.changed().await.unwrap();
+        assert_eq!(*receiver.borrow_and_update(), KillSwitchState::Active);
+
+        // State changes now propagate.
+        manager.set_state(KillSwitchState::Triggered).unwrap();
+        receiver.changed().await.unwrap();
+        assert_eq!(*receiver.borrow_and_update(), KillSwitchState::Triggered);
+
+        manager.set_enabled(false);
+        receiver.changed().await.unwrap();
+        assert_eq!(*receiver.borrow_and_update(), KillSwitchState::Disabled);
+        assert!(manager.set_state(KillSwitchState::Active).is_err());
+        assert_eq!(manager.state(), KillSwitchState::Disabled);
+    }
+
+    #[test]
+    fn test_only_valid_transitions_are_accepted() {
+        let manager = KillSwitchManager::new(true);
+
+        // Can't fail before the tunnel was ever up.
+        for state in [KillSwitchState::Triggered, KillSwitchState::Reconnecting] {
+            match manager.set_state(state) {
+                Err(ProtocolError::ProtocolViolation(msg)) => assert!(msg.contains("Disabled"), "{}", msg),
+                other => panic!("{:?} from Disabled should be rejected, got {:?}", state, other),
+            }
+        }
+        assert_eq!(manager.state(), KillSwitchState::Disabled);
+
+        let legal = [
+            KillSwitchState::Active,
+            KillSwitchState::Reconnecting,
+            KillSwitchState::Triggered,
+            KillSwitchState::Reconnecting,
+            KillSwitchState::Active,
+            KillSwitchState::Triggered,
+            KillSwitchState::Active,
+        ];
+        for state in legal {
+            manager.set_state(state).unwrap();
+            assert_eq!(manager.state(), state);
+        }
+
+        // Disabling goes through `set_enabled`, not `set_state`.
+        assert!(manager.set_state(KillSwitchState::Disabled).is_err());
+        assert_eq!(manager.state(), KillSwitchState::Active);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats_count_triggers() {
+        let manager = KillSwitchManager::new(true);
+        assert_eq!(manager.stats(), KillSwitchStats { trigger_count: 0, last_triggered: None });
+
+        manager.set_state(KillSwitchState::Active).unwrap();
+        manager.set_state(KillSwitchState::Triggered).unwrap();
+        let first = manager.stats().last_triggered.unwrap();
+        manager.set_state(KillSwitchState::Triggered).unwrap(); // Already triggered, not a new trigger.
+
+        sleep(Duration::from_secs(30)).await;
+        manager.set_state(KillSwitchState::Active).unwrap();
+        manager.set_state(KillSwitchState::Triggered).unwrap();
+
+        let stats = manager.stats();
+        assert_eq!(stats.trigger_count, 2);
+        assert_eq!(stats.last_triggered.unwrap() - first, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_state_change_callbacks_see_each_transition() {
+        let manager = KillSwitchManager::new(true);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let also_seen = Arc::new(Mutex::new(Vec::new()));
+
+        let log = seen.clone();
+        manager.on_state_change(move |state| log.lock().unwrap().push(state));
+        let log = also_seen.clone();
+        manager.on_state_change(move |state| log.lock().unwrap().push(state));
+
+        manager.set_state(KillSwitchState::Active).unwrap();
+        manager.set_state(KillSwitchState::Active).unwrap(); // Not a change, so no callback.
+        manager.set_state(KillSwitchState::Triggered).unwrap();
+        manager.set_state(KillSwitchState::Active).unwrap();
+
+        let expected = vec![KillSwitchState::Active, KillSwitchState::Triggered, KillSwitchState::Active];
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(*also_seen.lock().unwrap(), expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_below_threshold_do_not_trigger() {
+        let manager = KillSwitchManager::with_config(
+            true,
+            KillSwitchConfig {
+                probe_interval: Duration::from_secs(1),
+                probe_timeout: Duration::from_millis(500),
+                failure_threshold: 3,
+            },
+        );
+        // Healthy, then two failures, then healthy again, then three failures.
+        let results = [true, false, false, true, false, false, false];
+        let mut next = 0;
+        let observed = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let log = observed.clone();
+        let checker = manager.clone();
+        let checks = manager.run_health_check_with(move || {
+            let healthy = results[next.min(results.len() - 1)];
+            next += 1;
+            // Record what the manager looked like before this probe.
+            log.lock().unwrap().push((checker.state(), checker.consecutive_failures()));
+            async move { healthy }
+        });
+        let _ = timeout(Duration::from_millis(6_500), checks).await;
+
+        let observed = observed.lock().unwrap().clone();
+        assert_eq!(
+            observed,
+            vec![
+                (KillSwitchState::Disabled, 0),
+                (KillSwitchState::Active, 0),
+                (KillSwitchState::Reconnecting, 1),
+                (KillSwitchState::Reconnecting, 2),
+                (KillSwitchState::Active, 0),
+                (KillSwitchState::Reconnecting, 1),
+                (KillSwitchState::Reconnecting, 2),
+            ]
+        );
+        assert_eq!(manager.state(), KillSwitchState::Triggered);
+        assert_eq!(manager.consecutive_failures(), 3);
+    }
 }
 
 // To allow cloning for use in spawned tasks
@@ -151,6 +676,11 @@ impl Clone for KillSwitchManager {
             state_sender: self.state_sender.clone(),
             state_receiver: self.state_receiver.clone(),
             is_enabled: self.is_enabled.clone(),
+            config: self.config,
+            consecutive_failures: self.consecutive_failures.clone(),
+            callbacks: self.callbacks.clone(),
+            trigger_count: self.trigger_count.clone(),
+            last_triggered: self.last_triggered.clone(),
         }
     }
 }
@@ -0,0 +1,157 @@
+//! This module manages a prioritized list of mimic (cover) domains.
+//! If the current cover domain becomes a blocked SNI, handshakes using it start
+//! failing en masse. `MimicDomainRotator` watches the recent rejection rate and falls
+//! back to the next domain in the list once it crosses a threshold.
+//! NOTE: Library-only for now. The server's handshake is still simulated and never sees an
+//! SNI rejection, so nothing feeds the rotator; it is meant for the client side.
+
+use std::{collections::VecDeque, sync::Mutex};
+use tracing::debug;
+
+/// `MimicFallbackConfig` controls when the rotator gives up on a domain.
+#[derive(Debug, Clone, Copy)]
+pub struct MimicFallbackConfig {
+    /// Number of most recent handshakes considered.
+    pub window: usize,
+    /// Minimum handshakes in the window before a rotation is considered.
+    pub min_attempts: usize,
+    /// Rejection rate (0.0..=1.0) at or above which the domain is abandoned.
+    pub max_rejection_rate: f64,
+}
+
+impl Default for MimicFallbackConfig {
+    fn default() -> Self {
+        MimicFallbackConfig {
+            window: 50,
+            min_attempts: 10,
+            max_rejection_rate: 0.8,
+        }
+    }
+}
+
+struct RotatorState {
+    current: usize,
+    /// `true` for each recent handshake that was rejected.
+    recent: VecDeque<bool>,
+}
+
+/// `MimicDomainRotator` picks the cover domain for new connections.
+pub struct MimicDomainRotator {
+    domains: Vec<String>,
+    config: MimicFallbackConfig,
+    state: Mutex<RotatorState>,
+}
+
+impl MimicDomainRotator {
+    /// Creates a rotator over `domains`, in priority order. Returns `None` if the list is empty.
+    pub fn new(domains: Vec<String>, config: MimicFallbackConfig) -> Option<Self> {
+        if domains.is_empty() {
+            return None;
+        }
+        Some(MimicDomainRotator {
+            domains,
+            config,
+            state: Mutex::new(RotatorState {
+                current: 0,
+                recent: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// The domain new connections should mimic.
+    pub fn current_domain(&self) -> String {
+        let state = self.state.lock().unwrap();
+        self.domains[state.current].clone()
+    }
+
+    /// Records whether a handshake using the current domain was rejected.
+    /// Returns the new domain if this outcome caused a rotation.
+    pub fn record_handshake(&self, rejected: bool) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.recent.push_back(rejected);
+        while state.recent.len() > self.config.window {
+            state.recent.pop_front();
+        }
+
+        let attempts = state.recent.len();
+        if attempts < self.config.min_attempts || self.domains.len() < 2 {
+            return None;
+        }
+        let rejections = state.recent.iter().filter(|r| **r).count();
+        if (rejections as f64 / attempts as f64) < self.config.max_rejection_rate {
+            return None;
+        }
+
+        let previous = state.current;
+        state.current = (state.current + 1) % self.domains.len();
+        state.recent.clear();
+        debug!(
+            "Mimic Domains: '{}' is being rejected ({}/{} handshakes), falling back to '{}'",
+            self.domains[previous], rejections, attempts, self.domains[state.current]
+        );
+        Some(self.domains[state.current].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotator() -> MimicDomainRotator {
+        MimicDomainRotator::new(
+            vec!["www.primary.example".to_string(), "cdn.secondary.example".to_string()],
+            MimicFallbackConfig {
+                window: 10,
+                min_attempts: 5,
+                max_rejection_rate: 0.8,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_blocked_primary_rotates_to_secondary() {
+        let rotator = rotator();
+        let blocked = "www.primary.example";
+        let mut successes = 0;
+
+        for _ in 0..20 {
+            let domain = rotator.current_domain();
+            let rejected = domain == blocked;
+            if !rejected {
+                successes += 1;
+            }
+            rotator.record_handshake(rejected);
+        }
+
+        assert_eq!(rotator.current_domain(), "cdn.secondary.example");
+        // Five rejections on the primary, then every later connection succeeds.
+        assert_eq!(successes, 15);
+    }
+
+    #[test]
+    fn test_occasional_failures_do_not_rotate() {
+        let rotator = rotator();
+        for i in 0..50 {
+            assert_eq!(rotator.record_handshake(i % 3 == 0), None);
+        }
+        assert_eq!(rotator.current_domain(), "www.primary.example");
+    }
+
+    #[test]
+    fn test_rotation_wraps_around_the_list() {
+        let rotator = rotator();
+        let mut rotations = Vec::new();
+        for _ in 0..10 {
+            if let Some(domain) = rotator.record_handshake(true) {
+                rotations.push(domain);
+            }
+        }
+        assert_eq!(rotations, vec!["cdn.secondary.example", "www.primary.example"]);
+    }
+
+    #[test]
+    fn test_empty_domain_list_is_rejected() {
+        assert!(MimicDomainRotator::new(Vec::new(), MimicFallbackConfig::default()).is_none());
+    }
+}
@@ -1,3 +1,16 @@
 // این ماژول شامل مکانیزم‌های امنیتی پیشرفته هزار دستان است.
 pub mod kill_switch;
 pub mod traffic_obfuscation;
+pub mod probe_detection;
+pub mod batching;
+pub mod rotation;
+pub mod traffic_shaping;
+pub mod mimic_domains;
+pub mod firewall;
+pub mod trace_similarity;
+pub mod replay_guard;
+pub mod transform_registry;
+pub mod obfuscated_stream;
+pub mod obfuscation_codec;
+pub mod app_presets;
+pub mod handshake_padding;
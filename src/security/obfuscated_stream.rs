@@ -0,0 +1,190 @@
+//! This module adapts the `Obfuscator` to byte streams.
+//! `Obfuscator` works on discrete packets, while the protocol handlers tunnel continuous
+//! streams. `ObfuscatedStream` wraps any `AsyncRead + AsyncWrite` (e.g. a `TcpStream`):
+//! every write becomes one obfuscated frame, and reads return the deobfuscated bytes.
+//!
+//! The framing and buffering are `ObfuscationCodec`'s, driven through a `Framed`; this
+//! adapter only turns its frames back into a byte stream. Chaff frames are dropped on read.
+
+use futures_util::{Sink, Stream};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::codec::Framed;
+
+use crate::security::obfuscation_codec::ObfuscationCodec;
+use crate::security::traffic_obfuscation::Obfuscator;
+
+/// Largest payload put into one frame; longer writes are split across calls.
+const MAX_WRITE_CHUNK: usize = 16 * 1024;
+
+/// `ObfuscatedStream` obfuscates everything written to `inner` and deobfuscates everything read from it.
+pub struct ObfuscatedStream<S> {
+    framed: Framed<S, ObfuscationCodec>,
+    /// Deobfuscated payload not yet handed to the caller, and how much of it was consumed.
+    decoded: Vec<u8>,
+    decoded_pos: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite> ObfuscatedStream<S> {
+    /// Wraps `inner`. Both ends must use obfuscators that can reverse each other's frames.
+    pub fn new(inner: S, obfuscator: Arc<Obfuscator>) -> Self {
+        ObfuscatedStream {
+            framed: Framed::new(inner, ObfuscationCodec::new(obfuscator)),
+            decoded: Vec::new(),
+            decoded_pos: 0,
+        }
+    }
+}
+
+impl<S> ObfuscatedStream<S> {
+    pub fn get_ref(&self) -> &S {
+        self.framed.get_ref()
+    }
+
+    /// Unwraps the stream. Any buffered but unread or unwritten data is lost.
+    pub fn into_inner(self) -> S {
+        self.framed.into_inner()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for ObfuscatedStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.decoded_pos == this.decoded.len() {
+            match ready!(Pin::new(&mut this.framed).poll_next(cx)) {
+                Some(payload) => {
+                    this.decoded = payload?;
+                    this.decoded_pos = 0;
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = buf.remaining().min(this.decoded.len() - this.decoded_pos);
+        buf.put_slice(&this.decoded[this.decoded_pos..this.decoded_pos + n]);
+        this.decoded_pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for ObfuscatedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut framed = Pin::new(&mut this.framed);
+        // Waits while the codec's write buffer is over its backpressure boundary.
+        ready!(Sink::<&[u8]>::poll_ready(framed.as_mut(), cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let payload = &buf[..buf.len().min(MAX_WRITE_CHUNK)];
+        framed.as_mut().start_send(payload)?;
+        // Start sending right away; whatever doesn't fit goes out on the next write or flush.
+        if let Poll::Ready(Err(e)) = Sink::<&[u8]>::poll_flush(framed, cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(payload.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<&[u8]>::poll_flush(Pin::new(&mut self.get_mut().framed), cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<&[u8]>::poll_close(Pin::new(&mut self.get_mut().framed), cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::traffic_obfuscation::write_varint;
+    use bytes::BytesMut;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::Decoder;
+
+    fn pair(capacity: usize) -> (ObfuscatedStream<tokio::io::DuplexStream>, ObfuscatedStream<tokio::io::DuplexStream>) {
+        let (a, b) = duplex(capacity);
+        (
+            ObfuscatedStream::new(a, Arc::new(Obfuscator::with_key(b"user-key"))),
+            ObfuscatedStream::new(b, Arc::new(Obfuscator::with_key(b"user-key"))),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_duplex_round_trip() {
+        let (mut client, mut server) = pair(64 * 1024);
+        let message = b"GET /tunnel HTTP/1.1\r\n\r\nhello through the obfuscator";
+
+        client.write_all(message).await.unwrap();
+        client.flush().await.unwrap();
+        let mut received = vec![0u8; message.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, message);
+
+        server.write_all(b"pong").await.unwrap();
+        server.flush().await.unwrap();
+        let mut pong = [0u8; 4];
+        client.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_large_transfer_through_small_pipe() {
+        // A small duplex buffer forces partial reads and writes of frames.
+        let (mut client, mut server) = pair(1024);
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+        let expected = data.clone();
+        let writer = tokio::spawn(async move {
+            client.write_all(&data).await.unwrap();
+            client.shutdown().await.unwrap();
+        });
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_on_the_wire_are_obfuscated() {
+        let (a, mut raw) = duplex(64 * 1024);
+        let mut stream = ObfuscatedStream::new(a, Arc::new(Obfuscator::with_key(b"user-key")));
+        stream.write_all(b"secret plaintext").await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut wire = Vec::new();
+        raw.read_to_end(&mut wire).await.unwrap();
+        assert!(!wire.windows(b"secret plaintext".len()).any(|w| w == b"secret plaintext"));
+        // Exactly one frame, which a codec with the same key reads back.
+        let mut codec = ObfuscationCodec::new(Arc::new(Obfuscator::with_key(b"user-key")));
+        let mut wire = BytesMut::from(&wire[..]);
+        assert_eq!(codec.decode(&mut wire).unwrap().unwrap(), b"secret plaintext");
+        assert!(wire.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chaff_and_corrupt_frames() {
+        // Unkeyed, so the length prefixes are plain varints.
+        let obfuscator = Arc::new(Obfuscator::new());
+        let (mut raw, b) = duplex(64 * 1024);
+        let mut stream = ObfuscatedStream::new(b, obfuscator.clone());
+
+        let mut wire = Vec::new();
+        for frame in [obfuscator.chaff_frame(), obfuscator.transform(b"data"), b"bad".to_vec()] {
+            write_varint(&mut wire, frame.len());
+            wire.extend_from_slice(&frame);
+        }
+        raw.write_all(&wire).await.unwrap();
+
+        let mut data = [0u8; 4];
+        stream.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"data");
+        let err = stream.read(&mut data).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
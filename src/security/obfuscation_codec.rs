@@ -0,0 +1,413 @@
+//! This module frames obfuscated packets on a byte stream as a `tokio_util` codec.
+//! `ObfuscationCodec` implements both `Encoder` and `Decoder`, so it composes with
+//! `Framed`, `FramedRead` and `FramedWrite`, which own the buffering: partial reads
+//! simply leave the decoder waiting for more bytes, and the framed writer applies
+//! backpressure before its buffer grows without bound.
+//!
+//! Wire format: `[varint len][obfuscated frame]...`. Chaff frames are dropped by the decoder.
+//! Encoding goes through `Obfuscator::transform_as`, so no jitter is added here. A codec
+//! writes `FrameVersion::CURRENT` frames unless built `with_version` for an older peer, and
+//! decodes every version up to the one it writes; a newer frame fails the stream with an
+//! error naming both versions.
+//!
+//! With a keyed obfuscator the length prefix is masked too, much like QUIC header protection:
+//! each length byte is XORed with the next byte of a ChaCha20 keystream derived from the key.
+//! Version 3 frame headers are masked with the same key, so together an observer has no
+//! fixed pattern to find frame boundaries by. Older versions write their header in plaintext
+//! right after each length, which gives the boundaries away again.
+
+use bytes::{Buf, BytesMut};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::{collections::VecDeque, io, sync::Arc};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::protocols::common::ProtocolError;
+use crate::security::traffic_obfuscation::{read_varint, write_varint, FrameVersion, Obfuscator};
+
+/// Largest frame accepted from the peer. A bigger length means a corrupt or hostile stream.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+/// Most bytes a LEB128 varint of a `usize` can take.
+const MAX_VARINT_LEN: usize = 10;
+
+/// One direction's length-mask keystream. Every length byte uses up one keystream byte,
+/// so the mask never repeats within a stream.
+struct LengthMask {
+    keystream: ChaCha20Rng,
+    /// Generated keystream bytes not yet used.
+    pending: VecDeque<u8>,
+}
+
+impl LengthMask {
+    fn new(seed: [u8; 32]) -> Self {
+        LengthMask {
+            keystream: ChaCha20Rng::from_seed(seed),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// XORs `bytes` with the next keystream bytes without using them up, so a decoder still
+    /// waiting for the rest of a frame sees the same mask on its next attempt.
+    fn apply(&mut self, bytes: &mut [u8]) {
+        // Whole words at a time, so the byte sequence doesn't depend on how much is peeked.
+        while self.pending.len() < bytes.len() {
+            self.pending.extend(self.keystream.next_u32().to_le_bytes());
+        }
+        for (byte, mask) in bytes.iter_mut().zip(&self.pending) {
+            *byte ^= mask;
+        }
+    }
+
+    /// Uses up `n` keystream bytes once their length has been sent or parsed.
+    fn consume(&mut self, n: usize) {
+        self.pending.drain(..n);
+    }
+}
+
+/// `ObfuscationCodec` turns payloads into length-prefixed obfuscated frames and back.
+pub struct ObfuscationCodec {
+    obfuscator: Arc<Obfuscator>,
+    /// Version written, and the newest version read.
+    version: FrameVersion,
+    /// Masks for outgoing and incoming lengths; `None` when the obfuscator is unkeyed.
+    encode_mask: Option<LengthMask>,
+    decode_mask: Option<LengthMask>,
+}
+
+impl ObfuscationCodec {
+    /// Frames with `obfuscator`. Both ends must use obfuscators that can reverse each other's frames.
+    /// A keyed obfuscator also masks the length prefixes.
+    pub fn new(obfuscator: Arc<Obfuscator>) -> Self {
+        let seed = obfuscator.length_mask_seed();
+        ObfuscationCodec {
+            obfuscator,
+            version: FrameVersion::CURRENT,
+            encode_mask: seed.map(LengthMask::new),
+            decode_mask: seed.map(LengthMask::new),
+        }
+    }
+
+    /// Speaks frame `version` instead of the current one, for a peer that hasn't upgraded yet.
+    pub fn with_version(mut self, version: FrameVersion) -> Self {
+        self.version = version;
+        self
+    }
+}
+
+impl<'a> Encoder<&'a [u8]> for ObfuscationCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, payload: &'a [u8], dst: &mut BytesMut) -> io::Result<()> {
+        let frame = self.obfuscator.transform_as(payload, self.version);
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(ProtocolError::ObfuscationError(format!(
+                "payload of {} bytes makes a frame over the {} byte limit",
+                payload.len(),
+                MAX_FRAME_LEN
+            ))
+            .into());
+        }
+        let mut len = Vec::with_capacity(MAX_VARINT_LEN);
+        write_varint(&mut len, frame.len());
+        if let Some(mask) = &mut self.encode_mask {
+            mask.apply(&mut len);
+            mask.consume(len.len());
+        }
+        dst.reserve(len.len() + frame.len());
+        dst.extend_from_slice(&len);
+        dst.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+impl Decoder for ObfuscationCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let mut prefix = src[..src.len().min(MAX_VARINT_LEN)].to_vec();
+            if let Some(mask) = &mut self.decode_mask {
+                mask.apply(&mut prefix);
+            }
+            // Wait until the whole length prefix is buffered.
+            if !prefix.iter().any(|byte| byte & 0x80 == 0) {
+                if prefix.len() < MAX_VARINT_LEN {
+                    return Ok(None);
+                }
+                return Err(ProtocolError::ObfuscationError("oversized stream frame length".to_string()).into());
+            }
+            let (len, used) = read_varint(&prefix)?;
+            if len > MAX_FRAME_LEN {
+                return Err(
+                    ProtocolError::ObfuscationError(format!("stream frame of {} bytes exceeds the limit", len)).into()
+                );
+            }
+            if src.len() < used + len {
+                src.reserve(used + len - src.len());
+                return Ok(None);
+            }
+            if let Some(mask) = &mut self.decode_mask {
+                mask.consume(used);
+            }
+            src.advance(used);
+            let frame = src.split_to(len);
+            let payload = self.obfuscator.deobfuscate_as(&frame, self.version)?;
+            // Chaff deobfuscates to nothing and is simply skipped.
+            if !payload.is_empty() {
+                return Ok(Some(payload));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        match self.decode(src)? {
+            Some(payload) => Ok(Some(payload)),
+            None if src.is_empty() => Ok(None),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended inside a frame")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::traffic_obfuscation::{ObfuscationProfileTier, ObfuscatorConfig};
+    use crate::security::transform_registry::{TransformRegistry, TransformSpec};
+    use futures_util::{SinkExt, StreamExt};
+    use rand::{rngs::StdRng, Rng};
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    /// Moves at most a few bytes per call and stalls on every other call, so frames
+    /// straddle reads and writes at arbitrary offsets.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        rng: StdRng,
+        stall: bool,
+    }
+
+    impl Trickle {
+        fn new(data: Vec<u8>, seed: u64) -> Self {
+            Trickle {
+                data,
+                pos: 0,
+                rng: StdRng::seed_from_u64(seed),
+                stall: false,
+            }
+        }
+
+        /// Pending every other call, waking itself so the caller retries.
+        fn stalled(&mut self, cx: &mut Context<'_>) -> bool {
+            self.stall = !self.stall;
+            if self.stall {
+                cx.waker().wake_by_ref();
+            }
+            self.stall
+        }
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if self.stalled(cx) {
+                return Poll::Pending;
+            }
+            let n = self.rng.gen_range(1..=7).min(self.data.len() - self.pos).min(buf.remaining());
+            buf.put_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            if self.stalled(cx) {
+                return Poll::Pending;
+            }
+            let n = self.rng.gen_range(1..=7).min(buf.len());
+            self.data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn codec() -> ObfuscationCodec {
+        ObfuscationCodec::new(Arc::new(Obfuscator::with_key(b"user-key")))
+    }
+
+    /// An unkeyed codec, whose length prefixes are plain varints.
+    fn plain_codec() -> ObfuscationCodec {
+        ObfuscationCodec::new(Arc::new(Obfuscator::new()))
+    }
+
+    #[tokio::test]
+    async fn test_framed_round_trip_across_split_boundaries() {
+        let payloads: Vec<Vec<u8>> = [1usize, 2, 127, 128, 1400, 20_000]
+            .iter()
+            .map(|&len| (0..len).map(|i| (i % 251) as u8).collect())
+            .collect();
+
+        for seed in 0..4 {
+            let mut writer = FramedWrite::new(Trickle::new(Vec::new(), seed), codec());
+            for payload in &payloads {
+                writer.send(payload.as_slice()).await.unwrap();
+            }
+            let wire = writer.into_inner().data;
+
+            let mut reader = FramedRead::new(Trickle::new(wire, seed + 100), codec());
+            for payload in &payloads {
+                assert_eq!(reader.next().await.unwrap().unwrap(), *payload, "seed {}", seed);
+            }
+            assert!(reader.next().await.is_none());
+        }
+    }
+
+    #[test]
+    fn test_decoder_waits_for_whole_frames_and_skips_chaff() {
+        let obfuscator = Arc::new(Obfuscator::new());
+        let mut encoder = ObfuscationCodec::new(obfuscator.clone());
+        let mut wire = BytesMut::new();
+        let chaff = obfuscator.chaff_frame();
+        let mut prefix = Vec::new();
+        write_varint(&mut prefix, chaff.len());
+        wire.extend_from_slice(&prefix);
+        wire.extend_from_slice(&chaff);
+        encoder.encode(&b"data"[..], &mut wire).unwrap();
+
+        // Fed one byte at a time, nothing comes out until the real frame is complete.
+        let mut decoder = plain_codec();
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in wire {
+            src.extend_from_slice(&[byte]);
+            if let Some(payload) = decoder.decode(&mut src).unwrap() {
+                decoded.push(payload);
+            }
+        }
+        assert_eq!(decoded, vec![b"data".to_vec()]);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_bad_lengths_and_truncated_streams_are_rejected() {
+        let mut decoder = plain_codec();
+
+        let mut oversized = Vec::new();
+        write_varint(&mut oversized, MAX_FRAME_LEN + 1);
+        let err = decoder.decode(&mut BytesMut::from(&oversized[..])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = decoder.decode(&mut BytesMut::from(&[0xffu8; MAX_VARINT_LEN][..])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut wire = BytesMut::new();
+        codec().encode(&b"cut short"[..], &mut wire).unwrap();
+        let mut truncated = wire.split_to(wire.len() - 1);
+        let err = codec().decode_eof(&mut truncated).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_keyed_lengths_are_masked_on_the_wire() {
+        // Minimal tier: no noise or mimicry, so each frame is the payload plus 16 bytes.
+        let keyed = || {
+            ObfuscationCodec::new(Arc::new(Obfuscator::for_tier(ObfuscationProfileTier::Minimal, Some(b"user-key"), None)))
+        };
+        let lens = [10usize, 200, 10];
+        let mut wire = BytesMut::new();
+        let mut encoder = keyed();
+        for len in lens {
+            encoder.encode(&vec![0x42; len][..], &mut wire).unwrap();
+        }
+
+        let mut prefixes = Vec::new();
+        let mut rest = &wire[..];
+        for len in lens {
+            let mut plain = Vec::new();
+            write_varint(&mut plain, len + 16);
+            let (prefix, after) = rest.split_at(plain.len());
+            assert_ne!(prefix, plain, "length {} went out unmasked", len + 16);
+            prefixes.push(prefix.to_vec());
+            rest = &after[len + 16..];
+        }
+        assert!(rest.is_empty());
+        // The same length is masked differently each time.
+        assert_ne!(prefixes[0], prefixes[2]);
+
+        let mut decoder = keyed();
+        for len in lens {
+            assert_eq!(decoder.decode(&mut wire).unwrap().unwrap(), vec![0x42; len]);
+        }
+        assert!(wire.is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_lengths_are_masked_on_the_wire() {
+        let specs = [TransformSpec {
+            name: "keystream".to_string(),
+            params: [("key".to_string(), "user-key".to_string())].into(),
+        }];
+        let pipeline = || Arc::new(TransformRegistry::new().pipeline(&specs, ObfuscatorConfig::default()).unwrap());
+        let mut encoder = ObfuscationCodec::new(pipeline());
+        assert!(encoder.encode_mask.is_some(), "the keystream key must reach the length mask");
+
+        // Every frame is the same length, so unmasked prefixes would all be the same byte.
+        let mut wire = BytesMut::new();
+        for _ in 0..8 {
+            encoder.encode(&b"same length"[..], &mut wire).unwrap();
+        }
+        let frame_len = wire.len() / 8 - 1;
+        assert!(frame_len < 128, "one-byte prefixes expected");
+        let prefixes: Vec<u8> = wire.iter().step_by(frame_len + 1).copied().collect();
+        assert!(prefixes.iter().any(|prefix| *prefix != prefixes[0]));
+
+        let mut decoder = ObfuscationCodec::new(pipeline());
+        for _ in 0..8 {
+            assert_eq!(decoder.decode(&mut wire).unwrap().unwrap(), b"same length");
+        }
+        assert!(wire.is_empty());
+    }
+
+    #[test]
+    fn test_frame_version_matrix() {
+        let keyed = |version| {
+            ObfuscationCodec::new(Arc::new(Obfuscator::with_key(b"user-key"))).with_version(version)
+        };
+        for sender in FrameVersion::ALL {
+            for receiver in FrameVersion::ALL {
+                let mut wire = BytesMut::new();
+                let mut encoder = keyed(sender);
+                for payload in [&b"first"[..], &[0x42; 700][..]] {
+                    encoder.encode(payload, &mut wire).unwrap();
+                }
+
+                let mut decoder = keyed(receiver);
+                if sender <= receiver {
+                    assert_eq!(decoder.decode(&mut wire).unwrap().unwrap(), b"first", "{:?} -> {:?}", sender, receiver);
+                    assert_eq!(decoder.decode(&mut wire).unwrap().unwrap(), vec![0x42; 700]);
+                    assert!(wire.is_empty());
+                } else {
+                    let err = decoder.decode(&mut wire).unwrap_err();
+                    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                    let expected = format!(
+                        "frame version {} is not supported (this peer reads up to {})",
+                        sender as u8, receiver as u8
+                    );
+                    assert!(err.to_string().contains(&expected), "{:?} -> {:?}: {}", sender, receiver, err);
+                }
+            }
+        }
+    }
+}
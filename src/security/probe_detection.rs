@@ -0,0 +1,257 @@
+//! This module detects active-probing patterns against the TCP listener.
+//! Censors fingerprint servers by opening connections and watching how they react
+//! (resetting early, closing without sending anything, reconnecting in rapid bursts).
+//! When a source IP looks like a prober, it is switched into "decoy mode" for a
+//! cooldown period: the acceptor should only ever serve the cover page to it and
+//! never attempt the obfuscation handshake. OTLS/WS does this when the
+//! `[probe_detection]` table is configured (see `OtlsWsProtocol::with_probe_detector`).
+//!
+//! The reconnect limit (`DEFAULT_MAX_CONNECTS_PER_WINDOW` connections a minute) is deliberately
+//! far below the admission rate limit (`peer_connections_per_second`): that one only stops a
+//! peer from flooding the server, this one spots a peer that keeps coming back to look.
+//! NOTE: Raw TCP details such as SYN retransmits or window sizes are not visible from
+//! userland sockets; detection works on what the accept loop can actually observe.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::utils::logging::redact_ip;
+
+/// Default sliding window over which a peer's connections are counted.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+/// Default number of connections within `DEFAULT_WINDOW` beyond which a peer is flagged.
+pub const DEFAULT_MAX_CONNECTS_PER_WINDOW: usize = 20;
+
+/// An observation the accept loop reports about a single connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeEvent {
+    /// A new connection was accepted from the peer.
+    Connected,
+    /// The peer reset the connection before completing a handshake.
+    EarlyReset,
+    /// The peer closed the connection without sending any data.
+    ClosedWithoutData,
+}
+
+/// How the acceptor should treat a connection from a given IP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptMode {
+    /// Attempt the obfuscation handshake as usual.
+    Normal,
+    /// Serve the cover page only; never reveal the tunnel.
+    Decoy,
+}
+
+/// `ProbeDetectorConfig` controls when an IP is considered a prober.
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeDetectorConfig {
+    /// Sliding window over which events are counted.
+    pub window: Duration,
+    /// Number of early resets / empty closes within `window` that marks a prober.
+    pub suspicious_threshold: usize,
+    /// Number of connections within `window` that marks a prober.
+    pub max_connects_per_window: usize,
+    /// How long an IP stays in decoy mode once flagged.
+    pub decoy_cooldown: Duration,
+    /// Upper bound on the number of IPs tracked at once.
+    pub max_tracked_peers: usize,
+}
+
+impl Default for ProbeDetectorConfig {
+    fn default() -> Self {
+        ProbeDetectorConfig {
+            window: DEFAULT_WINDOW,
+            suspicious_threshold: 3,
+            max_connects_per_window: DEFAULT_MAX_CONNECTS_PER_WINDOW,
+            decoy_cooldown: Duration::from_secs(15 * 60),
+            max_tracked_peers: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PeerHistory {
+    events: VecDeque<(Instant, ProbeEvent)>,
+    decoy_until: Option<Instant>,
+}
+
+impl PeerHistory {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some((at, _)) = self.events.front() {
+            if now.saturating_duration_since(*at) > window {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+        if matches!(self.decoy_until, Some(until) if now >= until) {
+            self.decoy_until = None;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.events.is_empty() && self.decoy_until.is_none()
+    }
+}
+
+/// `ProbeDetector` tracks per-IP connection patterns for the accept loop.
+pub struct ProbeDetector {
+    config: ProbeDetectorConfig,
+    peers: Mutex<HashMap<IpAddr, PeerHistory>>,
+}
+
+impl ProbeDetector {
+    /// Creates a new `ProbeDetector` with the given configuration.
+    pub fn new(config: ProbeDetectorConfig) -> Self {
+        ProbeDetector {
+            config,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an event for `ip` and returns the mode future connections from it should use.
+    pub fn record(&self, ip: IpAddr, event: ProbeEvent) -> AcceptMode {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().unwrap();
+
+        if !peers.contains_key(&ip) && peers.len() >= self.config.max_tracked_peers {
+            peers.retain(|_, history| {
+                history.expire(now, self.config.window);
+                !history.is_idle()
+            });
+            if peers.len() >= self.config.max_tracked_peers {
+                // Still full of active peers; don't grow further.
+                return AcceptMode::Normal;
+            }
+        }
+
+        let history = peers.entry(ip).or_default();
+        history.expire(now, self.config.window);
+        history.events.push_back((now, event));
+
+        let suspicious = history
+            .events
+            .iter()
+            .filter(|(_, e)| matches!(e, ProbeEvent::EarlyReset | ProbeEvent::ClosedWithoutData))
+            .count();
+        let connects = history
+            .events
+            .iter()
+            .filter(|(_, e)| *e == ProbeEvent::Connected)
+            .count();
+
+        if suspicious >= self.config.suspicious_threshold
+            || connects > self.config.max_connects_per_window
+        {
+            if history.decoy_until.is_none() {
+                warn!("Probe Detector: {} looks like an active prober, switching to decoy mode.", redact_ip(ip));
+            }
+            history.decoy_until = Some(now + self.config.decoy_cooldown);
+            history.events.clear();
+        }
+
+        if history.decoy_until.is_some() {
+            AcceptMode::Decoy
+        } else {
+            AcceptMode::Normal
+        }
+    }
+
+    /// Returns the current mode for `ip` without recording anything.
+    pub fn mode(&self, ip: IpAddr) -> AcceptMode {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().unwrap();
+        match peers.get_mut(&ip) {
+            Some(history) => {
+                history.expire(now, self.config.window);
+                if history.decoy_until.is_some() {
+                    AcceptMode::Decoy
+                } else {
+                    AcceptMode::Normal
+                }
+            }
+            None => AcceptMode::Normal,
+        }
+    }
+
+    /// Number of IPs currently being tracked.
+    pub fn tracked_peers(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> ProbeDetector {
+        ProbeDetector::new(ProbeDetectorConfig {
+            window: Duration::from_secs(10),
+            suspicious_threshold: 3,
+            max_connects_per_window: 5,
+            decoy_cooldown: Duration::from_secs(60),
+            max_tracked_peers: 2,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_pattern_triggers_decoy_mode() {
+        let detector = detector();
+        let prober: IpAddr = "198.51.100.9".parse().unwrap();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(detector.record(prober, ProbeEvent::Connected), AcceptMode::Normal);
+            assert_eq!(detector.record(prober, ProbeEvent::EarlyReset), AcceptMode::Normal);
+        }
+        detector.record(prober, ProbeEvent::Connected);
+        assert_eq!(detector.record(prober, ProbeEvent::ClosedWithoutData), AcceptMode::Decoy);
+        assert_eq!(detector.mode(prober), AcceptMode::Decoy);
+
+        // A well-behaved client is unaffected.
+        assert_eq!(detector.record(client, ProbeEvent::Connected), AcceptMode::Normal);
+        assert_eq!(detector.mode(client), AcceptMode::Normal);
+
+        // Decoy mode lifts after the cooldown.
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(detector.mode(prober), AcceptMode::Normal);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rapid_reconnects_trigger_decoy_mode() {
+        let detector = detector();
+        let ip: IpAddr = "198.51.100.10".parse().unwrap();
+
+        for _ in 0..5 {
+            assert_eq!(detector.record(ip, ProbeEvent::Connected), AcceptMode::Normal);
+        }
+        assert_eq!(detector.record(ip, ProbeEvent::Connected), AcceptMode::Decoy);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_outside_window_are_forgotten() {
+        let detector = detector();
+        let ip: IpAddr = "198.51.100.11".parse().unwrap();
+
+        detector.record(ip, ProbeEvent::EarlyReset);
+        detector.record(ip, ProbeEvent::EarlyReset);
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert_eq!(detector.record(ip, ProbeEvent::EarlyReset), AcceptMode::Normal);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tracked_peers_are_bounded() {
+        let detector = detector();
+        for i in 1..=5u8 {
+            let ip = IpAddr::from([192, 0, 2, i]);
+            detector.record(ip, ProbeEvent::Connected);
+        }
+        assert!(detector.tracked_peers() <= 2);
+    }
+}
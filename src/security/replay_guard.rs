@@ -0,0 +1,218 @@
+//! This module rejects replayed handshake nonces.
+//! A censor that records a client's handshake and replays it later must not get a
+//! valid session. `ReplayGuard` remembers every nonce seen within a sliding time window
+//! and rejects duplicates; `check_fresh` also rejects a handshake whose timestamp is outside
+//! the window, so nothing older than the window needs remembering. Memory is bounded two
+//! ways: nonces are stored in time-bucketed generations that are dropped once they age out
+//! of the window, and at a hard capacity new nonces are refused rather than evicting ones
+//! still inside the window, since that would let their replays through.
+//! Nonces are stored as 64-bit keyed hashes, so each entry has a fixed size and the
+//! chance of a false rejection is negligible.
+//! OTLS/WS checks the random of every ClientHello it reads against one guard.
+
+use std::{
+    collections::{hash_map::RandomState, HashSet, VecDeque},
+    hash::BuildHasher,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Number of generations the window is split into. More generations age entries out
+/// more precisely at the cost of one extra lookup each.
+const GENERATIONS: u32 = 4;
+
+/// `ReplayGuardConfig` sets the window and memory bound of a `ReplayGuard`.
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayGuardConfig {
+    /// How long a nonce is remembered. `check_fresh` accepts timestamps up to half of this
+    /// away from the local clock, so a replay is refused by the timestamp check before its
+    /// nonce can be forgotten.
+    pub window: Duration,
+    /// Maximum number of nonces kept at once. Once full, new nonces are refused until old
+    /// ones age out.
+    pub capacity: usize,
+}
+
+impl Default for ReplayGuardConfig {
+    fn default() -> Self {
+        ReplayGuardConfig {
+            window: Duration::from_secs(120),
+            capacity: 1_000_000,
+        }
+    }
+}
+
+struct Generation {
+    started: Instant,
+    nonces: HashSet<u64>,
+}
+
+/// `ReplayGuard` tracks recently seen nonces.
+pub struct ReplayGuard {
+    config: ReplayGuardConfig,
+    hasher: RandomState,
+    generations: Mutex<VecDeque<Generation>>,
+}
+
+impl ReplayGuard {
+    /// Creates an empty guard.
+    pub fn new(config: ReplayGuardConfig) -> Self {
+        ReplayGuard {
+            config,
+            hasher: RandomState::new(),
+            generations: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn generation_span(&self) -> Duration {
+        self.config.window / GENERATIONS
+    }
+
+    /// Like `check_and_insert`, but first refuses a handshake whose `sent_at` timestamp is more
+    /// than half the window away from the local clock, in either direction.
+    pub fn check_fresh(&self, nonce: &[u8], sent_at: SystemTime) -> bool {
+        let now = SystemTime::now();
+        let skew = now.duration_since(sent_at).unwrap_or_else(|e| e.duration());
+        if skew > self.config.window / 2 {
+            debug!("Replay guard: refused a handshake timestamped {:?} away from the local clock", skew);
+            return false;
+        }
+        self.check_and_insert(nonce)
+    }
+
+    /// Records `nonce` and returns true if it is fresh, or false if it was already seen
+    /// within the window (a replay) or the guard is full.
+    pub fn check_and_insert(&self, nonce: &[u8]) -> bool {
+        let key = self.hasher.hash_one(nonce);
+        let now = Instant::now();
+        let mut generations = self.generations.lock().unwrap();
+
+        // Drop generations whose newest possible entry is older than the window.
+        let span = self.generation_span();
+        while let Some(oldest) = generations.front() {
+            if now.duration_since(oldest.started) >= self.config.window + span {
+                generations.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if generations.iter().any(|g| g.nonces.contains(&key)) {
+            return false;
+        }
+
+        // Evicting a generation still inside the window would reopen its nonces to replay.
+        let total: usize = generations.iter().map(|g| g.nonces.len()).sum();
+        if total >= self.config.capacity {
+            debug!("Replay guard: capacity {} reached, refusing new nonces", self.config.capacity);
+            return false;
+        }
+
+        let needs_new = match generations.back() {
+            Some(newest) => now.duration_since(newest.started) >= span,
+            None => true,
+        };
+        if needs_new {
+            generations.push_back(Generation {
+                started: now,
+                nonces: HashSet::new(),
+            });
+        }
+        generations.back_mut().unwrap().nonces.insert(key);
+        true
+    }
+
+    /// Number of nonces currently remembered.
+    pub fn len(&self) -> usize {
+        self.generations.lock().unwrap().iter().map(|g| g.nonces.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(window_secs: u64, capacity: usize) -> ReplayGuard {
+        ReplayGuard::new(ReplayGuardConfig {
+            window: Duration::from_secs(window_secs),
+            capacity,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recent_duplicate_is_rejected() {
+        let guard = guard(60, 1000);
+        assert!(guard.check_and_insert(b"nonce-1"));
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(!guard.check_and_insert(b"nonce-1"));
+        assert!(guard.check_and_insert(b"nonce-2"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonce_older_than_window_is_evicted() {
+        let guard = guard(60, 1000);
+        assert!(guard.check_and_insert(b"nonce-1"));
+
+        // Keep traffic flowing so generations rotate.
+        for i in 0..20u32 {
+            tokio::time::advance(Duration::from_secs(5)).await;
+            guard.check_and_insert(&i.to_be_bytes());
+        }
+        // 100s later nonce-1 has aged out and is accepted again.
+        assert!(guard.check_and_insert(b"nonce-1"));
+        assert!(!guard.check_and_insert(b"nonce-1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonce_is_kept_for_at_least_the_window() {
+        let guard = guard(60, 1000);
+        assert!(guard.check_and_insert(b"nonce-1"));
+        for i in 0..11u32 {
+            tokio::time::advance(Duration::from_secs(5)).await;
+            guard.check_and_insert(&i.to_be_bytes());
+        }
+        // 55s later it is still inside the window.
+        assert!(!guard.check_and_insert(b"nonce-1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_memory_stays_bounded_under_load() {
+        let guard = guard(60, 5_000);
+        assert!(guard.check_and_insert(b"recorded"));
+        let mut accepted = 1;
+        for i in 0..50_000u32 {
+            if i % 100 == 0 {
+                tokio::time::advance(Duration::from_millis(100)).await;
+            }
+            accepted += guard.check_and_insert(&i.to_be_bytes()) as usize;
+            assert!(guard.len() <= 5_000);
+        }
+        // A flood can't push a nonce out of the window early: once full, new nonces are
+        // refused until old ones age out, so the first one is still protected.
+        assert_eq!(accepted, 5_000);
+        assert!(!guard.check_and_insert(b"recorded"));
+
+        tokio::time::advance(Duration::from_secs(80)).await;
+        assert!(guard.check_and_insert(b"after the flood"));
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_outside_the_window_are_refused() {
+        let guard = guard(60, 1000);
+        let now = SystemTime::now();
+        assert!(guard.check_fresh(b"nonce-1", now - Duration::from_secs(20)));
+        assert!(guard.check_fresh(b"nonce-2", now + Duration::from_secs(20)));
+        assert!(!guard.check_fresh(b"nonce-1", now));
+
+        // Too old or too far ahead to still be in the guard's memory when replayed.
+        assert!(!guard.check_fresh(b"nonce-3", now - Duration::from_secs(40)));
+        assert!(!guard.check_fresh(b"nonce-4", now + Duration::from_secs(40)));
+        assert_eq!(guard.len(), 2);
+    }
+}
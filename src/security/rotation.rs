@@ -0,0 +1,258 @@
+//! This module coordinates obfuscation profile rotation across a fleet of servers.
+//! Every server derives the same profile sequence from a shared secret, but each one
+//! switches at its own offset within the rotation period. A censor therefore never sees
+//! the whole fleet change behaviour at the same instant, yet any server can predict
+//! exactly when (and to what) every other server will rotate.
+//! `SharedSchedule` applies the same idea between a client and its server: both derive
+//! the obfuscation parameters for each time slot from a shared secret, so nothing about
+//! them is ever negotiated on the wire.
+
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Derives 32 bytes of key material from `secret` and a list of labelled inputs.
+/// Both ends of a schedule must feed identical parts in the same order.
+pub(crate) fn derive_bytes(secret: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((secret.len() as u64).to_be_bytes());
+    hasher.update(secret);
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Interprets the first 8 bytes of derived material as a big-endian `u64`.
+pub(crate) fn derive_u64(secret: &[u8], parts: &[&[u8]]) -> u64 {
+    let bytes = derive_bytes(secret, parts);
+    u64::from_be_bytes(bytes[..8].try_into().expect("derived output is 32 bytes"))
+}
+
+/// `RotationSchedule` decides which obfuscation profile a server uses at a given time.
+///
+/// NOTE: Library-only for now. The server picks one obfuscation tier per tunnel from its
+/// configuration and has no fleet identity or schedule secret to rotate with, so nothing in
+/// `main` drives a schedule; it is meant for fleet tooling that pushes configurations.
+#[derive(Debug, Clone)]
+pub struct RotationSchedule {
+    secret: Vec<u8>,
+    server_id: String,
+    period_secs: u64,
+    profile_count: usize,
+    offset_secs: u64,
+}
+
+impl RotationSchedule {
+    /// Creates a schedule for `server_id` rotating among `profile_count` profiles every `period`.
+    pub fn new(secret: &[u8], server_id: &str, period: Duration, profile_count: usize) -> Self {
+        let period_secs = period.as_secs().max(1);
+        let offset_secs = derive_u64(secret, &[b"rotation-offset", server_id.as_bytes()]) % period_secs;
+        RotationSchedule {
+            secret: secret.to_vec(),
+            server_id: server_id.to_string(),
+            period_secs,
+            profile_count: profile_count.max(1),
+            offset_secs,
+        }
+    }
+
+    pub fn server_id(&self) -> &str {
+        &self.server_id
+    }
+
+    /// This server's offset within the rotation period.
+    pub fn offset(&self) -> Duration {
+        Duration::from_secs(self.offset_secs)
+    }
+
+    /// Returns the rotation slot this server is in at `unix_secs`.
+    pub fn slot_at(&self, unix_secs: u64) -> u64 {
+        // Shift by one full period so times before the offset don't underflow.
+        (unix_secs + self.period_secs - self.offset_secs) / self.period_secs
+    }
+
+    /// Returns the profile index this server uses at `unix_secs`.
+    /// The profile sequence is the same for the whole fleet; only the switch times differ.
+    pub fn profile_at(&self, unix_secs: u64) -> usize {
+        let slot = self.slot_at(unix_secs);
+        (derive_u64(&self.secret, &[b"rotation-profile", &slot.to_be_bytes()]) % self.profile_count as u64) as usize
+    }
+
+    /// Returns the first time strictly after `unix_secs` at which this server changes slot.
+    pub fn next_switch_after(&self, unix_secs: u64) -> u64 {
+        let next_slot = self.slot_at(unix_secs) + 1;
+        next_slot * self.period_secs + self.offset_secs - self.period_secs
+    }
+
+    /// Returns the profile index for the current wall-clock time.
+    pub fn current_profile(&self) -> usize {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.profile_at(now)
+    }
+}
+
+/// Upper bound (inclusive) on the padding a `SharedSchedule` slot can select, in bytes.
+pub const SCHEDULE_MAX_PADDING: usize = 255;
+/// Upper bound (inclusive) on the per-packet jitter a `SharedSchedule` slot can select.
+pub const SCHEDULE_MAX_JITTER_MS: u64 = 50;
+
+/// Obfuscation parameters in force for one `SharedSchedule` slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotParams {
+    /// Index of the obfuscation profile to use.
+    pub profile: usize,
+    /// Maximum random padding added per packet, in bytes.
+    pub padding: usize,
+    /// Maximum random delay added per packet.
+    pub timing: Duration,
+}
+
+/// `SharedSchedule` derives per-slot obfuscation parameters from a secret shared by a
+/// client and server. Both ends only need roughly synchronised clocks; callers that want
+/// to tolerate skew can accept the neighbouring slots as well.
+///
+/// NOTE: Library-only for now. Clients have no schedule secret to share yet and the server's
+/// obfuscators take their parameters from the configured tier, so nothing applies the slot
+/// parameters on either end.
+#[derive(Debug, Clone)]
+pub struct SharedSchedule {
+    secret: Vec<u8>,
+    slot_secs: u64,
+    profile_count: usize,
+}
+
+impl SharedSchedule {
+    /// Creates a schedule whose parameters change every `slot_length`, choosing among `profile_count` profiles.
+    pub fn new(secret: &[u8], slot_length: Duration, profile_count: usize) -> Self {
+        SharedSchedule {
+            secret: secret.to_vec(),
+            slot_secs: slot_length.as_secs().max(1),
+            profile_count: profile_count.max(1),
+        }
+    }
+
+    /// Returns the slot number at `unix_secs`.
+    pub fn slot_at(&self, unix_secs: u64) -> u64 {
+        unix_secs / self.slot_secs
+    }
+
+    /// Derives the parameters for `slot`.
+    pub fn params_for_slot(&self, slot: u64) -> SlotParams {
+        let slot = slot.to_be_bytes();
+        let bytes = derive_bytes(&self.secret, &[b"schedule-params", &slot]);
+        let word = |i: usize| u64::from_be_bytes(bytes[i * 8..(i + 1) * 8].try_into().expect("derived output is 32 bytes"));
+        SlotParams {
+            profile: (word(0) % self.profile_count as u64) as usize,
+            padding: (word(1) % (SCHEDULE_MAX_PADDING as u64 + 1)) as usize,
+            timing: Duration::from_millis(word(2) % (SCHEDULE_MAX_JITTER_MS + 1)),
+        }
+    }
+
+    /// Returns the parameters in force at `unix_secs`.
+    pub fn params_at(&self, unix_secs: u64) -> SlotParams {
+        self.params_for_slot(self.slot_at(unix_secs))
+    }
+
+    /// Returns the parameters for the current wall-clock time.
+    pub fn current_params(&self) -> SlotParams {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.params_at(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"fleet-shared-secret";
+    const PERIOD: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_schedule_is_deterministic() {
+        let a = RotationSchedule::new(SECRET, "server-a", PERIOD, 8);
+        let b = RotationSchedule::new(SECRET, "server-a", PERIOD, 8);
+        for t in (1_700_000_000..1_700_100_000).step_by(997) {
+            assert_eq!(a.profile_at(t), b.profile_at(t));
+        }
+    }
+
+    #[test]
+    fn test_servers_switch_at_staggered_times() {
+        let a = RotationSchedule::new(SECRET, "server-a", PERIOD, 8);
+        let b = RotationSchedule::new(SECRET, "server-b", PERIOD, 8);
+        assert_ne!(a.offset(), b.offset());
+
+        let t = 1_700_000_000;
+        let switch_a = a.next_switch_after(t);
+        let switch_b = b.next_switch_after(t);
+        assert_ne!(switch_a, switch_b);
+
+        // Each server follows its own period exactly.
+        for schedule in [&a, &b] {
+            let first = schedule.next_switch_after(t);
+            assert!(first > t && first <= t + PERIOD.as_secs());
+            assert_eq!(schedule.slot_at(first), schedule.slot_at(first - 1) + 1);
+            assert_eq!(schedule.next_switch_after(first), first + PERIOD.as_secs());
+        }
+    }
+
+    #[test]
+    fn test_fleet_follows_the_same_profile_sequence() {
+        let a = RotationSchedule::new(SECRET, "server-a", PERIOD, 8);
+        let b = RotationSchedule::new(SECRET, "server-b", PERIOD, 8);
+
+        // Starting from a period boundary, both servers move into the same slot
+        // (at different times) and agree on the profile for it.
+        let t = 1_700_000_000 - 1_700_000_000 % PERIOD.as_secs();
+        let after_both = a.next_switch_after(t).max(b.next_switch_after(t));
+        assert_eq!(a.slot_at(after_both), b.slot_at(after_both));
+        assert_eq!(a.profile_at(after_both), b.profile_at(after_both));
+    }
+
+    #[test]
+    fn test_different_secrets_give_different_schedules() {
+        let a = RotationSchedule::new(SECRET, "server-a", PERIOD, 1000);
+        let other = RotationSchedule::new(b"another-fleet", "server-a", PERIOD, 1000);
+        let differs = (0..24u64).any(|h| {
+            let t = 1_700_000_000 + h * PERIOD.as_secs();
+            a.profile_at(t) != other.profile_at(t)
+        });
+        assert!(differs);
+    }
+
+    #[test]
+    fn test_client_and_server_derive_identical_slot_params() {
+        let server = SharedSchedule::new(b"user-shared-secret", Duration::from_secs(300), 6);
+        let client = SharedSchedule::new(b"user-shared-secret", Duration::from_secs(300), 6);
+        for t in (1_700_000_000..1_700_050_000).step_by(611) {
+            assert_eq!(server.params_at(t), client.params_at(t));
+        }
+        // Any time within one slot gives the same parameters.
+        // 1_700_000_100 = 5_666_667 * 300, so it is the first second of a slot.
+        let start = 1_700_000_100;
+        assert_eq!(server.params_at(start), server.params_at(start + 299));
+    }
+
+    #[test]
+    fn test_slot_params_change_across_slots_and_secrets() {
+        let schedule = SharedSchedule::new(b"user-shared-secret", Duration::from_secs(300), 6);
+        let other = SharedSchedule::new(b"another-secret", Duration::from_secs(300), 6);
+
+        let slots: Vec<SlotParams> = (0..20u64).map(|s| schedule.params_for_slot(1_000 + s)).collect();
+        assert!(slots.windows(2).any(|w| w[0] != w[1]));
+        assert!((0..20u64).any(|s| schedule.params_for_slot(s) != other.params_for_slot(s)));
+
+        for params in slots {
+            assert!(params.profile < 6);
+            assert!(params.padding <= SCHEDULE_MAX_PADDING);
+            assert!(params.timing <= Duration::from_millis(SCHEDULE_MAX_JITTER_MS));
+        }
+    }
+}
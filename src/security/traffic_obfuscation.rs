@@ -2,54 +2,1130 @@
 //! It aims to make the VPN traffic indistinguishable from legitimate web traffic
 //! and resilient to deep packet inspection and AI-based censorship.
 
-use rand::{self, Rng};
-use std::time::Duration;
-use tokio::time::sleep;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
+use tracing::info;
+
+use crate::protocols::common::ProtocolError;
+use crate::security::handshake_padding::HandshakeSizes;
+use crate::security::rotation::derive_bytes;
+
+/// First header field of every obfuscated frame, used to reject input that was never framed.
+const FRAME_MAGIC: u8 = 0xD7;
+/// Bitmask flag marking a chaff frame, whose payload the receiver discards.
+/// It is masked along with the rest of the header, so only the peer can see it.
+const FLAG_CHAFF: u8 = 0x80;
+/// How many recent real payload sizes chaff sizes are sampled from.
+const CHAFF_SIZE_SAMPLES: usize = 32;
+/// Payload sizes chaff is drawn from before any real packet has been sent.
+const CHAFF_FALLBACK_SIZES: Range<usize> = 64..1200;
+/// Content type of a TLS handshake record, the first byte of a ClientHello cover.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+/// How the HTTP cover starts; a frame without a cover starts with `FRAME_MAGIC` instead.
+const HTTP_COVER_METHOD: &[u8] = b"GET ";
+/// Payloads every obfuscation profile and registered transform must round-trip before use.
+pub(crate) const SELF_TEST_SAMPLES: [&[u8]; 4] = [b"", b"x", b"GET / HTTP/1.1\r\n\r\n", &[0xAB; 1500]];
+/// Cover domain used when none is configured.
+const DEFAULT_MIMIC_DOMAIN: &str = "www.example.com";
+/// Length of the per-packet keystream nonce.
+const NONCE_LEN: usize = 8;
+/// Length of the random nonce in front of a masked frame header.
+const HEADER_NONCE_LEN: usize = 4;
+/// Bytes of the `[magic][version][strategy bitmask]` header fields.
+const HEADER_FIELDS_LEN: usize = 3;
+/// Bytes of a current frame header, `[nonce][masked fields]`, before the strategy layers.
+const FRAME_HEADER_LEN: usize = HEADER_NONCE_LEN + HEADER_FIELDS_LEN;
+/// Bytes of the keyed tag `FrameChecksum` appends to a frame.
+const CHECKSUM_LEN: usize = 8;
+
+/// `FrameVersion` is a revision of the frame header `[magic][version][strategy bitmask]`.
+/// A receiver reads every version up to the one it was built for, so peers can upgrade one
+/// side at a time; a sender can also write an older version for a peer that hasn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FrameVersion {
+    /// Every bitmask bit names a strategy, and chaff is a frame with an empty payload.
+    V1 = 1,
+    /// The top bitmask bit is `FLAG_CHAFF`, so chaff can carry a payload of realistic size.
+    V2 = 2,
+    /// The header fields are XORed with a mask derived from the session key and a random
+    /// per-frame nonce sent in front of them, so no header byte is fixed on the wire.
+    V3 = 3,
+}
+
+impl FrameVersion {
+    /// The version written by `transform` and the newest one `deobfuscate_data` reads.
+    pub const CURRENT: FrameVersion = FrameVersion::V3;
+    /// Every version, oldest first.
+    pub const ALL: [FrameVersion; 3] = [FrameVersion::V1, FrameVersion::V2, FrameVersion::V3];
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|version| *version as u8 == byte)
+    }
+}
+
+/// Error for a frame that doesn't match the obfuscation framing: an `ObfuscationError`
+/// carried inside an `InvalidData` `io::Error`.
+fn malformed(msg: &str) -> io::Error {
+    ProtocolError::ObfuscationError(format!("malformed obfuscated frame: {}", msg)).into()
+}
+
+/// XORs `data` in place with a ChaCha20 keystream derived from `key` and the packet `nonce`.
+/// Applying it twice with the same inputs restores the original bytes.
+fn apply_keystream(key: &[u8], nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+    let seed = derive_bytes(key, &[b"obfuscator-keystream", nonce]);
+    let mut keystream = ChaCha20Rng::from_seed(seed);
+    let mut block = [0u8; 64];
+    for chunk in data.chunks_mut(block.len()) {
+        keystream.fill_bytes(&mut block[..chunk.len()]);
+        for (byte, mask) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= mask;
+        }
+    }
+}
+
+/// Mask XORed over the header fields of a version 3 frame, derived from the session `key`
+/// (empty for an unkeyed `Obfuscator`) and the frame's header `nonce`.
+fn header_mask(key: &[u8], nonce: &[u8]) -> [u8; HEADER_FIELDS_LEN] {
+    let mask = derive_bytes(key, &[b"obfuscator-header", nonce]);
+    [mask[0], mask[1], mask[2]]
+}
+
+/// Checks that every strategy id fits the header bitmask below `FLAG_CHAFF` and that no two
+/// strategies share one, since the bitmask couldn't tell them apart.
+fn check_strategy_ids(strategies: &[Box<dyn ObfuscationStrategy>]) -> Result<(), ProtocolError> {
+    let mut used = 0u8;
+    for strategy in strategies {
+        let id = strategy.id();
+        if id >= 7 {
+            return Err(ProtocolError::ObfuscationError(format!("strategy id {} is outside 0..7", id)));
+        }
+        if used & (1 << id) != 0 {
+            return Err(ProtocolError::ObfuscationError(format!("strategy id {} is used more than once", id)));
+        }
+        used |= 1 << id;
+    }
+    Ok(())
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Reads an unsigned LEB128 varint from the front of `data`, returning it and the bytes consumed.
+pub(crate) fn read_varint(data: &[u8]) -> io::Result<(usize, usize)> {
+    let mut value: usize = 0;
+    for (i, byte) in data.iter().enumerate() {
+        // More than ten groups can't fit in a usize.
+        if i >= 10 {
+            break;
+        }
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(malformed("truncated or oversized varint"))
+}
+
+/// Strategy id of `KeystreamMask`.
+pub const STRATEGY_KEYSTREAM: u8 = 0;
+/// Strategy id of `NoisePadding`.
+pub const STRATEGY_NOISE: u8 = 1;
+/// Strategy id of `HttpMimicry`.
+pub const STRATEGY_HTTP_MIMICRY: u8 = 2;
+/// Strategy id of `TlsHelloMimicry`.
+pub const STRATEGY_TLS_MIMICRY: u8 = 3;
+/// Strategy id of `BucketPadding`.
+pub const STRATEGY_SIZE_BUCKETS: u8 = 4;
+/// Strategy id of `FrameChecksum`.
+pub const STRATEGY_CHECKSUM: u8 = 5;
+
+/// `ObfuscationStrategy` is one reversible layer of byte-level obfuscation.
+/// An `Obfuscator` applies its strategies in order and reverses them in reverse order,
+/// so each strategy only has to undo its own layer.
+pub trait ObfuscationStrategy: Send + Sync {
+    /// Stable id (0..=6) recorded in the frame header bitmask when this layer is applied.
+    /// Ids must be unique within one `Obfuscator`; bit 7 of the bitmask is reserved for `FLAG_CHAFF`.
+    fn id(&self) -> u8;
+    /// Wraps `data` in this strategy's layer.
+    fn apply(&self, data: &[u8]) -> Vec<u8>;
+    /// Removes this strategy's layer, or returns `InvalidData` if it is malformed.
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+    /// Whether this strategy disguises the frame as another protocol. Covers run after every
+    /// other layer and wrap the finished frame, header included, so the packet starts with the
+    /// cover bytes rather than the frame header. Their `reverse` must pass a frame without their
+    /// cover through unchanged.
+    fn is_cover(&self) -> bool {
+        false
+    }
+    /// Whether this strategy seals the whole frame, header included. Seals run on every frame
+    /// whatever the per-packet mask says, just inside any cover, and are checked before the
+    /// header is read, so a damaged header can't be mistaken for a different frame.
+    fn is_seal(&self) -> bool {
+        false
+    }
+    /// Whether this strategy changes frames at its settings. Noise of at most zero bytes, or a
+    /// cover applied with probability 0, leaves them as they are and adds no strength (see
+    /// `Obfuscator::level`).
+    fn is_active(&self) -> bool {
+        true
+    }
+}
+
+/// `NoisePadding` appends random noise to obscure packet size patterns.
+/// Layer: `[varint noise_len][data][noise]`.
+pub struct NoisePadding {
+    max_noise_bytes: usize,
+    rng: Mutex<StdRng>,
+}
+
+impl NoisePadding {
+    /// Pads each packet with `0..max_noise_bytes` random bytes.
+    pub fn new(max_noise_bytes: usize) -> Self {
+        Self::with_rng(max_noise_bytes, StdRng::from_entropy())
+    }
+
+    fn with_rng(max_noise_bytes: usize, rng: StdRng) -> Self {
+        NoisePadding {
+            max_noise_bytes,
+            rng: Mutex::new(rng),
+        }
+    }
+}
+
+impl ObfuscationStrategy for NoisePadding {
+    fn id(&self) -> u8 {
+        STRATEGY_NOISE
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let mut rng = self.rng.lock().unwrap();
+        let noise_len = if self.max_noise_bytes > 0 { rng.gen_range(0..self.max_noise_bytes) } else { 0 };
+
+        let mut out = Vec::with_capacity(data.len() + noise_len + 4);
+        write_varint(&mut out, noise_len);
+        out.extend_from_slice(data);
+        for _ in 0..noise_len {
+            out.push(rng.gen());
+        }
+        out
+    }
+
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let (noise_len, used) = read_varint(data)?;
+        let rest = &data[used..];
+        if noise_len > rest.len() {
+            return Err(malformed("noise longer than frame"));
+        }
+        Ok(rest[..rest.len() - noise_len].to_vec())
+    }
+
+    fn is_active(&self) -> bool {
+        // The noise length is drawn from `0..max_noise_bytes`, so below 2 it is always zero.
+        self.max_noise_bytes > 1
+    }
+}
+
+/// `HttpMimicry` sometimes prepends a fake HTTP request header so packets look like web traffic.
+/// Cover: `[header][frame]`; the receiver finds the end of the header by its blank line.
+pub struct HttpMimicry {
+    probability: f64,
+    header: Vec<u8>,
+    rng: Mutex<StdRng>,
+}
+
+impl HttpMimicry {
+    /// Prepends the fake header with the given probability (0.0..=1.0), naming `www.example.com`.
+    pub fn new(probability: f64) -> Self {
+        Self::with_host(probability, DEFAULT_MIMIC_DOMAIN)
+    }
+
+    /// Like `new`, but the header's `Host:` line names `host` (usually the mimic domain).
+    pub fn with_host(probability: f64, host: &str) -> Self {
+        Self::with_rng(probability, host, StdRng::from_entropy())
+    }
+
+    fn with_rng(probability: f64, host: &str, rng: StdRng) -> Self {
+        HttpMimicry {
+            probability,
+            header: format!("GET /index.html HTTP/1.1\r\nHost: {}\r\nUser-Agent: Mozilla/5.0\r\n\r\n", host).into_bytes(),
+            rng: Mutex::new(rng),
+        }
+    }
+}
+
+impl ObfuscationStrategy for HttpMimicry {
+    fn id(&self) -> u8 {
+        STRATEGY_HTTP_MIMICRY
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        if !self.rng.lock().unwrap().gen_bool(self.probability.clamp(0.0, 1.0)) {
+            return data.to_vec();
+        }
+        [self.header.as_slice(), data].concat()
+    }
+
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if !data.starts_with(HTTP_COVER_METHOD) {
+            return Ok(data.to_vec());
+        }
+        let end = data
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| malformed("unterminated HTTP cover"))?;
+        Ok(data[end + 4..].to_vec())
+    }
+
+    fn is_cover(&self) -> bool {
+        true
+    }
+
+    fn is_active(&self) -> bool {
+        self.probability > 0.0
+    }
+}
+
+/// `TlsHelloMimicry` sometimes prepends a synthesized TLS 1.3 ClientHello record, so passive
+/// inspection of the HTTPS port sees a TLS handshake rather than a plaintext HTTP request.
+/// Cover: `[ClientHello record][frame]`; the record header gives its length.
+pub struct TlsHelloMimicry {
+    probability: f64,
+    sni: String,
+    /// Sizes the ClientHello is padded to; `None` sends it at its natural size.
+    padding: Option<&'static HandshakeSizes>,
+    rng: Mutex<StdRng>,
+}
+
+impl TlsHelloMimicry {
+    /// Prepends a ClientHello for `sni` with the given probability (0.0..=1.0).
+    pub fn new(probability: f64, sni: &str) -> Self {
+        Self::with_rng(probability, sni, StdRng::from_entropy())
+    }
+
+    fn with_rng(probability: f64, sni: &str, rng: StdRng) -> Self {
+        TlsHelloMimicry {
+            probability,
+            sni: sni.to_string(),
+            padding: None,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Pads each ClientHello to a size drawn from `sizes` (see `HandshakeSizes::pad_client_hello`).
+    pub fn with_handshake_padding(mut self, sizes: &'static HandshakeSizes) -> Self {
+        self.padding = Some(sizes);
+        self
+    }
+}
+
+impl ObfuscationStrategy for TlsHelloMimicry {
+    fn id(&self) -> u8 {
+        STRATEGY_TLS_MIMICRY
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let mut rng = self.rng.lock().unwrap();
+        if !rng.gen_bool(self.probability.clamp(0.0, 1.0)) {
+            return data.to_vec();
+        }
+        let mut hello = fake_client_hello(&self.sni, &mut *rng);
+        if let Some(sizes) = self.padding {
+            hello = sizes.pad_client_hello(&hello, &mut *rng).expect("a synthesized ClientHello is well-formed");
+        }
+        [hello.as_slice(), data].concat()
+    }
+
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.first() != Some(&TLS_HANDSHAKE_RECORD) {
+            return Ok(data.to_vec());
+        }
+        if data.len() < 5 {
+            return Err(malformed("truncated TLS cover record"));
+        }
+        let record_len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let rest = &data[5..];
+        if record_len > rest.len() {
+            return Err(malformed("TLS cover record longer than frame"));
+        }
+        Ok(rest[record_len..].to_vec())
+    }
+
+    fn is_cover(&self) -> bool {
+        true
+    }
+
+    fn is_active(&self) -> bool {
+        self.probability > 0.0
+    }
+}
+
+/// `BucketPadding` pads every frame up to the next size from a fixed list, so packet lengths
+/// only ever take a handful of values instead of following the payload distribution.
+/// It must be the outermost layer: the target size includes the frame header (but not a
+/// mimicry cover, which wraps the frame). Frames already larger than the biggest bucket are sent unpadded rather than split.
+/// Layer: `[u32 inner_len][inner][padding]`.
+pub struct BucketPadding {
+    buckets: Vec<usize>,
+    rng: Mutex<StdRng>,
+}
+
+impl BucketPadding {
+    /// Pads to the sizes in `buckets` (order does not matter).
+    pub fn new(buckets: Vec<usize>) -> Self {
+        Self::with_rng(buckets, StdRng::from_entropy())
+    }
+
+    fn with_rng(mut buckets: Vec<usize>, rng: StdRng) -> Self {
+        buckets.sort_unstable();
+        buckets.dedup();
+        BucketPadding {
+            buckets,
+            rng: Mutex::new(rng),
+        }
+    }
+}
+
+impl ObfuscationStrategy for BucketPadding {
+    fn id(&self) -> u8 {
+        STRATEGY_SIZE_BUCKETS
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let unpadded = FRAME_HEADER_LEN + 4 + data.len();
+        let target = self.buckets.iter().copied().find(|b| *b >= unpadded).unwrap_or(unpadded);
+
+        let mut out = Vec::with_capacity(target - FRAME_HEADER_LEN);
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+        let padding_start = out.len();
+        out.resize(target - FRAME_HEADER_LEN, 0);
+        self.rng.lock().unwrap().fill_bytes(&mut out[padding_start..]);
+        out
+    }
+
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.len() < 4 {
+            return Err(malformed("truncated bucket length"));
+        }
+        let (len, rest) = data.split_at(4);
+        let len = u32::from_be_bytes(len.try_into().expect("split at 4")) as usize;
+        if len > rest.len() {
+            return Err(malformed("bucket length longer than frame"));
+        }
+        Ok(rest[..len].to_vec())
+    }
+}
+
+/// Appends `body` prefixed with its length as a big-endian integer of `len_bytes` bytes.
+fn push_with_len(out: &mut Vec<u8>, len_bytes: usize, body: &[u8]) {
+    let len = (body.len() as u32).to_be_bytes();
+    out.extend_from_slice(&len[4 - len_bytes..]);
+    out.extend_from_slice(body);
+}
+
+/// Appends a TLS extension (`type`, u16 length, `body`).
+fn push_extension(out: &mut Vec<u8>, ext_type: u16, body: &[u8]) {
+    out.extend_from_slice(&ext_type.to_be_bytes());
+    push_with_len(out, 2, body);
+}
+
+/// Synthesizes a TLS 1.3 ClientHello record for `sni`, shaped like a modern browser's:
+/// random, session id and key share are fresh random bytes, and the cipher suites,
+/// groups and signature algorithms are the common browser defaults.
+pub fn fake_client_hello<R: RngCore>(sni: &str, rng: &mut R) -> Vec<u8> {
+    let mut random = [0u8; 32];
+    let mut session_id = [0u8; 32];
+    let mut key_share = [0u8; 32];
+    rng.fill_bytes(&mut random);
+    rng.fill_bytes(&mut session_id);
+    rng.fill_bytes(&mut key_share);
+
+    let mut extensions = Vec::new();
+    // server_name: one host_name entry.
+    let mut server_name = Vec::new();
+    server_name.push(0x00);
+    push_with_len(&mut server_name, 2, sni.as_bytes());
+    let mut server_name_list = Vec::new();
+    push_with_len(&mut server_name_list, 2, &server_name);
+    push_extension(&mut extensions, 0x0000, &server_name_list);
+    // supported_groups: x25519, secp256r1, secp384r1.
+    push_extension(&mut extensions, 0x000a, &[0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18]);
+    // ALPN: h2, http/1.1.
+    push_extension(&mut extensions, 0x0010, b"\x00\x0c\x02h2\x08http/1.1");
+    // signature_algorithms.
+    push_extension(
+        &mut extensions,
+        0x000d,
+        &[0x00, 0x10, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01, 0x08, 0x06, 0x06, 0x01],
+    );
+    // key_share: one x25519 share.
+    let mut share = vec![0x00, 0x1d];
+    push_with_len(&mut share, 2, &key_share);
+    let mut shares = Vec::new();
+    push_with_len(&mut shares, 2, &share);
+    push_extension(&mut extensions, 0x0033, &shares);
+    // psk_key_exchange_modes: psk_dhe_ke.
+    push_extension(&mut extensions, 0x002d, &[0x01, 0x01]);
+    // supported_versions: TLS 1.3, TLS 1.2.
+    push_extension(&mut extensions, 0x002b, &[0x04, 0x03, 0x04, 0x03, 0x03]);
+
+    let mut hello = Vec::with_capacity(512);
+    hello.extend_from_slice(&[0x03, 0x03]); // legacy_version: TLS 1.2
+    hello.extend_from_slice(&random);
+    push_with_len(&mut hello, 1, &session_id);
+    push_with_len(
+        &mut hello,
+        2,
+        &[0x13, 0x01, 0x13, 0x02, 0x13, 0x03, 0xc0, 0x2b, 0xc0, 0x2f, 0xc0, 0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc, 0xa8],
+    );
+    hello.extend_from_slice(&[0x01, 0x00]); // compression_methods: null
+    push_with_len(&mut hello, 2, &extensions);
+
+    let mut handshake = vec![0x01]; // client_hello
+    push_with_len(&mut handshake, 3, &hello);
+
+    let mut record = vec![TLS_HANDSHAKE_RECORD, 0x03, 0x01]; // legacy TLS 1.0 version
+    push_with_len(&mut record, 2, &handshake);
+    record
+}
+
+/// `KeystreamMask` XORs the payload with a keyed ChaCha20 keystream and a random per-packet nonce.
+/// Layer: `[nonce][masked data]`.
+pub struct KeystreamMask {
+    key: Vec<u8>,
+    rng: Mutex<StdRng>,
+}
+
+impl KeystreamMask {
+    /// Masks payloads with a keystream derived from `key`. Both peers must use the same key.
+    pub fn new(key: &[u8]) -> Self {
+        Self::with_rng(key, StdRng::from_entropy())
+    }
+
+    fn with_rng(key: &[u8], rng: StdRng) -> Self {
+        KeystreamMask {
+            key: key.to_vec(),
+            rng: Mutex::new(rng),
+        }
+    }
+}
+
+impl ObfuscationStrategy for KeystreamMask {
+    fn id(&self) -> u8 {
+        STRATEGY_KEYSTREAM
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.lock().unwrap().fill_bytes(&mut nonce);
+        let mut out = Vec::with_capacity(NONCE_LEN + data.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(data);
+        apply_keystream(&self.key, &nonce, &mut out[NONCE_LEN..]);
+        out
+    }
+
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return Err(malformed("truncated nonce"));
+        }
+        let (nonce, masked) = data.split_at(NONCE_LEN);
+        let nonce = <[u8; NONCE_LEN]>::try_from(nonce).expect("split at NONCE_LEN");
+        let mut payload = masked.to_vec();
+        apply_keystream(&self.key, &nonce, &mut payload);
+        Ok(payload)
+    }
+}
+
+/// `FrameChecksum` appends a tag keyed with a shared secret, so a receiver rejects any frame
+/// that was corrupted or altered on the way. It seals the whole frame (see `is_seal`).
+/// Layer: `[frame][tag]`.
+pub struct FrameChecksum {
+    key: Vec<u8>,
+}
+
+impl FrameChecksum {
+    /// Tags frames with `key`. Both peers must use the same key.
+    pub fn new(key: &[u8]) -> Self {
+        FrameChecksum { key: key.to_vec() }
+    }
+
+    fn tag(&self, frame: &[u8]) -> [u8; CHECKSUM_LEN] {
+        let digest = derive_bytes(&self.key, &[b"obfuscator-checksum", frame]);
+        digest[..CHECKSUM_LEN].try_into().expect("digest is 32 bytes")
+    }
+}
+
+impl ObfuscationStrategy for FrameChecksum {
+    fn id(&self) -> u8 {
+        STRATEGY_CHECKSUM
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + CHECKSUM_LEN);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&self.tag(data));
+        out
+    }
+
+    fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.len() < CHECKSUM_LEN {
+            return Err(malformed("truncated checksum"));
+        }
+        let (frame, tag) = data.split_at(data.len() - CHECKSUM_LEN);
+        // Compared without an early exit, so the time taken doesn't tell how much matched.
+        let difference = self.tag(frame).iter().zip(tag).fold(0, |difference, (a, b)| difference | (a ^ b));
+        if difference != 0 {
+            return Err(malformed("checksum mismatch"));
+        }
+        Ok(frame.to_vec())
+    }
+
+    fn is_seal(&self) -> bool {
+        true
+    }
+}
+
+/// The kind of fake header the mimicry layer prepends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MimicryStyle {
+    /// A plaintext HTTP/1.1 request header.
+    Http,
+    /// A TLS 1.3 ClientHello record, for ports that claim to carry HTTPS.
+    TlsClientHello,
+}
+
+/// `ObfuscatorConfig` tunes how aggressively an `Obfuscator` disguises traffic.
+/// Low-latency profiles turn the delay down; high-obfuscation profiles turn noise and mimicry up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObfuscatorConfig {
+    /// Maximum random noise appended per packet, in bytes (exclusive upper bound).
+    pub max_noise_bytes: usize,
+    /// Probability (0.0..=1.0) of prepending a fake header.
+    pub mimicry_probability: f64,
+    /// What the fake header looks like.
+    pub mimicry_style: MimicryStyle,
+    /// Maximum random delay per packet, in milliseconds (exclusive upper bound).
+    pub max_delay_ms: u64,
+}
+
+impl Default for ObfuscatorConfig {
+    fn default() -> Self {
+        ObfuscatorConfig {
+            max_noise_bytes: 16,
+            mimicry_probability: 0.3,
+            mimicry_style: MimicryStyle::Http,
+            max_delay_ms: 50,
+        }
+    }
+}
+
+/// `ObfuscationProfileTier` lets a client pick, per connection, how much performance to trade
+/// for censorship resistance. It is carried in `TunnelConfig.protocol_params` under `obfuscation_tier`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObfuscationProfileTier {
+    /// Framing only: no noise, mimicry or delay. For fast, uncensored links.
+    Minimal,
+    /// The default settings.
+    Balanced,
+    /// Heavy noise, TLS mimicry on every packet, extra jitter and size-bucket padding.
+    Maximal,
+}
+
+impl ObfuscationProfileTier {
+    /// Every tier, lightest first.
+    pub const ALL: [ObfuscationProfileTier; 3] =
+        [ObfuscationProfileTier::Minimal, ObfuscationProfileTier::Balanced, ObfuscationProfileTier::Maximal];
+
+    /// Reads the tier from connection parameters, if one was requested.
+    pub fn from_params(params: &HashMap<String, String>) -> Option<Self> {
+        match params.get("obfuscation_tier")?.to_lowercase().as_str() {
+            "minimal" => Some(ObfuscationProfileTier::Minimal),
+            "balanced" => Some(ObfuscationProfileTier::Balanced),
+            "maximal" => Some(ObfuscationProfileTier::Maximal),
+            _ => None,
+        }
+    }
+
+    /// The name this tier is requested by, as read by `from_params`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObfuscationProfileTier::Minimal => "minimal",
+            ObfuscationProfileTier::Balanced => "balanced",
+            ObfuscationProfileTier::Maximal => "maximal",
+        }
+    }
+
+    /// The obfuscator settings bundled with this tier.
+    pub fn config(&self) -> ObfuscatorConfig {
+        match self {
+            ObfuscationProfileTier::Minimal => ObfuscatorConfig {
+                max_noise_bytes: 0,
+                mimicry_probability: 0.0,
+                mimicry_style: MimicryStyle::Http,
+                max_delay_ms: 0,
+            },
+            ObfuscationProfileTier::Balanced => ObfuscatorConfig::default(),
+            ObfuscationProfileTier::Maximal => ObfuscatorConfig {
+                max_noise_bytes: 64,
+                mimicry_probability: 1.0,
+                mimicry_style: MimicryStyle::TlsClientHello,
+                max_delay_ms: 100,
+            },
+        }
+    }
+
+    /// Frame sizes to pad to, if this tier normalizes packet lengths.
+    pub fn size_buckets(&self) -> Option<Vec<usize>> {
+        match self {
+            ObfuscationProfileTier::Maximal => Some(vec![512, 1024, 1460]),
+            _ => None,
+        }
+    }
+}
+
+/// `ObfuscationLevel` grades how much an `Obfuscator` hides, weakest first, so a policy can
+/// require a minimum (`min_obfuscation_level` in the configuration). Each level includes the
+/// ones below it: padding without scrambling still counts as `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObfuscationLevel {
+    /// Payload bytes are sent as they are (no `KeystreamMask`).
+    None,
+    /// Payloads are masked with a keystream, but packet sizes follow the data.
+    Scrambled,
+    /// Masked, and packet sizes are blurred by noise or size buckets.
+    Padded,
+    /// Masked, padded, and dressed in an HTTP or TLS cover.
+    Covered,
+}
+
+impl ObfuscationLevel {
+    /// The name this level is configured by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObfuscationLevel::None => "none",
+            ObfuscationLevel::Scrambled => "scrambled",
+            ObfuscationLevel::Padded => "padded",
+            ObfuscationLevel::Covered => "covered",
+        }
+    }
+}
+
+/// Largest noise range a profile may configure; more than this only wastes bandwidth.
+const MAX_NOISE_LIMIT: usize = 64 * 1024;
+/// Largest jitter a profile may configure; more than this stalls interactive traffic.
+const MAX_DELAY_LIMIT_MS: u64 = 5_000;
+
+impl ObfuscatorConfig {
+    /// Checks that every field is within its meaningful range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.mimicry_probability) {
+            return Err(format!("mimicry_probability must be within 0.0..=1.0, got {}", self.mimicry_probability));
+        }
+        if self.max_noise_bytes > MAX_NOISE_LIMIT {
+            return Err(format!("max_noise_bytes must be at most {}, got {}", MAX_NOISE_LIMIT, self.max_noise_bytes));
+        }
+        if self.max_delay_ms > MAX_DELAY_LIMIT_MS {
+            return Err(format!("max_delay_ms must be at most {}, got {}", MAX_DELAY_LIMIT_MS, self.max_delay_ms));
+        }
+        Ok(())
+    }
+}
+
+/// Runs every named profile through an obfuscate→deobfuscate round trip before serving traffic.
+/// Returns an `ObfuscationError` naming the first broken profile.
+pub fn validate_profiles(profiles: &[(&str, ObfuscatorConfig)]) -> Result<(), ProtocolError> {
+    for (name, config) in profiles {
+        config
+            .validate()
+            .map_err(|e| ProtocolError::ObfuscationError(format!("profile '{}': {}", name, e)))?;
+
+        let obfuscator = Obfuscator::build(*config, None, None, StdRng::from_entropy());
+        for sample in SELF_TEST_SAMPLES {
+            let recovered = obfuscator
+                .deobfuscate_data(&obfuscator.transform(sample))
+                .map_err(|e| ProtocolError::ObfuscationError(format!("profile '{}': round trip failed: {}", name, e)))?;
+            if recovered != sample {
+                return Err(ProtocolError::ObfuscationError(format!(
+                    "profile '{}': round trip returned different bytes",
+                    name
+                )));
+            }
+        }
+        info!("Obfuscation profile '{}' passed its self-check", name);
+    }
+    Ok(())
+}
+
+/// `ObfuscatorMetrics` is a snapshot of an `Obfuscator`'s overhead counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObfuscatorMetrics {
+    /// Payload bytes handed to the obfuscator.
+    pub input_bytes: u64,
+    /// Framed bytes produced, including headers, noise and mimicry.
+    pub output_bytes: u64,
+    /// Packets that carried a fake HTTP or TLS header.
+    pub packets_mimicked: u64,
+    /// Random bytes added by noise and bucket padding.
+    pub noise_bytes: u64,
+}
+
+impl ObfuscatorMetrics {
+    /// Output bytes per input byte, e.g. 1.25 for 25% overhead. Zero before any traffic.
+    pub fn overhead_ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 0.0;
+        }
+        self.output_bytes as f64 / self.input_bytes as f64
+    }
+}
+
+#[derive(Default)]
+struct MetricCounters {
+    input_bytes: AtomicU64,
+    output_bytes: AtomicU64,
+    packets_mimicked: AtomicU64,
+    noise_bytes: AtomicU64,
+}
+
+/// Upper bounds of the stage latency histogram buckets.
+pub const STAGE_LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_micros(1),
+    Duration::from_micros(5),
+    Duration::from_micros(10),
+    Duration::from_micros(50),
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+];
+
+/// Name a strategy's latency is reported under: the built-in transform name for a built-in id.
+pub fn stage_name(id: u8) -> String {
+    match id {
+        STRATEGY_KEYSTREAM => "keystream".to_string(),
+        STRATEGY_NOISE => "noise".to_string(),
+        STRATEGY_HTTP_MIMICRY => "http-mimicry".to_string(),
+        STRATEGY_TLS_MIMICRY => "tls-mimicry".to_string(),
+        STRATEGY_SIZE_BUCKETS => "size-buckets".to_string(),
+        STRATEGY_CHECKSUM => "checksum".to_string(),
+        id => format!("strategy-{}", id),
+    }
+}
+
+/// `StageLatency` is the latency histogram of one strategy: the time each `apply` or `reverse`
+/// of it took.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageLatency {
+    /// The strategy, as named by `stage_name`.
+    pub stage: String,
+    /// Samples at or under each bound of `STAGE_LATENCY_BUCKETS`, cumulative as in Prometheus.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_nanos: u64,
+}
+
+#[derive(Default)]
+struct StageHistogram {
+    buckets: [AtomicU64; STAGE_LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl StageHistogram {
+    fn record(&self, elapsed: Duration) {
+        for (bucket, bound) in self.buckets.iter().zip(STAGE_LATENCY_BUCKETS) {
+            if elapsed <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Parameter presets the mutation cycle rotates through, as
+/// `(max_noise_bytes, mimicry_probability, max_delay_ms)`.
+/// The mimicry style is left alone, so a receiver that hasn't mutated yet can still reverse every frame.
+const MUTATION_PRESETS: [(usize, f64, u64); 3] = [(16, 0.3, 50), (48, 0.1, 20), (8, 0.6, 80)];
+/// How often the mutation cycle switches presets.
+const MUTATION_INTERVAL: Duration = Duration::from_secs(60);
 
 /// `Obfuscator` manages various traffic obfuscation strategies.
 pub struct Obfuscator {
-    // Placeholder for configuration related to obfuscation strategies.
-    // e.g., which mimicry patterns to use, how often to mutate, etc.
-    _config_placeholder: (), 
+    /// Current parameters; replaced by `apply_params` and the mutation cycle.
+    config: RwLock<ObfuscatorConfig>,
+    /// Byte-level layers, applied in order and reversed in reverse order.
+    strategies: RwLock<Vec<Box<dyn ObfuscationStrategy>>>,
+    /// Keystream key, kept so the standard stack can be rebuilt on mutation.
+    key: Option<Vec<u8>>,
+    /// Bucket sizes for the outermost padding layer, if any.
+    size_buckets: Option<Vec<usize>>,
+    /// True when the strategies were supplied by the caller; mutation then only touches timing.
+    custom_strategies: bool,
+    /// Cover domain shown by the mimicry layer.
+    mimic_domain: String,
+    /// Source of timing decisions (and of the strategies' seeds).
+    rng: Mutex<StdRng>,
+    metrics: MetricCounters,
+    /// Latency histograms indexed by strategy id, if stage timing is on (see `with_stage_timing`).
+    stage_timing: Option<Box<[StageHistogram; 7]>>,
+    /// When the last real (non-chaff) packet was obfuscated.
+    last_activity: Mutex<Instant>,
+    /// Payload sizes of the most recent real packets, which chaff sizes are sampled from.
+    recent_sizes: Mutex<VecDeque<usize>>,
 }
 
 impl Obfuscator {
-    /// Creates a new `Obfuscator` instance.
+    /// Creates a new `Obfuscator` instance with the default configuration and an OS-seeded RNG.
     pub fn new() -> Self {
+        Self::with_config(ObfuscatorConfig::default())
+    }
+
+    /// Creates an `Obfuscator` with a custom configuration and an OS-seeded RNG.
+    pub fn with_config(config: ObfuscatorConfig) -> Self {
         println!("Traffic Obfuscator: Initialized.");
-        Obfuscator {
-            _config_placeholder: (),
+        Self::build(config, None, None, StdRng::from_entropy())
+    }
+
+    /// Creates an `Obfuscator` whose output is fully determined by `seed`.
+    /// Used for reproducible tests and fuzzing, and by peers that must generate matching noise.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::build(ObfuscatorConfig::default(), None, None, StdRng::seed_from_u64(seed))
+    }
+
+    /// Creates an `Obfuscator` that masks payloads with a keystream derived from `key`
+    /// (e.g. the `TunnelConfig.user_id`). Both peers must use the same key.
+    pub fn with_key(key: &[u8]) -> Self {
+        Self::build(ObfuscatorConfig::default(), Some(key), None, StdRng::from_entropy())
+    }
+
+    /// Creates an `Obfuscator` that pads every frame up to the next size in `buckets`
+    /// (e.g. `[512, 1024, 1460]`) to defeat packet-length fingerprinting.
+    pub fn with_size_buckets(buckets: Vec<usize>) -> Self {
+        let mut obfuscator = Self::build(ObfuscatorConfig::default(), None, None, StdRng::from_entropy());
+        obfuscator.add_size_buckets(buckets);
+        obfuscator
+    }
+
+    /// Creates an `Obfuscator` with the settings bundled in `tier`.
+    /// `key` and `mimic_domain` usually come from the connection's `TunnelConfig`.
+    pub fn for_tier(tier: ObfuscationProfileTier, key: Option<&[u8]>, mimic_domain: Option<&str>) -> Self {
+        let mut obfuscator = Self::build(tier.config(), key, mimic_domain, StdRng::from_entropy());
+        if let Some(buckets) = tier.size_buckets() {
+            obfuscator.add_size_buckets(buckets);
         }
+        obfuscator
     }
 
-    /// Applies obfuscation to outgoing data.
-    /// This method will integrate various techniques like mimicry, blending, and mutation.
-    pub async fn obfuscate_data(&self, data: &[u8]) -> Vec<u8> {
-        let mut rng = rand::thread_rng();
-        let mut obfuscated_data = data.to_vec();
+    fn add_size_buckets(&mut self, buckets: Vec<usize>) {
+        let seed = self.rng.get_mut().unwrap().gen();
+        // Outermost, so the padded size covers every other layer.
+        self.strategies
+            .get_mut()
+            .unwrap()
+            .push(Box::new(BucketPadding::with_rng(buckets.clone(), StdRng::seed_from_u64(seed))));
+        self.size_buckets = Some(buckets);
+    }
 
-        // 1. Add Random Noise/Padding (to obscure packet size patterns)
-        let noise_len = rng.gen_range(0..16); // Add 0-15 bytes of random noise
-        for _ in 0..noise_len {
-            obfuscated_data.push(rng.gen());
+    /// Creates an `Obfuscator` that mimics traffic to `domain` (e.g. the `TunnelConfig.mimic_domain`).
+    pub fn with_mimic_domain(domain: String) -> Self {
+        Self::build(ObfuscatorConfig::default(), None, Some(&domain), StdRng::from_entropy())
+    }
+
+    /// Creates an `Obfuscator` running an explicit list of strategies.
+    /// `config` still controls the timing jitter. `key` masks the frame headers and, through
+    /// `ObfuscationCodec`, the length prefixes; the strategies carry any keys of their own.
+    /// Fails if an id is outside 0..7 or used twice.
+    pub fn with_strategies(
+        config: ObfuscatorConfig,
+        key: Option<&[u8]>,
+        strategies: Vec<Box<dyn ObfuscationStrategy>>,
+    ) -> Result<Self, ProtocolError> {
+        check_strategy_ids(&strategies)?;
+        Ok(Obfuscator {
+            config: RwLock::new(config),
+            strategies: RwLock::new(strategies),
+            key: key.map(<[u8]>::to_vec),
+            size_buckets: None,
+            custom_strategies: true,
+            mimic_domain: DEFAULT_MIMIC_DOMAIN.to_string(),
+            rng: Mutex::new(StdRng::from_entropy()),
+            metrics: MetricCounters::default(),
+            stage_timing: None,
+            last_activity: Mutex::new(Instant::now()),
+            recent_sizes: Mutex::new(VecDeque::with_capacity(CHAFF_SIZE_SAMPLES)),
+        })
+    }
+
+    fn build(config: ObfuscatorConfig, key: Option<&[u8]>, mimic_domain: Option<&str>, mut rng: StdRng) -> Self {
+        let mimic_domain = mimic_domain.unwrap_or(DEFAULT_MIMIC_DOMAIN).to_string();
+        let strategies = Self::standard_stack(&config, key, &mimic_domain, None, &mut rng);
+        Obfuscator {
+            config: RwLock::new(config),
+            strategies: RwLock::new(strategies),
+            key: key.map(<[u8]>::to_vec),
+            size_buckets: None,
+            custom_strategies: false,
+            mimic_domain,
+            rng: Mutex::new(rng),
+            metrics: MetricCounters::default(),
+            stage_timing: None,
+            last_activity: Mutex::new(Instant::now()),
+            recent_sizes: Mutex::new(VecDeque::with_capacity(CHAFF_SIZE_SAMPLES)),
         }
-        // println!("Obfuscator: Added {} bytes of random noise.", noise_len);
+    }
 
-        // 2. Mimicry (e.g., adding fake HTTP headers or TLS handshakes)
-        // This is where we'd prepend/append data to make it look like a real HTTPS/QUIC packet.
-        // Example: Prepend a dummy HTTP GET request header
-        if rng.gen_bool(0.3) { // 30% chance to add a fake header
-            let fake_header = b"GET /index.html HTTP/1.1\r\nHost: www.example.com\r\nUser-Agent: Mozilla/5.0\r\n\r\n";
-            let mut mimicked_data = fake_header.to_vec();
-            mimicked_data.extend_from_slice(&obfuscated_data);
-            obfuscated_data = mimicked_data;
-            // println!("Obfuscator: Applied HTTP header mimicry.");
+    /// Builds the standard strategy stack for `config`: keystream masking (if keyed) innermost,
+    /// then noise padding, then bucket padding (if any), with mimicry covering the whole frame.
+    fn standard_stack(
+        config: &ObfuscatorConfig,
+        key: Option<&[u8]>,
+        mimic_domain: &str,
+        size_buckets: Option<&[usize]>,
+        rng: &mut StdRng,
+    ) -> Vec<Box<dyn ObfuscationStrategy>> {
+        let mut seeded = || StdRng::seed_from_u64(rng.gen());
+        let mut strategies: Vec<Box<dyn ObfuscationStrategy>> = Vec::new();
+        if let Some(key) = key {
+            strategies.push(Box::new(KeystreamMask::with_rng(key, seeded())));
+        }
+        strategies.push(Box::new(NoisePadding::with_rng(config.max_noise_bytes, seeded())));
+        match config.mimicry_style {
+            MimicryStyle::Http => {
+                strategies.push(Box::new(HttpMimicry::with_rng(config.mimicry_probability, mimic_domain, seeded())));
+            }
+            MimicryStyle::TlsClientHello => {
+                strategies.push(Box::new(TlsHelloMimicry::with_rng(config.mimicry_probability, mimic_domain, seeded())));
+            }
         }
+        if let Some(buckets) = size_buckets {
+            strategies.push(Box::new(BucketPadding::with_rng(buckets.to_vec(), seeded())));
+        }
+        strategies
+    }
+
+    /// Turns on per-stage latency histograms (see `stage_latencies`). Off by default, as it
+    /// reads the clock around every strategy of every packet.
+    pub fn with_stage_timing(mut self) -> Self {
+        self.stage_timing = Some(Box::default());
+        self
+    }
+
+    /// Runs one strategy's `apply` or `reverse`, timing it if stage timing is on.
+    fn timed<T>(&self, strategy: &dyn ObfuscationStrategy, run: impl FnOnce() -> T) -> T {
+        let Some(histograms) = &self.stage_timing else {
+            return run();
+        };
+        // A wall clock, so the samples stay real even under a paused Tokio clock.
+        let start = std::time::Instant::now();
+        let out = run();
+        histograms[usize::from(strategy.id())].record(start.elapsed());
+        out
+    }
+
+    /// Latency histograms of the strategies in use, in stack order. Empty unless stage timing
+    /// is on.
+    pub fn stage_latencies(&self) -> Vec<StageLatency> {
+        let Some(histograms) = &self.stage_timing else {
+            return Vec::new();
+        };
+        self.strategies
+            .read()
+            .unwrap()
+            .iter()
+            .map(|strategy| {
+                let histogram = &histograms[usize::from(strategy.id())];
+                StageLatency {
+                    stage: stage_name(strategy.id()),
+                    buckets: histogram.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+                    count: histogram.count.load(Ordering::Relaxed),
+                    sum_nanos: histogram.sum_nanos.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+
+    /// Seed of the keystream that masks stream frame lengths (see `ObfuscationCodec`), derived
+    /// from the payload key. `None` for an unkeyed obfuscator, whose lengths stay plain.
+    pub(crate) fn length_mask_seed(&self) -> Option<[u8; 32]> {
+        self.key.as_deref().map(|key| derive_bytes(key, &[b"obfuscator-length-mask"]))
+    }
+
+    /// Synthesizes a fake TLS 1.3 ClientHello record whose SNI is this obfuscator's mimic domain.
+    pub fn fake_client_hello(&self) -> Vec<u8> {
+        fake_client_hello(&self.mimic_domain, &mut *self.rng.lock().unwrap())
+    }
+
+    /// Returns a copy of the parameters currently in effect.
+    pub fn current_params(&self) -> ObfuscatorConfig {
+        *self.config.read().unwrap()
+    }
+
+    /// Switches to `config`. The standard strategy stack is rebuilt with the new parameters;
+    /// an explicit strategy list (see `with_strategies`) is kept and only the timing changes.
+    /// Packets obfuscated after this call observe the new parameters.
+    pub fn apply_params(&self, config: ObfuscatorConfig) {
+        if !self.custom_strategies {
+            let stack = Self::standard_stack(
+                &config,
+                self.key.as_deref(),
+                &self.mimic_domain,
+                self.size_buckets.as_deref(),
+                &mut self.rng.lock().unwrap(),
+            );
+            *self.strategies.write().unwrap() = stack;
+        }
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Replaces the strategy list, e.g. as part of a mutation cycle.
+    /// Both peers must switch at the same point or frames will no longer reverse.
+    /// Fails, keeping the current list, if an id is outside 0..7 or used twice.
+    pub fn set_strategies(&mut self, strategies: Vec<Box<dyn ObfuscationStrategy>>) -> Result<(), ProtocolError> {
+        check_strategy_ids(&strategies)?;
+        *self.strategies.get_mut().unwrap() = strategies;
+        self.custom_strategies = true;
+        Ok(())
+    }
+
+    /// Applies obfuscation to outgoing data, then waits a random jitter delay.
+    /// This method will integrate various techniques like mimicry, blending, and mutation.
+    pub async fn obfuscate_data(&self, data: &[u8]) -> Vec<u8> {
+        let obfuscated_data = self.transform(data);
 
-        // 3. Dynamic Mutation (changing obfuscation patterns over time/connections)
-        // This would involve cycling through different obfuscation algorithms or parameters.
-        // For now, we simulate a small, random delay to disrupt timing analysis.
-        let random_delay_ms = rng.gen_range(0..50); // 0-49ms random delay
+        // Dynamic Mutation (changing obfuscation patterns over time/connections)
+        // is driven by `run_mutation_cycle_simulation`, which swaps the parameters used here.
+        // The random delay below disrupts timing analysis.
+        let random_delay_ms = self.next_delay_ms();
         if random_delay_ms > 0 {
             sleep(Duration::from_millis(random_delay_ms)).await;
             // println!("Obfuscator: Introduced {}ms random delay.", random_delay_ms);
@@ -63,29 +1139,295 @@ impl Obfuscator {
         obfuscated_data
     }
 
+    /// Bitmask of every strategy this `Obfuscator` can apply and reverse.
+    pub fn strategy_mask(&self) -> u8 {
+        self.strategies
+            .read()
+            .unwrap()
+            .iter()
+            .fold(0, |mask, strategy| mask | (1 << strategy.id()))
+    }
+
+    /// How much this `Obfuscator` hides, judged by the strategies it actually applies.
+    pub fn level(&self) -> ObfuscationLevel {
+        let strategies = self.strategies.read().unwrap();
+        let active = |matches: &dyn Fn(&dyn ObfuscationStrategy) -> bool| {
+            strategies.iter().any(|strategy| strategy.is_active() && matches(strategy.as_ref()))
+        };
+        if !active(&|strategy| strategy.id() == STRATEGY_KEYSTREAM) {
+            ObfuscationLevel::None
+        } else if !active(&|strategy| [STRATEGY_NOISE, STRATEGY_SIZE_BUCKETS].contains(&strategy.id())) {
+            ObfuscationLevel::Scrambled
+        } else if !active(&|strategy| strategy.is_cover()) {
+            ObfuscationLevel::Padded
+        } else {
+            ObfuscationLevel::Covered
+        }
+    }
+
+    /// Applies the byte-level obfuscation only, without any timing jitter.
+    /// Callers that shape timing elsewhere (or benchmark throughput) can use this directly.
+    pub fn transform(&self, data: &[u8]) -> Vec<u8> {
+        self.transform_with(data, self.strategy_mask())
+    }
+
+    /// Like `transform`, but applies only the strategies whose id bit is set in `mask`.
+    /// The applied set is recorded in the frame header, so the sender can vary it per packet
+    /// and the receiver still knows exactly which layers to remove.
+    ///
+    /// Output: `[cover][nonce][masked magic, version, applied bitmask][layers...][seal]`. The
+    /// cover (a fake HTTP request or TLS ClientHello) is only there when a mimicry strategy chose
+    /// to add one, so on the wire the packet starts like the protocol it imitates; without it the
+    /// packet starts with random bytes. Seals (see `FrameChecksum`) are applied whatever `mask` says.
+    pub fn transform_with(&self, data: &[u8], mask: u8) -> Vec<u8> {
+        self.frame(data, mask, FrameVersion::CURRENT, false)
+    }
+
+    /// Like `transform`, but writes a `version` header, for a peer that only reads older frames.
+    /// Versions before 3 carry their header in plaintext.
+    pub fn transform_as(&self, data: &[u8], version: FrameVersion) -> Vec<u8> {
+        self.frame(data, self.strategy_mask(), version, false)
+    }
+
+    /// Builds one frame, flagged as chaff if `chaff` is set. Only real frames count as activity.
+    /// Chaff is only ever built as the current version, which has the chaff flag.
+    fn frame(&self, data: &[u8], mask: u8, version: FrameVersion, chaff: bool) -> Vec<u8> {
+        let strategies = self.strategies.read().unwrap();
+        let selected: Vec<_> = strategies
+            .iter()
+            .filter(|strategy| strategy.is_seal() || mask & (1 << strategy.id()) != 0)
+            .collect();
+        let applied = selected.iter().fold(0u8, |applied, strategy| applied | (1 << strategy.id()));
+        let mut noise = 0usize;
+        let layered = selected
+            .iter()
+            .filter(|strategy| !strategy.is_cover() && !strategy.is_seal())
+            .fold(data.to_vec(), |inner, strategy| {
+                let outer = self.timed(strategy.as_ref(), || strategy.apply(&inner));
+                match strategy.id() {
+                    STRATEGY_NOISE => noise += read_varint(&outer).map_or(0, |(len, _)| len),
+                    STRATEGY_SIZE_BUCKETS => noise += outer.len().saturating_sub(inner.len() + 4),
+                    _ => {}
+                }
+                outer
+            });
+
+        let fields = [FRAME_MAGIC, version as u8, if chaff { applied | FLAG_CHAFF } else { applied }];
+        let mut framed = Vec::with_capacity(layered.len() + FRAME_HEADER_LEN);
+        if version >= FrameVersion::V3 {
+            let nonce = self.header_nonce();
+            let mask = header_mask(self.key.as_deref().unwrap_or_default(), &nonce);
+            framed.extend_from_slice(&nonce);
+            framed.extend(fields.iter().zip(mask).map(|(field, mask)| field ^ mask));
+        } else {
+            framed.extend_from_slice(&fields);
+        }
+        framed.extend_from_slice(&layered);
+        let framed = selected
+            .iter()
+            .filter(|strategy| strategy.is_seal())
+            .fold(framed, |frame, seal| self.timed(seal.as_ref(), || seal.apply(&frame)));
+        let mut mimicked = false;
+        let obfuscated_data = selected.iter().filter(|strategy| strategy.is_cover()).fold(framed, |frame, cover| {
+            let covered = self.timed(cover.as_ref(), || cover.apply(&frame));
+            mimicked |= covered.len() > frame.len();
+            covered
+        });
+
+        if chaff {
+            // A chaff payload is random filler, so it counts as noise rather than input.
+            noise += data.len();
+        } else {
+            self.metrics.input_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        if !chaff && !data.is_empty() {
+            *self.last_activity.lock().unwrap() = Instant::now();
+            let mut recent_sizes = self.recent_sizes.lock().unwrap();
+            if recent_sizes.len() == CHAFF_SIZE_SAMPLES {
+                recent_sizes.pop_front();
+            }
+            recent_sizes.push_back(data.len());
+        }
+        self.metrics.output_bytes.fetch_add(obfuscated_data.len() as u64, Ordering::Relaxed);
+        self.metrics.noise_bytes.fetch_add(noise as u64, Ordering::Relaxed);
+        if mimicked {
+            self.metrics.packets_mimicked.fetch_add(1, Ordering::Relaxed);
+        }
+        obfuscated_data
+    }
+
+    /// Draws the nonce in front of a masked header. Its first byte is never `FRAME_MAGIC` or
+    /// the first byte of a cover, so a receiver can tell a masked header from a plaintext one
+    /// and a frame without a cover from a covered one.
+    fn header_nonce(&self) -> [u8; HEADER_NONCE_LEN] {
+        let mut rng = self.rng.lock().unwrap();
+        let mut nonce = [0u8; HEADER_NONCE_LEN];
+        loop {
+            rng.fill_bytes(&mut nonce);
+            if ![FRAME_MAGIC, TLS_HANDSHAKE_RECORD, HTTP_COVER_METHOD[0]].contains(&nonce[0]) {
+                return nonce;
+            }
+        }
+    }
+
+    /// Snapshot of the overhead counters for everything obfuscated so far
+    /// (through `obfuscate_data`, `transform` or `transform_with`).
+    pub fn metrics(&self) -> ObfuscatorMetrics {
+        ObfuscatorMetrics {
+            input_bytes: self.metrics.input_bytes.load(Ordering::Relaxed),
+            output_bytes: self.metrics.output_bytes.load(Ordering::Relaxed),
+            packets_mimicked: self.metrics.packets_mimicked.load(Ordering::Relaxed),
+            noise_bytes: self.metrics.noise_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Draws the jitter delay for the next packet.
+    fn next_delay_ms(&self) -> u64 {
+        let max_delay_ms = self.config.read().unwrap().max_delay_ms;
+        if max_delay_ms == 0 {
+            return 0;
+        }
+        self.rng.lock().unwrap().gen_range(0..max_delay_ms)
+    }
+
+    /// Builds a chaff frame: a random payload, as long as one of the recent real packets, run
+    /// through the full strategy stack, so it has the size and shape of real traffic. The
+    /// `FLAG_CHAFF` bit in its masked header makes the receiver's `deobfuscate_data` return an empty
+    /// payload for it, which callers drop.
+    pub fn chaff_frame(&self) -> Vec<u8> {
+        let payload = {
+            let mut rng = self.rng.lock().unwrap();
+            let recent_sizes = self.recent_sizes.lock().unwrap();
+            let len = match recent_sizes.len() {
+                0 => rng.gen_range(CHAFF_FALLBACK_SIZES),
+                n => recent_sizes[rng.gen_range(0..n)],
+            };
+            let mut payload = vec![0u8; len];
+            rng.fill_bytes(&mut payload);
+            payload
+        };
+        self.frame(&payload, self.strategy_mask(), FrameVersion::CURRENT, true)
+    }
+
+    /// Spawns a task that sends a chaff frame to `socket_tx` every `interval` during which
+    /// no real packet was obfuscated, so idle periods don't show up on the wire.
+    /// Chaff payloads are sized like recent real packets (see `chaff_frame`).
+    /// The task ends when the receiving side of `socket_tx` is dropped.
+    pub fn spawn_chaff(self: &Arc<Self>, socket_tx: mpsc::Sender<Vec<u8>>, interval: Duration) -> JoinHandle<()> {
+        let obfuscator = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                sleep(interval).await;
+                let idle_for = obfuscator.last_activity.lock().unwrap().elapsed();
+                if idle_for < interval {
+                    continue;
+                }
+                if socket_tx.send(obfuscator.chaff_frame()).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
     /// Removes obfuscation from incoming data.
     /// This method must accurately reverse the obfuscation applied by `obfuscate_data`.
+    /// Chaff frames (flagged with `FLAG_CHAFF`) come back as an empty payload and should be discarded.
+    /// Returns `InvalidData` if the frame or any strategy layer is malformed, or if a seal such as
+    /// `FrameChecksum` finds the frame was altered.
     pub fn deobfuscate_data(&self, data: &[u8]) -> io::Result<Vec<u8>> {
-        // TODO: Implement sophisticated de-obfuscation logic.
-        // This is the reverse of `obfuscate_data`. It must intelligently
-        // identify and remove noise, fake headers, and other obfuscation layers.
-        // This is significantly harder than obfuscation as it needs to be precise.
+        self.deobfuscate_as(data, FrameVersion::CURRENT)
+    }
+
+    /// Like `deobfuscate_data`, but only reads frames up to version `newest`, as a peer built
+    /// for that version would. Newer frames fail with an error naming both versions.
+    pub fn deobfuscate_as(&self, data: &[u8], newest: FrameVersion) -> io::Result<Vec<u8>> {
+        let strategies = self.strategies.read().unwrap();
+        // Covers wrap the frame header, so they come off first.
+        let mut frame = data.to_vec();
+        for cover in strategies.iter().rev().filter(|strategy| strategy.is_cover()) {
+            frame = self.timed(cover.as_ref(), || cover.reverse(&frame))?;
+        }
+        // Seals protect the header too, so they are checked before it is trusted.
+        for seal in strategies.iter().rev().filter(|strategy| strategy.is_seal()) {
+            frame = self.timed(seal.as_ref(), || seal.reverse(&frame))?;
+        }
+
+        // Only a plaintext header starts with the magic byte; a masked one starts with its nonce.
+        let masked = frame.first() != Some(&FRAME_MAGIC);
+        let header_len = if masked { FRAME_HEADER_LEN } else { HEADER_FIELDS_LEN };
+        if frame.len() < header_len {
+            return Err(malformed("truncated header"));
+        }
+        let (header, rest) = frame.split_at(header_len);
+        let (nonce, fields) = header.split_at(header_len - HEADER_FIELDS_LEN);
+        let mut fields: [u8; HEADER_FIELDS_LEN] = fields.try_into().expect("split at header length");
+        if masked {
+            let mask = header_mask(self.key.as_deref().unwrap_or_default(), nonce);
+            fields.iter_mut().zip(mask).for_each(|(field, mask)| *field ^= mask);
+        }
 
-        // For now, we return a copy, assuming no obfuscation was applied (or it's simple).
-        // A real implementation would need to parse and reconstruct the original data.
-        Ok(data.to_vec()) 
+        let [magic, version, applied] = fields;
+        if magic != FRAME_MAGIC {
+            return Err(malformed("bad magic byte"));
+        }
+        let version = FrameVersion::from_byte(version)
+            .filter(|version| *version <= newest)
+            .ok_or_else(|| {
+                malformed(&format!("frame version {} is not supported (this peer reads up to {})", version, newest as u8))
+            })?;
+        if masked != (version >= FrameVersion::V3) {
+            return Err(malformed(&format!("frame version {} has the wrong header masking", version as u8)));
+        }
+        if version >= FrameVersion::V2 && applied & FLAG_CHAFF != 0 {
+            return Ok(Vec::new());
+        }
+        let known = strategies.iter().fold(0, |mask, strategy| mask | (1 << strategy.id()));
+        let unknown = applied & !known;
+        if unknown != 0 {
+            return Err(malformed(&format!("unsupported strategies {:#04x}", unknown)));
+        }
+
+        let mut payload = rest.to_vec();
+        for strategy in strategies.iter().rev().filter(|strategy| !strategy.is_cover() && !strategy.is_seal()) {
+            if applied & (1 << strategy.id()) != 0 {
+                payload = self.timed(strategy.as_ref(), || strategy.reverse(&payload))?;
+            }
+        }
+        Ok(payload)
+    }
+
+    /// Switches to the parameter preset (noise range, mimicry probability and jitter) after the
+    /// one in use, or the first if the current parameters aren't a preset, so every call changes
+    /// the traffic pattern. Only the sending side changes; peers keep reversing its frames.
+    /// Returns the parameters now in effect.
+    pub fn mutate(&self) -> ObfuscatorConfig {
+        let current = self.current_params();
+        let in_use = MUTATION_PRESETS.iter().position(|&preset| {
+            preset == (current.max_noise_bytes, current.mimicry_probability, current.max_delay_ms)
+        });
+        let next = in_use.map_or(0, |index| (index + 1) % MUTATION_PRESETS.len());
+        let (max_noise_bytes, mimicry_probability, max_delay_ms) = MUTATION_PRESETS[next];
+        let config = ObfuscatorConfig {
+            max_noise_bytes,
+            mimicry_probability,
+            max_delay_ms,
+            ..current
+        };
+        self.apply_params(config);
+        config
     }
 
-    /// Simulates dynamic mutation of obfuscation parameters over time.
-    /// In a real system, this would change the `_config_placeholder` based on
-    /// a schedule or detection of new censorship patterns.
+    /// Runs the dynamic mutation cycle: every minute, switches to the next parameter preset
+    /// (see `mutate`) so the traffic pattern keeps changing. Runs until the future is dropped.
     pub async fn run_mutation_cycle_simulation(&self) {
         println!("Traffic Obfuscator: Starting dynamic mutation cycle simulation...");
         loop {
-            sleep(Duration::from_secs(60)).await; // Simulate mutation every minute
-            println!("Traffic Obfuscator: Performing dynamic mutation (parameters changing).");
-            // In a real scenario, this would update internal obfuscation parameters
-            // based on new strategies or detected censorship patterns.
+            sleep(MUTATION_INTERVAL).await;
+            let config = self.mutate();
+            println!(
+                "Traffic Obfuscator: Performing dynamic mutation (noise {}, mimicry {}, delay {}ms).",
+                config.max_noise_bytes, config.mimicry_probability, config.max_delay_ms
+            );
         }
     }
 }
@@ -93,8 +1435,36 @@ impl Obfuscator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
     use tokio::runtime::Runtime;
 
+    /// Version byte written by default.
+    const FRAME_VERSION: u8 = FrameVersion::CURRENT as u8;
+
+    /// Offset of the frame header, past any mimicry cover.
+    fn header_offset(packet: &[u8]) -> usize {
+        if packet[0] == TLS_HANDSHAKE_RECORD {
+            5 + u16::from_be_bytes([packet[3], packet[4]]) as usize
+        } else if packet.starts_with(HTTP_COVER_METHOD) {
+            packet.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4
+        } else {
+            0
+        }
+    }
+
+    /// The frame inside `packet` with its header unmasked using `key`, laid out like a
+    /// plaintext `[magic][version][strategy bitmask][layers...]` frame.
+    fn unmasked_frame(packet: &[u8], key: &[u8]) -> Vec<u8> {
+        let frame = &packet[header_offset(packet)..];
+        if frame[0] == FRAME_MAGIC {
+            return frame.to_vec();
+        }
+        let (nonce, rest) = frame.split_at(HEADER_NONCE_LEN);
+        let mut unmasked = rest.to_vec();
+        unmasked.iter_mut().zip(header_mask(key, nonce)).for_each(|(byte, mask)| *byte ^= mask);
+        unmasked
+    }
+
     #[test]
     fn test_obfuscate_and_deobfuscate_basic() {
         let rt = Runtime::new().unwrap();
@@ -103,18 +1473,748 @@ mod tests {
             let original_data = b"Hello, HezarDastan!";
 
             let obfuscated = obfuscator.obfuscate_data(original_data).await;
-            // Since `deobfuscate_data` is a placeholder, it just returns a copy.
-            // In a real test, we'd assert equality after full de-obfuscation.
             let deobfuscated = obfuscator.deobfuscate_data(&obfuscated).unwrap();
 
-            // For a placeholder, we can't assert equality yet due to noise/headers
-            // println!("Original: {:?}", original_data);
-            // println!("Obfuscated: {:?}", obfuscated);
-            // println!("Deobfuscated (placeholder): {:?}", deobfuscated);
-
-            // We'll just assert that obfuscated data is generally larger or different
-            assert!(obfuscated.len() >= original_data.len());
+            assert!(obfuscated.len() > original_data.len());
             assert_ne!(obfuscated, original_data.to_vec());
+            assert_eq!(deobfuscated, original_data.to_vec());
+        });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_seeded_obfuscator_is_reproducible() {
+        let payload = b"Hello, HezarDastan!";
+        let a = Obfuscator::with_seed(42);
+        let b = Obfuscator::with_seed(42);
+        for _ in 0..10 {
+            assert_eq!(a.obfuscate_data(payload).await, b.obfuscate_data(payload).await);
+        }
+
+        // Known answer: seed 42 must keep producing these bytes across releases, or seeded
+        // peers stop generating matching noise. Header, noise length, payload, then the noise.
+        let first = unmasked_frame(&Obfuscator::with_seed(42).obfuscate_data(payload).await, b"");
+        let expected = [
+            &[FRAME_MAGIC, FRAME_VERSION, (1 << STRATEGY_NOISE) | (1 << STRATEGY_HTTP_MIMICRY), 10][..],
+            payload,
+            &[0x55, 0xeb, 0x4e, 0xd8, 0xe9, 0x2f, 0x41, 0x18, 0x67, 0x92],
+        ]
+        .concat();
+        assert_eq!(first, expected);
+        let other_seed = Obfuscator::with_seed(43);
+        let mut outputs = Vec::new();
+        for _ in 0..10 {
+            outputs.push(other_seed.obfuscate_data(payload).await);
+        }
+        assert!(outputs.iter().any(|o| *o != first));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_config_controls_noise_and_mimicry() {
+        let payload = b"payload";
+
+        // No noise, never mimic: only the frame header and an empty noise length are added.
+        let plain = Obfuscator::with_config(ObfuscatorConfig {
+            max_noise_bytes: 0,
+            mimicry_probability: 0.0,
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
+        });
+        let out = plain.obfuscate_data(payload).await;
+        let mask = (1 << STRATEGY_NOISE) | (1 << STRATEGY_HTTP_MIMICRY);
+        assert_eq!(out.len(), FRAME_HEADER_LEN + 1 + payload.len());
+        assert_eq!(unmasked_frame(&out, b""), [&[FRAME_MAGIC, FRAME_VERSION, mask, 0x00][..], payload].concat());
+
+        // Always mimic.
+        let mimic = Obfuscator::with_config(ObfuscatorConfig {
+            mimicry_probability: 1.0,
+            ..ObfuscatorConfig::default()
+        });
+        for _ in 0..10 {
+            let out = mimic.obfuscate_data(payload).await;
+            assert!(out.starts_with(b"GET /index.html HTTP/1.1\r\n"));
+            assert_eq!(unmasked_frame(&out, b"")[0], FRAME_MAGIC);
+            assert!(out.len() <= 80 + FRAME_HEADER_LEN + 1 + payload.len() + 15);
+            assert_eq!(mimic.deobfuscate_data(&out).unwrap(), payload);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_delay_config_does_not_sleep() {
+        let obfuscator = Obfuscator::with_config(ObfuscatorConfig {
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
         });
+        let start = tokio::time::Instant::now();
+        for _ in 0..20 {
+            obfuscator.obfuscate_data(b"x").await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_transform_without_runtime() {
+        let obfuscator = Obfuscator::with_seed(7);
+        let payload = b"no runtime needed";
+        let framed = obfuscator.transform(payload);
+        assert_eq!(unmasked_frame(&framed, b"")[0], FRAME_MAGIC);
+        assert_eq!(obfuscator.deobfuscate_data(&framed).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_validate_profiles_accepts_good_profiles() {
+        let low_latency = ObfuscatorConfig {
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
+        };
+        let heavy = ObfuscatorConfig {
+            max_noise_bytes: 512,
+            mimicry_probability: 1.0,
+            mimicry_style: MimicryStyle::TlsClientHello,
+            max_delay_ms: 200,
+        };
+        validate_profiles(&[("default", ObfuscatorConfig::default()), ("low-latency", low_latency), ("heavy", heavy)])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_profiles_rejects_broken_profile() {
+        let broken = ObfuscatorConfig {
+            mimicry_probability: 1.5,
+            ..ObfuscatorConfig::default()
+        };
+        let err = validate_profiles(&[("default", ObfuscatorConfig::default()), ("broken", broken)]).unwrap_err();
+        match err {
+            ProtocolError::ObfuscationError(msg) => {
+                assert!(msg.contains("profile 'broken'"));
+                assert!(msg.contains("mimicry_probability"));
+            }
+            other => panic!("unexpected error {}", other),
+        }
+
+        let nan = ObfuscatorConfig {
+            mimicry_probability: f64::NAN,
+            ..ObfuscatorConfig::default()
+        };
+        assert!(validate_profiles(&[("nan", nan)]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keyed_payload_is_masked_and_recovered() {
+        let payload = b"secret tunnel payload that must not appear on the wire".repeat(4);
+        let sender = Obfuscator::with_key(b"user-6f1c2a9e");
+        let receiver = Obfuscator::with_key(b"user-6f1c2a9e");
+
+        for _ in 0..10 {
+            let obfuscated = sender.obfuscate_data(&payload).await;
+            assert!(!obfuscated.windows(16).any(|w| payload.windows(16).any(|p| p == w)));
+            assert_eq!(receiver.deobfuscate_data(&obfuscated).unwrap(), payload);
+        }
+
+        // Per-packet nonces mean the same payload never produces the same ciphertext.
+        assert_ne!(sender.transform(&payload), sender.transform(&payload));
+    }
+
+    #[test]
+    fn test_keyed_frame_needs_the_right_key() {
+        let payload = b"hello".to_vec();
+        let framed = Obfuscator::with_key(b"key-a").transform(&payload);
+
+        // The header mask is keyed too, so the wrong key fails at the header.
+        assert!(Obfuscator::with_key(b"key-b").deobfuscate_data(&framed).is_err());
+        let err = Obfuscator::new().deobfuscate_data(&framed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Reverses the bytes; a trivially reversible strategy for testing custom stacks.
+    struct Reverse;
+
+    impl ObfuscationStrategy for Reverse {
+        fn id(&self) -> u8 {
+            6
+        }
+
+        fn apply(&self, data: &[u8]) -> Vec<u8> {
+            data.iter().rev().copied().collect()
+        }
+
+        fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(data.iter().rev().copied().collect())
+        }
+    }
+
+    #[test]
+    fn test_custom_strategies_apply_in_order_and_reverse() {
+        let config = ObfuscatorConfig {
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
+        };
+        let mut obfuscator = Obfuscator::with_strategies(
+            config,
+            None,
+            vec![Box::new(Reverse), Box::new(HttpMimicry::new(1.0)), Box::new(NoisePadding::new(32))],
+        )
+        .unwrap();
+        let payload = b"abcdef".to_vec();
+        let framed = obfuscator.transform(&payload);
+        // Mimicry covers the whole frame wherever it sits in the list; noise padding is the
+        // outermost layer inside the frame, so its varint follows the frame header.
+        assert!(framed.starts_with(b"GET /index.html HTTP/1.1\r\n"));
+        let frame = unmasked_frame(&framed, b"");
+        assert_eq!(frame[..3], [FRAME_MAGIC, FRAME_VERSION, 0x40 | (1 << STRATEGY_HTTP_MIMICRY) | (1 << STRATEGY_NOISE)]);
+        let (noise_len, _) = read_varint(&frame[3..]).unwrap();
+        assert!(frame[..frame.len() - noise_len].ends_with(b"fedcba"));
+        assert_eq!(obfuscator.deobfuscate_data(&framed).unwrap(), payload);
+
+        // Swapping the list changes the wire format without touching the Obfuscator itself.
+        obfuscator.set_strategies(vec![Box::new(Reverse)]).unwrap();
+        assert_eq!(
+            unmasked_frame(&obfuscator.transform(&payload), b""),
+            [&[FRAME_MAGIC, FRAME_VERSION, 0x40][..], b"fedcba"].concat()
+        );
+    }
+
+    /// Passes data through unchanged under a chosen id.
+    struct Passthrough(u8);
+
+    impl ObfuscationStrategy for Passthrough {
+        fn id(&self) -> u8 {
+            self.0
+        }
+
+        fn apply(&self, data: &[u8]) -> Vec<u8> {
+            data.to_vec()
+        }
+
+        fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+    }
+
+    #[test]
+    fn test_strategy_ids_are_validated() {
+        let config = ObfuscatorConfig::default();
+        let chaff_bit = Obfuscator::with_strategies(config, None, vec![Box::new(Passthrough(7))]);
+        assert!(matches!(chaff_bit, Err(ProtocolError::ObfuscationError(msg)) if msg.contains("outside 0..7")));
+        let doubled = Obfuscator::with_strategies(config, None, vec![Box::new(Reverse), Box::new(Passthrough(6))]);
+        assert!(matches!(doubled, Err(ProtocolError::ObfuscationError(msg)) if msg.contains("used more than once")));
+
+        // A rejected list leaves the current one in place.
+        let mut obfuscator = Obfuscator::with_strategies(config, None, vec![Box::new(Reverse)]).unwrap();
+        assert!(obfuscator.set_strategies(vec![Box::new(Passthrough(1)), Box::new(Passthrough(1))]).is_err());
+        assert!(obfuscator.set_strategies(vec![Box::new(Passthrough(200))]).is_err());
+        assert_eq!(obfuscator.strategy_mask(), 1 << 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_round_trip_arbitrary_payloads() {
+        let obfuscator = Obfuscator::new();
+        let mut rng = rand::thread_rng();
+        for len in [0usize, 1, 2, 15, 16, 127, 128, 300, 1400, 65_536] {
+            // Repeat each size so both the mimicry and plain paths are exercised.
+            for _ in 0..20 {
+                let payload: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+                let obfuscated = obfuscator.obfuscate_data(&payload).await;
+                assert_eq!(obfuscator.deobfuscate_data(&obfuscated).unwrap(), payload);
+            }
+        }
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0usize, 1, 127, 128, 300, 16_383, 16_384, usize::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert_eq!(read_varint(&buf).unwrap(), (value, buf.len()));
+        }
+        assert!(read_varint(&[0x80, 0x80]).is_err());
+    }
+
+    #[test]
+    fn test_deobfuscate_rejects_malformed_input() {
+        let obfuscator = Obfuscator::new();
+        let mask = (1 << STRATEGY_NOISE) | (1 << STRATEGY_HTTP_MIMICRY);
+        // Plaintext version 2 headers, so each case controls the header fields directly.
+        let v2 = FrameVersion::V2 as u8;
+        let cases: [&[u8]; 10] = [
+            b"",
+            b"plain unframed data",
+            &[FRAME_MAGIC],
+            &[FRAME_MAGIC, v2],
+            &[FRAME_MAGIC, FRAME_VERSION + 1, mask, 0x00],
+            &[FRAME_MAGIC, FRAME_VERSION, mask, 0x00], // Current version without the header mask.
+            &[FRAME_MAGIC, v2, mask, 0x80],
+            &[FRAME_MAGIC, v2, mask, 0x05, b'a', b'b'], // Claims 5 bytes of noise, has 2.
+            &[FRAME_MAGIC, v2, 0x40, b'x'], // Strategy this receiver doesn't have.
+            b"GET /index.html HTTP/1.1\r\nHost: www.example.com\r\n", // Cover never ends.
+        ];
+        for case in cases {
+            let err = obfuscator.deobfuscate_data(case).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let inner = err.get_ref().and_then(|e| e.downcast_ref::<ProtocolError>());
+            assert!(matches!(inner, Some(ProtocolError::ObfuscationError(_))), "{:?}", err);
+        }
+
+    }
+
+    #[test]
+    fn test_truncated_frames_are_rejected() {
+        let obfuscator = Obfuscator::with_key(b"user-key");
+        let frame = obfuscator.transform(b"a payload long enough to cut at every position");
+        assert_eq!(obfuscator.deobfuscate_data(&frame).unwrap(), b"a payload long enough to cut at every position");
+
+        // Cutting inside the header or the keystream nonce can never parse.
+        for len in 0..FRAME_HEADER_LEN + NONCE_LEN {
+            let err = obfuscator.deobfuscate_data(&frame[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_checksum_rejects_every_single_byte_corruption() {
+        let config = ObfuscatorConfig {
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
+        };
+        let strategies: Vec<Box<dyn ObfuscationStrategy>> = vec![
+            Box::new(KeystreamMask::new(b"user-key")),
+            Box::new(NoisePadding::new(32)),
+            Box::new(FrameChecksum::new(b"user-key")),
+        ];
+        let obfuscator = Obfuscator::with_strategies(config, Some(b"user-key"), strategies).unwrap();
+        let mut rng = StdRng::seed_from_u64(241);
+        for size in [0, 1, 17, 256, 1500] {
+            for _ in 0..4 {
+                let mut payload = vec![0u8; size];
+                rng.fill_bytes(&mut payload);
+                let frames = [obfuscator.transform(&payload), obfuscator.transform_with(&payload, 0)];
+                for frame in frames {
+                    assert_eq!(obfuscator.deobfuscate_data(&frame).unwrap(), payload);
+                    for at in 0..frame.len() {
+                        let mut corrupted = frame.clone();
+                        corrupted[at] ^= rng.gen_range(1..=u8::MAX);
+                        let err = obfuscator.deobfuscate_data(&corrupted).unwrap_err();
+                        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "size {}, byte {}", size, at);
+                    }
+                }
+            }
+        }
+
+        // Chaff is sealed like any frame, so a damaged one isn't quietly taken for chaff.
+        let mut chaff = obfuscator.chaff_frame();
+        assert_eq!(obfuscator.deobfuscate_data(&chaff).unwrap(), b"");
+        chaff[0] ^= 0x01;
+        assert!(obfuscator.deobfuscate_data(&chaff).is_err());
+    }
+
+    #[test]
+    fn test_stage_timing_samples_every_stage() {
+        let config = ObfuscatorConfig {
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
+        };
+        let strategies = || -> Vec<Box<dyn ObfuscationStrategy>> {
+            vec![
+                Box::new(KeystreamMask::new(b"user-key")),
+                Box::new(NoisePadding::new(32)),
+                Box::new(FrameChecksum::new(b"user-key")),
+                Box::new(HttpMimicry::new(1.0)),
+            ]
+        };
+        let untimed = Obfuscator::with_strategies(config, Some(b"user-key"), strategies()).unwrap();
+        untimed.transform(b"payload");
+        assert!(untimed.stage_latencies().is_empty());
+
+        let obfuscator = Obfuscator::with_strategies(config, Some(b"user-key"), strategies()).unwrap().with_stage_timing();
+        for _ in 0..10 {
+            let frame = obfuscator.transform(&[0x5A; 1200]);
+            obfuscator.deobfuscate_data(&frame).unwrap();
+        }
+        let latencies = obfuscator.stage_latencies();
+        let stages: Vec<&str> = latencies.iter().map(|latency| latency.stage.as_str()).collect();
+        assert_eq!(stages, ["keystream", "noise", "checksum", "http-mimicry"]);
+        for latency in latencies {
+            // Ten packets out and ten back in.
+            assert_eq!(latency.count, 20, "{}", latency.stage);
+            assert!(latency.sum_nanos > 0, "{}", latency.stage);
+            // Each pass over a 1.2 KB packet takes well under a second.
+            assert!(latency.sum_nanos < 20 * 1_000_000_000, "{}", latency.stage);
+            assert_eq!(latency.buckets.len(), STAGE_LATENCY_BUCKETS.len());
+            assert!(latency.buckets.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", latency.buckets);
+            assert!(*latency.buckets.last().unwrap() <= latency.count);
+        }
+    }
+
+    #[test]
+    fn test_wrong_magic_byte_is_rejected() {
+        let obfuscator = Obfuscator::new();
+        let mut frame = obfuscator.transform(b"payload");
+        let magic_at = header_offset(&frame) + HEADER_NONCE_LEN;
+        frame[magic_at] ^= 0xFF;
+        let err = obfuscator.deobfuscate_data(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("bad magic byte"), "{}", err);
+    }
+
+    #[test]
+    fn test_per_packet_strategy_combinations() {
+        let sender = Obfuscator::with_key(b"user-key");
+        let receiver = Obfuscator::with_key(b"user-key");
+        let payload = b"adaptive mutation".to_vec();
+
+        let all = sender.strategy_mask();
+        assert_eq!(all, 0b111);
+        for mask in 0..=all {
+            let framed = sender.transform_with(&payload, mask);
+            assert_eq!(unmasked_frame(&framed, b"user-key")[2], mask);
+            assert_eq!(receiver.deobfuscate_data(&framed).unwrap(), payload, "mask {:#05b}", mask);
+        }
+
+        // Only the applied layers are recorded, even if the mask asks for more.
+        let plain = Obfuscator::new();
+        let framed = plain.transform_with(&payload, 0xff);
+        assert_eq!(unmasked_frame(&framed, b"")[2], plain.strategy_mask());
+        assert_eq!(
+            unmasked_frame(&plain.transform_with(&payload, 0), b""),
+            [&[FRAME_MAGIC, FRAME_VERSION, 0][..], &payload].concat()
+        );
+    }
+
+    #[test]
+    fn test_older_frame_versions_stay_readable() {
+        let sender = Obfuscator::with_key(b"user-key");
+        let receiver = Obfuscator::with_key(b"user-key");
+        let payload = b"versioned payload".to_vec();
+
+        let v1 = sender.transform_as(&payload, FrameVersion::V1);
+        assert_eq!(v1[header_offset(&v1) + 1], 1);
+        assert_eq!(receiver.deobfuscate_data(&v1).unwrap(), payload);
+        assert_eq!(receiver.deobfuscate_as(&v1, FrameVersion::V1).unwrap(), payload);
+
+        let v2 = sender.transform_as(&payload, FrameVersion::V2);
+        assert_eq!(v2[header_offset(&v2) + 1], 2);
+        assert_eq!(receiver.deobfuscate_as(&v2, FrameVersion::V2).unwrap(), payload);
+
+        // Older peers can't read current frames, and say why.
+        let v3 = sender.transform(&payload);
+        assert_eq!(unmasked_frame(&v3, b"user-key")[1], 3);
+        for newest in [FrameVersion::V1, FrameVersion::V2] {
+            let err = receiver.deobfuscate_as(&v3, newest).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let expected = format!("frame version 3 is not supported (this peer reads up to {})", newest as u8);
+            assert!(err.to_string().contains(&expected), "{}", err);
+        }
+
+        // Version 1 has no chaff flag: the top bit is just an unknown strategy there.
+        let flagged = sender.chaff_frame();
+        let mut as_v1 = unmasked_frame(&flagged, b"user-key");
+        as_v1[1] = 1;
+        assert!(receiver.deobfuscate_data(&flagged).unwrap().is_empty());
+        assert!(receiver.deobfuscate_data(&as_v1).is_err());
+    }
+
+    #[test]
+    fn test_frames_share_no_fixed_prefix() {
+        // Two sessions with the same settings, without covers, so the frame header leads.
+        let no_cover = ObfuscatorConfig {
+            mimicry_probability: 0.0,
+            ..ObfuscatorConfig::default()
+        };
+        for key in [None, Some(&b"user-key"[..])] {
+            let sessions = [
+                Obfuscator::build(no_cover, key, None, StdRng::from_entropy()),
+                Obfuscator::build(no_cover, key, None, StdRng::from_entropy()),
+            ];
+            let frames: Vec<_> = sessions
+                .iter()
+                .flat_map(|session| (0..32).map(|_| session.transform(b"same payload")))
+                .collect();
+            assert!(frames.iter().all(|frame| frame[0] != FRAME_MAGIC));
+            for position in 0..FRAME_HEADER_LEN {
+                let first = frames[0][position];
+                assert!(frames.iter().any(|frame| frame[position] != first), "header byte {} is fixed", position);
+            }
+            for frame in &frames {
+                assert_eq!(sessions[1].deobfuscate_data(frame).unwrap(), b"same payload");
+            }
+        }
+    }
+
+    /// Walks a ClientHello record and returns its SNI host name.
+    fn parse_sni(record: &[u8]) -> String {
+        let u16_at = |b: &[u8], i: usize| u16::from_be_bytes([b[i], b[i + 1]]) as usize;
+        let hello = &record[9..];
+        let mut i = 2 + 32;
+        i += 1 + hello[i] as usize; // session id
+        i += 2 + u16_at(hello, i); // cipher suites
+        i += 1 + hello[i] as usize; // compression methods
+        let ext_end = i + 2 + u16_at(hello, i);
+        i += 2;
+        while i < ext_end {
+            let (ext_type, len) = (u16_at(hello, i), u16_at(hello, i + 2));
+            if ext_type == 0 {
+                let name_len = u16_at(hello, i + 7);
+                return String::from_utf8(hello[i + 9..i + 9 + name_len].to_vec()).unwrap();
+            }
+            i += 4 + len;
+        }
+        panic!("no SNI extension");
+    }
+
+    #[test]
+    fn test_fake_client_hello_has_valid_record_header() {
+        let obfuscator = Obfuscator::with_mimic_domain("cdn.example.net".to_string());
+        let record = obfuscator.fake_client_hello();
+
+        // Record header: handshake (22), legacy version 3.1, length of the rest.
+        assert_eq!(record[0], 0x16);
+        assert_eq!(&record[1..3], &[0x03, 0x01]);
+        let record_len = u16::from_be_bytes([record[3], record[4]]) as usize;
+        assert_eq!(record_len, record.len() - 5);
+
+        // Handshake header: client_hello (1) with a 24-bit length, then legacy_version 3.3.
+        assert_eq!(record[5], 0x01);
+        let hs_len = u32::from_be_bytes([0, record[6], record[7], record[8]]) as usize;
+        assert_eq!(hs_len, record_len - 4);
+        assert_eq!(&record[9..11], &[0x03, 0x03]);
+
+        assert_eq!(parse_sni(&record), "cdn.example.net");
+        assert_ne!(record, obfuscator.fake_client_hello(), "random fields must be fresh");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tls_mimicry_style_round_trips() {
+        let config = ObfuscatorConfig {
+            mimicry_probability: 1.0,
+            mimicry_style: MimicryStyle::TlsClientHello,
+            ..ObfuscatorConfig::default()
+        };
+        let obfuscator = Obfuscator::build(config, None, Some("www.cover.example"), StdRng::seed_from_u64(1));
+        let payload = b"tunnelled bytes".to_vec();
+        let framed = obfuscator.obfuscate_data(&payload).await;
+
+        // The packet opens with a TLS handshake record header, then the frame follows the record.
+        assert_eq!(&framed[..3], &[0x16, 0x03, 0x01]);
+        let record_len = u16::from_be_bytes([framed[3], framed[4]]) as usize;
+        let (record, frame) = framed.split_at(5 + record_len);
+        assert_eq!(parse_sni(record), "www.cover.example");
+        assert_eq!(
+            unmasked_frame(frame, b"")[..3],
+            [FRAME_MAGIC, FRAME_VERSION, (1 << STRATEGY_NOISE) | (1 << STRATEGY_TLS_MIMICRY)]
+        );
+        assert_eq!(obfuscator.deobfuscate_data(&framed).unwrap(), payload);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_http_header_names_the_mimic_domain() {
+        let contains = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+        let always_mimic = ObfuscatorConfig {
+            mimicry_probability: 1.0,
+            ..ObfuscatorConfig::default()
+        };
+
+        let obfuscator = Obfuscator::build(always_mimic, None, Some("cdn.example.net"), StdRng::seed_from_u64(1));
+        let framed = obfuscator.obfuscate_data(b"payload").await;
+        assert!(contains(&framed, b"\r\nHost: cdn.example.net\r\n"));
+        assert_eq!(obfuscator.deobfuscate_data(&framed).unwrap(), b"payload");
+
+        // Without a mimic domain the header falls back to www.example.com.
+        let framed = Obfuscator::with_config(always_mimic).obfuscate_data(b"payload").await;
+        assert!(contains(&framed, b"\r\nHost: www.example.com\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_size_buckets_normalize_frame_lengths() {
+        let buckets = vec![512, 1024, 1460];
+        let obfuscator = Obfuscator::with_size_buckets(buckets.clone());
+        for len in [0usize, 1, 100, 400, 500, 700, 1000, 1300] {
+            let payload = vec![0x42; len];
+            for _ in 0..5 {
+                let framed = obfuscator.obfuscate_data(&payload).await;
+                let frame_len = framed.len() - header_offset(&framed);
+                assert!(buckets.contains(&frame_len), "payload {} -> frame {}", len, frame_len);
+                assert_eq!(obfuscator.deobfuscate_data(&framed).unwrap(), payload);
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversized_payload_is_left_unpadded() {
+        let obfuscator = Obfuscator::with_size_buckets(vec![512, 1024]);
+        let payload = vec![7u8; 4000];
+        let framed = obfuscator.transform(&payload);
+        assert!(framed.len() > 4000 && framed.len() < 4000 + 128);
+        assert_eq!(obfuscator.deobfuscate_data(&framed).unwrap(), payload);
+
+        // A receiver without bucket padding can't read these frames.
+        assert!(Obfuscator::new().deobfuscate_data(&framed).is_err());
+    }
+
+    #[test]
+    fn test_tier_from_params() {
+        let mut params = HashMap::new();
+        assert_eq!(ObfuscationProfileTier::from_params(&params), None);
+        params.insert("obfuscation_tier".to_string(), "Maximal".to_string());
+        assert_eq!(ObfuscationProfileTier::from_params(&params), Some(ObfuscationProfileTier::Maximal));
+        params.insert("obfuscation_tier".to_string(), "extreme".to_string());
+        assert_eq!(ObfuscationProfileTier::from_params(&params), None);
+    }
+
+    #[test]
+    fn test_tiers_configure_expected_transforms() {
+        let minimal = Obfuscator::for_tier(ObfuscationProfileTier::Minimal, None, None);
+        let balanced = Obfuscator::for_tier(ObfuscationProfileTier::Balanced, None, None);
+        let maximal = Obfuscator::for_tier(ObfuscationProfileTier::Maximal, Some(b"user-key"), Some("cdn.example.net"));
+
+        assert_eq!(minimal.current_params().max_delay_ms, 0);
+        assert_eq!(balanced.current_params(), ObfuscatorConfig::default());
+        assert_eq!(
+            maximal.strategy_mask(),
+            (1 << STRATEGY_KEYSTREAM) | (1 << STRATEGY_NOISE) | (1 << STRATEGY_TLS_MIMICRY) | (1 << STRATEGY_SIZE_BUCKETS)
+        );
+
+        // Overhead grows with the tier.
+        let payload = vec![0u8; 200];
+        let minimal_len = minimal.transform(&payload).len();
+        let balanced_max = (0..50).map(|_| balanced.transform(&payload).len()).max().unwrap();
+        let maximal_packet = maximal.transform(&payload);
+        assert_eq!(minimal_len, payload.len() + FRAME_HEADER_LEN + 1);
+        assert!(balanced_max <= 80 + FRAME_HEADER_LEN + 1 + payload.len() + 15);
+        // Every maximal packet carries a ClientHello cover in front of the bucket-padded frame.
+        assert_eq!(maximal_packet[0], TLS_HANDSHAKE_RECORD);
+        assert!([512, 1024, 1460].contains(&(maximal_packet.len() - header_offset(&maximal_packet))));
+        assert!(maximal_packet.len() > balanced_max);
+
+        for obfuscator in [&minimal, &balanced, &maximal] {
+            assert_eq!(obfuscator.deobfuscate_data(&obfuscator.transform(&payload)).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_level_counts_only_the_strategies_that_change_frames() {
+        let key = Some(b"user-key".as_slice());
+        assert!(ObfuscationLevel::None < ObfuscationLevel::Scrambled);
+        assert!(ObfuscationLevel::Padded < ObfuscationLevel::Covered);
+        // Without a key nothing is scrambled, however much is padded and covered.
+        assert_eq!(Obfuscator::for_tier(ObfuscationProfileTier::Maximal, None, None).level(), ObfuscationLevel::None);
+        // The minimal tier carries a noise layer and a cover, both switched off.
+        assert_eq!(Obfuscator::for_tier(ObfuscationProfileTier::Minimal, key, None).level(), ObfuscationLevel::Scrambled);
+        assert_eq!(Obfuscator::for_tier(ObfuscationProfileTier::Balanced, key, None).level(), ObfuscationLevel::Covered);
+
+        let padded: Vec<Box<dyn ObfuscationStrategy>> =
+            vec![Box::new(KeystreamMask::new(b"user-key")), Box::new(BucketPadding::new(vec![512, 1024]))];
+        let padded = Obfuscator::with_strategies(ObfuscatorConfig::default(), key, padded).unwrap();
+        assert_eq!(padded.level(), ObfuscationLevel::Padded);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mutation_cycle_changes_params() {
+        let sender = Obfuscator::with_key(b"user-key");
+        let receiver = Obfuscator::with_key(b"user-key");
+        let before = sender.current_params();
+
+        // Let one cycle run (plus a little slack), then stop it.
+        let cycle = tokio::time::timeout(Duration::from_secs(61), sender.run_mutation_cycle_simulation()).await;
+        assert!(cycle.is_err());
+
+        let after = sender.current_params();
+        assert_ne!(after, before);
+        assert_eq!(after.mimicry_style, before.mimicry_style);
+
+        // Packets sent after the mutation still reverse on a peer that hasn't mutated.
+        let payload = b"after the mutation";
+        let framed = sender.obfuscate_data(payload).await;
+        assert_eq!(receiver.deobfuscate_data(&framed).unwrap(), payload);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metrics_track_overhead() {
+        let obfuscator = Obfuscator::with_config(ObfuscatorConfig {
+            max_noise_bytes: 32,
+            mimicry_probability: 1.0,
+            ..ObfuscatorConfig::default()
+        });
+        assert_eq!(obfuscator.metrics(), ObfuscatorMetrics::default());
+
+        let payloads: [&[u8]; 3] = [b"first", &[0u8; 300], b"third payload"];
+        let mut sent = 0u64;
+        for payload in payloads {
+            sent += obfuscator.obfuscate_data(payload).await.len() as u64;
+        }
+
+        let metrics = obfuscator.metrics();
+        assert_eq!(metrics.input_bytes, payloads.iter().map(|p| p.len() as u64).sum::<u64>());
+        assert_eq!(metrics.output_bytes, sent);
+        assert!(metrics.output_bytes > metrics.input_bytes);
+        assert!(metrics.overhead_ratio() > 1.0);
+        assert_eq!(metrics.packets_mimicked, 3);
+        assert!(metrics.noise_bytes < 3 * 32);
+    }
+
+    #[test]
+    fn test_chaff_frame_deobfuscates_to_nothing() {
+        // No noise or mimicry, so frame lengths follow payload lengths exactly.
+        let exact = ObfuscatorConfig {
+            max_noise_bytes: 0,
+            mimicry_probability: 0.0,
+            ..ObfuscatorConfig::default()
+        };
+        let sender = Obfuscator::build(exact, Some(b"user-key"), None, StdRng::from_entropy());
+        let receiver = Obfuscator::with_key(b"user-key");
+
+        // Before any real traffic, chaff still carries a payload.
+        let early = sender.chaff_frame();
+        assert!(early.len() >= FRAME_HEADER_LEN + NONCE_LEN + 1 + CHAFF_FALLBACK_SIZES.start);
+        assert!(receiver.deobfuscate_data(&early).unwrap().is_empty());
+
+        let real = sender.transform(b"real payload");
+        let chaff = sender.chaff_frame();
+        // Same framing and size as a real packet; only the chaff flag tells them apart.
+        let (chaff_header, real_header) = (unmasked_frame(&chaff, b"user-key"), unmasked_frame(&real, b"user-key"));
+        assert_eq!(chaff_header[..2], real_header[..2]);
+        assert_eq!(chaff_header[2], real_header[2] | FLAG_CHAFF);
+        assert_eq!(chaff.len(), real.len());
+        assert!(receiver.deobfuscate_data(&chaff).unwrap().is_empty());
+        assert_eq!(receiver.deobfuscate_data(&real).unwrap(), b"real payload");
+        assert_eq!(sender.metrics().input_bytes, b"real payload".len() as u64);
+
+        // On the wire the flag is masked: the bitmask byte of both kinds of frame takes both
+        // values of the chaff bit.
+        let wire_flags = |frames: Vec<Vec<u8>>| {
+            frames.iter().map(|frame| frame[FRAME_HEADER_LEN - 1] & FLAG_CHAFF).collect::<HashSet<_>>()
+        };
+        let chaff = wire_flags((0..64).map(|_| sender.chaff_frame()).collect());
+        let real = wire_flags((0..64).map(|_| sender.transform(b"real payload")).collect());
+        assert_eq!(chaff.len(), 2);
+        assert_eq!(real.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chaff_is_sent_only_while_idle() {
+        let obfuscator = Arc::new(Obfuscator::with_size_buckets(vec![256]));
+        let (tx, mut rx) = mpsc::channel(64);
+        let chaff_task = obfuscator.spawn_chaff(tx, Duration::from_millis(100));
+
+        // Busy: a real packet every 50ms, so no chaff is needed.
+        for _ in 0..10 {
+            obfuscator.transform(b"real traffic");
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(rx.try_recv().is_err());
+
+        // Idle: chaff keeps the channel going, shaped like real packets.
+        sleep(Duration::from_millis(550)).await;
+        let mut chaff = Vec::new();
+        while let Ok(frame) = rx.try_recv() {
+            chaff.push(frame);
+        }
+        assert!(chaff.len() >= 4, "got {} chaff frames", chaff.len());
+        for frame in &chaff {
+            assert_eq!(frame.len() - header_offset(frame), 256);
+            assert!(obfuscator.deobfuscate_data(frame).unwrap().is_empty());
+        }
+
+        drop(rx);
+        sleep(Duration::from_millis(200)).await;
+        assert!(chaff_task.is_finished());
     }
 }
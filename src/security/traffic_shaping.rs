@@ -0,0 +1,209 @@
+//! This module shapes outbound traffic to look like an adaptive-bitrate video session.
+//! ABR players fetch a media segment in a fast burst, then trickle along at a low
+//! steady rate until the next segment is due. Pacing a high-bandwidth tunnel the same
+//! way makes its volume-over-time profile blend in with ordinary streaming.
+//! OTLS/WS shapes the client-bound side of relayed tunnels when `[video_shaping]` is
+//! configured (see `Relay::with_video_shaping`).
+//!
+//! `ResponseTimingConfig` covers the start of a connection instead: how long a real web
+//! server thinks before the first byte of its response. A server that answers every
+//! handshake after exactly the same pause, or with none at all, stands out.
+
+use rand::Rng;
+use std::f64::consts::PI;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+/// `VideoShapingConfig` describes the burst/steady cycle to mimic.
+#[derive(Debug, Clone, Copy)]
+pub struct VideoShapingConfig {
+    /// Length of one segment cycle (burst followed by steady state).
+    pub segment_interval: Duration,
+    /// Bytes sent at the burst rate at the start of each cycle.
+    pub burst_bytes: usize,
+    /// Pacing rate during the burst, in bytes per second.
+    pub burst_rate_bytes_per_sec: u64,
+    /// Pacing rate for the rest of the cycle, in bytes per second.
+    pub steady_rate_bytes_per_sec: u64,
+}
+
+impl Default for VideoShapingConfig {
+    fn default() -> Self {
+        // Roughly a 4s HLS/DASH segment at ~2.5 Mbit/s.
+        VideoShapingConfig {
+            segment_interval: Duration::from_secs(4),
+            burst_bytes: 1_250_000,
+            burst_rate_bytes_per_sec: 5_000_000,
+            steady_rate_bytes_per_sec: 50_000,
+        }
+    }
+}
+
+/// `VideoShaper` paces writes for a single connection.
+pub struct VideoShaper {
+    config: VideoShapingConfig,
+    cycle_start: Instant,
+    sent_in_cycle: usize,
+}
+
+impl VideoShaper {
+    /// Creates a shaper whose first cycle starts now.
+    pub fn new(config: VideoShapingConfig) -> Self {
+        VideoShaper {
+            config,
+            cycle_start: Instant::now(),
+            sent_in_cycle: 0,
+        }
+    }
+
+    /// Returns how long sending `bytes` should take at `now`, and accounts for them.
+    pub fn next_delay(&mut self, now: Instant, bytes: usize) -> Duration {
+        if now.saturating_duration_since(self.cycle_start) >= self.config.segment_interval {
+            self.cycle_start = now;
+            self.sent_in_cycle = 0;
+        }
+
+        let rate = if self.sent_in_cycle < self.config.burst_bytes {
+            self.config.burst_rate_bytes_per_sec
+        } else {
+            self.config.steady_rate_bytes_per_sec
+        };
+        self.sent_in_cycle += bytes;
+        Duration::from_secs_f64(bytes as f64 / rate.max(1) as f64)
+    }
+
+    /// Waits as long as the video pattern says `bytes` should take to send.
+    pub async fn pace(&mut self, bytes: usize) {
+        let delay = self.next_delay(Instant::now(), bytes);
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+    }
+
+    /// True while the current cycle is still in its burst phase.
+    pub fn in_burst(&self) -> bool {
+        self.sent_in_cycle < self.config.burst_bytes
+    }
+}
+
+/// `ResponseTimingConfig` describes the delay before the server's first response byte.
+/// Server think time is roughly log-normal: most responses come near the median, with a
+/// long tail of slow ones. Samples are clamped to `min..=max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponseTimingConfig {
+    pub min: Duration,
+    pub median: Duration,
+    pub max: Duration,
+}
+
+impl Default for ResponseTimingConfig {
+    fn default() -> Self {
+        // A lightly loaded origin a few milliseconds behind its front end.
+        ResponseTimingConfig {
+            min: Duration::from_millis(5),
+            median: Duration::from_millis(30),
+            max: Duration::from_millis(250),
+        }
+    }
+}
+
+impl ResponseTimingConfig {
+    /// Spread of the distribution: the standard deviation of the delay's logarithm.
+    const SIGMA: f64 = 0.6;
+
+    /// Draws one delay from the distribution.
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        // Box-Muller turns two uniform draws into a standard normal one.
+        let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+        let u2: f64 = rng.gen();
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+        let delay = Duration::from_secs_f64(self.median.as_secs_f64() * (Self::SIGMA * z).exp());
+        delay.clamp(self.min, self.max)
+    }
+
+    /// Waits for one sampled delay before the first response byte is sent.
+    pub async fn delay_first_response(&self) {
+        let delay = self.sample(&mut rand::thread_rng());
+        sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn config() -> VideoShapingConfig {
+        VideoShapingConfig {
+            segment_interval: Duration::from_secs(2),
+            burst_bytes: 100_000,
+            burst_rate_bytes_per_sec: 1_000_000,
+            steady_rate_bytes_per_sec: 10_000,
+        }
+    }
+
+    fn assert_close(actual: Duration, expected_secs: f64) {
+        let diff = (actual.as_secs_f64() - expected_secs).abs();
+        assert!(diff < 0.01, "expected ~{}s, got {:?}", expected_secs, actual);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacing_follows_burst_then_steady_pattern() {
+        let start = Instant::now();
+        let mut shaper = VideoShaper::new(config());
+
+        // Burst: 100 KB at 1 MB/s takes 0.1s.
+        for _ in 0..100 {
+            shaper.pace(1_000).await;
+        }
+        assert_close(start.elapsed(), 0.1);
+        assert!(!shaper.in_burst());
+
+        // Steady state: 10 KB at 10 KB/s takes another second.
+        for _ in 0..10 {
+            shaper.pace(1_000).await;
+        }
+        assert_close(start.elapsed(), 1.1);
+
+        // Keep trickling until the next segment is due, then the burst resumes.
+        while start.elapsed() < Duration::from_secs(2) {
+            shaper.pace(1_000).await;
+        }
+        let cycle_two = Instant::now();
+        shaper.pace(1_000).await;
+        assert!(shaper.in_burst());
+        for _ in 0..99 {
+            shaper.pace(1_000).await;
+        }
+        assert_close(cycle_two.elapsed(), 0.1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_connection_starts_a_fresh_cycle() {
+        let mut shaper = VideoShaper::new(config());
+        shaper.next_delay(Instant::now(), 150_000);
+        assert!(!shaper.in_burst());
+
+        tokio::time::advance(Duration::from_secs(3)).await;
+        let delay = shaper.next_delay(Instant::now(), 1_000);
+        assert_eq!(delay, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_response_timing_clusters_around_the_median() {
+        let timing = ResponseTimingConfig {
+            min: Duration::from_millis(10),
+            median: Duration::from_millis(40),
+            max: Duration::from_millis(200),
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut samples: Vec<Duration> = (0..1000).map(|_| timing.sample(&mut rng)).collect();
+        assert!(samples.iter().all(|delay| (timing.min..=timing.max).contains(delay)));
+        samples.sort();
+        let median = samples[samples.len() / 2].as_secs_f64();
+        assert!((0.035..0.045).contains(&median), "median {}s", median);
+        // The tail is long: well over the median, but rarely at the cap.
+        assert!(samples[950] > Duration::from_millis(80));
+        assert!(samples.iter().filter(|delay| **delay == timing.max).count() < 20);
+    }
+}
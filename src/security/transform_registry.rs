@@ -0,0 +1,615 @@
+//! This module lists the obfuscation transforms the server can build and lets operators check them.
+//! A panel building a custom strategy stack can show what each transform does and what
+//! parameters it takes, and validate a transform's parameters by running a round trip
+//! on sample data before the stack is deployed.
+//!
+//! `TransformRegistry::new` knows the built-in transforms. Code linked into the server (a
+//! third-party crate, say) can `register` more by name before the configuration is read;
+//! the `[[transforms]]` entries of the configuration then name the transforms, in order,
+//! that `pipeline` chains into one `Obfuscator`. The chain is checked first (see
+//! `TransformPipeline::validate`), so a pipeline that would garble traffic fails at startup.
+
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::protocols::common::ProtocolError;
+use crate::security::handshake_padding::HandshakeSizes;
+use crate::security::traffic_obfuscation::{
+    FrameChecksum, HttpMimicry, KeystreamMask, NoisePadding, ObfuscationStrategy, Obfuscator, ObfuscatorConfig,
+    TlsHelloMimicry, SELF_TEST_SAMPLES, STRATEGY_CHECKSUM,
+};
+
+/// The type of a transform parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    /// A non-negative integer.
+    Unsigned,
+    /// A probability within 0.0..=1.0.
+    Probability,
+    /// Free-form text.
+    Text,
+    /// `true` or `false`.
+    Flag,
+}
+
+/// Describes one parameter of a transform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+/// Describes one registered transform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransformInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The parameter schema. Empty for registered transforms, which check their own parameters.
+    pub params: Vec<ParamInfo>,
+}
+
+/// Builds a transform from its parameters, or explains why they are unusable.
+pub type TransformFactory =
+    Arc<dyn Fn(&HashMap<String, String>) -> Result<Box<dyn ObfuscationStrategy>, ProtocolError> + Send + Sync>;
+
+/// One entry of a pipeline: the transform's registered name and its parameters.
+/// In the configuration this is a `[[transforms]]` table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformSpec {
+    pub name: String,
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+}
+
+/// `PipelineError` explains why a chain of transforms can't frame traffic as configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineError {
+    /// The transform's id doesn't fit the frame header bitmask.
+    IdOutOfRange { transform: String, id: u8 },
+    /// Two transforms share an id, so the frame header couldn't tell which one was applied.
+    DuplicateId { transform: String, id: u8 },
+    /// A second cover would dress one fake protocol up as another.
+    SecondCover { first: String, second: String },
+    /// `transform` is listed after `after`, which wraps it on the wire: payload layers come
+    /// first, then seals, then the cover.
+    OutOfOrder { transform: String, after: String },
+    /// Integrity is required but no `checksum` transform seals the frames.
+    MissingChecksum,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::IdOutOfRange { transform, id } => write!(f, "transform '{}': id {} is outside 0..7", transform, id),
+            PipelineError::DuplicateId { transform, id } => {
+                write!(f, "transform '{}': id {} is already used earlier in the pipeline", transform, id)
+            }
+            PipelineError::SecondCover { first, second } => {
+                write!(f, "transform '{}': the pipeline is already covered by '{}'", second, first)
+            }
+            PipelineError::OutOfOrder { transform, after } => {
+                write!(f, "transform '{}' must be listed before '{}', which wraps it", transform, after)
+            }
+            PipelineError::MissingChecksum => write!(f, "integrity is required but no 'checksum' transform is listed"),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+impl From<PipelineError> for ProtocolError {
+    fn from(err: PipelineError) -> Self {
+        ProtocolError::ObfuscationError(err.to_string())
+    }
+}
+
+/// Where a transform sits in a frame, innermost first. A pipeline lists them in this order.
+fn stage_rank(strategy: &dyn ObfuscationStrategy) -> u8 {
+    if strategy.is_cover() {
+        2
+    } else if strategy.is_seal() {
+        1
+    } else {
+        0
+    }
+}
+
+/// `TransformPipeline` is a chain of built transforms, in configuration order, that hasn't
+/// been turned into an `Obfuscator` yet.
+pub struct TransformPipeline {
+    stages: Vec<(String, Box<dyn ObfuscationStrategy>)>,
+    /// Key of the `keystream` transform, which also keys the frame header and length masks.
+    key: Option<Vec<u8>>,
+    checksum_required: bool,
+}
+
+impl TransformPipeline {
+    /// Makes `validate` fail unless a `checksum` transform seals every frame.
+    pub fn with_required_checksum(mut self, required: bool) -> Self {
+        self.checksum_required = required;
+        self
+    }
+
+    /// Checks that the transforms fit together: every id fits the header bitmask and is used
+    /// once, at most one cover is listed, payload layers come before seals and covers last (the
+    /// order they wrap each other in on the wire), and a checksum is present if required.
+    pub fn validate(&self) -> Result<(), PipelineError> {
+        let mut cover: Option<&str> = None;
+        let mut outermost: Option<&(String, Box<dyn ObfuscationStrategy>)> = None;
+        for (index, (name, strategy)) in self.stages.iter().enumerate() {
+            let id = strategy.id();
+            if id >= 7 {
+                return Err(PipelineError::IdOutOfRange { transform: name.clone(), id });
+            }
+            if self.stages[..index].iter().any(|(_, earlier)| earlier.id() == id) {
+                return Err(PipelineError::DuplicateId { transform: name.clone(), id });
+            }
+            if strategy.is_cover() {
+                if let Some(first) = cover {
+                    return Err(PipelineError::SecondCover { first: first.to_string(), second: name.clone() });
+                }
+                cover = Some(name);
+            }
+            match outermost {
+                Some((after, wrapper)) if stage_rank(wrapper.as_ref()) > stage_rank(strategy.as_ref()) => {
+                    return Err(PipelineError::OutOfOrder { transform: name.clone(), after: after.clone() });
+                }
+                _ => outermost = Some(&self.stages[index]),
+            }
+        }
+        if self.checksum_required && !self.stages.iter().any(|(_, strategy)| strategy.id() == STRATEGY_CHECKSUM) {
+            return Err(PipelineError::MissingChecksum);
+        }
+        Ok(())
+    }
+
+    /// Validates the pipeline and chains it into one `Obfuscator`; `config` still controls the
+    /// timing jitter.
+    pub fn into_obfuscator(self, config: ObfuscatorConfig) -> Result<Obfuscator, ProtocolError> {
+        self.validate()?;
+        let strategies = self.stages.into_iter().map(|(_, strategy)| strategy).collect();
+        Obfuscator::with_strategies(config, self.key.as_deref(), strategies)
+    }
+}
+
+/// Description listed for transforms added with `register`.
+const REGISTERED_DESCRIPTION: &str = "Registered at startup.";
+
+/// `TransformRegistry` knows the available transforms by name. Clones share the factories.
+#[derive(Clone)]
+pub struct TransformRegistry {
+    transforms: Vec<(TransformInfo, TransformFactory)>,
+}
+
+impl Default for TransformRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransformRegistry {
+    /// A registry holding the built-in transforms.
+    pub fn new() -> Self {
+        let mut registry = TransformRegistry { transforms: Vec::new() };
+        for info in Self::builtins() {
+            let factory: TransformFactory = {
+                let info = info.clone();
+                Arc::new(move |params| {
+                    check_params(&info, params)?;
+                    Ok(build_builtin(info.name, params))
+                })
+            };
+            registry.transforms.push((info, factory));
+        }
+        registry
+    }
+
+    /// Makes `factory` buildable as `name`, replacing any transform already registered under it.
+    /// The factory gets the parameters exactly as configured and must check them itself.
+    pub fn register<F>(&mut self, name: &'static str, factory: F)
+    where
+        F: Fn(&HashMap<String, String>) -> Result<Box<dyn ObfuscationStrategy>, ProtocolError> + Send + Sync + 'static,
+    {
+        self.transforms.retain(|(info, _)| info.name != name);
+        let info = TransformInfo {
+            name,
+            description: REGISTERED_DESCRIPTION,
+            params: Vec::new(),
+        };
+        self.transforms.push((info, Arc::new(factory)));
+    }
+
+    /// Lists every transform, built-ins first, with its parameter schema.
+    pub fn list(&self) -> Vec<TransformInfo> {
+        self.transforms.iter().map(|(info, _)| info.clone()).collect()
+    }
+
+    /// Builds the transform `name` from `params`, validating them against its schema.
+    pub fn build(&self, name: &str, params: &HashMap<String, String>) -> Result<Box<dyn ObfuscationStrategy>, ProtocolError> {
+        let (_, factory) = self
+            .transforms
+            .iter()
+            .find(|(info, _)| info.name == name)
+            .ok_or_else(|| ProtocolError::ObfuscationError(format!("unknown transform '{}'", name)))?;
+        factory(params)
+    }
+
+    /// Builds the transforms named by `specs`, in order, without checking how they fit
+    /// together yet (see `TransformPipeline::validate`). The `keystream` transform's key also
+    /// keys the frame header and length masks; without it those masks can be undone by anyone.
+    pub fn build_pipeline(&self, specs: &[TransformSpec]) -> Result<TransformPipeline, ProtocolError> {
+        let stages = specs
+            .iter()
+            .map(|spec| Ok((spec.name.clone(), self.build(&spec.name, &spec.params)?)))
+            .collect::<Result<_, ProtocolError>>()?;
+        let key = specs
+            .iter()
+            .find(|spec| spec.name == "keystream")
+            .and_then(|spec| spec.params.get("key"))
+            .map(|key| key.as_bytes().to_vec());
+        Ok(TransformPipeline {
+            stages,
+            key,
+            checksum_required: false,
+        })
+    }
+
+    /// Chains the transforms named by `specs`, in order, into one `Obfuscator`, once the chain
+    /// passes `TransformPipeline::validate`. `config` still controls the timing jitter.
+    pub fn pipeline(&self, specs: &[TransformSpec], config: ObfuscatorConfig) -> Result<Obfuscator, ProtocolError> {
+        self.build_pipeline(specs)?.into_obfuscator(config)
+    }
+
+    /// Builds the transform and checks that it round-trips a set of sample payloads.
+    /// The samples are framed like real packets, since mimicry covers only ever wrap a frame.
+    pub fn self_test(&self, name: &str, params: &HashMap<String, String>) -> Result<(), ProtocolError> {
+        let obfuscator = Obfuscator::with_strategies(ObfuscatorConfig::default(), None, vec![self.build(name, params)?])?;
+        for sample in SELF_TEST_SAMPLES {
+            // Repeat so randomized branches (e.g. mimicry on/off) are both exercised.
+            for _ in 0..8 {
+                let recovered = obfuscator
+                    .deobfuscate_data(&obfuscator.transform(sample))
+                    .map_err(|e| ProtocolError::ObfuscationError(format!("transform '{}': {}", name, e)))?;
+                if recovered != sample {
+                    return Err(ProtocolError::ObfuscationError(format!(
+                        "transform '{}': round trip returned different bytes",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The built-in transforms with their parameter schemas.
+    fn builtins() -> Vec<TransformInfo> {
+        vec![
+            TransformInfo {
+                name: "noise",
+                description: "Appends random noise to obscure packet sizes.",
+                params: vec![ParamInfo {
+                    name: "max_noise_bytes",
+                    kind: ParamKind::Unsigned,
+                    required: true,
+                    description: "Exclusive upper bound on noise bytes per packet.",
+                }],
+            },
+            TransformInfo {
+                name: "http-mimicry",
+                description: "Sometimes prepends a fake HTTP request header.",
+                params: vec![
+                    ParamInfo {
+                        name: "probability",
+                        kind: ParamKind::Probability,
+                        required: true,
+                        description: "Chance of adding the header to a packet.",
+                    },
+                    ParamInfo {
+                        name: "host",
+                        kind: ParamKind::Text,
+                        required: false,
+                        description: "Host named in the header; defaults to www.example.com.",
+                    },
+                ],
+            },
+            TransformInfo {
+                name: "tls-mimicry",
+                description: "Sometimes prepends a synthesized TLS 1.3 ClientHello record.",
+                params: vec![
+                    ParamInfo {
+                        name: "probability",
+                        kind: ParamKind::Probability,
+                        required: true,
+                        description: "Chance of adding the record to a packet.",
+                    },
+                    ParamInfo {
+                        name: "sni",
+                        kind: ParamKind::Text,
+                        required: true,
+                        description: "Server name shown in the ClientHello.",
+                    },
+                    ParamInfo {
+                        name: "pad_handshake",
+                        kind: ParamKind::Flag,
+                        required: false,
+                        description: "Pads the ClientHello to the sizes browsers send to the server name.",
+                    },
+                ],
+            },
+            TransformInfo {
+                name: "keystream",
+                description: "Masks the payload with a keyed ChaCha20 keystream.",
+                params: vec![ParamInfo {
+                    name: "key",
+                    kind: ParamKind::Text,
+                    required: true,
+                    description: "Shared key; both peers must use the same value.",
+                }],
+            },
+            TransformInfo {
+                name: "checksum",
+                description: "Appends a keyed tag so altered or corrupted frames are rejected.",
+                params: vec![ParamInfo {
+                    name: "key",
+                    kind: ParamKind::Text,
+                    required: true,
+                    description: "Shared key; both peers must use the same value.",
+                }],
+            },
+        ]
+    }
+}
+
+/// Checks `params` against a built-in transform's schema.
+fn check_params(info: &TransformInfo, params: &HashMap<String, String>) -> Result<(), ProtocolError> {
+    let name = info.name;
+    let invalid = |msg: String| ProtocolError::ObfuscationError(format!("transform '{}': {}", name, msg));
+    for key in params.keys() {
+        if !info.params.iter().any(|p| p.name == key) {
+            return Err(invalid(format!("unknown parameter '{}'", key)));
+        }
+    }
+    for param in &info.params {
+        let value = match params.get(param.name) {
+            Some(value) => value,
+            None if param.required => return Err(invalid(format!("missing parameter '{}'", param.name))),
+            None => continue,
+        };
+        let valid = match param.kind {
+            ParamKind::Unsigned => value.parse::<usize>().is_ok(),
+            ParamKind::Probability => value.parse::<f64>().is_ok_and(|p| (0.0..=1.0).contains(&p)),
+            ParamKind::Text => !value.is_empty(),
+            ParamKind::Flag => value.parse::<bool>().is_ok(),
+        };
+        if !valid {
+            return Err(invalid(format!("invalid value '{}' for '{}'", value, param.name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds a built-in transform from parameters `check_params` accepted.
+fn build_builtin(name: &str, params: &HashMap<String, String>) -> Box<dyn ObfuscationStrategy> {
+    // `check_params` accepted the values, so these parses cannot fail.
+    let get = |key: &str| params[key].as_str();
+    match name {
+        "noise" => Box::new(NoisePadding::new(get("max_noise_bytes").parse().unwrap())),
+        "http-mimicry" => match params.get("host") {
+            Some(host) => Box::new(HttpMimicry::with_host(get("probability").parse().unwrap(), host)),
+            None => Box::new(HttpMimicry::new(get("probability").parse().unwrap())),
+        },
+        "tls-mimicry" => {
+            let mimicry = TlsHelloMimicry::new(get("probability").parse().unwrap(), get("sni"));
+            match params.get("pad_handshake").map(|flag| flag.parse().unwrap()) {
+                Some(true) => Box::new(mimicry.with_handshake_padding(HandshakeSizes::for_domain(get("sni")))),
+                _ => Box::new(mimicry),
+            }
+        }
+        "keystream" => Box::new(KeystreamMask::new(get("key").as_bytes())),
+        "checksum" => Box::new(FrameChecksum::new(get("key").as_bytes())),
+        _ => unreachable!("every built-in transform is buildable"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::traffic_obfuscation::FrameVersion;
+    use std::io;
+
+    /// A custom transform, as a third-party crate might register: reverses the payload.
+    struct Reverse;
+
+    impl ObfuscationStrategy for Reverse {
+        fn id(&self) -> u8 {
+            6
+        }
+        fn apply(&self, data: &[u8]) -> Vec<u8> {
+            data.iter().rev().copied().collect()
+        }
+        fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(self.apply(data))
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn valid_params(name: &str) -> HashMap<String, String> {
+        match name {
+            "noise" => params(&[("max_noise_bytes", "32")]),
+            "http-mimicry" => params(&[("probability", "0.5"), ("host", "cdn.example.net")]),
+            "tls-mimicry" => params(&[("probability", "0.5"), ("sni", "www.example.com"), ("pad_handshake", "true")]),
+            "keystream" | "checksum" => params(&[("key", "user-secret")]),
+            other => panic!("no test params for '{}'", other),
+        }
+    }
+
+    #[test]
+    fn test_list_contains_builtin_transforms() {
+        let names: Vec<&str> = TransformRegistry::new().list().iter().map(|info| info.name).collect();
+        assert_eq!(names, vec!["noise", "http-mimicry", "tls-mimicry", "keystream", "checksum"]);
+        for info in TransformRegistry::new().list() {
+            assert!(!info.description.is_empty());
+            assert!(!info.params.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_self_test_passes_with_valid_params() {
+        for info in TransformRegistry::new().list() {
+            TransformRegistry::new().self_test(info.name, &valid_params(info.name))
+                .unwrap_or_else(|e| panic!("{} failed: {}", info.name, e));
+        }
+    }
+
+    #[test]
+    fn test_self_test_rejects_invalid_params() {
+        let cases = [
+            ("noise", params(&[("max_noise_bytes", "-1")])),
+            ("noise", params(&[])),
+            ("http-mimicry", params(&[("probability", "1.5")])),
+            ("http-mimicry", params(&[("probability", "0.5"), ("extra", "1")])),
+            ("tls-mimicry", params(&[("probability", "0.5"), ("sni", "")])),
+            ("tls-mimicry", params(&[("probability", "0.5"), ("sni", "www.example.com"), ("pad_handshake", "yes")])),
+            ("keystream", params(&[])),
+            ("rot13", params(&[])),
+        ];
+        for (name, params) in cases {
+            match TransformRegistry::new().self_test(name, &params) {
+                Err(ProtocolError::ObfuscationError(msg)) => assert!(msg.contains(name), "{}", msg),
+                other => panic!("{} with {:?} should fail, got {:?}", name, params, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_registered_transform_builds_into_a_pipeline() {
+        let mut registry = TransformRegistry::new();
+        registry.register("reverse", |params: &HashMap<String, String>| {
+            if !params.is_empty() {
+                return Err(ProtocolError::ObfuscationError("transform 'reverse' takes no parameters".to_string()));
+            }
+            Ok(Box::new(Reverse) as Box<dyn ObfuscationStrategy>)
+        });
+        let info = registry.list().pop().unwrap();
+        assert_eq!((info.name, info.params.len()), ("reverse", 0));
+        registry.self_test("reverse", &HashMap::new()).unwrap();
+        assert!(registry.build("reverse", &params(&[("depth", "2")])).is_err());
+        // Only the registry it was added to knows it.
+        assert!(TransformRegistry::new().build("reverse", &HashMap::new()).is_err());
+
+        let specs: Vec<TransformSpec> = toml::from_str::<HashMap<String, Vec<TransformSpec>>>(
+            r#"
+            [[transforms]]
+            name = "reverse"
+
+            [[transforms]]
+            name = "noise"
+            params = { max_noise_bytes = "16" }
+            "#,
+        )
+        .unwrap()
+        .remove("transforms")
+        .unwrap();
+        let config = ObfuscatorConfig {
+            max_delay_ms: 0,
+            ..ObfuscatorConfig::default()
+        };
+        let obfuscator = registry.pipeline(&specs, config).unwrap();
+        // A version 2 header is plaintext: the header, then the noise layer wrapping the
+        // reversed payload.
+        let frame = obfuscator.transform_as(b"abc", FrameVersion::V2);
+        assert_eq!(&frame[1..3], &[2, (1 << 6) | (1 << 1)]);
+        let noise_len = frame[3] as usize;
+        assert_eq!(&frame[4..frame.len() - noise_len], b"cba");
+        assert_eq!(obfuscator.deobfuscate_data(&frame).unwrap(), b"abc");
+        assert_eq!(obfuscator.deobfuscate_data(&obfuscator.transform(b"abc")).unwrap(), b"abc");
+
+        let doubled = [specs[0].clone(), specs[0].clone()];
+        match registry.pipeline(&doubled, config) {
+            Err(ProtocolError::ObfuscationError(msg)) => assert!(msg.contains("already used"), "{}", msg),
+            other => panic!("a repeated id should be rejected, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn specs(names: &[&str]) -> Vec<TransformSpec> {
+        names
+            .iter()
+            .map(|name| TransformSpec {
+                name: name.to_string(),
+                params: valid_params(name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_accepts_a_coherent_pipeline() {
+        let registry = TransformRegistry::new();
+        let pipeline = registry
+            .build_pipeline(&specs(&["keystream", "noise", "checksum", "tls-mimicry"]))
+            .unwrap()
+            .with_required_checksum(true);
+        assert_eq!(pipeline.validate(), Ok(()));
+        let obfuscator = pipeline.into_obfuscator(ObfuscatorConfig::default()).unwrap();
+        assert_eq!(obfuscator.deobfuscate_data(&obfuscator.transform(b"payload")).unwrap(), b"payload");
+    }
+
+    #[test]
+    fn test_validate_rejects_incoherent_pipelines() {
+        let out_of_order = |transform: &str, after: &str| PipelineError::OutOfOrder {
+            transform: transform.to_string(),
+            after: after.to_string(),
+        };
+        let cases = [
+            (vec!["tls-mimicry", "noise"], out_of_order("noise", "tls-mimicry")),
+            (vec!["checksum", "keystream"], out_of_order("keystream", "checksum")),
+            (vec!["noise", "http-mimicry", "checksum"], out_of_order("checksum", "http-mimicry")),
+            (
+                vec!["http-mimicry", "tls-mimicry"],
+                PipelineError::SecondCover { first: "http-mimicry".to_string(), second: "tls-mimicry".to_string() },
+            ),
+            (vec!["noise", "noise"], PipelineError::DuplicateId { transform: "noise".to_string(), id: 1 }),
+        ];
+        let registry = TransformRegistry::new();
+        for (names, expected) in cases {
+            let pipeline = registry.build_pipeline(&specs(&names)).unwrap();
+            assert_eq!(pipeline.validate(), Err(expected.clone()), "{:?}", names);
+            match pipeline.into_obfuscator(ObfuscatorConfig::default()) {
+                Err(ProtocolError::ObfuscationError(msg)) => assert_eq!(msg, expected.to_string()),
+                other => panic!("{:?} should not build, got {:?}", names, other.map(|_| ())),
+            }
+        }
+
+        // A checksum is only demanded when integrity is required.
+        let unsealed = registry.build_pipeline(&specs(&["keystream", "noise"])).unwrap();
+        assert_eq!(unsealed.validate(), Ok(()));
+        assert_eq!(unsealed.with_required_checksum(true).validate(), Err(PipelineError::MissingChecksum));
+
+        // An id past the header bitmask, from a registered transform.
+        struct Oversized;
+        impl ObfuscationStrategy for Oversized {
+            fn id(&self) -> u8 {
+                7
+            }
+            fn apply(&self, data: &[u8]) -> Vec<u8> {
+                data.to_vec()
+            }
+            fn reverse(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+                Ok(data.to_vec())
+            }
+        }
+        let mut registry = TransformRegistry::new();
+        registry.register("oversized", |_: &HashMap<String, String>| Ok(Box::new(Oversized) as Box<dyn ObfuscationStrategy>));
+        let spec = TransformSpec { name: "oversized".to_string(), params: HashMap::new() };
+        assert_eq!(
+            registry.build_pipeline(&[spec]).unwrap().validate(),
+            Err(PipelineError::IdOutOfRange { transform: "oversized".to_string(), id: 7 })
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! This module provides a global bandwidth limiter for HezarDastan Core.
+//! A single token bucket caps the total rate across all connections, and each
+//! connection additionally gets its own bucket so no single tunnel can starve the others.
+//! When several connections are waiting on the global bucket, it is handed out in
+//! weighted-fair order according to each connection's `ConnectionPriority`.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+
+/// Fraction of a second worth of tokens a bucket may accumulate while idle.
+const BURST_WINDOW_SECS: f64 = 0.1;
+/// How long a waiter that is not next in line sleeps before checking again.
+const TURN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// `ConnectionPriority` decides how the global bucket is shared under contention.
+/// Interactive traffic (small packets, latency-sensitive) is served more often than bulk transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPriority {
+    Interactive,
+    Normal,
+    Bulk,
+}
+
+impl ConnectionPriority {
+    /// Reads the `priority` key from a tunnel's `protocol_params`, if present and valid.
+    pub fn from_params(params: &HashMap<String, String>) -> Option<Self> {
+        match params.get("priority")?.to_lowercase().as_str() {
+            "interactive" => Some(ConnectionPriority::Interactive),
+            "normal" => Some(ConnectionPriority::Normal),
+            "bulk" => Some(ConnectionPriority::Bulk),
+            _ => None,
+        }
+    }
+
+    /// Guesses a priority from the average size of the packets seen so far.
+    pub fn from_traffic(avg_packet_len: usize) -> Self {
+        if avg_packet_len <= 256 {
+            ConnectionPriority::Interactive
+        } else if avg_packet_len >= 1200 {
+            ConnectionPriority::Bulk
+        } else {
+            ConnectionPriority::Normal
+        }
+    }
+
+    /// Relative share of the global bucket under contention.
+    pub fn weight(&self) -> f64 {
+        match self {
+            ConnectionPriority::Interactive => 4.0,
+            ConnectionPriority::Normal => 2.0,
+            ConnectionPriority::Bulk => 1.0,
+        }
+    }
+}
+
+/// `BandwidthConfig` controls the global limiter.
+#[derive(Debug, Clone, Copy)]
+pub struct BandwidthConfig {
+    /// Total bytes per second allowed across all connections.
+    pub total_bytes_per_sec: u64,
+    /// Largest fraction (0.0..=1.0) of the total rate a single connection may use.
+    /// `1.0` disables per-connection fair sharing.
+    pub max_connection_share: f64,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        BandwidthConfig {
+            total_bytes_per_sec: 10 * 1024 * 1024, // 10 MiB/s
+            max_connection_share: 1.0,
+        }
+    }
+}
+
+/// A classic token bucket refilled continuously at `rate` tokens per second.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        let capacity = (rate * BURST_WINDOW_SECS).max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Time until `amount` tokens will be available, or zero if they already are.
+    fn wait_time(&self, amount: f64) -> Duration {
+        if self.tokens >= amount {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((amount - self.tokens) / self.rate)
+        }
+    }
+}
+
+/// Shared state behind the global bucket: the bucket itself plus the queue of
+/// connections currently waiting for it, ordered by virtual finish time.
+struct GlobalState {
+    bucket: TokenBucket,
+    /// Virtual time of the most recently served waiter.
+    virtual_time: f64,
+    /// `(pass, connection id)` for every connection waiting on the bucket.
+    waiters: Vec<(f64, u64)>,
+}
+
+impl GlobalState {
+    fn next_in_line(&self) -> Option<u64> {
+        self.waiters
+            .iter()
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal).then(a.1.cmp(&b.1)))
+            .map(|(_, id)| *id)
+    }
+
+    fn remove_waiter(&mut self, id: u64) {
+        self.waiters.retain(|(_, waiter)| *waiter != id);
+    }
+}
+
+/// `BandwidthLimiter` owns the global bucket shared by every connection.
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    global: Arc<Mutex<GlobalState>>,
+    config: BandwidthConfig,
+    next_id: Arc<AtomicU64>,
+}
+
+impl BandwidthLimiter {
+    /// Creates a new limiter from the given configuration.
+    pub fn new(config: BandwidthConfig) -> Self {
+        BandwidthLimiter {
+            global: Arc::new(Mutex::new(GlobalState {
+                bucket: TokenBucket::new(config.total_bytes_per_sec as f64),
+                virtual_time: 0.0,
+                waiters: Vec::new(),
+            })),
+            config,
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the configuration this limiter was built with.
+    pub fn config(&self) -> BandwidthConfig {
+        self.config
+    }
+
+    /// Creates the per-connection handle used on a tunnel's write path, with `Normal` priority.
+    pub fn connection(&self) -> ConnectionBandwidth {
+        self.connection_with_priority(ConnectionPriority::Normal)
+    }
+
+    /// Creates a per-connection handle scheduled with the given priority.
+    pub fn connection_with_priority(&self, priority: ConnectionPriority) -> ConnectionBandwidth {
+        let share = self.config.max_connection_share.clamp(0.0, 1.0);
+        let own_rate = (self.config.total_bytes_per_sec as f64 * share).max(1.0);
+        ConnectionBandwidth {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            priority,
+            global: self.global.clone(),
+            own: Mutex::new(ConnectionState {
+                bucket: TokenBucket::new(own_rate),
+                pass: 0.0,
+            }),
+        }
+    }
+}
+
+struct ConnectionState {
+    bucket: TokenBucket,
+    /// Virtual finish time of this connection's last admitted chunk.
+    pass: f64,
+}
+
+/// `ConnectionBandwidth` rate-limits a single connection against both its own share
+/// and the global bucket.
+pub struct ConnectionBandwidth {
+    id: u64,
+    priority: ConnectionPriority,
+    global: Arc<Mutex<GlobalState>>,
+    own: Mutex<ConnectionState>,
+}
+
+/// Removes a connection from the waiter queue if `acquire` is cancelled mid-wait.
+struct WaiterGuard<'a> {
+    conn: &'a ConnectionBandwidth,
+    queued: bool,
+}
+
+impl Drop for WaiterGuard<'_> {
+    fn drop(&mut self) {
+        if self.queued {
+            if let Ok(mut global) = self.conn.global.lock() {
+                global.remove_waiter(self.conn.id);
+            }
+        }
+    }
+}
+
+impl ConnectionBandwidth {
+    /// Returns the scheduling priority of this connection.
+    pub fn priority(&self) -> ConnectionPriority {
+        self.priority
+    }
+
+    /// Waits until `bytes` may be written without exceeding either limit.
+    /// Large writes are admitted in burst-sized chunks so they cannot monopolise the bucket.
+    pub async fn acquire(&self, bytes: usize) {
+        let mut remaining = bytes as f64;
+        let mut guard = WaiterGuard { conn: self, queued: false };
+        while remaining > 0.0 {
+            let wait = {
+                let now = Instant::now();
+                let mut own = self.own.lock().unwrap();
+                let mut global = self.global.lock().unwrap();
+                own.bucket.refill(now);
+                global.bucket.refill(now);
+
+                if !guard.queued {
+                    // Don't let an idle connection bank credit from the past.
+                    own.pass = own.pass.max(global.virtual_time);
+                    global.waiters.push((own.pass, self.id));
+                    guard.queued = true;
+                }
+
+                let chunk = remaining.min(own.bucket.capacity).min(global.bucket.capacity);
+                if global.next_in_line() != Some(self.id) {
+                    global.bucket.wait_time(chunk).max(TURN_POLL_INTERVAL)
+                } else {
+                    let wait = own.bucket.wait_time(chunk).max(global.bucket.wait_time(chunk));
+                    if wait.is_zero() {
+                        own.bucket.tokens -= chunk;
+                        global.bucket.tokens -= chunk;
+                        global.virtual_time = own.pass;
+                        own.pass += chunk / self.priority.weight();
+                        global.remove_waiter(self.id);
+                        guard.queued = false;
+                        remaining -= chunk;
+                    }
+                    wait
+                }
+            };
+            if !wait.is_zero() {
+                sleep(wait).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_total_throughput_stays_under_cap() {
+        let limiter = BandwidthLimiter::new(BandwidthConfig {
+            total_bytes_per_sec: 10_000,
+            max_connection_share: 1.0,
+        });
+        let conn = limiter.connection();
+
+        let start = Instant::now();
+        for _ in 0..50 {
+            conn.acquire(1_000).await;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        // 50 KB at 10 KB/s, minus the initial 1 KB burst, needs at least 4.9s.
+        assert!(elapsed >= 4.8, "sent 50 KB in {:.2}s, cap exceeded", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_two_connections_share_fairly() {
+        let limiter = BandwidthLimiter::new(BandwidthConfig {
+            total_bytes_per_sec: 10_000,
+            max_connection_share: 0.5,
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let mut handles = Vec::new();
+        let counters: Vec<Arc<AtomicU64>> = (0..2).map(|_| Arc::new(AtomicU64::new(0))).collect();
+        for counter in &counters {
+            let conn = limiter.connection();
+            let counter = counter.clone();
+            handles.push(tokio::spawn(async move {
+                while Instant::now() < deadline {
+                    conn.acquire(500).await;
+                    counter.fetch_add(500, Ordering::SeqCst);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let a = counters[0].load(Ordering::SeqCst) as f64;
+        let b = counters[1].load(Ordering::SeqCst) as f64;
+        let total = a + b;
+        assert!(total <= 10_000.0 * 10.0 + 2_000.0, "total {} exceeds cap", total);
+        assert!((a / total - 0.5).abs() < 0.1, "unfair split: {} vs {}", a, b);
+    }
+
+    #[test]
+    fn test_priority_from_params_and_traffic() {
+        let mut params = HashMap::new();
+        assert_eq!(ConnectionPriority::from_params(&params), None);
+        params.insert("priority".to_string(), "Interactive".to_string());
+        assert_eq!(ConnectionPriority::from_params(&params), Some(ConnectionPriority::Interactive));
+        params.insert("priority".to_string(), "bogus".to_string());
+        assert_eq!(ConnectionPriority::from_params(&params), None);
+
+        assert_eq!(ConnectionPriority::from_traffic(80), ConnectionPriority::Interactive);
+        assert_eq!(ConnectionPriority::from_traffic(600), ConnectionPriority::Normal);
+        assert_eq!(ConnectionPriority::from_traffic(1400), ConnectionPriority::Bulk);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interactive_connection_gets_lower_latency_than_bulk() {
+        let limiter = BandwidthLimiter::new(BandwidthConfig {
+            total_bytes_per_sec: 10_000,
+            max_connection_share: 1.0,
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let run = |conn: ConnectionBandwidth| {
+            tokio::spawn(async move {
+                let mut waits = Vec::new();
+                while Instant::now() < deadline {
+                    let start = Instant::now();
+                    conn.acquire(500).await;
+                    waits.push(start.elapsed());
+                }
+                waits
+            })
+        };
+        let bulk = run(limiter.connection_with_priority(ConnectionPriority::Bulk));
+        let interactive = run(limiter.connection_with_priority(ConnectionPriority::Interactive));
+
+        let mean = |waits: &[Duration]| waits.iter().sum::<Duration>() / waits.len() as u32;
+        let bulk_waits = bulk.await.unwrap();
+        let interactive_waits = interactive.await.unwrap();
+
+        // The bulk connection still makes progress, but waits noticeably longer.
+        assert!(bulk_waits.len() > 1);
+        assert!(interactive_waits.len() > bulk_waits.len() * 2);
+        assert!(mean(&interactive_waits) * 2 < mean(&bulk_waits));
+    }
+}
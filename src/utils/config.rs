@@ -1 +1,1114 @@
+//! This module loads the server configuration from a TOML file and the command line.
+//! Every key is optional: anything missing keeps the built-in default, so an empty file
+//! (or no file at all) gives the same server as before configuration loading existed.
+//! Command-line flags override the file.
+//!
+//! `region` selects a bundled `RegionProfile`, which supplies the mimic domain and obfuscation
+//! tier for keys the file leaves out.
+//!
+//! On SIGHUP the file is read again (see `reload`). The mimic domain, the obfuscation tier,
+//! the app preset and the Kill Switch toggle take effect on the running protocols; listen addresses, the protocol list and
+//! the connection limits only change on restart.
+//!
+//! ```toml
+//! tcp_listen_addrs = ["0.0.0.0:8443"]
+//! udp_listen_addr = "0.0.0.0:8444"
+//! unix_listen_path = "/run/hezardastan/otls-ws.sock"
+//! enabled_protocols = ["otls-ws", "aoquic"]
+//! region = "ir"
+//! mimic_domain = "www.example.com"
+//! obfuscation_tier = "balanced"
+//! app_preset = "teams"
+//! min_obfuscation_level = "padded"
+//! upstream_addr = "127.0.0.1:1080"
+//! max_connections = 1024
+//! peer_connections_per_second = 10.0
+//! max_concurrent_handshakes = 64
+//! handshake_queue_secs = 5
+//! metrics_addr = "127.0.0.1:9090"
+//! dual_stack = false
+//! audit_log_path = "/var/log/hezardastan/audit.log"
+//! require_checksum = true
+//! obfuscation_stage_timing = false
+//!
+//! [listener_stagger]
+//! min_ms = 50
+//! max_ms = 500
+//! warmup_ms = 5000
+//!
+//! [probe_detection]
+//! window_secs = 60
+//! suspicious_threshold = 3
+//! max_connects_per_window = 20
+//! decoy_cooldown_secs = 900
+//!
+//! [video_shaping]
+//! segment_interval_secs = 4
+//! burst_bytes = 1250000
+//! burst_rate_bytes_per_sec = 5000000
+//! steady_rate_bytes_per_sec = 50000
+//!
+//! [response_timing]
+//! min_ms = 5
+//! median_ms = 30
+//! max_ms = 250
+//!
+//! [bandwidth]
+//! total_bytes_per_sec = 10485760
+//! max_connection_share = 0.25
+//!
+//! [redaction]
+//! mode = "hash"
+//! salt = "per-deployment secret"
+//!
+//! [rejection]
+//! enabled = true
+//! retry_after_secs = 30
+//! server_header = "cloudflare"
+//!
+//! [kill_switch]
+//! enabled = true
+//! probe_target = "1.1.1.1:443"
+//! probe_interval_secs = 10
+//! probe_timeout_secs = 5
+//! failure_threshold = 3
+//! # Linux builds with the `firewall` feature only.
+//! firewall_table = "hezardastan_kill_switch"
+//!
+//! [[transforms]]
+//! name = "keystream"
+//! params = { key = "per-deployment secret" }
+//!
+//! [[transforms]]
+//! name = "noise"
+//! params = { max_noise_bytes = "64" }
+//!
+//! [[transforms]]
+//! name = "checksum"
+//! params = { key = "per-deployment secret" }
+//! ```
 
+use serde::Deserialize;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::protocols::common::{ProtocolConfig, ProtocolError, ProtocolType};
+use crate::protocols::handshake_limiter::{HandshakeLimiterConfig, OverflowPolicy};
+use crate::protocols::listener::BindStagger;
+use crate::protocols::registry::ProtocolRegistry;
+use crate::protocols::rejection::RejectionResponseConfig;
+use crate::security::kill_switch::{KillSwitchConfig, KillSwitchManager};
+use crate::security::probe_detection::ProbeDetectorConfig;
+use crate::security::transform_registry::TransformSpec;
+use crate::utils::bandwidth::BandwidthConfig;
+use crate::security::traffic_obfuscation::{ObfuscationLevel, ObfuscationProfileTier};
+use crate::security::traffic_shaping::{ResponseTimingConfig, VideoShapingConfig};
+use crate::utils::logging::{RedactionMode, Redactor};
+use crate::security::app_presets::{AppPreset, APP_PRESET_PARAM};
+use crate::utils::region::RegionProfile;
+
+/// `ServerConfig` holds everything `main` needs to start the listeners.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses the OTLS/WS listeners bind to, one listener each.
+    pub tcp_listen_addrs: Vec<SocketAddr>,
+    /// Address the AOQUIC listener binds to.
+    pub udp_listen_addr: SocketAddr,
+    /// Unix domain socket OTLS/WS also listens on, for chaining behind a proxy on the same
+    /// host. Unix only. Without one, no local socket is bound.
+    pub unix_listen_path: Option<PathBuf>,
+    /// Protocols to serve, by their `ProtocolType` names (`"otls-ws"`, `"aoquic"`).
+    pub enabled_protocols: Vec<String>,
+    /// Region code (e.g. `"ir"`) whose `RegionProfile` fills in the settings below it that the
+    /// file doesn't set.
+    pub region: Option<String>,
+    /// Domain the protocols imitate.
+    pub mimic_domain: String,
+    /// Obfuscation tier that frames new OTLS/WS tunnels when no `transforms` pipeline is set.
+    /// Without one (and without `transforms`), tunnels are relayed unframed.
+    pub obfuscation_tier: Option<ObfuscationProfileTier>,
+    /// Name of an `AppPreset` (e.g. `"teams"`) that frames new OTLS/WS tunnels to look like
+    /// that app. Takes precedence over `obfuscation_tier`; a `transforms` pipeline wins over both.
+    pub app_preset: Option<String>,
+    /// Weakest obfuscation (`"none"`, `"scrambled"`, `"padded"` or `"covered"`) an OTLS/WS
+    /// tunnel may use; tunnels below it are refused. Without one, any is accepted.
+    pub min_obfuscation_level: Option<ObfuscationLevel>,
+    /// Where OTLS/WS tunnels are relayed after the handshake. Without one, connections are
+    /// closed once the handshake completes.
+    pub upstream_addr: Option<SocketAddr>,
+    /// Most TCP connections handled at once, across all listeners. Further connections are refused.
+    pub max_connections: usize,
+    /// New connections each peer IP may open per second; excess connections are dropped.
+    pub peer_connections_per_second: f64,
+    /// Most handshakes in progress at once, across all protocols. Established tunnels don't count.
+    pub max_concurrent_handshakes: usize,
+    /// How long a handshake waits for a free slot before it is rejected; `0` rejects straight away.
+    pub handshake_queue_secs: u64,
+    /// Address the Prometheus metrics endpoint listens on. Without one, no endpoint is served.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Lets an IPv6 listen address such as `[::]:8443` accept IPv4 clients as well, by clearing
+    /// `IPV6_V6ONLY`. Off by default, so `0.0.0.0` and `[::]` can be bound side by side.
+    pub dual_stack: bool,
+    /// File the connection-attempt audit log is appended to. Without one, no audit log is kept.
+    pub audit_log_path: Option<PathBuf>,
+    /// Random pause before each listener (TCP and UDP) is bound at startup, and a warm-up after
+    /// binding during which the connection cap ramps up. Without the table, every listener is
+    /// bound straight away and the full cap applies from the start.
+    pub listener_stagger: Option<ListenerStaggerSettings>,
+    /// Serves the cover page instead of the OTLS/WS handshake to peers that look like active
+    /// probers. Without the table, every peer gets the handshake.
+    pub probe_detection: Option<ProbeDetectionSettings>,
+    /// How peer addresses and user ids appear in the general log. The audit log keeps full values.
+    pub redaction: RedactionSettings,
+    /// Caps the total relayed traffic. Without the table, relaying is unlimited.
+    pub bandwidth: Option<BandwidthSettings>,
+    /// Paces relayed OTLS/WS traffic toward clients like an adaptive-bitrate video session.
+    /// Without the table, it is sent as fast as the bandwidth cap allows.
+    pub video_shaping: Option<VideoShapingSettings>,
+    /// Delays the first byte OTLS/WS sends on each connection by a server-like think time.
+    /// Without the table, it is sent as soon as it is ready.
+    pub response_timing: Option<ResponseTimingSettings>,
+    /// What refused connections are sent before they are closed.
+    pub rejection: RejectionResponseConfig,
+    pub kill_switch: KillSwitchSettings,
+    /// Obfuscation pipeline for relayed OTLS/WS tunnels: transforms from the
+    /// `TransformRegistry`, applied in order. Names are resolved at startup; without any
+    /// entries, tunnels are relayed unframed.
+    pub transforms: Vec<TransformSpec>,
+    /// Refuses to start unless `transforms` includes a `checksum`, so frames that were altered
+    /// on the way are rejected instead of relayed.
+    pub require_checksum: bool,
+    /// Times each `transforms` stage and exports the latencies as histograms on the metrics
+    /// endpoint. Off by default, since it reads the clock around every stage of every packet.
+    pub obfuscation_stage_timing: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            tcp_listen_addrs: vec!["0.0.0.0:8443".parse().expect("valid default address")],
+            udp_listen_addr: "0.0.0.0:8444".parse().expect("valid default address"),
+            unix_listen_path: None,
+            enabled_protocols: vec!["otls-ws".to_string(), "aoquic".to_string()],
+            region: None,
+            mimic_domain: "www.example.com".to_string(),
+            obfuscation_tier: None,
+            app_preset: None,
+            min_obfuscation_level: None,
+            upstream_addr: None,
+            max_connections: 1024,
+            peer_connections_per_second: 10.0,
+            max_concurrent_handshakes: HandshakeLimiterConfig::default().max_concurrent,
+            handshake_queue_secs: 5,
+            metrics_addr: None,
+            dual_stack: false,
+            audit_log_path: None,
+            listener_stagger: None,
+            probe_detection: None,
+            redaction: RedactionSettings::default(),
+            bandwidth: None,
+            video_shaping: None,
+            response_timing: None,
+            rejection: RejectionResponseConfig::default(),
+            kill_switch: KillSwitchSettings::default(),
+            transforms: Vec::new(),
+            require_checksum: false,
+            obfuscation_stage_timing: false,
+        }
+    }
+}
+
+/// The `[bandwidth]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthSettings {
+    /// Total bytes per second relayed across all connections, in both directions.
+    pub total_bytes_per_sec: u64,
+    /// Largest fraction of the total one connection may use.
+    pub max_connection_share: f64,
+}
+
+impl Default for BandwidthSettings {
+    fn default() -> Self {
+        let defaults = BandwidthConfig::default();
+        BandwidthSettings {
+            total_bytes_per_sec: defaults.total_bytes_per_sec,
+            max_connection_share: defaults.max_connection_share,
+        }
+    }
+}
+
+impl BandwidthSettings {
+    pub fn to_config(&self) -> BandwidthConfig {
+        BandwidthConfig {
+            total_bytes_per_sec: self.total_bytes_per_sec,
+            max_connection_share: self.max_connection_share,
+        }
+    }
+}
+
+/// The `[video_shaping]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoShapingSettings {
+    pub segment_interval_secs: u64,
+    pub burst_bytes: usize,
+    pub burst_rate_bytes_per_sec: u64,
+    pub steady_rate_bytes_per_sec: u64,
+}
+
+impl Default for VideoShapingSettings {
+    fn default() -> Self {
+        let defaults = VideoShapingConfig::default();
+        VideoShapingSettings {
+            segment_interval_secs: defaults.segment_interval.as_secs(),
+            burst_bytes: defaults.burst_bytes,
+            burst_rate_bytes_per_sec: defaults.burst_rate_bytes_per_sec,
+            steady_rate_bytes_per_sec: defaults.steady_rate_bytes_per_sec,
+        }
+    }
+}
+
+impl VideoShapingSettings {
+    pub fn to_config(&self) -> VideoShapingConfig {
+        VideoShapingConfig {
+            segment_interval: Duration::from_secs(self.segment_interval_secs),
+            burst_bytes: self.burst_bytes,
+            burst_rate_bytes_per_sec: self.burst_rate_bytes_per_sec,
+            steady_rate_bytes_per_sec: self.steady_rate_bytes_per_sec,
+        }
+    }
+}
+
+/// The `[response_timing]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseTimingSettings {
+    pub min_ms: u64,
+    pub median_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Default for ResponseTimingSettings {
+    fn default() -> Self {
+        let defaults = ResponseTimingConfig::default();
+        ResponseTimingSettings {
+            min_ms: defaults.min.as_millis() as u64,
+            median_ms: defaults.median.as_millis() as u64,
+            max_ms: defaults.max.as_millis() as u64,
+        }
+    }
+}
+
+impl ResponseTimingSettings {
+    pub fn to_config(&self) -> ResponseTimingConfig {
+        ResponseTimingConfig {
+            min: Duration::from_millis(self.min_ms),
+            median: Duration::from_millis(self.median_ms),
+            max: Duration::from_millis(self.max_ms),
+        }
+    }
+}
+
+/// The `[listener_stagger]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerStaggerSettings {
+    pub min_ms: u64,
+    pub max_ms: u64,
+    /// How long after binding the connection cap takes to ramp up to `max_connections`.
+    pub warmup_ms: u64,
+}
+
+impl Default for ListenerStaggerSettings {
+    fn default() -> Self {
+        ListenerStaggerSettings {
+            min_ms: 50,
+            max_ms: 500,
+            warmup_ms: 5000,
+        }
+    }
+}
+
+impl ListenerStaggerSettings {
+    pub fn to_stagger(&self) -> BindStagger {
+        BindStagger {
+            min: Duration::from_millis(self.min_ms),
+            max: Duration::from_millis(self.max_ms),
+            warmup: Duration::from_millis(self.warmup_ms),
+        }
+    }
+}
+
+/// The `[probe_detection]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeDetectionSettings {
+    pub window_secs: u64,
+    /// Early resets and empty closes within the window that flag a peer.
+    pub suspicious_threshold: usize,
+    pub max_connects_per_window: usize,
+    /// How long a flagged peer only gets the cover page.
+    pub decoy_cooldown_secs: u64,
+}
+
+impl Default for ProbeDetectionSettings {
+    fn default() -> Self {
+        let defaults = ProbeDetectorConfig::default();
+        ProbeDetectionSettings {
+            window_secs: defaults.window.as_secs(),
+            suspicious_threshold: defaults.suspicious_threshold,
+            max_connects_per_window: defaults.max_connects_per_window,
+            decoy_cooldown_secs: defaults.decoy_cooldown.as_secs(),
+        }
+    }
+}
+
+impl ProbeDetectionSettings {
+    pub fn to_config(&self) -> ProbeDetectorConfig {
+        ProbeDetectorConfig {
+            window: Duration::from_secs(self.window_secs),
+            suspicious_threshold: self.suspicious_threshold,
+            max_connects_per_window: self.max_connects_per_window,
+            decoy_cooldown: Duration::from_secs(self.decoy_cooldown_secs),
+            ..ProbeDetectorConfig::default()
+        }
+    }
+}
+
+/// The `[redaction]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionSettings {
+    pub mode: RedactionMode,
+    /// Salt for `hash` mode; should be a per-deployment secret.
+    pub salt: String,
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        RedactionSettings {
+            mode: RedactionMode::Off,
+            salt: String::new(),
+        }
+    }
+}
+
+impl RedactionSettings {
+    pub fn to_redactor(&self) -> Redactor {
+        Redactor::new(self.mode, self.salt.as_bytes())
+    }
+}
+
+/// The `[kill_switch]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KillSwitchSettings {
+    pub enabled: bool,
+    /// Endpoint the health check connects to. Without one, no health check runs.
+    pub probe_target: Option<SocketAddr>,
+    pub probe_interval_secs: u64,
+    pub probe_timeout_secs: u64,
+    pub failure_threshold: u32,
+    /// nftables table that blocks all outbound traffic except to `probe_target` while the
+    /// Kill Switch is triggered, so the health check can still see the path recover. Linux
+    /// builds with the `firewall` feature only. Without one, no firewall rules are installed.
+    pub firewall_table: Option<String>,
+}
+
+impl Default for KillSwitchSettings {
+    fn default() -> Self {
+        let defaults = KillSwitchConfig::default();
+        KillSwitchSettings {
+            enabled: false,
+            probe_target: None,
+            probe_interval_secs: defaults.probe_interval.as_secs(),
+            probe_timeout_secs: defaults.probe_timeout.as_secs(),
+            failure_threshold: defaults.failure_threshold,
+            firewall_table: None,
+        }
+    }
+}
+
+impl KillSwitchSettings {
+    pub fn to_config(&self) -> KillSwitchConfig {
+        KillSwitchConfig {
+            probe_interval: Duration::from_secs(self.probe_interval_secs),
+            probe_timeout: Duration::from_secs(self.probe_timeout_secs),
+            failure_threshold: self.failure_threshold,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads and parses the TOML file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ProtocolError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| ProtocolError::Other(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parses a configuration from TOML text and checks that it is usable.
+    /// Keys missing from the text are taken from the `region` profile, if one is named.
+    pub fn from_toml(text: &str) -> Result<Self, ProtocolError> {
+        let invalid = |e: toml::de::Error| ProtocolError::Other(format!("invalid config: {}", e));
+        let mut table: toml::Table = toml::from_str(text).map_err(invalid)?;
+        if table.contains_key("congestion_controller") {
+            return Err(ProtocolError::Other(
+                "congestion_controller is not supported: AOQUIC has no congestion control to configure".to_string(),
+            ));
+        }
+        if let Some(code) = table.get("region").and_then(toml::Value::as_str).map(str::to_string) {
+            let profile = RegionProfile::lookup(&code).ok_or_else(|| {
+                ProtocolError::Other(format!("unknown region \"{}\" (bundled: {})", code, RegionProfile::codes().join(", ")))
+            })?;
+            for (key, value) in profile.defaults() {
+                table.entry(key).or_insert_with(|| toml::Value::String(value.to_string()));
+            }
+        }
+        let config: ServerConfig = toml::Value::Table(table).try_into().map_err(invalid)?;
+        config.protocol_types()?;
+        if let Some(name) = config.app_preset.as_deref().filter(|name| AppPreset::lookup(name).is_none()) {
+            return Err(ProtocolError::Other(format!(
+                "unknown app_preset \"{}\" (bundled: {})",
+                name,
+                AppPreset::names().join(", ")
+            )));
+        }
+        if config.tcp_listen_addrs.is_empty() {
+            return Err(ProtocolError::Other("tcp_listen_addrs must list at least one address".to_string()));
+        }
+        if cfg!(not(unix)) && config.unix_listen_path.is_some() {
+            return Err(ProtocolError::Other("unix_listen_path is only supported on Unix".to_string()));
+        }
+        if config.max_connections == 0 {
+            return Err(ProtocolError::Other("max_connections must be at least 1".to_string()));
+        }
+        let rate = config.peer_connections_per_second;
+        if rate.is_nan() || rate <= 0.0 {
+            return Err(ProtocolError::Other("peer_connections_per_second must be positive".to_string()));
+        }
+        if let Some(bandwidth) = &config.bandwidth {
+            if bandwidth.total_bytes_per_sec == 0 {
+                return Err(ProtocolError::Other("bandwidth.total_bytes_per_sec must be at least 1".to_string()));
+            }
+            let share = bandwidth.max_connection_share;
+            if !(share > 0.0 && share <= 1.0) {
+                return Err(ProtocolError::Other("bandwidth.max_connection_share must be in (0, 1]".to_string()));
+            }
+        }
+        if let Some(shaping) = &config.video_shaping {
+            if shaping.segment_interval_secs == 0 {
+                return Err(ProtocolError::Other("video_shaping.segment_interval_secs must be at least 1".to_string()));
+            }
+            if shaping.burst_rate_bytes_per_sec == 0 || shaping.steady_rate_bytes_per_sec == 0 {
+                return Err(ProtocolError::Other("video_shaping rates must be at least 1 byte per second".to_string()));
+            }
+        }
+        if let Some(timing) = &config.response_timing {
+            if !(timing.min_ms <= timing.median_ms && timing.median_ms <= timing.max_ms) {
+                return Err(ProtocolError::Other(
+                    "response_timing must satisfy min_ms <= median_ms <= max_ms".to_string(),
+                ));
+            }
+        }
+        if let Some(stagger) = &config.listener_stagger {
+            if stagger.min_ms > stagger.max_ms {
+                return Err(ProtocolError::Other("listener_stagger.min_ms must not exceed max_ms".to_string()));
+            }
+        }
+        if let Some(probe) = &config.probe_detection {
+            if probe.window_secs == 0 {
+                return Err(ProtocolError::Other("probe_detection.window_secs must be at least 1".to_string()));
+            }
+            if probe.suspicious_threshold == 0 {
+                return Err(ProtocolError::Other("probe_detection.suspicious_threshold must be at least 1".to_string()));
+            }
+        }
+        // Without a salt, hashed IPv4 addresses can be reversed by hashing the whole address space.
+        if config.redaction.mode == RedactionMode::Hash && config.redaction.salt.is_empty() {
+            return Err(ProtocolError::Other("redaction.salt must be set when redaction.mode is \"hash\"".to_string()));
+        }
+        if config.max_concurrent_handshakes == 0 {
+            return Err(ProtocolError::Other("max_concurrent_handshakes must be at least 1".to_string()));
+        }
+        if config.kill_switch.failure_threshold == 0 {
+            return Err(ProtocolError::Other("kill_switch.failure_threshold must be at least 1".to_string()));
+        }
+        // A zero interval would make the health check's ticker panic, and a zero timeout fails every probe.
+        if config.kill_switch.probe_interval_secs == 0 {
+            return Err(ProtocolError::Other("kill_switch.probe_interval_secs must be at least 1".to_string()));
+        }
+        if config.kill_switch.probe_timeout_secs == 0 {
+            return Err(ProtocolError::Other("kill_switch.probe_timeout_secs must be at least 1".to_string()));
+        }
+        if config.require_checksum && config.transforms.is_empty() {
+            return Err(ProtocolError::Other("require_checksum needs a transforms pipeline".to_string()));
+        }
+        if config.kill_switch.firewall_table.is_some() {
+            if cfg!(not(all(target_os = "linux", feature = "firewall"))) {
+                return Err(ProtocolError::Other(
+                    "kill_switch.firewall_table needs a Linux build with the `firewall` feature".to_string(),
+                ));
+            }
+            if config.kill_switch.probe_target.is_none() {
+                return Err(ProtocolError::Other("kill_switch.firewall_table requires kill_switch.probe_target".to_string()));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Applies command-line overrides on top of the file (or default) configuration.
+    pub fn apply_cli(&mut self, cli: &CliArgs) {
+        if !cli.tcp_addrs.is_empty() {
+            self.tcp_listen_addrs = cli.tcp_addrs.clone();
+        }
+        if let Some(addr) = cli.udp_addr {
+            self.udp_listen_addr = addr;
+        }
+    }
+
+    /// The handshake limiter settings.
+    pub fn handshake_limiter_config(&self) -> HandshakeLimiterConfig {
+        let overflow = match self.handshake_queue_secs {
+            0 => OverflowPolicy::Reject,
+            secs => OverflowPolicy::Queue(Duration::from_secs(secs)),
+        };
+        HandshakeLimiterConfig {
+            max_concurrent: self.max_concurrent_handshakes,
+            overflow,
+        }
+    }
+
+    /// Copies the settings that can change at runtime into a protocol's configuration.
+    pub fn apply_to(&self, protocol_config: &mut ProtocolConfig) {
+        protocol_config.tunnel.mimic_domain = self.mimic_domain.clone();
+        protocol_config.upstream_addr = self.upstream_addr;
+        protocol_config.tunnel.enable_kill_switch = self.kill_switch.enabled;
+        let params = &mut protocol_config.tunnel.protocol_params;
+        match self.obfuscation_tier {
+            Some(tier) => params.insert("obfuscation_tier".to_string(), tier.as_str().to_string()),
+            None => params.remove("obfuscation_tier"),
+        };
+        match &self.app_preset {
+            Some(name) => params.insert(APP_PRESET_PARAM.to_string(), name.clone()),
+            None => params.remove(APP_PRESET_PARAM),
+        };
+    }
+
+    /// Takes the settings that can change at runtime from `other`, leaving the rest as they are.
+    fn copy_reloadable(&mut self, other: &ServerConfig) {
+        self.region = other.region.clone();
+        self.mimic_domain = other.mimic_domain.clone();
+        self.obfuscation_tier = other.obfuscation_tier;
+        self.app_preset = other.app_preset.clone();
+        self.upstream_addr = other.upstream_addr;
+        self.kill_switch.enabled = other.kill_switch.enabled;
+    }
+
+    /// The settings that differ from `other` but only take effect on restart.
+    fn restart_only_changes(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.tcp_listen_addrs != other.tcp_listen_addrs {
+            changed.push("tcp_listen_addrs");
+        }
+        if self.udp_listen_addr != other.udp_listen_addr {
+            changed.push("udp_listen_addr");
+        }
+        if self.unix_listen_path != other.unix_listen_path {
+            changed.push("unix_listen_path");
+        }
+        if self.enabled_protocols != other.enabled_protocols {
+            changed.push("enabled_protocols");
+        }
+        if self.max_connections != other.max_connections {
+            changed.push("max_connections");
+        }
+        if self.peer_connections_per_second != other.peer_connections_per_second {
+            changed.push("peer_connections_per_second");
+        }
+        if (self.max_concurrent_handshakes, self.handshake_queue_secs) != (other.max_concurrent_handshakes, other.handshake_queue_secs) {
+            changed.push("handshake limits");
+        }
+        if self.metrics_addr != other.metrics_addr {
+            changed.push("metrics_addr");
+        }
+        if self.dual_stack != other.dual_stack {
+            changed.push("dual_stack");
+        }
+        if self.listener_stagger != other.listener_stagger {
+            changed.push("listener_stagger");
+        }
+        if self.probe_detection != other.probe_detection {
+            changed.push("probe_detection");
+        }
+        if self.bandwidth != other.bandwidth {
+            changed.push("bandwidth");
+        }
+        if self.video_shaping != other.video_shaping {
+            changed.push("video_shaping");
+        }
+        if self.response_timing != other.response_timing {
+            changed.push("response_timing");
+        }
+        if self.min_obfuscation_level != other.min_obfuscation_level {
+            changed.push("min_obfuscation_level");
+        }
+        if self.redaction != other.redaction {
+            changed.push("redaction");
+        }
+        if self.audit_log_path != other.audit_log_path {
+            changed.push("audit_log_path");
+        }
+        if self.rejection != other.rejection {
+            changed.push("rejection");
+        }
+        let probe = |settings: &KillSwitchSettings| {
+            (settings.probe_target, settings.probe_interval_secs, settings.probe_timeout_secs, settings.failure_threshold)
+        };
+        if probe(&self.kill_switch) != probe(&other.kill_switch) {
+            changed.push("kill_switch probe settings");
+        }
+        if self.kill_switch.firewall_table != other.kill_switch.firewall_table {
+            changed.push("kill_switch.firewall_table");
+        }
+        if (&self.transforms, self.require_checksum) != (&other.transforms, other.require_checksum) {
+            changed.push("transforms");
+        }
+        if self.obfuscation_stage_timing != other.obfuscation_stage_timing {
+            changed.push("obfuscation_stage_timing");
+        }
+        changed
+    }
+
+    /// The enabled protocols, in the order they are listed.
+    pub fn protocol_types(&self) -> Result<Vec<ProtocolType>, ProtocolError> {
+        self.enabled_protocols
+            .iter()
+            .map(|name| {
+                ProtocolType::from_str(name).ok_or_else(|| ProtocolError::Other(format!("unknown protocol \"{}\"", name)))
+            })
+            .collect()
+    }
+}
+
+/// Re-reads the configuration file at `path` and applies it to the running server: every
+/// registered protocol gets the new mimic domain, and the Kill Switch is turned on or off.
+/// Nothing is rebound: restart-only settings that changed are logged and left as they are in
+/// `current`. If the file is missing or invalid, nothing changes and the error is returned, so
+/// the caller keeps `current`. Returns the configuration now in effect.
+pub fn reload(
+    path: impl AsRef<Path>,
+    cli: &CliArgs,
+    current: &ServerConfig,
+    registry: &ProtocolRegistry,
+    kill_switch: &KillSwitchManager,
+) -> Result<ServerConfig, ProtocolError> {
+    let mut reloaded = ServerConfig::from_file(path)?;
+    reloaded.apply_cli(cli);
+
+    for setting in reloaded.restart_only_changes(current) {
+        warn!("Ignoring the new {} until the server is restarted", setting);
+    }
+    let mut config = current.clone();
+    config.copy_reloadable(&reloaded);
+    for protocol_type in current.protocol_types()? {
+        let Some(protocol) = registry.get(&protocol_type) else {
+            continue;
+        };
+        let mut protocol_config = protocol.get_config().clone();
+        config.apply_to(&mut protocol_config);
+        registry.update_config(&protocol_type, protocol_config);
+    }
+    kill_switch.set_enabled(config.kill_switch.enabled);
+    info!("Configuration reloaded (mimic domain {})", config.mimic_domain);
+    Ok(config)
+}
+
+/// Environment variable naming the configuration file; `--config <path>` takes precedence.
+pub const CONFIG_ENV: &str = "HEZARDASTAN_CONFIG";
+
+/// `CliArgs` holds the command-line flags:
+/// `--config <path>`, `--tcp-addr <addr>` (repeatable) and `--udp-addr <addr>`.
+/// Each flag also accepts the `--flag=value` form.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CliArgs {
+    pub config: Option<String>,
+    pub tcp_addrs: Vec<SocketAddr>,
+    pub udp_addr: Option<SocketAddr>,
+}
+
+impl CliArgs {
+    /// Parses `args` (without the program name). Addresses are validated here,
+    /// so a typo is reported before anything is bound.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ProtocolError> {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ProtocolError::Other(format!("{} needs a value", flag)))
+            };
+            match flag.as_str() {
+                "--config" => cli.config = Some(value()?),
+                "--tcp-addr" => cli.tcp_addrs.push(parse_listen_addr("--tcp-addr", &value()?)?),
+                "--udp-addr" => cli.udp_addr = Some(parse_listen_addr("--udp-addr", &value()?)?),
+                _ => return Err(ProtocolError::Other(format!("unknown argument \"{}\"", flag))),
+            }
+        }
+        Ok(cli)
+    }
+
+    /// The configuration file to load: `--config`, then `HEZARDASTAN_CONFIG`, if either is set.
+    pub fn config_path(&self) -> Option<String> {
+        self.config.clone().or_else(|| std::env::var(CONFIG_ENV).ok())
+    }
+}
+
+fn parse_listen_addr(flag: &str, value: &str) -> Result<SocketAddr, ProtocolError> {
+    value.parse().map_err(|_| {
+        ProtocolError::Other(format!(
+            "{} \"{}\" is not a valid socket address (expected e.g. 0.0.0.0:8443 or [::]:8443)",
+            flag, value
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_sample_config() {
+        let config = ServerConfig::from_toml(
+            r#"
+            tcp_listen_addrs = ["127.0.0.1:9443", "[::1]:9443"]
+            udp_listen_addr = "[::]:9444"
+            unix_listen_path = "/run/hezardastan/otls-ws.sock"
+            enabled_protocols = ["aoquic"]
+            mimic_domain = "cdn.example.net"
+            obfuscation_tier = "minimal"
+            app_preset = "aparat"
+            min_obfuscation_level = "scrambled"
+            upstream_addr = "127.0.0.1:1080"
+            max_connections = 64
+            peer_connections_per_second = 2.5
+            max_concurrent_handshakes = 8
+            handshake_queue_secs = 0
+            metrics_addr = "127.0.0.1:9090"
+            dual_stack = true
+            audit_log_path = "/var/log/hezardastan/audit.log"
+            require_checksum = true
+            obfuscation_stage_timing = true
+
+            [listener_stagger]
+            max_ms = 200
+
+            [probe_detection]
+            suspicious_threshold = 5
+            decoy_cooldown_secs = 300
+
+            [bandwidth]
+            total_bytes_per_sec = 1000000
+            max_connection_share = 0.25
+
+            [video_shaping]
+            burst_bytes = 500000
+
+            [response_timing]
+            median_ms = 60
+
+            [redaction]
+            mode = "truncate"
+
+            [rejection]
+            retry_after_secs = 120
+            server_header = "AmazonS3"
+
+            [kill_switch]
+            enabled = true
+            probe_target = "192.0.2.1:443"
+            probe_interval_secs = 30
+            failure_threshold = 5
+
+            [[transforms]]
+            name = "noise"
+            params = { max_noise_bytes = "64" }
+
+            [[transforms]]
+            name = "checksum"
+            params = { key = "secret" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            ServerConfig {
+                tcp_listen_addrs: vec!["127.0.0.1:9443".parse().unwrap(), "[::1]:9443".parse().unwrap()],
+                udp_listen_addr: "[::]:9444".parse().unwrap(),
+                unix_listen_path: Some(PathBuf::from("/run/hezardastan/otls-ws.sock")),
+                enabled_protocols: vec!["aoquic".to_string()],
+                region: None,
+                mimic_domain: "cdn.example.net".to_string(),
+                obfuscation_tier: Some(ObfuscationProfileTier::Minimal),
+                app_preset: Some("aparat".to_string()),
+                min_obfuscation_level: Some(ObfuscationLevel::Scrambled),
+                upstream_addr: Some("127.0.0.1:1080".parse().unwrap()),
+                max_connections: 64,
+                peer_connections_per_second: 2.5,
+                max_concurrent_handshakes: 8,
+                handshake_queue_secs: 0,
+                metrics_addr: Some("127.0.0.1:9090".parse().unwrap()),
+                dual_stack: true,
+                audit_log_path: Some(PathBuf::from("/var/log/hezardastan/audit.log")),
+                listener_stagger: Some(ListenerStaggerSettings {
+                    min_ms: 50,
+                    max_ms: 200,
+                    warmup_ms: 5000,
+                }),
+                probe_detection: Some(ProbeDetectionSettings {
+                    window_secs: 60,
+                    suspicious_threshold: 5,
+                    max_connects_per_window: 20,
+                    decoy_cooldown_secs: 300,
+                }),
+                redaction: RedactionSettings {
+                    mode: RedactionMode::Truncate,
+                    salt: String::new(),
+                },
+                bandwidth: Some(BandwidthSettings {
+                    total_bytes_per_sec: 1_000_000,
+                    max_connection_share: 0.25,
+                }),
+                video_shaping: Some(VideoShapingSettings {
+                    burst_bytes: 500_000,
+                    ..VideoShapingSettings::default()
+                }),
+                response_timing: Some(ResponseTimingSettings {
+                    min_ms: 5,
+                    median_ms: 60,
+                    max_ms: 250,
+                }),
+                rejection: RejectionResponseConfig {
+                    enabled: true,
+                    retry_after_secs: 120,
+                    server_header: "AmazonS3".to_string(),
+                },
+                kill_switch: KillSwitchSettings {
+                    enabled: true,
+                    probe_target: Some("192.0.2.1:443".parse().unwrap()),
+                    probe_interval_secs: 30,
+                    probe_timeout_secs: 5,
+                    failure_threshold: 5,
+                    firewall_table: None,
+                },
+                transforms: vec![
+                    TransformSpec {
+                        name: "noise".to_string(),
+                        params: [("max_noise_bytes".to_string(), "64".to_string())].into(),
+                    },
+                    TransformSpec {
+                        name: "checksum".to_string(),
+                        params: [("key".to_string(), "secret".to_string())].into(),
+                    },
+                ],
+                require_checksum: true,
+                obfuscation_stage_timing: true,
+            }
+        );
+        assert_eq!(config.protocol_types().unwrap(), vec![ProtocolType::AoQuic]);
+        assert_eq!(config.kill_switch.to_config().probe_interval, Duration::from_secs(30));
+        assert_eq!(config.handshake_limiter_config().overflow, OverflowPolicy::Reject);
+        assert_eq!(config.redaction.to_redactor().addr("203.0.113.7:51234".parse().unwrap()), "203.0.113.x");
+        assert_eq!(
+            ServerConfig::default().handshake_limiter_config().overflow,
+            OverflowPolicy::Queue(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_empty_config_matches_defaults() {
+        assert_eq!(ServerConfig::from_toml("").unwrap(), ServerConfig::default());
+        assert_eq!(
+            ServerConfig::default().protocol_types().unwrap(),
+            vec![ProtocolType::OtlsWs, ProtocolType::AoQuic]
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_config() {
+        assert!(ServerConfig::from_toml(r#"enabled_protocols = ["wireguard"]"#).is_err());
+        assert!(ServerConfig::from_toml(r#"tcp_listen_addrs = ["not an address"]"#).is_err());
+        assert!(ServerConfig::from_toml("tcp_listen_addrs = []").is_err());
+        assert!(ServerConfig::from_toml("max_connections = 0").is_err());
+        assert!(ServerConfig::from_toml("peer_connections_per_second = 0.0").is_err());
+        assert!(ServerConfig::from_toml("max_concurrent_handshakes = 0").is_err());
+        assert!(ServerConfig::from_toml("[bandwidth]\ntotal_bytes_per_sec = 0").is_err());
+        assert!(ServerConfig::from_toml("[bandwidth]\nmax_connection_share = 1.5").is_err());
+        assert!(ServerConfig::from_toml("[listener_stagger]\nmin_ms = 600").is_err());
+        assert!(ServerConfig::from_toml("require_checksum = true").is_err());
+        assert!(ServerConfig::from_toml("[probe_detection]\nsuspicious_threshold = 0").is_err());
+        assert!(ServerConfig::from_toml("[video_shaping]\nsteady_rate_bytes_per_sec = 0").is_err());
+        assert!(ServerConfig::from_toml("[response_timing]\nmedian_ms = 500").is_err());
+        assert!(ServerConfig::from_toml(r#"min_obfuscation_level = "strong""#).is_err());
+        assert!(ServerConfig::from_toml(r#"listen_port = 443"#).is_err());
+        assert!(ServerConfig::from_toml("[kill_switch]\nfailure_threshold = 0").is_err());
+    }
+
+    #[test]
+    fn test_region_profile_supplies_defaults() {
+        let config = ServerConfig::from_toml("region = \"ir\"").unwrap();
+        assert_eq!(config.region.as_deref(), Some("ir"));
+        assert_eq!(config.mimic_domain, "www.aparat.com");
+        assert_eq!(config.obfuscation_tier, Some(ObfuscationProfileTier::Maximal));
+
+        let mut protocol_config = ProtocolConfig::default_for(ProtocolType::AoQuic);
+        config.apply_to(&mut protocol_config);
+        assert_eq!(protocol_config.tunnel.mimic_domain, "www.aparat.com");
+        let params = &protocol_config.tunnel.protocol_params;
+        assert_eq!(ObfuscationProfileTier::from_params(params), Some(ObfuscationProfileTier::Maximal));
+
+        // Without a tier, tunnels stop being framed by one, even after a reload.
+        ServerConfig::default().apply_to(&mut protocol_config);
+        assert_eq!(ObfuscationProfileTier::from_params(&protocol_config.tunnel.protocol_params), None);
+    }
+
+    #[test]
+    fn test_explicit_settings_override_the_region_profile() {
+        let config = ServerConfig::from_toml(
+            r#"
+            region = "CN"
+            mimic_domain = "cdn.example.net"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mimic_domain, "cdn.example.net");
+        // Only the key left out comes from the profile.
+        assert_eq!(config.obfuscation_tier, Some(ObfuscationProfileTier::Maximal));
+
+        let err = ServerConfig::from_toml("region = \"atlantis\"").unwrap_err();
+        assert!(err.to_string().contains("unknown region \"atlantis\" (bundled: ir, cn, ru, tr)"), "{}", err);
+        assert!(ServerConfig::from_toml("obfuscation_tier = \"paranoid\"").is_err());
+        let err = ServerConfig::from_toml("app_preset = \"skype\"").unwrap_err();
+        assert!(err.to_string().contains("unknown app_preset \"skype\" (bundled: teams, aparat)"), "{}", err);
+
+        // Nothing applies a congestion controller, so asking for one is an error.
+        let err = ServerConfig::from_toml("congestion_controller = \"bbr\"").unwrap_err();
+        assert!(err.to_string().contains("congestion_controller is not supported"), "{}", err);
+    }
+
+    #[test]
+    fn test_hash_redaction_needs_a_salt() {
+        let err = ServerConfig::from_toml("[redaction]\nmode = \"hash\"").unwrap_err();
+        assert!(err.to_string().contains("redaction.salt"), "{}", err);
+
+        let config = ServerConfig::from_toml("[redaction]\nmode = \"hash\"\nsalt = \"s3cret\"").unwrap();
+        let user = config.redaction.to_redactor().user_id("6f1c2a9e-alice");
+        assert!(user.starts_with("user:") && !user.contains("alice"));
+        assert!(ServerConfig::from_toml("[redaction]\nmode = \"reversible\"").is_err());
+    }
+
+    #[test]
+    fn test_rejects_zero_probe_interval() {
+        let err = ServerConfig::from_toml("[kill_switch]\nprobe_interval_secs = 0").unwrap_err();
+        assert!(err.to_string().contains("kill_switch.probe_interval_secs"), "{}", err);
+    }
+
+    #[test]
+    fn test_rejects_zero_probe_timeout() {
+        let err = ServerConfig::from_toml("[kill_switch]\nprobe_timeout_secs = 0").unwrap_err();
+        assert!(err.to_string().contains("kill_switch.probe_timeout_secs"), "{}", err);
+    }
+
+    #[test]
+    fn test_firewall_table_needs_a_probe_target_and_the_firewall_build() {
+        let err = ServerConfig::from_toml("[kill_switch]\nfirewall_table = \"hd\"").unwrap_err();
+        assert!(err.to_string().contains("kill_switch.firewall_table"), "{}", err);
+        let with_target = "[kill_switch]\nprobe_target = \"192.0.2.1:443\"\nfirewall_table = \"hd\"";
+        let parsed = ServerConfig::from_toml(with_target);
+        if cfg!(all(target_os = "linux", feature = "firewall")) {
+            assert_eq!(parsed.unwrap().kill_switch.firewall_table.as_deref(), Some("hd"));
+        } else {
+            assert!(parsed.unwrap_err().to_string().contains("`firewall` feature"));
+        }
+    }
+
+    #[test]
+    fn test_missing_file_is_io_error() {
+        let err = ServerConfig::from_file("/nonexistent/hezardastan.toml").unwrap_err();
+        assert!(matches!(err, ProtocolError::Io(_)));
+    }
+
+    #[test]
+    fn test_cli_flags_override_config() {
+        let args = ["--tcp-addr", "127.0.0.1:9443", "--tcp-addr=[::]:9443", "--udp-addr", "0.0.0.0:9444", "--config", "server.toml"];
+        let cli = CliArgs::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(cli.config_path().as_deref(), Some("server.toml"));
+
+        let mut config = ServerConfig::default();
+        config.apply_cli(&cli);
+        assert_eq!(config.tcp_listen_addrs, vec!["127.0.0.1:9443".parse().unwrap(), "[::]:9443".parse().unwrap()]);
+        assert_eq!(config.udp_listen_addr, "0.0.0.0:9444".parse().unwrap());
+
+        // Without flags the configuration is left alone.
+        let mut untouched = ServerConfig::default();
+        untouched.apply_cli(&CliArgs::default());
+        assert_eq!(untouched, ServerConfig::default());
+    }
+
+    #[test]
+    fn test_reload_updates_running_protocols() {
+        use crate::protocols::otls_ws::OtlsWsProtocol;
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("hezardastan-reload-{}.toml", std::process::id()));
+        let current = ServerConfig::default();
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        let kill_switch = KillSwitchManager::new(false);
+        let mimic_domain = |registry: &ProtocolRegistry| {
+            registry.get(&ProtocolType::OtlsWs).unwrap().get_config().tunnel.mimic_domain.clone()
+        };
+
+        fs::write(&path, "mimic_domain = \"cdn.example.net\"\nmax_connections = 7\n[kill_switch]\nenabled = true\n").unwrap();
+        let reloaded = reload(&path, &CliArgs::default(), &current, &registry, &kill_switch).unwrap();
+        assert_eq!(reloaded.mimic_domain, "cdn.example.net");
+        assert!(reloaded.kill_switch.enabled);
+        // Restart-only settings keep the values the server is running with.
+        assert_eq!(reloaded.max_connections, current.max_connections);
+        assert_eq!(mimic_domain(&registry), "cdn.example.net");
+        assert!(registry.get(&ProtocolType::OtlsWs).unwrap().get_config().tunnel.enable_kill_switch);
+        assert!(kill_switch.is_enabled());
+
+        // An invalid file is rejected and the running configuration is kept.
+        fs::write(&path, "mimic_domain = \"other.example.org\"\nmax_connections = 0\n").unwrap();
+        assert!(reload(&path, &CliArgs::default(), &reloaded, &registry, &kill_switch).is_err());
+        assert_eq!(mimic_domain(&registry), "cdn.example.net");
+        assert!(kill_switch.is_enabled());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cli_rejects_bad_addresses_and_flags() {
+        let parse = |args: &[&str]| CliArgs::parse(args.iter().map(|arg| arg.to_string()));
+        let err = parse(&["--tcp-addr", "0.0.0.0"]).unwrap_err().to_string();
+        assert!(err.contains("--tcp-addr \"0.0.0.0\" is not a valid socket address"), "{}", err);
+        assert!(parse(&["--udp-addr", "example.com:443"]).is_err());
+        assert!(parse(&["--udp-addr"]).is_err());
+        assert!(parse(&["--listen", "0.0.0.0:1"]).is_err());
+    }
+}
@@ -1 +1,403 @@
+//! This module provides logging helpers for HezarDastan Core.
+//! It includes the connection-attempt audit log, which is kept separate from the
+//! general `tracing` output so it can be retained and analysed on its own, and the
+//! `Redactor` used to keep user ids and peer addresses out of the general logs.
 
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::protocols::common::ProtocolType;
+
+/// `AuditOutcome` describes how a single connection attempt ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The connection was accepted and handed to a protocol handler.
+    Accepted,
+    /// The connection was dropped because the peer exceeded its rate limit.
+    RejectedByRateLimit,
+    /// The connection was refused by an access rule (unknown user, blocked IP, ...).
+    RejectedByAccess,
+    /// The connection was refused because the server was at its connection cap.
+    RejectedByCapacity,
+    /// The obfuscation handshake did not complete.
+    HandshakeFailed,
+    /// The tunnel ran to completion and was closed normally.
+    Completed,
+}
+
+impl AuditOutcome {
+    /// Returns the stable string form written to the audit log.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Accepted => "accepted",
+            AuditOutcome::RejectedByRateLimit => "rejected-rate-limit",
+            AuditOutcome::RejectedByAccess => "rejected-access",
+            AuditOutcome::RejectedByCapacity => "rejected-capacity",
+            AuditOutcome::HandshakeFailed => "handshake-failed",
+            AuditOutcome::Completed => "completed",
+        }
+    }
+}
+
+/// A single audit entry for one connection attempt.
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub source_ip: IpAddr,
+    pub timestamp: SystemTime,
+    pub outcome: AuditOutcome,
+    pub protocol: ProtocolType,
+    /// Name of the obfuscation profile in use for this connection.
+    pub profile: String,
+}
+
+impl AuditRecord {
+    /// Creates a new record stamped with the current time.
+    pub fn new(source_ip: IpAddr, protocol: ProtocolType, profile: &str, outcome: AuditOutcome) -> Self {
+        AuditRecord {
+            source_ip,
+            timestamp: SystemTime::now(),
+            outcome,
+            protocol,
+            profile: profile.to_string(),
+        }
+    }
+}
+
+// One line per record, `key=value` pairs, so the log is easy to grep and parse.
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        write!(
+            f,
+            "ts={} ip={} outcome={} protocol={} profile={}",
+            millis,
+            self.source_ip,
+            self.outcome.as_str(),
+            self.protocol.to_string_repr(),
+            self.profile
+        )
+    }
+}
+
+/// A destination for audit records.
+/// Implementations must be shareable across the accept loops and handler tasks.
+pub trait AuditSink: Send + Sync {
+    /// Persists a single audit record.
+    fn record(&self, record: &AuditRecord) -> io::Result<()>;
+}
+
+/// `FileAuditSink` appends audit records to a file, one line per record.
+pub struct FileAuditSink {
+    file: Mutex<File>,
+}
+
+impl FileAuditSink {
+    /// Opens (or creates) the audit log at `path` in append mode.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileAuditSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| io::Error::other("audit log lock poisoned"))?;
+        writeln!(file, "{}", record)?;
+        file.flush()
+    }
+}
+
+/// `MemoryAuditSink` keeps records in memory, e.g. for tests or an embedding application.
+#[derive(Default)]
+pub struct MemoryAuditSink {
+    records: Mutex<Vec<AuditRecord>>,
+}
+
+impl MemoryAuditSink {
+    /// Every record written so far, oldest first.
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// The outcomes of every record written so far, oldest first.
+    pub fn outcomes(&self) -> Vec<AuditOutcome> {
+        self.records.lock().unwrap().iter().map(|record| record.outcome).collect()
+    }
+}
+
+impl AuditSink for MemoryAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        self.records.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
+
+/// `AuditLog` is what the accept loops and protocol handlers write audit records through.
+/// Without a sink it does nothing. A failing sink is reported in the general log and never
+/// fails the connection. Clones share the same sink.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    sink: Option<Arc<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    pub fn new(sink: Arc<dyn AuditSink>) -> Self {
+        AuditLog { sink: Some(sink) }
+    }
+
+    /// An audit log that discards every record.
+    pub fn disabled() -> Self {
+        AuditLog { sink: None }
+    }
+
+    /// Records `outcome` for a connection attempt from `source_ip`.
+    pub fn record(&self, source_ip: IpAddr, protocol: &ProtocolType, profile: &str, outcome: AuditOutcome) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let record = AuditRecord::new(source_ip, protocol.clone(), profile, outcome);
+        if let Err(e) = sink.record(&record) {
+            warn!("Failed to write audit record ({}): {}", outcome.as_str(), e);
+        }
+    }
+}
+
+/// How sensitive values are rendered in general logs.
+/// In the configuration it is written in lower case (`"off"`, `"hash"`, `"truncate"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    /// Log values as-is.
+    Off,
+    /// Replace values with a stable salted hash, so one user/peer can still be followed.
+    Hash,
+    /// Keep only a coarse prefix (first characters of a user id, /24 or /48 of an IP).
+    Truncate,
+}
+
+/// `Redactor` rewrites user ids and peer addresses before they reach the general logs.
+/// Audit records are never passed through it and keep the full values.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    mode: RedactionMode,
+    salt: Vec<u8>,
+}
+
+impl Redactor {
+    /// Creates a redactor. `salt` should be a per-deployment secret so hashes can't be reversed
+    /// by brute-forcing the IPv4 space.
+    pub fn new(mode: RedactionMode, salt: &[u8]) -> Self {
+        Redactor {
+            mode,
+            salt: salt.to_vec(),
+        }
+    }
+
+    fn hash(&self, kind: &str, value: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(kind.as_bytes());
+        hasher.update(value.as_bytes());
+        let digest = hasher.finalize();
+        let hex: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}:{}", kind, hex)
+    }
+
+    /// Renders a user id for logging.
+    pub fn user_id(&self, user_id: &str) -> String {
+        match self.mode {
+            RedactionMode::Off => user_id.to_string(),
+            RedactionMode::Hash => self.hash("user", user_id),
+            RedactionMode::Truncate => {
+                let prefix: String = user_id.chars().take(4).collect();
+                format!("{}…", prefix)
+            }
+        }
+    }
+
+    /// Renders an IP address for logging.
+    pub fn ip(&self, ip: IpAddr) -> String {
+        match self.mode {
+            RedactionMode::Off => ip.to_string(),
+            RedactionMode::Hash => self.hash("ip", &ip.to_string()),
+            RedactionMode::Truncate => match ip {
+                IpAddr::V4(v4) => {
+                    let [a, b, c, _] = v4.octets();
+                    format!("{}.{}.{}.x", a, b, c)
+                }
+                IpAddr::V6(v6) => {
+                    let s = v6.segments();
+                    format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+                }
+            },
+        }
+    }
+
+    /// Renders a socket address for logging. The port is dropped unless redaction is off.
+    pub fn addr(&self, addr: SocketAddr) -> String {
+        match self.mode {
+            RedactionMode::Off => addr.to_string(),
+            _ => self.ip(addr.ip()),
+        }
+    }
+}
+
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+/// Installs the process-wide redactor used by `redact_addr` and `redact_user`.
+/// Only the first call takes effect; returns false if one was already installed.
+pub fn set_redactor(redactor: Redactor) -> bool {
+    REDACTOR.set(redactor).is_ok()
+}
+
+/// Renders a peer IP for general logs using the installed redactor (if any).
+pub fn redact_ip(ip: IpAddr) -> String {
+    match REDACTOR.get() {
+        Some(redactor) => redactor.ip(ip),
+        None => ip.to_string(),
+    }
+}
+
+/// Renders a peer address for general logs using the installed redactor (if any).
+pub fn redact_addr(addr: SocketAddr) -> String {
+    match REDACTOR.get() {
+        Some(redactor) => redactor.addr(addr),
+        None => addr.to_string(),
+    }
+}
+
+/// Renders a user id for general logs using the installed redactor (if any).
+pub fn redact_user(user_id: &str) -> String {
+    match REDACTOR.get() {
+        Some(redactor) => redactor.user_id(user_id),
+        None => user_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_log_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("hezardastan-audit-{}-{}.log", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_each_outcome_produces_one_record() {
+        let path = temp_log_path("outcomes");
+        let _ = fs::remove_file(&path);
+        let sink = FileAuditSink::open(&path).unwrap();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let outcomes = [
+            AuditOutcome::Accepted,
+            AuditOutcome::RejectedByRateLimit,
+            AuditOutcome::RejectedByAccess,
+            AuditOutcome::RejectedByCapacity,
+            AuditOutcome::HandshakeFailed,
+            AuditOutcome::Completed,
+        ];
+        for outcome in outcomes {
+            sink.record(&AuditRecord::new(ip, ProtocolType::OtlsWs, "default", outcome)).unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), outcomes.len());
+        for (line, outcome) in lines.iter().zip(outcomes) {
+            assert!(line.starts_with("ts="));
+            assert!(line.contains("ip=203.0.113.7"));
+            assert!(line.contains(&format!("outcome={}", outcome.as_str())));
+            assert!(line.contains("protocol=otls-ws"));
+            assert!(line.contains("profile=default"));
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_file_sink_appends_across_reopen() {
+        let path = temp_log_path("append");
+        let _ = fs::remove_file(&path);
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let record = AuditRecord::new(ip, ProtocolType::AoQuic, "quic-default", AuditOutcome::HandshakeFailed);
+        FileAuditSink::open(&path).unwrap().record(&record).unwrap();
+        FileAuditSink::open(&path).unwrap().record(&record).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.contains("ip=2001:db8::1 outcome=handshake-failed protocol=aoquic"));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_audit_log_writes_through_its_sink() {
+        let sink = Arc::new(MemoryAuditSink::default());
+        let log = AuditLog::new(sink.clone());
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        log.record(ip, &ProtocolType::OtlsWs, "default", AuditOutcome::Accepted);
+        log.record(ip, &ProtocolType::OtlsWs, "default", AuditOutcome::Completed);
+        AuditLog::disabled().record(ip, &ProtocolType::OtlsWs, "default", AuditOutcome::Accepted);
+
+        assert_eq!(sink.outcomes(), vec![AuditOutcome::Accepted, AuditOutcome::Completed]);
+        assert_eq!(sink.records()[0].source_ip, ip);
+    }
+
+    #[test]
+    fn test_redactor_hash_is_stable_and_hides_values() {
+        let redactor = Redactor::new(RedactionMode::Hash, b"deployment-salt");
+        let user = redactor.user_id("6f1c2a9e-alice");
+        assert_eq!(user, redactor.user_id("6f1c2a9e-alice"));
+        assert!(user.starts_with("user:"));
+        assert!(!user.contains("alice"));
+        assert_ne!(user, redactor.user_id("6f1c2a9e-bob"));
+
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(!redactor.ip(ip).contains("203.0.113"));
+        assert_ne!(redactor.ip(ip), Redactor::new(RedactionMode::Hash, b"other-salt").ip(ip));
+    }
+
+    #[test]
+    fn test_redactor_truncate_and_off() {
+        let truncate = Redactor::new(RedactionMode::Truncate, b"");
+        assert_eq!(truncate.user_id("6f1c2a9e-alice"), "6f1c…");
+        assert_eq!(truncate.addr("203.0.113.7:51234".parse().unwrap()), "203.0.113.x");
+        assert_eq!(truncate.ip("2001:db8:abcd:12::1".parse().unwrap()), "2001:db8:abcd::/48");
+
+        let off = Redactor::new(RedactionMode::Off, b"");
+        assert_eq!(off.addr("203.0.113.7:51234".parse().unwrap()), "203.0.113.7:51234");
+    }
+
+    #[test]
+    fn test_audit_records_keep_full_values() {
+        let redactor = Redactor::new(RedactionMode::Hash, b"salt");
+        let ip: IpAddr = "198.51.100.23".parse().unwrap();
+        let record = AuditRecord::new(ip, ProtocolType::OtlsWs, "default", AuditOutcome::Accepted);
+
+        assert!(!redactor.ip(ip).contains("198.51.100.23"));
+        assert!(record.to_string().contains("ip=198.51.100.23"));
+    }
+}
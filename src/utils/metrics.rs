@@ -0,0 +1,587 @@
+//! This module serves the server's counters in the Prometheus text exposition format, so
+//! operators can scrape them without any client-side instrumentation.
+//! `MetricsExporter` gathers the per-protocol `ProtocolMetrics` and connection states, the overhead of any
+//! registered obfuscators (with per-stage latency histograms when stage timing is on), the per-subnet handshake outcomes and the Kill Switch stats
+//! into a `MetricsSnapshot` on every scrape; `serve` answers `GET /metrics` on a dedicated listener (see `metrics_addr` in
+//! the configuration). `GET /metrics.json` serves the same snapshot as JSON, for dashboards
+//! that don't speak Prometheus.
+//!
+//! The same listener doubles as a small admin interface for debugging leaks:
+//! `GET /connections` lists every live connection with its own byte, error and retry counters
+//! (see `ConnectionCounters`), `POST /connections/<protocol>/<id>/reset` zeroes one
+//! connection's counters and `POST /connections/reset` all of them. The protocol-wide totals
+//! above are never reset. Bind `metrics_addr` to an address only operators can reach.
+//!
+//! The HTTP side is deliberately tiny: one request per connection, no keep-alive.
+
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write as _, io, sync::Arc, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::protocols::common::ProtocolMetrics;
+use crate::protocols::connection_state::ConnectionCounts;
+use crate::protocols::handshake_stats::HandshakeStatsTracker;
+use crate::protocols::registry::ProtocolRegistry;
+use crate::security::kill_switch::{KillSwitchManager, KillSwitchState};
+use crate::security::traffic_obfuscation::{Obfuscator, ObfuscatorMetrics, StageLatency, STAGE_LATENCY_BUCKETS};
+use crate::utils::logging::redact_addr;
+
+/// Largest request head read from a scraper.
+const MAX_REQUEST_BYTES: usize = 8192;
+/// How long a scraper may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Content type of the text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Content type of `/metrics.json`.
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// A metric reported once per protocol: name, type, help text and how to read it.
+type ProtocolMetric = (&'static str, &'static str, &'static str, fn(&ProtocolMetrics) -> u64);
+
+const PER_PROTOCOL: [ProtocolMetric; 6] = [
+    ("hezardastan_active_connections", "gauge", "Connections currently being handled.", |m| m.active_connections),
+    ("hezardastan_bytes_in_total", "counter", "Bytes received from clients.", |m| m.bytes_in),
+    ("hezardastan_bytes_out_total", "counter", "Bytes sent to clients.", |m| m.bytes_out),
+    ("hezardastan_handshake_failures_total", "counter", "Handshakes that failed or timed out.", |m| m.handshake_failures),
+    ("hezardastan_throttle_events_total", "counter", "Relayed connections whose throughput collapsed.", |m| m.throttle_events),
+    ("hezardastan_stalled_connections_total", "counter", "Relayed connections closed for making no progress.", |m| m.stalled_connections),
+];
+
+/// Every counter at one moment. Both endpoints render from this.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// One entry per registered protocol, sorted by label.
+    pub protocols: Vec<ProtocolSnapshot>,
+    /// Live connections per protocol and lifecycle state, for protocols that track them.
+    pub connection_states: Vec<ConnectionStateSnapshot>,
+    /// Overhead summed over every registered obfuscator.
+    pub obfuscator: ObfuscatorMetrics,
+    /// Time spent in each obfuscation stage, summed over the obfuscators that time their stages.
+    pub stage_latencies: Vec<StageLatency>,
+    /// Handshake outcomes per source subnet, worst success rate first.
+    pub subnets: Vec<SubnetSnapshot>,
+    pub kill_switch: KillSwitchSnapshot,
+}
+
+/// One protocol's counters, labelled as in the Prometheus output (e.g. `"otls-ws"`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolSnapshot {
+    pub protocol: String,
+    #[serde(flatten)]
+    pub metrics: ProtocolMetrics,
+}
+
+/// How many of one protocol's live connections are in `state` (e.g. `"tunneling"`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionStateSnapshot {
+    pub protocol: String,
+    pub state: String,
+    pub connections: u64,
+}
+
+/// One live connection and its own counters, as `GET /connections` lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionSnapshot {
+    pub protocol: String,
+    /// Id of the connection within its protocol, for `POST /connections/<protocol>/<id>/reset`.
+    pub id: u64,
+    /// Peer address, redacted as in the general log.
+    pub peer: String,
+    pub state: String,
+    /// How long the connection has been in its current state, in milliseconds.
+    pub in_state_ms: u64,
+    #[serde(flatten)]
+    pub counts: ConnectionCounts,
+}
+
+/// Handshake outcomes for one subnet (e.g. `"203.0.113.0/24"`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubnetSnapshot {
+    pub subnet: String,
+    pub successes: u64,
+    pub failures: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KillSwitchSnapshot {
+    pub enabled: bool,
+    pub trigger_count: u64,
+    /// The current state: `"active"`, `"reconnecting"`, `"triggered"` or `"disabled"`.
+    pub state: String,
+}
+
+/// Kill Switch states with the labels they are reported under.
+const KILL_SWITCH_STATES: [(&str, KillSwitchState); 4] = [
+    ("active", KillSwitchState::Active),
+    ("reconnecting", KillSwitchState::Reconnecting),
+    ("triggered", KillSwitchState::Triggered),
+    ("disabled", KillSwitchState::Disabled),
+];
+
+/// `MetricsExporter` renders the current counters on demand. Clones share the same sources.
+#[derive(Clone)]
+pub struct MetricsExporter {
+    registry: Arc<ProtocolRegistry>,
+    kill_switch: KillSwitchManager,
+    obfuscators: Vec<Arc<Obfuscator>>,
+    handshake_stats: Option<Arc<HandshakeStatsTracker>>,
+}
+
+impl MetricsExporter {
+    pub fn new(registry: Arc<ProtocolRegistry>, kill_switch: KillSwitchManager) -> Self {
+        MetricsExporter {
+            registry,
+            kill_switch,
+            obfuscators: Vec::new(),
+            handshake_stats: None,
+        }
+    }
+
+    /// Includes `obfuscator`'s overhead counters in the totals.
+    pub fn with_obfuscator(mut self, obfuscator: Arc<Obfuscator>) -> Self {
+        self.obfuscators.push(obfuscator);
+        self
+    }
+
+    /// Reports `stats` per subnet, so a region-level block shows up as one subnet's failures.
+    pub fn with_handshake_stats(mut self, stats: Arc<HandshakeStatsTracker>) -> Self {
+        self.handshake_stats = Some(stats);
+        self
+    }
+
+    /// Reads every counter once.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut protocols = Vec::new();
+        let mut connection_states = Vec::new();
+        for protocol in self.registry.protocols() {
+            let label = protocol.get_config().tunnel.protocol_type.to_string_repr().to_string();
+            for (state, connections) in protocol.connection_states() {
+                connection_states.push(ConnectionStateSnapshot {
+                    protocol: label.clone(),
+                    state: state.as_str().to_string(),
+                    connections: connections as u64,
+                });
+            }
+            protocols.push(ProtocolSnapshot {
+                protocol: label,
+                metrics: protocol.metrics(),
+            });
+        }
+        protocols.sort_by(|a, b| a.protocol.cmp(&b.protocol));
+        connection_states.sort_by(|a, b| a.protocol.cmp(&b.protocol));
+
+        let mut obfuscator = ObfuscatorMetrics::default();
+        for metrics in self.obfuscators.iter().map(|obfuscator| obfuscator.metrics()) {
+            obfuscator.input_bytes += metrics.input_bytes;
+            obfuscator.output_bytes += metrics.output_bytes;
+            obfuscator.packets_mimicked += metrics.packets_mimicked;
+            obfuscator.noise_bytes += metrics.noise_bytes;
+        }
+        let mut stage_latencies: Vec<StageLatency> = Vec::new();
+        for latency in self.obfuscators.iter().flat_map(|obfuscator| obfuscator.stage_latencies()) {
+            match stage_latencies.iter_mut().find(|total| total.stage == latency.stage) {
+                Some(total) => {
+                    total.buckets.iter_mut().zip(&latency.buckets).for_each(|(total, count)| *total += count);
+                    total.count += latency.count;
+                    total.sum_nanos += latency.sum_nanos;
+                }
+                None => stage_latencies.push(latency),
+            }
+        }
+
+        let subnets = self.handshake_stats.as_ref().map_or_else(Vec::new, |stats| {
+            stats
+                .snapshot()
+                .into_iter()
+                .map(|(subnet, stats)| SubnetSnapshot {
+                    subnet: subnet.to_string(),
+                    successes: stats.successes,
+                    failures: stats.failures,
+                })
+                .collect()
+        });
+
+        let state = self.kill_switch.state();
+        let state = KILL_SWITCH_STATES.iter().find(|(_, candidate)| *candidate == state).map_or("disabled", |(label, _)| label);
+        MetricsSnapshot {
+            protocols,
+            connection_states,
+            obfuscator,
+            stage_latencies,
+            subnets,
+            kill_switch: KillSwitchSnapshot {
+                enabled: self.kill_switch.is_enabled(),
+                trigger_count: self.kill_switch.stats().trigger_count,
+                state: state.to_string(),
+            },
+        }
+    }
+
+    /// Lists every live connection of every protocol, by protocol label and then id.
+    pub fn connections(&self) -> Vec<ConnectionSnapshot> {
+        let mut connections = Vec::new();
+        for protocol in self.registry.protocols() {
+            let label = protocol.get_config().tunnel.protocol_type.to_string_repr();
+            connections.extend(protocol.connections().into_iter().map(|summary| ConnectionSnapshot {
+                protocol: label.to_string(),
+                id: summary.id,
+                peer: redact_addr(summary.peer_addr),
+                state: summary.state.as_str().to_string(),
+                in_state_ms: summary.in_state_for.as_millis() as u64,
+                counts: summary.counts,
+            }));
+        }
+        connections.sort_by(|a, b| (&a.protocol, a.id).cmp(&(&b.protocol, b.id)));
+        connections
+    }
+
+    /// Zeroes the counters of connection `id` of the protocol labelled `protocol`, or of every
+    /// live connection if `target` is `None`. Returns how many connections were reset.
+    pub fn reset_connection_counters(&self, target: Option<(&str, u64)>) -> usize {
+        let protocols = self.registry.protocols();
+        match target {
+            None => protocols.iter().map(|protocol| protocol.reset_connection_counters(None)).sum(),
+            Some((label, id)) => protocols
+                .iter()
+                .filter(|protocol| protocol.get_config().tunnel.protocol_type.to_string_repr() == label)
+                .map(|protocol| protocol.reset_connection_counters(Some(id)))
+                .sum(),
+        }
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+        for (name, kind, help, value) in PER_PROTOCOL {
+            write_header(&mut out, name, kind, help);
+            for protocol in &snapshot.protocols {
+                let _ = writeln!(out, "{}{{protocol=\"{}\"}} {}", name, protocol.protocol, value(&protocol.metrics));
+            }
+        }
+        write_header(&mut out, "hezardastan_connections", "gauge", "Live connections by lifecycle state.");
+        for entry in &snapshot.connection_states {
+            let _ = writeln!(
+                out,
+                "hezardastan_connections{{protocol=\"{}\",state=\"{}\"}} {}",
+                entry.protocol, entry.state, entry.connections
+            );
+        }
+
+        let obfuscator = &snapshot.obfuscator;
+        write_metric(&mut out, "hezardastan_obfuscator_input_bytes_total", "counter", "Payload bytes handed to the obfuscators.", obfuscator.input_bytes);
+        write_metric(&mut out, "hezardastan_obfuscator_output_bytes_total", "counter", "Framed bytes produced by the obfuscators.", obfuscator.output_bytes);
+        write_metric(&mut out, "hezardastan_obfuscator_packets_mimicked_total", "counter", "Packets that carried a fake HTTP or TLS header.", obfuscator.packets_mimicked);
+        write_metric(&mut out, "hezardastan_obfuscator_noise_bytes_total", "counter", "Random bytes added as noise and padding.", obfuscator.noise_bytes);
+        write_header(&mut out, "hezardastan_obfuscator_stage_seconds", "histogram", "Time spent in each obfuscation stage per packet.");
+        for latency in &snapshot.stage_latencies {
+            for (bound, count) in STAGE_LATENCY_BUCKETS.iter().zip(&latency.buckets) {
+                let _ = writeln!(
+                    out,
+                    "hezardastan_obfuscator_stage_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                    latency.stage,
+                    bound.as_secs_f64(),
+                    count
+                );
+            }
+            let _ = writeln!(out, "hezardastan_obfuscator_stage_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}", latency.stage, latency.count);
+            let _ = writeln!(out, "hezardastan_obfuscator_stage_seconds_sum{{stage=\"{}\"}} {}", latency.stage, latency.sum_nanos as f64 / 1e9);
+            let _ = writeln!(out, "hezardastan_obfuscator_stage_seconds_count{{stage=\"{}\"}} {}", latency.stage, latency.count);
+        }
+
+        write_header(&mut out, "hezardastan_subnet_handshakes_total", "counter", "Handshakes by source subnet and outcome.");
+        for subnet in &snapshot.subnets {
+            for (outcome, value) in [("success", subnet.successes), ("failure", subnet.failures)] {
+                let _ = writeln!(out, "hezardastan_subnet_handshakes_total{{subnet=\"{}\",outcome=\"{}\"}} {}", subnet.subnet, outcome, value);
+            }
+        }
+
+        let kill_switch = &snapshot.kill_switch;
+        write_metric(&mut out, "hezardastan_kill_switch_enabled", "gauge", "Whether the Kill Switch is enabled.", kill_switch.enabled as u64);
+        write_metric(&mut out, "hezardastan_kill_switch_triggers_total", "counter", "Transitions of the Kill Switch into Triggered.", kill_switch.trigger_count);
+        write_header(&mut out, "hezardastan_kill_switch_state", "gauge", "Current Kill Switch state (1 for the current one).");
+        for (label, _) in KILL_SWITCH_STATES {
+            let _ = writeln!(out, "hezardastan_kill_switch_state{{state=\"{}\"}} {}", label, (kill_switch.state == label) as u64);
+        }
+        out
+    }
+
+    /// Renders the snapshot as JSON.
+    pub fn render_json(&self) -> String {
+        serde_json::to_string(&self.snapshot()).expect("metrics snapshot serializes")
+    }
+
+    /// Answers scrapes on `listener` until `shutdown` is cancelled.
+    pub async fn serve(self, listener: TcpListener, shutdown: CancellationToken) {
+        loop {
+            let (stream, peer_addr) = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Metrics: failed to accept scrape: {}", e);
+                        continue;
+                    }
+                },
+            };
+            let exporter = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = exporter.respond(stream).await {
+                    debug!("Metrics: scrape from {} failed: {}", peer_addr, e);
+                }
+            });
+        }
+    }
+
+    async fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        let request_line = tokio::time::timeout(REQUEST_TIMEOUT, read_request_line(&mut stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no request within the timeout"))??;
+        let mut parts = request_line.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => http_response("200 OK", CONTENT_TYPE, &self.render()),
+            (Some("GET"), Some("/metrics.json")) => http_response("200 OK", JSON_CONTENT_TYPE, &self.render_json()),
+            (Some("GET"), Some("/connections")) => {
+                let body = serde_json::to_string(&self.connections()).expect("connection snapshots serialize");
+                http_response("200 OK", JSON_CONTENT_TYPE, &body)
+            }
+            (Some("POST"), Some(path)) => match reset_target(path) {
+                Some(target) => {
+                    let reset = self.reset_connection_counters(target);
+                    let body = serde_json::json!({ "reset": reset }).to_string();
+                    match (target, reset) {
+                        (Some(_), 0) => http_response("404 Not Found", JSON_CONTENT_TYPE, &body),
+                        _ => http_response("200 OK", JSON_CONTENT_TYPE, &body),
+                    }
+                }
+                None => http_response("404 Not Found", "text/plain; charset=utf-8", "not found\n"),
+            },
+            _ => http_response("404 Not Found", "text/plain; charset=utf-8", "not found\n"),
+        };
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+/// Parses a counter reset path: `Some(None)` for `/connections/reset`, `Some(Some(..))` for
+/// `/connections/<protocol>/<id>/reset`, `None` for anything else.
+fn reset_target(path: &str) -> Option<Option<(&str, u64)>> {
+    let rest = path.strip_prefix("/connections/")?.strip_suffix("reset")?;
+    if rest.is_empty() {
+        return Some(None);
+    }
+    let (protocol, id) = rest.strip_suffix('/')?.split_once('/')?;
+    Some(Some((protocol, id.parse().ok()?)))
+}
+
+/// Reads the request head and returns its first line.
+async fn read_request_line(stream: &mut TcpStream) -> io::Result<String> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let head = String::from_utf8_lossy(&buf);
+    Ok(head.lines().next().unwrap_or_default().to_string())
+}
+
+fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    write_header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::aoquic::AoQuicProtocol;
+    use crate::protocols::common::ProtocolType;
+    use crate::protocols::otls_ws::OtlsWsProtocol;
+    use crate::protocols::registry::Incoming;
+    use crate::security::traffic_obfuscation::ObfuscatorConfig;
+    use tokio::net::UdpSocket;
+
+    async fn scrape(addr: std::net::SocketAddr, path: &str) -> String {
+        request(addr, "GET", path).await
+    }
+
+    async fn request(addr: std::net::SocketAddr, method: &str, path: &str) -> String {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path);
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_serves_prometheus_text() {
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(OtlsWsProtocol::new()));
+        registry.register(ProtocolType::AoQuic, Arc::new(AoQuicProtocol::new()));
+        let registry = Arc::new(registry);
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packet = Incoming::Udp { socket: &socket, buf: b"ping", peer_addr: "127.0.0.1:12345".parse().unwrap() };
+        registry.dispatch(&ProtocolType::AoQuic, packet).await.unwrap();
+
+        let obfuscator = Arc::new(
+            Obfuscator::with_config(ObfuscatorConfig {
+                max_delay_ms: 0,
+                ..ObfuscatorConfig::default()
+            })
+            .with_stage_timing(),
+        );
+        obfuscator.obfuscate_data(b"hello").await;
+        let stats = Arc::new(HandshakeStatsTracker::new(16));
+        stats.record("203.0.113.7".parse().unwrap(), true);
+        stats.record("198.51.100.9".parse().unwrap(), false);
+        let exporter = MetricsExporter::new(registry, KillSwitchManager::new(true))
+            .with_obfuscator(obfuscator.clone())
+            .with_handshake_stats(stats);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(exporter.clone().serve(listener, shutdown.clone()));
+
+        let response = scrape(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("# TYPE hezardastan_bytes_in_total counter"));
+        assert!(response.contains("hezardastan_bytes_in_total{protocol=\"aoquic\"} 4"));
+        assert!(response.contains("hezardastan_bytes_in_total{protocol=\"otls-ws\"} 0"));
+        assert!(response.contains("hezardastan_throttle_events_total{protocol=\"aoquic\"} 0"));
+        assert!(response.contains("hezardastan_connections{protocol=\"otls-ws\",state=\"tunneling\"} 0"));
+        assert!(!response.contains("hezardastan_connections{protocol=\"aoquic\""));
+        assert!(response.contains("hezardastan_obfuscator_input_bytes_total 5"));
+        let output = format!("hezardastan_obfuscator_output_bytes_total {}", obfuscator.metrics().output_bytes);
+        assert!(response.contains(&output));
+        assert!(response.contains("# TYPE hezardastan_obfuscator_stage_seconds histogram"));
+        assert!(response.contains("hezardastan_obfuscator_stage_seconds_bucket{stage=\"noise\",le=\"0.000001\"}"));
+        assert!(response.contains("hezardastan_obfuscator_stage_seconds_count{stage=\"noise\"} 1"));
+        assert!(response.contains("hezardastan_subnet_handshakes_total{subnet=\"203.0.113.0/24\",outcome=\"success\"} 1"));
+        assert!(response.contains("hezardastan_subnet_handshakes_total{subnet=\"198.51.100.0/24\",outcome=\"failure\"} 1"));
+        assert!(response.contains("hezardastan_kill_switch_enabled 1"));
+        assert!(response.contains("hezardastan_kill_switch_state{state=\"disabled\"} 1"));
+
+        let response = scrape(addr, "/metrics.json").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("Content-Type: application/json"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let snapshot: MetricsSnapshot = serde_json::from_str(body).unwrap();
+        assert_eq!(snapshot, exporter.snapshot());
+        let labels: Vec<&str> = snapshot.protocols.iter().map(|p| p.protocol.as_str()).collect();
+        assert_eq!(labels, ["aoquic", "otls-ws"]);
+        assert_eq!(snapshot.protocols[0].metrics.bytes_in, 4);
+        assert_eq!(snapshot.obfuscator, obfuscator.metrics());
+        assert_eq!(
+            snapshot.subnets[0],
+            SubnetSnapshot { subnet: "198.51.100.0/24".to_string(), successes: 0, failures: 1 }
+        );
+        assert_eq!(snapshot.kill_switch, KillSwitchSnapshot { enabled: true, trigger_count: 0, state: "disabled".to_string() });
+
+        assert!(scrape(addr, "/").await.starts_with("HTTP/1.1 404 Not Found"));
+
+        shutdown.cancel();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_lists_and_resets_connection_counters() {
+        use crate::protocols::common::ProtocolConfig;
+        use crate::protocols::ObfuscatedProtocol;
+
+        // A tunnel to an upstream that echoes whatever it receives.
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = ProtocolConfig::default_for(ProtocolType::OtlsWs);
+        config.upstream_addr = Some(upstream.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let (mut read, mut write) = stream.split();
+            tokio::io::copy(&mut read, &mut write).await.unwrap();
+        });
+        let mut protocol = OtlsWsProtocol::new();
+        protocol.update_config(config);
+        let mut registry = ProtocolRegistry::new();
+        registry.register(ProtocolType::OtlsWs, Arc::new(protocol.clone()));
+        let exporter = MetricsExporter::new(Arc::new(registry), KillSwitchManager::new(true));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(exporter.clone().serve(listener, shutdown.clone()));
+
+        let tunnel = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(tunnel.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = tunnel.accept().await.unwrap();
+        let handler = tokio::spawn(async move { protocol.handle_tcp_stream(stream).await });
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0u8; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+
+        let response = scrape(addr, "/connections").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let connections: Vec<ConnectionSnapshot> = serde_json::from_str(body).unwrap();
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[0];
+        assert_eq!((connection.protocol.as_str(), connection.state.as_str()), ("otls-ws", "tunneling"));
+        assert_eq!(connection.peer, redact_addr(client.local_addr().unwrap()));
+        assert_eq!((connection.counts.bytes_in, connection.counts.bytes_out), (5, 5));
+        assert_eq!((connection.counts.errors, connection.counts.retries), (0, 0));
+
+        // Counters keep growing with the connection, and reset without touching the protocol totals.
+        client.write_all(b" tunnel").await.unwrap();
+        let mut echoed = [0u8; 7];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(exporter.connections()[0].counts.bytes_in, 12);
+        let path = format!("/connections/otls-ws/{}/reset", connection.id);
+        let response = request(addr, "POST", &path).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("{\"reset\":1}"), "{}", response);
+        assert_eq!(exporter.connections()[0].counts, ConnectionCounts::default());
+        assert_eq!(exporter.snapshot().protocols[0].metrics.bytes_in, 12);
+
+        let unknown = format!("/connections/otls-ws/{}/reset", connection.id + 1);
+        assert!(request(addr, "POST", &unknown).await.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(request(addr, "POST", "/connections/aoquic/1/reset").await.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(request(addr, "GET", "/connections/reset").await.starts_with("HTTP/1.1 404 Not Found"));
+
+        client.write_all(b"!").await.unwrap();
+        client.read_exact(&mut echoed[..1]).await.unwrap();
+        assert_eq!(exporter.connections()[0].counts.bytes_in, 1);
+        let response = request(addr, "POST", "/connections/reset").await;
+        assert!(response.ends_with("{\"reset\":1}"), "{}", response);
+        assert_eq!(exporter.connections()[0].counts, ConnectionCounts::default());
+
+        client.shutdown().await.unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        handler.await.unwrap().unwrap();
+        assert!(exporter.connections().is_empty());
+        shutdown.cancel();
+        server.await.unwrap();
+    }
+}
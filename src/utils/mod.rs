@@ -1,3 +1,6 @@
 // این ماژول شامل توابع کمکی برای هسته هزار دستان است.
 pub mod logging;
 pub mod config;
+pub mod bandwidth;
+pub mod metrics;
+pub mod region;
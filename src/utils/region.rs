@@ -0,0 +1,71 @@
+//! This module bundles per-region defaults for deployments in different censorship regimes.
+//! Setting `region` in the configuration fills in the mimic domain and the obfuscation tier
+//! from that region's `RegionProfile`; either one set explicitly in the file wins over the
+//! profile.
+
+use crate::security::traffic_obfuscation::ObfuscationProfileTier;
+
+/// `RegionProfile` is the defaults bundled for one region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionProfile {
+    /// Lower-case ISO 3166-1 alpha-2 code the profile is selected by.
+    pub code: &'static str,
+    /// A domain that stays reachable in the region, so mimicking it doesn't stand out.
+    pub mimic_domain: &'static str,
+    pub obfuscation_tier: ObfuscationProfileTier,
+}
+
+/// The bundled profiles.
+const PROFILES: [RegionProfile; 4] = [
+    // Deep packet inspection and heavy throttling of unknown protocols.
+    RegionProfile {
+        code: "ir",
+        mimic_domain: "www.aparat.com",
+        obfuscation_tier: ObfuscationProfileTier::Maximal,
+    },
+    RegionProfile {
+        code: "cn",
+        mimic_domain: "www.microsoft.com",
+        obfuscation_tier: ObfuscationProfileTier::Maximal,
+    },
+    RegionProfile {
+        code: "ru",
+        mimic_domain: "www.yandex.ru",
+        obfuscation_tier: ObfuscationProfileTier::Balanced,
+    },
+    RegionProfile {
+        code: "tr",
+        mimic_domain: "www.trendyol.com",
+        obfuscation_tier: ObfuscationProfileTier::Balanced,
+    },
+];
+
+impl RegionProfile {
+    /// The profile for `code`, ignoring case.
+    pub fn lookup(code: &str) -> Option<&'static RegionProfile> {
+        PROFILES.iter().find(|profile| profile.code.eq_ignore_ascii_case(code))
+    }
+
+    /// Codes of every bundled profile.
+    pub fn codes() -> Vec<&'static str> {
+        PROFILES.iter().map(|profile| profile.code).collect()
+    }
+
+    /// The configuration keys this profile supplies, with their values.
+    pub fn defaults(&self) -> [(&'static str, &'static str); 2] {
+        [("mimic_domain", self.mimic_domain), ("obfuscation_tier", self.obfuscation_tier.as_str())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_ignores_case_and_rejects_unknown_codes() {
+        assert_eq!(RegionProfile::lookup("IR").unwrap().obfuscation_tier, ObfuscationProfileTier::Maximal);
+        assert_eq!(RegionProfile::lookup("ru").unwrap().mimic_domain, "www.yandex.ru");
+        assert!(RegionProfile::lookup("xx").is_none());
+        assert_eq!(RegionProfile::codes(), vec!["ir", "cn", "ru", "tr"]);
+    }
+}